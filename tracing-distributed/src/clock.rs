@@ -0,0 +1,78 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+/// Source of the times at which spans are initialized and completed, and events and
+/// transitions occur, as reported by `TelemetryLayer`. Defaults to `SystemClock`.
+pub trait Clock: Debug + Send + Sync + 'static {
+    /// The current time.
+    fn now(&self) -> SystemTime;
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> SystemTime {
+        (**self).now()
+    }
+}
+
+/// `Clock` reading the system time.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// `Clock` for use in tests, standing still until advanced, so the timing of reported spans
+/// can be asserted exactly.
+///
+/// Handles are cheap to clone, clones share the same time.
+#[derive(Clone, Debug)]
+pub struct MockClock(Arc<Mutex<SystemTime>>);
+
+impl MockClock {
+    /// Create a clock reading `now` until advanced.
+    pub fn new(now: SystemTime) -> Self {
+        MockClock(Arc::new(Mutex::new(now)))
+    }
+
+    fn lock(&self) -> impl std::ops::DerefMut<Target = SystemTime> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let now = self.0.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let now = self.0.lock();
+
+        now
+    }
+
+    /// Move the time of this clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.lock() += duration;
+    }
+
+    /// Set the time of this clock.
+    pub fn set(&self, now: SystemTime) {
+        *self.lock() = now;
+    }
+}
+
+impl Default for MockClock {
+    /// A clock reading the unix epoch until advanced.
+    fn default() -> Self {
+        MockClock::new(SystemTime::UNIX_EPOCH)
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> SystemTime {
+        *self.lock()
+    }
+}
@@ -0,0 +1,140 @@
+use std::cell::Cell;
+use tracing::Metadata;
+
+// target of the probe span created by `check_subscriber`, never reported
+const PROBE_TARGET: &str = "tracing_distributed::probe";
+
+#[derive(Clone, Copy, Default)]
+struct ProbeCounts {
+    opened: usize,
+    closed: usize,
+}
+
+thread_local! {
+    // layer callbacks run on the thread creating and closing the probe span
+    static PROBE_COUNTS: Cell<ProbeCounts> = Cell::new(ProbeCounts::default());
+}
+
+pub(crate) fn is_probe(meta: &Metadata<'_>) -> bool {
+    meta.target() == PROBE_TARGET
+}
+
+pub(crate) fn probe_opened() {
+    PROBE_COUNTS.with(|counts| {
+        let mut c = counts.get();
+        c.opened += 1;
+        counts.set(c);
+    });
+}
+
+pub(crate) fn probe_closed() {
+    PROBE_COUNTS.with(|counts| {
+        let mut c = counts.get();
+        c.closed += 1;
+        counts.set(c);
+    });
+}
+
+/// Problem with the way the default subscriber is assembled, detected by `check_subscriber`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SubscriberIssue {
+    /// No `TelemetryLayer` is installed, so no spans or events are reported.
+    NoTelemetryLayer,
+    /// The given number of `TelemetryLayer`s are installed. Layers recording the same type of
+    /// fields conflict, and spans and events may be reported more than once.
+    DuplicateTelemetryLayers(usize),
+    /// A `TelemetryLayer` observed spans being created but not closed, e.g. because a layer
+    /// stacked in front of it does not forward span close notifications. Spans are only
+    /// reported when they close, so none are reported.
+    SpanCloseNotObserved,
+    /// The subscriber is not built on `tracing_subscriber::Registry`, which is required to
+    /// resolve the current distributed trace context, e.g. by `current_dist_trace_ctx`.
+    RegistryNotRegistered,
+    /// The spans created by the check were disabled by a filter, so the `TelemetryLayer`
+    /// could not be checked.
+    ProbeFiltered,
+}
+
+/// Result of `check_subscriber`.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct SubscriberDiagnostics {
+    /// Problems detected, empty if none were.
+    pub issues: Vec<SubscriberIssue>,
+}
+
+impl SubscriberDiagnostics {
+    /// Whether no problems were detected.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+}
+
+/// Check that the default subscriber is assembled such that `TelemetryLayer` can report
+/// spans and events, as stacking problems otherwise silently break reporting. Meant to be
+/// called once at startup, after the subscriber has been installed.
+///
+/// Creates and closes an `ERROR` level span with the `tracing_distributed::probe` target,
+/// which is never reported.
+pub fn check_subscriber() -> SubscriberDiagnostics {
+    PROBE_COUNTS.with(|counts| counts.set(ProbeCounts::default()));
+
+    let has_registry = tracing::dispatcher::get_default(|dispatch| {
+        dispatch
+            .downcast_ref::<tracing_subscriber::Registry>()
+            .is_some()
+    });
+
+    let probe = tracing::error_span!(target: PROBE_TARGET, "telemetry_layer_probe");
+    let filtered = probe.is_disabled();
+    drop(probe);
+
+    let counts = PROBE_COUNTS.with(|counts| counts.replace(ProbeCounts::default()));
+
+    let mut issues = Vec::new();
+    if !has_registry {
+        issues.push(SubscriberIssue::RegistryNotRegistered);
+    }
+    match counts.opened {
+        _ if filtered => issues.push(SubscriberIssue::ProbeFiltered),
+        0 => issues.push(SubscriberIssue::NoTelemetryLayer),
+        1 => {}
+        n => issues.push(SubscriberIssue::DuplicateTelemetryLayers(n)),
+    }
+    if counts.opened > 0 && counts.closed < counts.opened {
+        issues.push(SubscriberIssue::SpanCloseNotObserved);
+    }
+
+    SubscriberDiagnostics { issues }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::telemetry::BlackholeTelemetry;
+    use crate::TelemetryLayer;
+    use tracing_subscriber::layer::Layer;
+
+    fn layer() -> TelemetryLayer<BlackholeTelemetry<tracing::Id, u64>, tracing::Id, u64> {
+        TelemetryLayer::new("diagnostics", BlackholeTelemetry::default(), |id| id)
+    }
+
+    #[test]
+    fn detects_missing_and_duplicate_layers() {
+        let subscriber = layer().with_subscriber(tracing_subscriber::Registry::default());
+        let diagnostics = tracing::subscriber::with_default(subscriber, check_subscriber);
+        assert!(diagnostics.is_ok(), "{:?}", diagnostics);
+
+        let subscriber = tracing_subscriber::Registry::default();
+        let diagnostics = tracing::subscriber::with_default(subscriber, check_subscriber);
+        assert_eq!(diagnostics.issues, vec![SubscriberIssue::NoTelemetryLayer]);
+
+        let subscriber = layer()
+            .and_then(layer())
+            .with_subscriber(tracing_subscriber::Registry::default());
+        let diagnostics = tracing::subscriber::with_default(subscriber, check_subscriber);
+        assert_eq!(
+            diagnostics.issues,
+            vec![SubscriberIssue::DuplicateTelemetryLayers(2)]
+        );
+    }
+}
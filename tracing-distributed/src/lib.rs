@@ -9,12 +9,17 @@
 //! This crate is primarily intended to be used by people implementing their own backends.
 //! A concrete implementation using honeycomb.io as a backend is available in the [`tracing-honeycomb` crate](https://crates.io/crates/tracing-honeycomb).
 
+mod clock;
+mod diagnostics;
 mod telemetry;
 mod telemetry_layer;
 mod trace;
 
-pub use crate::telemetry::{BlackholeTelemetry, Telemetry};
-pub use crate::telemetry_layer::TelemetryLayer;
+pub use crate::clock::{Clock, MockClock, SystemClock};
+pub use crate::diagnostics::{check_subscriber, SubscriberDiagnostics, SubscriberIssue};
+pub use crate::telemetry::{BlackholeTelemetry, ReportedCounts, Telemetry};
+pub use crate::telemetry_layer::{RedundantRootPolicy, TelemetryLayer};
 pub use crate::trace::{
-    current_dist_trace_ctx, register_dist_tracing_root, Event, Span, TraceCtxError,
+    current_dist_trace_ctx, dist_trace_ctx_of, register_dist_tracing_root, Event, FollowsFrom,
+    Span, SpanStart, TraceCtxError, Transition, TransitionKind,
 };
@@ -1,5 +1,13 @@
-use crate::trace::{Event, Span};
+use crate::trace::{Event, FollowsFrom, Span, SpanStart, Transition};
+use std::any::TypeId;
+use std::collections::HashMap;
 use std::marker::PhantomData;
+use std::sync::Arc;
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
 
 /// Represents the ability to publish events and spans to some arbitrary backend.
 pub trait Telemetry {
@@ -18,6 +26,86 @@ pub trait Telemetry {
 
     /// Report an `Event` to this Telemetry instance's backend.
     fn report_event(&self, event: Event<Self::Visitor, Self::SpanId, Self::TraceId>);
+
+    /// Whether spans and events should currently be recorded and reported. Defaults to `true`.
+    ///
+    /// While disabled, `TelemetryLayer` does not record fields, evaluate trace contexts or call
+    /// any other method of this instance. Spans opened while disabled are never reported.
+    fn is_enabled(&self) -> bool {
+        true
+    }
+
+    /// Whether the fields of spans and events within a trace depend on the trace, i.e. whether
+    /// `records_trace` should be called. Defaults to `false`, as looking up the trace of every
+    /// span as it is created is not free.
+    fn samples_traces(&self) -> bool {
+        false
+    }
+
+    /// Whether the fields of spans and events within the trace `trace_id` should be recorded,
+    /// e.g. `false` for traces that are sampled out. Only called if `samples_traces` returns
+    /// `true`, once per span created within a trace and once per event.
+    ///
+    /// The decision is made when a span is created and holds for the span's lifetime, and for
+    /// its descendants. Spans and events of unrecorded traces are still reported, so per-trace
+    /// bookkeeping is unaffected, but with a visitor that recorded no fields.
+    fn records_trace(&self, _trace_id: &Self::TraceId) -> bool {
+        true
+    }
+
+    /// Whether fields recorded on ancestor spans should be copied onto spans and events via
+    /// `inherit_fields` when they are reported. Defaults to `false`.
+    fn inherits_fields(&self) -> bool {
+        false
+    }
+
+    /// Copy fields from the visitor of an ancestor span onto the visitor of a span or event
+    /// about to be reported. Called once per ancestor, nearest first. Only called if
+    /// `inherits_fields` returns `true`.
+    fn inherit_fields(&self, _ancestor: &Self::Visitor, _visitor: &mut Self::Visitor) {}
+
+    /// Whether spans being entered and exited should be reported via `report_transition`.
+    /// Defaults to `false`, as evaluating the trace context on every transition is not free.
+    fn reports_transitions(&self) -> bool {
+        false
+    }
+
+    /// Report a `Transition` to this Telemetry instance's backend. Only called if
+    /// `reports_transitions` returns `true`.
+    fn report_transition(&self, _transition: Transition<Self::SpanId, Self::TraceId>) {}
+
+    /// Whether spans should be reported via `report_span_start` when first entered, in addition
+    /// to being reported via `report_span` when closed. Defaults to `false`.
+    fn reports_span_starts(&self) -> bool {
+        false
+    }
+
+    /// Report a `SpanStart` to this Telemetry instance's backend. Only called if
+    /// `reports_span_starts` returns `true`, at most once per span.
+    fn report_span_start(&self, _start: SpanStart<Self::SpanId, Self::TraceId>) {}
+
+    /// `TraceId` of a new trace in which to report an event recorded outside of any trace, via
+    /// `report_event` with no parent span. Called once per such event. Defaults to `None`, in
+    /// which case these events are not reported.
+    fn orphan_trace_id(&self) -> Option<Self::TraceId> {
+        None
+    }
+
+    /// Report a `FollowsFrom` relationship to this Telemetry instance's backend. Called when
+    /// the span that follows from the other span closes, before it is reported via
+    /// `report_span`, if both spans are part of a trace.
+    fn report_follows_from(&self, _follows_from: FollowsFrom<Self::SpanId, Self::TraceId>) {}
+
+    /// Allows `TelemetryLayer` to be downcast to components of this Telemetry instance, in
+    /// addition to the instance itself. Defaults to `None`.
+    ///
+    /// # Safety
+    ///
+    /// Implementations must only return a pointer to a value of the type identified by `id`,
+    /// that lives as long as `self`.
+    unsafe fn downcast_raw(&self, _id: TypeId) -> Option<*const ()> {
+        None
+    }
 }
 
 /// Visitor that records no information when visiting tracing fields.
@@ -28,13 +116,22 @@ impl tracing::field::Visit for BlackholeVisitor {
     fn record_debug(&mut self, _: &tracing::field::Field, _: &dyn std::fmt::Debug) {}
 }
 
-/// Telemetry implementation that does not publish information to any backend.
-/// For use in tests.
-pub struct BlackholeTelemetry<S, T>(PhantomData<S>, PhantomData<T>);
+/// Telemetry implementation that does not publish information to any backend, only counting
+/// the spans and events reported to it, see `BlackholeTelemetry::counts`.
+/// For use in tests and benchmarks.
+pub struct BlackholeTelemetry<S, T>(PhantomData<S>, PhantomData<T>, ReportedCounts);
 
 impl<S, T> Default for BlackholeTelemetry<S, T> {
     fn default() -> Self {
-        BlackholeTelemetry(PhantomData, PhantomData)
+        BlackholeTelemetry(PhantomData, PhantomData, ReportedCounts::default())
+    }
+}
+
+impl<S, T> BlackholeTelemetry<S, T> {
+    /// Get a handle on the number of spans and events reported to this instance, e.g. to check
+    /// that a `TelemetryLayer` reports data as expected without network I/O.
+    pub fn counts(&self) -> ReportedCounts {
+        self.2.clone()
     }
 }
 
@@ -51,14 +148,110 @@ where
         Default::default()
     }
 
-    fn report_span(&self, _: Span<Self::Visitor, Self::SpanId, Self::TraceId>) {}
+    fn report_span(&self, span: Span<Self::Visitor, Self::SpanId, Self::TraceId>) {
+        self.2.record(span.meta, |counts| counts.spans += 1);
+    }
+
+    fn report_event(&self, event: Event<Self::Visitor, Self::SpanId, Self::TraceId>) {
+        self.2.record(event.meta, |counts| counts.events += 1);
+    }
+}
+
+/// Number of spans and events reported to a `BlackholeTelemetry`, by level and target.
+///
+/// Handles are cheap to clone, clones share the same counts.
+#[derive(Clone, Debug, Default)]
+pub struct ReportedCounts(Arc<Mutex<HashMap<String, [Counts; 5]>>>);
+
+#[derive(Clone, Copy, Debug, Default)]
+struct Counts {
+    spans: u64,
+    events: u64,
+}
+
+// index of each level in the counts of a target, most verbose first
+fn level_index(level: &tracing::Level) -> usize {
+    match *level {
+        tracing::Level::TRACE => 0,
+        tracing::Level::DEBUG => 1,
+        tracing::Level::INFO => 2,
+        tracing::Level::WARN => 3,
+        tracing::Level::ERROR => 4,
+    }
+}
+
+impl ReportedCounts {
+    fn lock(&self) -> impl std::ops::DerefMut<Target = HashMap<String, [Counts; 5]>> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let counts = self.0.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let counts = self.0.lock();
+
+        counts
+    }
+
+    fn record<F: FnOnce(&mut Counts)>(&self, meta: &tracing::Metadata<'_>, f: F) {
+        let mut counts = self.lock();
+        let target = match counts.get_mut(meta.target()) {
+            Some(target) => target,
+            None => counts.entry(meta.target().to_string()).or_default(),
+        };
+        f(&mut target[level_index(meta.level())]);
+    }
+
+    fn sum<F: Fn(&str, &Counts) -> u64>(&self, f: F) -> u64 {
+        self.lock()
+            .iter()
+            .flat_map(|(target, counts)| counts.iter().map(move |counts| (target, counts)))
+            .map(|(target, counts)| f(target, counts))
+            .sum()
+    }
+
+    /// Number of spans reported with the given level and target.
+    pub fn spans(&self, level: tracing::Level, target: &str) -> u64 {
+        self.lock()
+            .get(target)
+            .map_or(0, |counts| counts[level_index(&level)].spans)
+    }
+
+    /// Number of events reported with the given level and target.
+    pub fn events(&self, level: tracing::Level, target: &str) -> u64 {
+        self.lock()
+            .get(target)
+            .map_or(0, |counts| counts[level_index(&level)].events)
+    }
+
+    /// Number of spans reported with the given target, at any level.
+    pub fn target_spans(&self, target: &str) -> u64 {
+        self.sum(|t, counts| if t == target { counts.spans } else { 0 })
+    }
+
+    /// Number of events reported with the given target, at any level.
+    pub fn target_events(&self, target: &str) -> u64 {
+        self.sum(|t, counts| if t == target { counts.events } else { 0 })
+    }
+
+    /// Number of spans reported, with any level and target.
+    pub fn total_spans(&self) -> u64 {
+        self.sum(|_, counts| counts.spans)
+    }
 
-    fn report_event(&self, _: Event<Self::Visitor, Self::SpanId, Self::TraceId>) {}
+    /// Number of events reported, with any level and target.
+    pub fn total_events(&self) -> u64 {
+        self.sum(|_, counts| counts.events)
+    }
+
+    /// Reset all counts to zero, e.g. between benchmark iterations.
+    pub fn reset(&self) {
+        self.lock().clear();
+    }
 }
 
 #[cfg(test)]
 pub(crate) mod test {
     use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
     use std::sync::Mutex;
 
@@ -66,18 +259,57 @@ pub(crate) mod test {
     pub(crate) type TraceId = u64;
     pub(crate) type SpanId = tracing::Id;
 
+    type SpanStarts = Arc<Mutex<Vec<SpanStart<SpanId, TraceId>>>>;
+    type FollowsFroms = Arc<Mutex<Vec<FollowsFrom<SpanId, TraceId>>>>;
+
     /// Mock telemetry capability
     pub struct TestTelemetry {
         spans: Arc<Mutex<Vec<Span<BlackholeVisitor, SpanId, TraceId>>>>,
         events: Arc<Mutex<Vec<Event<BlackholeVisitor, SpanId, TraceId>>>>,
+        transitions: Arc<Mutex<Vec<Transition<SpanId, TraceId>>>>,
+        // `None` unless span starts are reported, see `with_span_starts`
+        span_starts: Option<SpanStarts>,
+        // `None` unless follows from relationships are recorded, see `with_follows_from`
+        follows_from: Option<FollowsFroms>,
+        orphan_trace_id: Option<TraceId>,
+        enabled: Arc<AtomicBool>,
     }
 
     impl TestTelemetry {
         pub fn new(
             spans: Arc<Mutex<Vec<Span<BlackholeVisitor, SpanId, TraceId>>>>,
             events: Arc<Mutex<Vec<Event<BlackholeVisitor, SpanId, TraceId>>>>,
+            transitions: Arc<Mutex<Vec<Transition<SpanId, TraceId>>>>,
         ) -> Self {
-            TestTelemetry { spans, events }
+            TestTelemetry {
+                spans,
+                events,
+                transitions,
+                span_starts: None,
+                follows_from: None,
+                orphan_trace_id: None,
+                enabled: Arc::new(AtomicBool::new(true)),
+            }
+        }
+
+        pub fn with_span_starts(mut self, span_starts: SpanStarts) -> Self {
+            self.span_starts = Some(span_starts);
+            self
+        }
+
+        pub fn with_follows_from(mut self, follows_from: FollowsFroms) -> Self {
+            self.follows_from = Some(follows_from);
+            self
+        }
+
+        pub fn with_orphan_trace_id(mut self, trace_id: TraceId) -> Self {
+            self.orphan_trace_id = Some(trace_id);
+            self
+        }
+
+        pub fn with_enabled(mut self, enabled: Arc<AtomicBool>) -> Self {
+            self.enabled = enabled;
+            self
         }
     }
 
@@ -101,5 +333,43 @@ pub(crate) mod test {
             let mut events = self.events.lock().unwrap();
             events.push(event);
         }
+
+        fn is_enabled(&self) -> bool {
+            self.enabled.load(Ordering::Relaxed)
+        }
+
+        fn reports_transitions(&self) -> bool {
+            true
+        }
+
+        fn report_transition(&self, transition: Transition<SpanId, TraceId>) {
+            // succeed or die. failure is unrecoverable (mutex poisoned)
+            let mut transitions = self.transitions.lock().unwrap();
+            transitions.push(transition);
+        }
+
+        fn reports_span_starts(&self) -> bool {
+            self.span_starts.is_some()
+        }
+
+        fn report_span_start(&self, start: SpanStart<SpanId, TraceId>) {
+            if let Some(span_starts) = &self.span_starts {
+                // succeed or die. failure is unrecoverable (mutex poisoned)
+                let mut span_starts = span_starts.lock().unwrap();
+                span_starts.push(start);
+            }
+        }
+
+        fn orphan_trace_id(&self) -> Option<TraceId> {
+            self.orphan_trace_id
+        }
+
+        fn report_follows_from(&self, follows_from: FollowsFrom<SpanId, TraceId>) {
+            if let Some(recorded) = &self.follows_from {
+                // succeed or die. failure is unrecoverable (mutex poisoned)
+                let mut recorded = recorded.lock().unwrap();
+                recorded.push(follows_from);
+            }
+        }
     }
 }
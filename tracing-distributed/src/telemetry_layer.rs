@@ -1,7 +1,11 @@
+use crate::clock::{Clock, SystemClock};
+use crate::diagnostics;
 use crate::telemetry::Telemetry;
 use crate::trace;
 use std::any::TypeId;
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::SystemTime;
 use tracing::span::{Attributes, Id, Record};
 use tracing::{Event, Subscriber};
@@ -17,6 +21,7 @@ use std::sync::RwLock;
 pub struct TelemetryLayer<Telemetry, SpanId, TraceId> {
     pub(crate) telemetry: Telemetry,
     service_name: &'static str,
+    clock: Arc<dyn Clock>,
     // used to construct span ids to avoid collisions
     pub(crate) trace_ctx_registry: TraceCtxRegistry<SpanId, TraceId>,
 }
@@ -31,6 +36,25 @@ pub(crate) struct TraceCtx<SpanId, TraceId> {
 pub(crate) struct TraceCtxRegistry<SpanId, TraceId> {
     registry: RwLock<HashMap<Id, TraceCtx<SpanId, TraceId>>>,
     promote_span_id: Box<dyn 'static + Send + Sync + Fn(Id) -> SpanId>,
+    pub(crate) redundant_root_policy: RedundantRootPolicy,
+    // compares trace ids to detect redundant roots, set along with `redundant_root_policy`, whose
+    // setter is the only API requiring `TraceId: PartialEq`
+    pub(crate) same_trace_id: Option<fn(&TraceId, &TraceId) -> bool>,
+}
+
+/// What to do when `register_dist_tracing_root` is called within a trace that was already
+/// registered with a different `TraceId`, e.g. by both an HTTP middleware and the request
+/// handler it wraps.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum RedundantRootPolicy {
+    /// The later registration takes effect, splitting the request across two traces.
+    #[default]
+    LastWins,
+    /// The later registration is ignored, the first `TraceId` is kept.
+    FirstWins,
+    /// The later registration is ignored, and an `ERROR` level event recording the conflict is
+    /// emitted within the first trace.
+    ErrorEvent,
 }
 
 impl<SpanId, TraceId> TraceCtxRegistry<SpanId, TraceId>
@@ -42,6 +66,27 @@ where
         (self.promote_span_id)(id)
     }
 
+    /// Get the `SpanId` assigned to a span when it was created.
+    pub(crate) fn span_id<'a, X: registry::LookupSpan<'a>>(
+        &self,
+        span_ref: &registry::SpanRef<'a, X>,
+    ) -> SpanId {
+        match span_ref.extensions().get::<PromotedSpanId<SpanId>>() {
+            Some(PromotedSpanId(span_id)) => span_id.clone(),
+            // spans created before this layer was registered
+            None => self.promote_span_id(span_ref.id()),
+        }
+    }
+
+    pub(crate) fn is_local_root(&self, id: &Id) -> bool {
+        #[cfg(not(feature = "use_parking_lot"))]
+        let trace_ctx_registry = self.registry.read().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let trace_ctx_registry = self.registry.read();
+
+        trace_ctx_registry.contains_key(id)
+    }
+
     pub(crate) fn record_trace_ctx(
         &self,
         trace_id: TraceId,
@@ -61,6 +106,69 @@ where
         trace_ctx_registry.insert(id, trace_ctx); // TODO: handle overwrite?
     }
 
+    /// Forget the trace ctx recorded for a span, called once the span has closed so that a
+    /// new span reusing its `Id` does not inherit it.
+    pub(crate) fn remove_trace_ctx(&self, id: &Id) {
+        // only registered spans have a trace ctx, so closing any other span skips the write
+        // lock. closed spans cannot be registered concurrently, having no handle left
+        if !self.is_local_root(id) {
+            return;
+        }
+
+        #[cfg(not(feature = "use_parking_lot"))]
+        let mut trace_ctx_registry = self.registry.write().expect("write lock!");
+        #[cfg(feature = "use_parking_lot")]
+        let mut trace_ctx_registry = self.registry.write();
+
+        trace_ctx_registry.remove(id);
+    }
+
+    /// Forget the trace ctx evaluated and cached on a span by `eval_ctx`, e.g. when the span
+    /// was entered before being registered as the root of a distributed trace.
+    pub(crate) fn forget_evaluated_ctx<'a, X: registry::LookupSpan<'a>>(
+        &self,
+        span_ref: &registry::SpanRef<'a, X>,
+    ) {
+        let evaluated = span_ref
+            .extensions_mut()
+            .remove::<LazyTraceCtx<SpanId, TraceId>>();
+        // descendants may have been evaluated through the span, sharing its validity
+        if let Some(LazyTraceCtx(_, valid)) = evaluated {
+            valid.store(false, Ordering::Release);
+        }
+    }
+
+    /// Get the trace id of the nearest registered span among `iter`, without caching the
+    /// evaluated trace ctx on the spans traversed, as `eval_ctx` does.
+    pub(crate) fn peek_trace_id<
+        'a,
+        X: 'a + registry::LookupSpan<'a>,
+        I: std::iter::Iterator<Item = registry::SpanRef<'a, X>>,
+    >(
+        &self,
+        iter: I,
+    ) -> Option<TraceId> {
+        #[cfg(not(feature = "use_parking_lot"))]
+        let trace_ctx_registry = self.registry.read().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let trace_ctx_registry = self.registry.read();
+
+        for span_ref in iter {
+            if let Some(LazyTraceCtx(trace_ctx, valid)) =
+                span_ref.extensions().get::<LazyTraceCtx<SpanId, TraceId>>()
+            {
+                if valid.load(Ordering::Acquire) {
+                    return Some(trace_ctx.trace_id.clone());
+                }
+            }
+            if let Some(trace_ctx) = trace_ctx_registry.get(&span_ref.id()) {
+                return Some(trace_ctx.trace_id.clone());
+            }
+        }
+
+        None
+    }
+
     pub(crate) fn eval_ctx<
         'a,
         X: 'a + registry::LookupSpan<'a>,
@@ -73,7 +181,11 @@ where
 
         for span_ref in iter {
             let mut write_guard = span_ref.extensions_mut();
-            match write_guard.get_mut::<LazyTraceCtx<SpanId, TraceId>>() {
+            // cached trace ctxs are stale once a span they were evaluated through is registered
+            let evaluated = write_guard
+                .get_mut::<LazyTraceCtx<SpanId, TraceId>>()
+                .filter(|LazyTraceCtx(_, valid)| valid.load(Ordering::Acquire));
+            match evaluated {
                 None => {
                     #[cfg(not(feature = "use_parking_lot"))]
                     let trace_ctx_registry = self.registry.read().unwrap();
@@ -86,7 +198,9 @@ where
                             path.push(span_ref);
                         }
                         Some(local_trace_root) => {
-                            write_guard.insert(LazyTraceCtx(local_trace_root.clone()));
+                            let valid = Arc::new(AtomicBool::new(true));
+                            write_guard
+                                .replace(LazyTraceCtx(local_trace_root.clone(), valid.clone()));
 
                             let res = if path.is_empty() {
                                 local_trace_root.clone()
@@ -99,18 +213,19 @@ where
 
                             for span_ref in path.into_iter() {
                                 let mut write_guard = span_ref.extensions_mut();
-                                write_guard.insert::<LazyTraceCtx<SpanId, TraceId>>(LazyTraceCtx(
+                                write_guard.replace::<LazyTraceCtx<SpanId, TraceId>>(LazyTraceCtx(
                                     TraceCtx {
                                         trace_id: local_trace_root.trace_id.clone(),
                                         parent_span: None,
                                     },
+                                    valid.clone(),
                                 ));
                             }
                             return Some(res);
                         }
                     }
                 }
-                Some(LazyTraceCtx(already_evaluated)) => {
+                Some(LazyTraceCtx(already_evaluated, valid)) => {
                     let res = if path.is_empty() {
                         already_evaluated.clone()
                     } else {
@@ -122,11 +237,12 @@ where
 
                     for span_ref in path.into_iter() {
                         let mut write_guard = span_ref.extensions_mut();
-                        write_guard.insert::<LazyTraceCtx<SpanId, TraceId>>(LazyTraceCtx(
+                        write_guard.replace::<LazyTraceCtx<SpanId, TraceId>>(LazyTraceCtx(
                             TraceCtx {
                                 trace_id: already_evaluated.trace_id.clone(),
                                 parent_span: None,
                             },
+                            valid.clone(),
                         ));
                     }
                     return Some(res);
@@ -144,6 +260,8 @@ where
         TraceCtxRegistry {
             registry,
             promote_span_id,
+            redundant_root_policy: RedundantRootPolicy::default(),
+            same_trace_id: None,
         }
     }
 }
@@ -156,6 +274,10 @@ where
     /// Construct a new TelemetryLayer using the provided `Telemetry` capability.
     /// Uses the provided function, `F`, to promote `tracing::span::Id` instances to the
     /// `SpanId` type associated with the provided `Telemetry` instance.
+    ///
+    /// Each span's id is promoted exactly once, when the span is created, so `F` may
+    /// incorporate non-deterministic salts (eg a generation counter) to distinguish spans
+    /// that reuse the `tracing::span::Id` of a previously closed span.
     pub fn new<F: 'static + Send + Sync + Fn(Id) -> SpanId>(
         service_name: &'static str,
         telemetry: T,
@@ -165,10 +287,217 @@ where
 
         TelemetryLayer {
             service_name,
+            clock: Arc::new(SystemClock),
             telemetry,
             trace_ctx_registry,
         }
     }
+
+    /// Set the clock used to time spans, events and transitions, e.g. a `MockClock` so tests
+    /// can assert exact span durations. Defaults to `SystemClock`.
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+}
+
+impl<T, SpanId, TraceId> TelemetryLayer<T, SpanId, TraceId>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + PartialEq + Send + Sync,
+{
+    /// Set what to do when a span is registered as the root of a distributed trace within a
+    /// trace that was already registered with a different `TraceId`. Defaults to
+    /// `RedundantRootPolicy::LastWins`.
+    pub fn redundant_root_policy(mut self, policy: RedundantRootPolicy) -> Self {
+        self.trace_ctx_registry.redundant_root_policy = policy;
+        self.trace_ctx_registry.same_trace_id = Some(PartialEq::eq);
+        self
+    }
+}
+
+impl<TraceId, SpanId, V, T> TelemetryLayer<T, SpanId, TraceId>
+where
+    TraceId: 'static + Clone + Eq + Send + Sync,
+    SpanId: 'static + Clone + Eq + Send + Sync,
+    V: 'static + tracing::field::Visit + Send + Sync,
+    T: 'static + Telemetry<Visitor = V, TraceId = TraceId, SpanId = SpanId>,
+{
+    // copies fields from the visitors of `parent` and its ancestors, if the telemetry opts in
+    fn inherit_fields<'a, R>(&self, visitor: &mut V, parent: Option<registry::SpanRef<'a, R>>)
+    where
+        R: registry::LookupSpan<'a>,
+    {
+        if !self.telemetry.inherits_fields() {
+            return;
+        }
+
+        let mut ancestor = parent;
+        while let Some(span) = ancestor {
+            if let Some(ancestor_visitor) = span.extensions().get::<V>() {
+                self.telemetry.inherit_fields(ancestor_visitor, visitor);
+            }
+            ancestor = span.parent();
+        }
+    }
+
+    // whether the fields of `span`, being created, should be recorded. spans are not
+    // registered as the root of a trace until created, so only ancestors are looked up
+    fn records_span<'a, R>(&self, span: &registry::SpanRef<'a, R>) -> bool
+    where
+        R: registry::LookupSpan<'a>,
+    {
+        if !self.telemetry.samples_traces() {
+            return true;
+        }
+
+        let parent = match span.parent() {
+            Some(parent) => parent,
+            None => return true,
+        };
+        if parent.extensions().get::<Unrecorded>().is_some() {
+            // the decision holds for descendants, which belong to the same trace
+            return false;
+        }
+        let ancestors = itertools::unfold(Some(parent), |st| {
+            let res = st.take()?;
+            *st = res.parent();
+            Some(res)
+        });
+        match self.trace_ctx_registry.peek_trace_id(ancestors) {
+            Some(trace_id) => self.telemetry.records_trace(&trace_id),
+            None => true,
+        }
+    }
+
+    fn report_transition<S>(&self, id: &Id, kind: trace::TransitionKind, ctx: Context<'_, S>)
+    where
+        S: Subscriber + for<'a> registry::LookupSpan<'a>,
+    {
+        if !self.telemetry.is_enabled() || !self.telemetry.reports_transitions() {
+            return;
+        }
+
+        let occurred_at = self.clock.now();
+        let iter = itertools::unfold(Some(id.clone()), |st| match st {
+            Some(target_id) => {
+                let res = ctx
+                    .span(target_id)
+                    .expect("span data not found during eval_ctx");
+                *st = res.parent().map(|x| x.id());
+                Some(res)
+            }
+            None => None,
+        });
+
+        // only report transitions of spans that are part of a trace
+        if let Some(trace_ctx) = self.trace_ctx_registry.eval_ctx(iter) {
+            let span = ctx
+                .span(id)
+                .expect("span data not found during report_transition");
+
+            let transition = trace::Transition {
+                kind,
+                span_id: self.trace_ctx_registry.span_id(&span),
+                trace_id: trace_ctx.trace_id,
+                occurred_at,
+                meta: span.metadata(),
+                service_name: self.service_name,
+            };
+
+            self.telemetry.report_transition(transition);
+        }
+    }
+
+    // report an event recorded outside of any trace in a new trace of its own, if the telemetry
+    // provides one
+    fn report_orphan_event<S>(&self, event: &Event<'_>, parent_id: Option<Id>, ctx: Context<'_, S>)
+    where
+        S: Subscriber + for<'a> registry::LookupSpan<'a>,
+    {
+        let trace_id = match self.telemetry.orphan_trace_id() {
+            Some(trace_id) => trace_id,
+            None => return,
+        };
+
+        let initialized_at = self.clock.now();
+        let mut visitor = self.telemetry.mk_visitor();
+        if !self.telemetry.samples_traces() || self.telemetry.records_trace(&trace_id) {
+            event.record(&mut visitor);
+            if let Some(parent_id) = parent_id {
+                self.inherit_fields(&mut visitor, ctx.span(&parent_id));
+            }
+        }
+
+        let event = trace::Event {
+            trace_id,
+            parent_id: None,
+            initialized_at,
+            meta: event.metadata(),
+            service_name: self.service_name,
+            values: visitor,
+        };
+
+        self.telemetry.report_event(event);
+    }
+
+    fn report_span_start<S>(&self, id: &Id, ctx: Context<'_, S>)
+    where
+        S: Subscriber + for<'a> registry::LookupSpan<'a>,
+    {
+        if !self.telemetry.is_enabled() || !self.telemetry.reports_span_starts() {
+            return;
+        }
+
+        let span = ctx
+            .span(id)
+            .expect("span data not found during report_span_start");
+        // reported on the first enter only, as the trace ctx of a span is usually registered
+        // after the span is created
+        if span.extensions().get::<Started>().is_some() {
+            return;
+        }
+
+        let iter = itertools::unfold(Some(id.clone()), |st| match st {
+            Some(target_id) => {
+                let res = ctx
+                    .span(target_id)
+                    .expect("span data not found during eval_ctx");
+                *st = res.parent().map(|x| x.id());
+                Some(res)
+            }
+            None => None,
+        });
+
+        // spans entered before being part of a trace are reported on a later enter, if any
+        if let Some(trace_ctx) = self.trace_ctx_registry.eval_ctx(iter) {
+            let mut extensions_mut = span.extensions_mut();
+            extensions_mut.insert(Started);
+            let initialized_at = match extensions_mut.get_mut::<SpanInitAt>() {
+                Some(SpanInitAt(initialized_at)) => *initialized_at,
+                None => self.clock.now(),
+            };
+            drop(extensions_mut);
+
+            let parent_id = match trace_ctx.parent_span {
+                None => span
+                    .parent()
+                    .map(|parent_ref| self.trace_ctx_registry.span_id(&parent_ref)),
+                Some(parent_span) => Some(parent_span),
+            };
+
+            let start = trace::SpanStart {
+                span_id: self.trace_ctx_registry.span_id(&span),
+                parent_id,
+                trace_id: trace_ctx.trace_id,
+                initialized_at,
+                meta: span.metadata(),
+                service_name: self.service_name,
+            };
+
+            self.telemetry.report_span_start(start);
+        }
+    }
 }
 
 impl<S, TraceId, SpanId, V, T> Layer<S> for TelemetryLayer<T, SpanId, TraceId>
@@ -180,46 +509,63 @@ where
     T: 'static + Telemetry<Visitor = V, TraceId = TraceId, SpanId = SpanId>,
 {
     fn new_span(&self, attrs: &Attributes, id: &Id, ctx: Context<S>) {
+        if diagnostics::is_probe(attrs.metadata()) {
+            diagnostics::probe_opened();
+            return;
+        }
+
         let span = ctx.span(id).expect("span data not found during new_span");
         let mut extensions_mut = span.extensions_mut();
-        extensions_mut.insert(SpanInitAt::new());
+        extensions_mut.insert(SpanInitAt(self.clock.now()));
+        extensions_mut.insert(PromotedSpanId(
+            self.trace_ctx_registry.promote_span_id(id.clone()),
+        ));
+
+        if !self.telemetry.is_enabled() {
+            return;
+        }
 
         let mut visitor: V = self.telemetry.mk_visitor();
-        attrs.record(&mut visitor);
+        if self.records_span(&span) {
+            attrs.record(&mut visitor);
+        } else {
+            extensions_mut.insert(Unrecorded);
+        }
         extensions_mut.insert::<V>(visitor);
     }
 
     fn on_record(&self, id: &Id, values: &Record, ctx: Context<S>) {
         let span = ctx.span(id).expect("span data not found during on_record");
         let mut extensions_mut = span.extensions_mut();
-        let visitor: &mut V = extensions_mut
-            .get_mut()
-            .expect("fields extension not found during on_record");
-        values.record(visitor);
+        if extensions_mut.get_mut::<Unrecorded>().is_some() {
+            return;
+        }
+        // not present on spans opened while the telemetry was disabled
+        if let Some(visitor) = extensions_mut.get_mut::<V>() {
+            values.record(visitor);
+        }
     }
 
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if !self.telemetry.is_enabled() {
+            return;
+        }
+
         let parent_id = if let Some(parent_id) = event.parent() {
             // explicit parent
             Some(parent_id.clone())
         } else if event.is_root() {
             // don't bother checking thread local if span is explicitly root according to this fn
             None
-        } else if let Some(parent_id) = ctx.current_span().id() {
-            // implicit parent from threadlocal ctx
-            Some(parent_id.clone())
         } else {
-            // no parent span, thus this is a root span
-            None
+            // implicit parent from threadlocal ctx, or no parent span, thus this is a root span
+            ctx.current_span().id().cloned()
         };
 
-        match parent_id {
-            None => {} // not part of a trace, don't bother recording via honeycomb
+        match parent_id.clone() {
+            None => {} // not part of a trace, reported as an orphan event if at all
             Some(parent_id) => {
-                let initialized_at = SystemTime::now();
-
-                let mut visitor = self.telemetry.mk_visitor();
-                event.record(&mut visitor);
+                let initialized_at = self.clock.now();
 
                 // TODO: dedup
                 let iter = itertools::unfold(Some(parent_id.clone()), |st| match st {
@@ -235,23 +581,90 @@ where
 
                 // only report event if it's part of a trace
                 if let Some(parent_trace_ctx) = self.trace_ctx_registry.eval_ctx(iter) {
+                    let parent = ctx
+                        .span(&parent_id)
+                        .expect("span data not found during on_event");
+                    let mut visitor = self.telemetry.mk_visitor();
+                    let recorded = parent.extensions().get::<Unrecorded>().is_none()
+                        && (!self.telemetry.samples_traces()
+                            || self.telemetry.records_trace(&parent_trace_ctx.trace_id));
+                    if recorded {
+                        event.record(&mut visitor);
+                        self.inherit_fields(&mut visitor, ctx.span(&parent_id));
+                    }
+
                     let event = trace::Event {
                         trace_id: parent_trace_ctx.trace_id,
-                        parent_id: Some(self.trace_ctx_registry.promote_span_id(parent_id)),
+                        parent_id: Some(self.trace_ctx_registry.span_id(&parent)),
                         initialized_at,
                         meta: event.metadata(),
-                        service_name: &self.service_name,
+                        service_name: self.service_name,
                         values: visitor,
                     };
 
                     self.telemetry.report_event(event);
+                    return;
                 }
             }
         }
+
+        self.report_orphan_event(event, parent_id, ctx);
+    }
+
+    fn on_follows_from(&self, id: &Id, follows: &Id, ctx: Context<'_, S>) {
+        if !self.telemetry.is_enabled() {
+            return;
+        }
+
+        let iter = itertools::unfold(Some(follows.clone()), |st| match st {
+            Some(target_id) => {
+                let res = ctx
+                    .span(target_id)
+                    .expect("span data not found during eval_ctx");
+                *st = res.parent().map(|x| x.id());
+                Some(res)
+            }
+            None => None,
+        });
+
+        // the span followed from may have closed by the time the span that follows from it
+        // closes, so its trace ctx is evaluated now
+        if let Some(follows_trace_ctx) = self.trace_ctx_registry.eval_ctx(iter) {
+            let follows_span = ctx
+                .span(follows)
+                .expect("span data not found during on_follows_from");
+            let followed = Followed {
+                span_id: self.trace_ctx_registry.span_id(&follows_span),
+                trace_id: follows_trace_ctx.trace_id,
+                occurred_at: self.clock.now(),
+            };
+
+            let span = ctx
+                .span(id)
+                .expect("span data not found during on_follows_from");
+            let mut extensions_mut = span.extensions_mut();
+            match extensions_mut.get_mut::<FollowedSpans<SpanId, TraceId>>() {
+                Some(FollowedSpans(followed_spans)) => followed_spans.push(followed),
+                None => extensions_mut.insert(FollowedSpans(vec![followed])),
+            }
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        self.report_span_start(id, ctx.clone());
+        self.report_transition(id, trace::TransitionKind::Enter, ctx);
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        self.report_transition(id, trace::TransitionKind::Exit, ctx);
     }
 
     fn on_close(&self, id: Id, ctx: Context<'_, S>) {
         let span = ctx.span(&id).expect("span data not found during on_close");
+        if diagnostics::is_probe(span.metadata()) {
+            diagnostics::probe_closed();
+            return;
+        }
 
         // TODO: could be span.parents() but also needs span itself
         let iter = itertools::unfold(Some(id.clone()), |st| match st {
@@ -266,29 +679,64 @@ where
         });
 
         // if span's enclosing ctx has a trace id, eval & use to report telemetry
-        if let Some(trace_ctx) = self.trace_ctx_registry.eval_ctx(iter) {
-            let mut extensions_mut = span.extensions_mut();
-            let visitor: V = extensions_mut
-                .remove()
-                .expect("should be present on all spans");
-            let SpanInitAt(initialized_at) = extensions_mut
+        let trace_ctx = if self.telemetry.is_enabled() {
+            self.trace_ctx_registry.eval_ctx(iter)
+        } else {
+            None
+        };
+        // the visitor is missing if the span was opened while the telemetry was disabled
+        let visitor = span.extensions_mut().remove::<V>();
+        if let (Some(trace_ctx), Some(mut visitor)) = (trace_ctx, visitor) {
+            let span_id = self.trace_ctx_registry.span_id(&span);
+            let SpanInitAt(initialized_at) = span
+                .extensions_mut()
                 .remove()
                 .expect("should be present on all spans");
 
-            let completed_at = SystemTime::now();
+            if span.extensions().get::<Unrecorded>().is_none() {
+                self.inherit_fields(&mut visitor, span.parent());
+            }
+
+            let followed_spans = span
+                .extensions_mut()
+                .remove::<FollowedSpans<SpanId, TraceId>>();
+            for followed in followed_spans.into_iter().flat_map(|FollowedSpans(f)| f) {
+                self.telemetry.report_follows_from(trace::FollowsFrom {
+                    span_id: span_id.clone(),
+                    trace_id: trace_ctx.trace_id.clone(),
+                    follows_span_id: followed.span_id,
+                    follows_trace_id: followed.trace_id,
+                    occurred_at: followed.occurred_at,
+                    meta: span.metadata(),
+                    service_name: self.service_name,
+                });
+            }
+
+            let completed_at = self.clock.now();
 
             let parent_id = match trace_ctx.parent_span {
                 None => span
                     .parent()
-                    .map(|parent_ref| self.trace_ctx_registry.promote_span_id(parent_ref.id())),
+                    .map(|parent_ref| self.trace_ctx_registry.span_id(&parent_ref)),
                 Some(parent_span) => Some(parent_span),
             };
 
+            let is_local_root = self.trace_ctx_registry.is_local_root(&id);
+
+            let parent_initialized_at = span.parent().and_then(|parent_ref| {
+                parent_ref
+                    .extensions()
+                    .get::<SpanInitAt>()
+                    .map(|SpanInitAt(initialized_at)| *initialized_at)
+            });
+
             let span = trace::Span {
-                id: self.trace_ctx_registry.promote_span_id(id),
+                id: span_id,
+                is_local_root,
                 meta: span.metadata(),
                 parent_id,
                 initialized_at,
+                parent_initialized_at,
                 trace_id: trace_ctx.trace_id,
                 completed_at,
                 service_name: self.service_name,
@@ -297,6 +745,8 @@ where
 
             self.telemetry.report_span(span);
         };
+
+        self.trace_ctx_registry.remove_trace_ctx(&id);
     }
 
     // FIXME: do I need to do something here? I think no (better to require explicit re-marking as root after copy).
@@ -305,35 +755,49 @@ where
 
     unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
         // This `downcast_raw` impl allows downcasting this layer to any of
-        // its components (currently just trace ctx registry)
-        // as well as to the layer's type itself (technique borrowed from formatting subscriber)
+        // its components (the telemetry capability, any component it exposes and the
+        // trace ctx registry) as well as to the layer's type itself (technique borrowed from formatting subscriber)
         match () {
             _ if id == TypeId::of::<Self>() => Some(self as *const Self as *const ()),
+            _ if id == TypeId::of::<T>() => Some(&self.telemetry as *const T as *const ()),
             _ if id == TypeId::of::<TraceCtxRegistry<SpanId, TraceId>>() => Some(
                 &self.trace_ctx_registry as *const TraceCtxRegistry<SpanId, TraceId> as *const (),
             ),
-            _ => None,
+            _ => self.telemetry.downcast_raw(id),
         }
     }
 }
 
 // TODO: delete?
-struct LazyTraceCtx<SpanId, TraceId>(TraceCtx<SpanId, TraceId>);
+// the trace ctx evaluated for a span, along with its validity, shared by all spans evaluated
+// through the same span and cleared once any of them is registered as the root of a trace, see
+// `forget_evaluated_ctx`
+struct LazyTraceCtx<SpanId, TraceId>(TraceCtx<SpanId, TraceId>, Arc<AtomicBool>);
 
 struct SpanInitAt(SystemTime);
 
-impl SpanInitAt {
-    fn new() -> Self {
-        let initialized_at = SystemTime::now();
+struct PromotedSpanId<SpanId>(SpanId);
 
-        Self(initialized_at)
-    }
+// marks spans created within a trace the telemetry does not record, see `records_trace`
+struct Unrecorded;
+
+// marks spans already reported via `report_span_start`
+struct Started;
+
+// spans a span follows from, reported via `report_follows_from` when the span closes
+struct FollowedSpans<SpanId, TraceId>(Vec<Followed<SpanId, TraceId>>);
+
+struct Followed<SpanId, TraceId> {
+    span_id: SpanId,
+    trace_id: TraceId,
+    occurred_at: SystemTime,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::telemetry::test::{SpanId, TestTelemetry, TraceId};
+    use std::sync::atomic::{AtomicBool, Ordering};
     use std::sync::Arc;
     use std::sync::Mutex;
     use std::time::Duration;
@@ -349,6 +813,76 @@ mod tests {
         Id::from_u64(246)
     }
 
+    #[test]
+    fn test_redundant_root_policy() {
+        for policy in &[
+            RedundantRootPolicy::LastWins,
+            RedundantRootPolicy::FirstWins,
+            RedundantRootPolicy::ErrorEvent,
+        ] {
+            let spans = Arc::new(Mutex::new(Vec::new()));
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let transitions = Arc::new(Mutex::new(Vec::new()));
+            let cap = TestTelemetry::new(spans.clone(), events.clone(), transitions);
+            let layer =
+                TelemetryLayer::new("test_svc_name", cap, |x| x).redundant_root_policy(*policy);
+            let subscriber = layer.with_subscriber(registry::Registry::default());
+
+            tracing::subscriber::with_default(subscriber, || {
+                let _middleware = tracing::info_span!("middleware").entered();
+                trace::register_dist_tracing_root(explicit_trace_id(), None::<SpanId>).unwrap();
+
+                let _handler = tracing::info_span!("handler").entered();
+                trace::register_dist_tracing_root(explicit_trace_id() + 1, None::<SpanId>).unwrap();
+            });
+
+            let spans = spans.lock().unwrap();
+            let handler_trace_id = match policy {
+                RedundantRootPolicy::LastWins => explicit_trace_id() + 1,
+                _ => explicit_trace_id(),
+            };
+            assert_eq!(spans[0].trace_id, handler_trace_id);
+            assert_eq!(spans[1].trace_id, explicit_trace_id());
+
+            let events = events.lock().unwrap();
+            assert_eq!(
+                events.len(),
+                (*policy == RedundantRootPolicy::ErrorEvent) as usize
+            );
+        }
+    }
+
+    #[test]
+    fn test_disabled_telemetry() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let enabled = Arc::new(AtomicBool::new(false));
+        let cap = TestTelemetry::new(spans.clone(), events.clone(), transitions.clone())
+            .with_enabled(enabled.clone());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root", n = tracing::field::Empty);
+            let _guard = root.enter();
+            trace::register_dist_tracing_root(explicit_trace_id(), None::<SpanId>).unwrap();
+            tracing::info!("dropped");
+
+            // spans opened while disabled are not reported once re-enabled
+            enabled.store(true, Ordering::Relaxed);
+            root.record("n", 1);
+            tracing::info_span!("child").in_scope(|| tracing::info!("reported"));
+        });
+
+        let spans = spans.lock().unwrap();
+        assert_eq!(spans.len(), 1);
+        assert_eq!(spans[0].meta.name(), "child");
+        assert_eq!(spans[0].trace_id, explicit_trace_id());
+        assert_eq!(events.lock().unwrap().len(), 1);
+        assert!(!transitions.lock().unwrap().is_empty());
+    }
+
     #[test]
     fn test_instrument() {
         with_test_scenario_runner(|| {
@@ -431,7 +965,9 @@ mod tests {
     {
         let spans = Arc::new(Mutex::new(Vec::new()));
         let events = Arc::new(Mutex::new(Vec::new()));
-        let cap: TestTelemetry = TestTelemetry::new(spans.clone(), events.clone());
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let cap: TestTelemetry =
+            TestTelemetry::new(spans.clone(), events.clone(), transitions.clone());
         let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
 
         let subscriber = layer.with_subscriber(registry::Registry::default());
@@ -439,6 +975,7 @@ mod tests {
 
         let spans = spans.lock().unwrap();
         let events = events.lock().unwrap();
+        let transitions = transitions.lock().unwrap();
 
         // root span is exited (and reported) last
         let root_span = &spans[3];
@@ -448,13 +985,369 @@ mod tests {
 
         assert_eq!(root_span.parent_id, Some(explicit_parent_span_id()));
         assert_eq!(root_span.trace_id, expected_trace_id);
+        assert!(root_span.is_local_root);
+        assert_eq!(root_span.parent_initialized_at, None);
 
         for (span, event) in child_spans.iter().zip(events.iter()) {
             // confirm parent and trace ids are as expected
             assert_eq!(span.parent_id, Some(root_span.id.clone()));
+            assert!(!span.is_local_root);
+            assert_eq!(span.parent_initialized_at, Some(root_span.initialized_at));
             assert_eq!(event.parent_id, Some(span.id.clone()));
             assert_eq!(span.trace_id, explicit_trace_id());
             assert_eq!(event.trace_id, explicit_trace_id());
+
+            // each span is entered and exited at least once
+            for kind in &[trace::TransitionKind::Enter, trace::TransitionKind::Exit] {
+                assert!(transitions
+                    .iter()
+                    .any(|transition| transition.span_id == span.id
+                        && transition.kind == *kind
+                        && transition.trace_id == explicit_trace_id()));
+            }
         }
     }
+
+    #[test]
+    fn test_mock_clock() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), events.clone(), transitions);
+        let clock = crate::MockClock::default();
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x).clock(clock.clone());
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _root = tracing::info_span!("root").entered();
+            trace::register_dist_tracing_root(explicit_trace_id(), None::<SpanId>).unwrap();
+            clock.advance(Duration::from_millis(250));
+            tracing::info!("halfway");
+            clock.advance(Duration::from_millis(250));
+        });
+
+        let spans = spans.lock().unwrap();
+        assert_eq!(spans[0].initialized_at, std::time::UNIX_EPOCH);
+        assert_eq!(
+            spans[0].completed_at,
+            std::time::UNIX_EPOCH + Duration::from_millis(500)
+        );
+        let events = events.lock().unwrap();
+        assert_eq!(
+            events[0].initialized_at,
+            std::time::UNIX_EPOCH + Duration::from_millis(250)
+        );
+    }
+
+    #[test]
+    fn test_span_starts() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let span_starts = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), events, transitions)
+            .with_span_starts(span_starts.clone());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            // not part of a trace yet when first entered
+            root.in_scope(|| {
+                trace::register_dist_tracing_root(explicit_trace_id(), None::<SpanId>).unwrap();
+            });
+            root.in_scope(|| {
+                let child = tracing::info_span!("child");
+                child.in_scope(|| {});
+                child.in_scope(|| {});
+            });
+        });
+
+        let spans = spans.lock().unwrap();
+        let span_starts = span_starts.lock().unwrap();
+        // reported once per span, the root on its second enter
+        assert_eq!(span_starts.len(), 2);
+        let (root_start, child_start) = (&span_starts[0], &span_starts[1]);
+        assert_eq!(root_start.meta.name(), "root");
+        assert_eq!(root_start.parent_id, None);
+        assert_eq!(child_start.meta.name(), "child");
+        assert_eq!(child_start.parent_id, Some(root_start.span_id.clone()));
+
+        // the span reported on close shares the id and start of the reported start
+        let (child_span, root_span) = (&spans[0], &spans[1]);
+        assert_eq!(child_span.id, child_start.span_id);
+        assert_eq!(child_span.initialized_at, child_start.initialized_at);
+        assert_eq!(root_span.id, root_start.span_id);
+        for start in span_starts.iter() {
+            assert_eq!(start.trace_id, explicit_trace_id());
+        }
+    }
+
+    #[test]
+    fn test_follows_from() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let follows_from = Arc::new(Mutex::new(Vec::new()));
+        let consumer_trace_id: TraceId = 246;
+        let cap = TestTelemetry::new(spans.clone(), events, transitions)
+            .with_follows_from(follows_from.clone());
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let producer = tracing::info_span!("producer");
+            producer.in_scope(|| {
+                trace::register_dist_tracing_root(explicit_trace_id(), None::<SpanId>).unwrap();
+            });
+            // not part of a trace, can't be followed from
+            let untraced = tracing::info_span!("untraced");
+
+            let consumer = tracing::info_span!("consumer");
+            consumer.follows_from(&producer);
+            consumer.follows_from(&untraced);
+            // the producer closes before the consumer
+            drop(producer);
+            consumer.in_scope(|| {
+                trace::register_dist_tracing_root(consumer_trace_id, None::<SpanId>).unwrap();
+            });
+        });
+
+        let spans = spans.lock().unwrap();
+        let follows_from = follows_from.lock().unwrap();
+        assert_eq!(follows_from.len(), 1);
+        let (producer, consumer) = (&spans[0], &spans[1]);
+        assert_eq!(follows_from[0].span_id, consumer.id);
+        assert_eq!(follows_from[0].trace_id, consumer_trace_id);
+        assert_eq!(follows_from[0].follows_span_id, producer.id);
+        assert_eq!(follows_from[0].follows_trace_id, explicit_trace_id());
+        assert_eq!(follows_from[0].meta.name(), "consumer");
+    }
+
+    #[test]
+    fn test_orphan_events() {
+        for orphan_trace_id in &[None, Some(357)] {
+            let spans = Arc::new(Mutex::new(Vec::new()));
+            let events = Arc::new(Mutex::new(Vec::new()));
+            let transitions = Arc::new(Mutex::new(Vec::new()));
+            let mut cap = TestTelemetry::new(spans, events.clone(), transitions);
+            if let Some(trace_id) = orphan_trace_id {
+                cap = cap.with_orphan_trace_id(*trace_id);
+            }
+            let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+            let subscriber = layer.with_subscriber(registry::Registry::default());
+
+            tracing::subscriber::with_default(subscriber, || {
+                tracing::info!("outside of any span");
+                tracing::info_span!("untraced").in_scope(|| tracing::info!("outside of any trace"));
+                tracing::info_span!("traced").in_scope(|| {
+                    trace::register_dist_tracing_root(explicit_trace_id(), None::<SpanId>).unwrap();
+                    tracing::info!("traced");
+                });
+            });
+
+            let events = events.lock().unwrap();
+            let orphans: Vec<_> = events
+                .iter()
+                .filter(|event| event.parent_id.is_none())
+                .collect();
+            match orphan_trace_id {
+                None => assert!(orphans.is_empty()),
+                Some(trace_id) => {
+                    assert_eq!(orphans.len(), 2);
+                    assert!(orphans.iter().all(|event| event.trace_id == *trace_id));
+                }
+            }
+            let traced = events.iter().find(|event| event.parent_id.is_some());
+            assert_eq!(traced.unwrap().trace_id, explicit_trace_id());
+        }
+    }
+
+    #[test]
+    fn test_concurrent_traces() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), events, transitions);
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let dispatch = tracing::Dispatch::new(layer.with_subscriber(registry::Registry::default()));
+
+        // each job is a trace of its own, its spans entered on several threads
+        let handles: Vec<_> = (1..=8u64)
+            .map(|trace_id| {
+                let dispatch = dispatch.clone();
+                std::thread::spawn(move || {
+                    tracing::dispatcher::with_default(&dispatch, || {
+                        let job = tracing::info_span!("job", trace_id);
+                        job.in_scope(|| {
+                            trace::register_dist_tracing_root(trace_id, None::<SpanId>).unwrap()
+                        });
+                        let step = tracing::info_span!(parent: &job, "step");
+                        let step_id = step.id().unwrap();
+                        std::thread::spawn({
+                            let dispatch = dispatch.clone();
+                            let step = step.clone();
+                            move || {
+                                tracing::dispatcher::with_default(&dispatch, || {
+                                    step.in_scope(|| tracing::info_span!("query").in_scope(|| {}))
+                                })
+                            }
+                        })
+                        .join()
+                        .unwrap();
+                        let (step_trace_id, _) =
+                            trace::dist_trace_ctx_of::<SpanId, TraceId>(&step_id).unwrap();
+                        assert_eq!(step_trace_id, trace_id);
+                    })
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        let spans = spans.lock().unwrap();
+        assert_eq!(spans.len(), 8 * 3);
+        for trace_id in 1..=8u64 {
+            let trace: Vec<_> = spans
+                .iter()
+                .filter(|span| span.trace_id == trace_id)
+                .collect();
+            assert_eq!(trace.len(), 3);
+            assert_eq!(trace.iter().filter(|span| span.is_local_root).count(), 1);
+        }
+    }
+
+    #[test]
+    fn test_nested_traces_are_scoped_to_their_subtree() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans.clone(), events, transitions);
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+        let nested_trace_id: TraceId = 246;
+
+        tracing::subscriber::with_default(subscriber, || {
+            let root = tracing::info_span!("root");
+            root.in_scope(|| {
+                trace::register_dist_tracing_root(explicit_trace_id(), None::<SpanId>).unwrap()
+            });
+            let nested = tracing::info_span!(parent: &root, "nested");
+            let inner = tracing::info_span!(parent: &nested, "inner");
+            let sibling = tracing::info_span!(parent: &root, "sibling");
+            // evaluated as part of the outer trace before being registered
+            inner.in_scope(|| {});
+            nested.in_scope(|| {
+                trace::register_dist_tracing_root(nested_trace_id, None::<SpanId>).unwrap()
+            });
+
+            let trace_of = |span: &tracing::Span| {
+                let id = span.id().unwrap();
+                trace::dist_trace_ctx_of::<SpanId, TraceId>(&id).map(|(trace_id, _)| trace_id)
+            };
+            assert_eq!(trace_of(&root), Ok(explicit_trace_id()));
+            assert_eq!(trace_of(&sibling), Ok(explicit_trace_id()));
+            assert_eq!(trace_of(&nested), Ok(nested_trace_id));
+            assert_eq!(trace_of(&inner), Ok(nested_trace_id));
+
+            let untraced = tracing::info_span!(parent: None, "untraced");
+            assert_eq!(
+                trace_of(&untraced),
+                Err(trace::TraceCtxError::NoParentNodeHasTraceCtx)
+            );
+            let closed = untraced.id().unwrap();
+            drop(untraced);
+            assert_eq!(
+                trace::dist_trace_ctx_of::<SpanId, TraceId>(&closed).map(|(trace_id, _)| trace_id),
+                Err(trace::TraceCtxError::NoEnabledSpan)
+            );
+        });
+
+        let spans = spans.lock().unwrap();
+        let trace_of = |name| {
+            spans
+                .iter()
+                .find(|span| span.meta.name() == name)
+                .map(|span| span.trace_id)
+        };
+        assert_eq!(trace_of("inner"), Some(nested_trace_id));
+        assert_eq!(trace_of("nested"), Some(nested_trace_id));
+        assert_eq!(trace_of("sibling"), Some(explicit_trace_id()));
+        assert_eq!(trace_of("root"), Some(explicit_trace_id()));
+    }
+
+    #[test]
+    fn test_nested_roots_keep_the_trace_ctx_cached_in_other_traces() {
+        let spans = Arc::new(Mutex::new(Vec::new()));
+        let events = Arc::new(Mutex::new(Vec::new()));
+        let transitions = Arc::new(Mutex::new(Vec::new()));
+        let cap = TestTelemetry::new(spans, events, transitions);
+        let layer = TelemetryLayer::new("test_svc_name", cap, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let is_cached = |span: &tracing::Span| {
+                let id = span.id().unwrap();
+                tracing::dispatcher::get_default(|dispatch| {
+                    use registry::LookupSpan;
+                    let registry = dispatch.downcast_ref::<registry::Registry>().unwrap();
+                    let span_ref = registry.span(&id).unwrap();
+                    let extensions = span_ref.extensions();
+                    extensions
+                        .get::<LazyTraceCtx<SpanId, TraceId>>()
+                        .is_some_and(|LazyTraceCtx(_, valid)| valid.load(Ordering::Acquire))
+                })
+            };
+
+            let traces: Vec<(tracing::Span, tracing::Span)> = (0..2)
+                .map(|trace_id| {
+                    let root = tracing::info_span!(parent: None, "root");
+                    root.in_scope(|| {
+                        trace::register_dist_tracing_root(trace_id as TraceId, None::<SpanId>)
+                            .unwrap()
+                    });
+                    let child = tracing::info_span!(parent: &root, "child");
+                    child.in_scope(|| {});
+                    (root, child)
+                })
+                .collect();
+            assert!(traces.iter().all(|(_, child)| is_cached(child)));
+
+            let (_, registered) = &traces[0];
+            registered.in_scope(|| {
+                trace::register_dist_tracing_root(7 as TraceId, None::<SpanId>).unwrap()
+            });
+            assert!(!is_cached(&traces[0].0));
+            assert!(is_cached(&traces[1].0));
+            assert!(is_cached(&traces[1].1));
+        });
+    }
+
+    #[test]
+    fn blackhole_telemetry_counts_reports() {
+        let telemetry = crate::BlackholeTelemetry::<SpanId, TraceId>::default();
+        let counts = telemetry.counts();
+        let layer = TelemetryLayer::new("test_svc_name", telemetry, |x| x);
+        let subscriber = layer.with_subscriber(registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _root = tracing::info_span!("root").entered();
+            trace::register_dist_tracing_root(explicit_trace_id(), None::<SpanId>).unwrap();
+            tracing::debug_span!(target: "db", "query").in_scope(|| {
+                tracing::warn!(target: "db", "slow query");
+                tracing::warn!(target: "db", "retrying");
+            });
+            tracing::error!("failed");
+        });
+
+        assert_eq!(counts.spans(tracing::Level::DEBUG, "db"), 1);
+        assert_eq!(counts.events(tracing::Level::WARN, "db"), 2);
+        assert_eq!(counts.target_events("db"), 2);
+        assert_eq!(counts.total_spans(), 2);
+        assert_eq!(counts.total_events(), 3);
+
+        counts.reset();
+        assert_eq!(counts.total_events(), 0);
+    }
 }
@@ -1,8 +1,11 @@
-use crate::telemetry_layer::TraceCtxRegistry;
+use crate::telemetry_layer::{RedundantRootPolicy, TraceCtxRegistry};
 use std::time::SystemTime;
 use tracing_subscriber::registry::LookupSpan;
 
 /// Register the current span as the local root of a distributed trace.
+///
+/// If the current span already belongs to a trace with a different `TraceId`, the outcome
+/// depends on the `RedundantRootPolicy` of the `TelemetryLayer`.
 pub fn register_dist_tracing_root<SpanId, TraceId>(
     trace_id: TraceId,
     remote_parent_span: Option<SpanId>,
@@ -12,21 +15,62 @@ where
     TraceId: 'static + Clone + Send + Sync,
 {
     let span = tracing::Span::current();
-    span.with_subscriber(|(current_span_id, dispatch)| {
-        if let Some(trace_ctx_registry) =
-            dispatch.downcast_ref::<TraceCtxRegistry<SpanId, TraceId>>()
-        {
+    let conflict = span
+        .with_subscriber(|(current_span_id, dispatch)| {
+            let trace_ctx_registry = dispatch
+                .downcast_ref::<TraceCtxRegistry<SpanId, TraceId>>()
+                .ok_or(TraceCtxError::TelemetryLayerNotRegistered)?;
+            let policy = trace_ctx_registry.redundant_root_policy;
+
+            if let Some(registry) = dispatch.downcast_ref::<tracing_subscriber::Registry>() {
+                let iter = itertools::unfold(Some(current_span_id.clone()), |st| match st {
+                    Some(target_id) => {
+                        // failure here indicates a broken parent id span link, panic is valid
+                        let res = registry
+                            .span(target_id)
+                            .expect("span data not found during register_dist_tracing_root");
+                        *st = res.parent().map(|x| x.id());
+                        Some(res)
+                    }
+                    None => None,
+                });
+
+                let same_trace_id = trace_ctx_registry
+                    .same_trace_id
+                    .filter(|_| policy != RedundantRootPolicy::LastWins);
+                if let Some(same_trace_id) = same_trace_id {
+                    match trace_ctx_registry.peek_trace_id(iter) {
+                        Some(existing) if !same_trace_id(&existing, &trace_id) => {
+                            return Ok(Some(policy))
+                        }
+                        _ => {}
+                    }
+                }
+
+                // the span may have been evaluated as part of its parent's trace, e.g. when
+                // entered before being registered
+                if let Some(span_ref) = registry.span(current_span_id) {
+                    trace_ctx_registry.forget_evaluated_ctx(&span_ref);
+                }
+            }
+
             trace_ctx_registry.record_trace_ctx(
                 trace_id,
                 remote_parent_span,
                 current_span_id.clone(),
             );
-            Ok(())
-        } else {
-            Err(TraceCtxError::TelemetryLayerNotRegistered)
-        }
-    })
-    .ok_or(TraceCtxError::NoEnabledSpan)?
+            Ok(None)
+        })
+        .ok_or(TraceCtxError::NoEnabledSpan)??;
+
+    // emitted once the subscriber is no longer borrowed
+    if conflict == Some(RedundantRootPolicy::ErrorEvent) {
+        tracing::error!(
+            redundant_root = true,
+            "ignored registration of the current span as the root of another distributed trace"
+        );
+    }
+    Ok(())
 }
 
 /// Retrieve the distributed trace context associated with the current span. Returns the
@@ -39,39 +83,71 @@ where
 {
     let span = tracing::Span::current();
     span.with_subscriber(|(current_span_id, dispatch)| {
-        let trace_ctx_registry = dispatch
-            .downcast_ref::<TraceCtxRegistry<SpanId, TraceId>>()
-            .ok_or(TraceCtxError::TelemetryLayerNotRegistered)?;
-
-        let registry = dispatch
-            .downcast_ref::<tracing_subscriber::Registry>()
-            .ok_or(TraceCtxError::RegistrySubscriberNotRegistered)?;
-
-        let iter = itertools::unfold(Some(current_span_id.clone()), |st| match st {
-            Some(target_id) => {
-                // failure here indicates a broken parent id span link, panic is valid
-                let res = registry
-                    .span(target_id)
-                    .expect("span data not found during eval_ctx for current_trace_ctx");
-                *st = res.parent().map(|x| x.id());
-                Some(res)
-            }
-            None => None,
-        });
-
-        trace_ctx_registry
-            .eval_ctx(iter)
-            .map(|x| {
-                (
-                    x.trace_id,
-                    trace_ctx_registry.promote_span_id(current_span_id.clone()),
-                )
-            })
-            .ok_or(TraceCtxError::NoParentNodeHasTraceCtx)
+        let res = eval_dist_trace_ctx(current_span_id, dispatch);
+        // failure here indicates that the current span was closed while entered, panic is valid
+        assert!(
+            !matches!(res, Err(TraceCtxError::NoEnabledSpan)),
+            "span data not found during current_trace_ctx"
+        );
+        res
     })
     .ok_or(TraceCtxError::NoEnabledSpan)?
 }
 
+/// Retrieve the distributed trace context associated with the open span `id` of the default
+/// subscriber, e.g. to look up the trace of a job from the span it was scheduled with. Returns
+/// the `TraceId`, if any, that the span is associated with along with its `SpanId`.
+///
+/// A span belongs to the trace registered on its nearest ancestor (or itself) via
+/// `register_dist_tracing_root`, so any number of unrelated traces can be in flight at once,
+/// each scoped to the subtree of spans below its local root.
+pub fn dist_trace_ctx_of<SpanId, TraceId>(
+    id: &tracing::Id,
+) -> Result<(TraceId, SpanId), TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+{
+    tracing::dispatcher::get_default(|dispatch| eval_dist_trace_ctx(id, dispatch))
+}
+
+fn eval_dist_trace_ctx<SpanId, TraceId>(
+    id: &tracing::Id,
+    dispatch: &tracing::Dispatch,
+) -> Result<(TraceId, SpanId), TraceCtxError>
+where
+    SpanId: 'static + Clone + Send + Sync,
+    TraceId: 'static + Clone + Send + Sync,
+{
+    let trace_ctx_registry = dispatch
+        .downcast_ref::<TraceCtxRegistry<SpanId, TraceId>>()
+        .ok_or(TraceCtxError::TelemetryLayerNotRegistered)?;
+
+    let registry = dispatch
+        .downcast_ref::<tracing_subscriber::Registry>()
+        .ok_or(TraceCtxError::RegistrySubscriberNotRegistered)?;
+
+    let span = registry.span(id).ok_or(TraceCtxError::NoEnabledSpan)?;
+
+    let iter = itertools::unfold(Some(id.clone()), |st| match st {
+        Some(target_id) => {
+            // failure here indicates a broken parent id span link, panic is valid
+            let res = registry
+                .span(target_id)
+                .expect("span data not found during eval_ctx for dist_trace_ctx");
+            *st = res.parent().map(|x| x.id());
+            Some(res)
+        }
+        None => None,
+    });
+
+    let trace_ctx = trace_ctx_registry
+        .eval_ctx(iter)
+        .ok_or(TraceCtxError::NoParentNodeHasTraceCtx)?;
+
+    Ok((trace_ctx.trace_id, trace_ctx_registry.span_id(&span)))
+}
+
 /// Errors that can occur while registering the current span as a distributed trace root or
 /// attempting to retrieve the current trace context.
 #[derive(PartialEq, Eq, Hash, Clone, Debug)]
@@ -81,14 +157,18 @@ pub enum TraceCtxError {
     TelemetryLayerNotRegistered,
     /// Expected a `tracing_subscriber::Registry` to be registered as a subscriber associated with the current Span.
     RegistrySubscriberNotRegistered,
-    /// Expected the span returned by `tracing::Span::current()` to be enabled, with an associated subscriber.
+    /// Expected the span returned by `tracing::Span::current()` to be enabled, with an associated subscriber,
+    /// or the span passed to `dist_trace_ctx_of` to be open in the default subscriber.
     NoEnabledSpan,
     /// Attempted to evaluate the current distributed trace context but none was found. If this occurs, you should check to make sure that `register_dist_tracing_root` is called in some parent of the current span.
     NoParentNodeHasTraceCtx,
 }
 
 /// A `Span` holds ready-to-publish information gathered during the lifetime of a `tracing::Span`.
+///
+/// Spans are only constructed by `TelemetryLayer`, and may gain fields in minor releases.
 #[derive(Debug, Clone)]
+#[non_exhaustive]
 pub struct Span<Visitor, SpanId, TraceId> {
     /// id identifying this span
     pub id: SpanId,
@@ -96,8 +176,12 @@ pub struct Span<Visitor, SpanId, TraceId> {
     pub trace_id: TraceId,
     /// optional parent span id
     pub parent_id: Option<SpanId>,
+    /// whether this span was registered as the local root of a distributed trace via `register_dist_tracing_root`
+    pub is_local_root: bool,
     /// UTC time at which this span was initialized
     pub initialized_at: SystemTime,
+    /// UTC time at which this span's parent was initialized, if the parent is a local span
+    pub parent_initialized_at: Option<SystemTime>,
     /// `chrono::Duration` elapsed between the time this span was initialized and the time it was completed
     pub completed_at: SystemTime,
     /// `tracing::Metadata` for this span
@@ -108,6 +192,71 @@ pub struct Span<Visitor, SpanId, TraceId> {
     pub values: Visitor,
 }
 
+/// Whether a `tracing::Span` was entered or exited.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum TransitionKind {
+    /// The span was entered.
+    Enter,
+    /// The span was exited.
+    Exit,
+}
+
+/// A `Transition` records a `tracing::Span` being entered or exited, e.g. each time an
+/// instrumented future is polled.
+#[derive(Clone, Debug)]
+pub struct Transition<SpanId, TraceId> {
+    /// whether the span was entered or exited
+    pub kind: TransitionKind,
+    /// id of the span that was entered or exited
+    pub span_id: SpanId,
+    /// `TraceId` identifying the trace to which the span belongs
+    pub trace_id: TraceId,
+    /// UTC time at which the span was entered or exited
+    pub occurred_at: SystemTime,
+    /// `tracing::Metadata` for the span
+    pub meta: &'static tracing::Metadata<'static>,
+    /// name of the service on which the span occured
+    pub service_name: &'static str,
+}
+
+/// A `SpanStart` records a `tracing::Span` being entered for the first time, so backends can
+/// show spans that are still in progress.
+#[derive(Clone, Debug)]
+pub struct SpanStart<SpanId, TraceId> {
+    /// id of the span that started
+    pub span_id: SpanId,
+    /// optional parent span id
+    pub parent_id: Option<SpanId>,
+    /// `TraceId` identifying the trace to which the span belongs
+    pub trace_id: TraceId,
+    /// UTC time at which the span was initialized
+    pub initialized_at: SystemTime,
+    /// `tracing::Metadata` for the span
+    pub meta: &'static tracing::Metadata<'static>,
+    /// name of the service on which the span occured
+    pub service_name: &'static str,
+}
+
+/// A `FollowsFrom` records that a `tracing::Span` follows from another span, as declared via
+/// `tracing::Span::follows_from`: the other span caused it, without being its parent.
+#[derive(Clone, Debug)]
+pub struct FollowsFrom<SpanId, TraceId> {
+    /// id of the span that follows from the other span
+    pub span_id: SpanId,
+    /// `TraceId` identifying the trace to which the span belongs
+    pub trace_id: TraceId,
+    /// id of the span followed from
+    pub follows_span_id: SpanId,
+    /// `TraceId` identifying the trace to which the span followed from belongs
+    pub follows_trace_id: TraceId,
+    /// UTC time at which the relationship was declared
+    pub occurred_at: SystemTime,
+    /// `tracing::Metadata` for the span that follows from the other span
+    pub meta: &'static tracing::Metadata<'static>,
+    /// name of the service on which the span occured
+    pub service_name: &'static str,
+}
+
 /// An `Event` holds ready-to-publish information derived from a `tracing::Event`.
 #[derive(Clone, Debug)]
 pub struct Event<Visitor, SpanId, TraceId> {
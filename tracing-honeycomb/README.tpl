@@ -18,6 +18,8 @@ This crate provides two functions for out of band interaction with the `Telemetr
 - `register_dist_tracing_root` registers the current span as the local root of a distributed trace.
 - `current_dist_trace_ctx` fetches the `TraceId` and `SpanId` associated with the current span.
 
+When using trace-level sampling, `current_sampling_decision` and `register_dist_tracing_root_with_sampling` can be used to propagate the sampling decision along with the `TraceId` and `SpanId`, so that traces are either fully kept or fully dropped across services.
+
 Here's an example of how they might be used together:
 1. Some span is registered as the global tracing root using a newly-generated `TraceId`.
 2. A child of that span uses `current_dist_trace_ctx` to fetch the current `TraceId` and `SpanId`. It passes these values along with an RPC request, as metadata.
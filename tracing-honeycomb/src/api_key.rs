@@ -0,0 +1,68 @@
+use std::fmt;
+use std::sync::Arc;
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::RwLock;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::RwLock;
+
+/// Handle used to replace the honeycomb.io API key of a telemetry layer at runtime, e.g. when
+/// a secrets manager rotates it, without rebuilding the subscriber.
+///
+/// Spans and events reported after the key is replaced are sent with the new key. Those
+/// already queued for sending are sent with the key in effect when they were reported, so
+/// the previous key should remain valid for a short while after rotation.
+#[derive(Clone)]
+pub struct ApiKeyHandle(Arc<RwLock<String>>);
+
+impl fmt::Debug for ApiKeyHandle {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ApiKeyHandle")
+    }
+}
+
+impl ApiKeyHandle {
+    pub(crate) fn new(api_key: String) -> Self {
+        ApiKeyHandle(Arc::new(RwLock::new(api_key)))
+    }
+
+    /// Replace the API key used for all spans and events reported from now on.
+    pub fn set(&self, api_key: impl Into<String>) {
+        let api_key = api_key.into();
+        // succeed or die. failure is unrecoverable (lock poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let mut current = self.0.write().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let mut current = self.0.write();
+
+        *current = api_key;
+    }
+
+    pub(crate) fn read(&self) -> impl std::ops::Deref<Target = String> + '_ {
+        // succeed or die. failure is unrecoverable (lock poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let current = self.0.read().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let current = self.0.read();
+
+        current
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::HoneycombTelemetry;
+
+    #[test]
+    fn handles_share_the_current_key() {
+        let builder = HoneycombTelemetry::builder();
+        let handle = builder.api_key_handle();
+        let builder = builder.api_key("first");
+        assert_eq!(*handle.read(), "first");
+
+        handle.set("second");
+        assert_eq!(*builder.api_key_handle().read(), "second");
+        // the configured key is kept for reporting purposes only
+        assert_eq!(builder.honeycomb_config.options.api_key, "first");
+    }
+}
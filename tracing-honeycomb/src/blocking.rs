@@ -0,0 +1,603 @@
+use chrono::{DateTime, Utc};
+use libhoney::{json, Value};
+use std::collections::HashMap;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::budget::MemoryBudget;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::dead_letter::DeadLetterSpool;
+use crate::diagnostics::{Diagnostic, InternalLogMode};
+#[cfg(feature = "test-support")]
+use crate::faults::{Fault, FaultInjector};
+use crate::pool::BufferPool;
+use crate::stats::{Stats, TelemetryStats};
+use crate::telemetry_error::{ErrorHandler, TelemetryError};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+const BATCH_ENDPOINT: &str = "/1/batch/";
+
+/// Sends events to honeycomb.io synchronously, on the thread reporting them, see
+/// `Builder::send_now`.
+///
+/// Events are buffered until `max_batch_size` of them are pending, the local root span of a
+/// trace closes, or the transmission is dropped, then sent using one HTTP call per dataset.
+/// Their fields are serialized straight into the body of the request, without building an
+/// intermediate JSON document for the batch.
+#[derive(Debug)]
+pub(crate) struct BlockingTransmission {
+    client: reqwest::blocking::Client,
+    #[cfg(feature = "test-support")]
+    deadline: Duration,
+    max_batch_size: usize,
+    pending: Mutex<Vec<PendingEvent>>,
+    on_error: ErrorHandler,
+    internal_log_mode: InternalLogMode,
+    stats: Stats,
+    spool: Option<Arc<DeadLetterSpool>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    // set while honeycomb.io is unreachable or overloaded
+    failing: AtomicBool,
+    #[cfg(feature = "test-support")]
+    faults: Option<FaultInjector>,
+    buffers: Option<Arc<BufferPool>>,
+    // bytes of the pending events, including those sent by `send_pending`
+    budget: MemoryBudget,
+}
+
+#[derive(Debug)]
+pub(crate) struct PendingEvent {
+    options: libhoney::client::Options,
+    time: DateTime<Utc>,
+    data: HashMap<String, Value>,
+    // as charged to the memory budget
+    bytes: usize,
+}
+
+impl PendingEvent {
+    /// An event reported now, to be sent with `options`, of `bytes` charged to the memory
+    /// budget of the transmission.
+    pub(crate) fn new(
+        options: libhoney::client::Options,
+        data: HashMap<String, Value>,
+        bytes: usize,
+    ) -> Self {
+        PendingEvent {
+            options,
+            time: Utc::now(),
+            data,
+            bytes,
+        }
+    }
+}
+
+impl BlockingTransmission {
+    pub(crate) fn new(
+        deadline: Duration,
+        max_batch_size: usize,
+        on_error: ErrorHandler,
+        internal_log_mode: InternalLogMode,
+    ) -> Self {
+        let client = reqwest::blocking::Client::builder()
+            .timeout(deadline)
+            .build()
+            .expect("failed to initialize honeycomb http client");
+
+        BlockingTransmission {
+            client,
+            #[cfg(feature = "test-support")]
+            deadline,
+            max_batch_size: max_batch_size.max(1),
+            pending: Mutex::new(Vec::new()),
+            on_error,
+            internal_log_mode,
+            stats: Stats::default(),
+            spool: None,
+            circuit_breaker: None,
+            failing: AtomicBool::new(false),
+            #[cfg(feature = "test-support")]
+            faults: None,
+            buffers: None,
+            budget: MemoryBudget::default(),
+        }
+    }
+
+    /// Inject the faults scripted by `faults` into the batches sent, see `FaultInjector`.
+    #[cfg(feature = "test-support")]
+    pub(crate) fn with_faults(mut self, faults: FaultInjector) -> Self {
+        self.faults = Some(faults);
+        self
+    }
+
+    /// Feed the outcome of each batch to `circuit_breaker`.
+    pub(crate) fn with_circuit_breaker(mut self, circuit_breaker: Arc<CircuitBreaker>) -> Self {
+        self.circuit_breaker = Some(circuit_breaker);
+        self
+    }
+
+    /// Recycle the fields of events into `buffers` once they are serialized.
+    pub(crate) fn with_buffers(mut self, buffers: Arc<BufferPool>) -> Self {
+        self.buffers = Some(buffers);
+        self
+    }
+
+    /// Bound the bytes of pending events to `bytes`, see `Builder::memory_budget`.
+    pub(crate) fn with_memory_budget(self, bytes: usize) -> Self {
+        self.budget.set_limit(bytes);
+        self
+    }
+
+    /// Spill batches that fail to send because honeycomb.io is unreachable to `spool`, and
+    /// send spooled events again once a batch is sent successfully.
+    pub(crate) fn with_spool(mut self, spool: Arc<DeadLetterSpool>) -> Self {
+        self.spool = Some(spool);
+        self
+    }
+
+    fn pending(&self) -> impl std::ops::DerefMut<Target = Vec<PendingEvent>> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let pending = self.pending.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let pending = self.pending.lock();
+
+        pending
+    }
+
+    /// Buffer an event, sending the pending batch if it is full. Sampling is assumed to have
+    /// already happened.
+    ///
+    /// If the pending events exceed the memory budget, the oldest of them are shed, including
+    /// this one if it is larger than the budget.
+    pub(crate) fn send(&self, options: &libhoney::client::Options, data: HashMap<String, Value>) {
+        let bytes = self.budget.size_of(&data);
+        let full = {
+            let mut pending = self.pending();
+            self.budget.charge(bytes);
+            pending.push(PendingEvent::new(options.clone(), data, bytes));
+            while self.budget.exceeded() && !pending.is_empty() {
+                let shed = pending.remove(0);
+                self.budget.release(shed.bytes);
+                self.stats.record_shed(1);
+                self.recycle(shed.data);
+            }
+            pending.len() >= self.max_batch_size
+        };
+
+        if full {
+            self.flush();
+        }
+    }
+
+    /// Send all pending events, blocking until honeycomb.io responds or the deadline passes.
+    pub(crate) fn flush(&self) {
+        let pending = std::mem::take(&mut *self.pending());
+        self.send_pending(pending);
+    }
+
+    /// Send `pending` events, bypassing the buffer, along with the events spooled while
+    /// honeycomb.io was unavailable.
+    pub(crate) fn send_pending(&self, mut pending: Vec<PendingEvent>) {
+        self.budget
+            .release(pending.iter().map(|event| event.bytes).sum());
+        while !pending.is_empty() {
+            pending = self.send_batches(pending);
+        }
+    }
+
+    // sends pending events, returning spooled events to send next if all batches were sent
+    fn send_batches(&self, pending: Vec<PendingEvent>) -> Vec<PendingEvent> {
+        let mut batches: HashMap<(String, String, String), Batch> = HashMap::new();
+        for event in pending {
+            let libhoney::client::Options {
+                api_key,
+                api_host,
+                dataset,
+                sample_rate,
+            } = event.options;
+            let time = event.time.to_rfc3339();
+            let batch = batches
+                .entry((api_host.clone(), dataset.clone(), api_key))
+                .or_default();
+            batch.push(&event.data, &time, sample_rate);
+            if self.spool.is_some() {
+                batch.records.push(json!({
+                    "data": event.data,
+                    "time": time,
+                    "samplerate": sample_rate,
+                    "api_host": api_host,
+                    "dataset": dataset,
+                }));
+            } else {
+                self.recycle(event.data);
+            }
+        }
+
+        let mut all_sent = true;
+        for ((api_host, dataset, api_key), mut batch) in batches {
+            let events = batch.events;
+            let records = std::mem::take(&mut batch.records);
+            let sent_at = Instant::now();
+            let url = format!(
+                "{}{}{}",
+                api_host.trim_end_matches('/'),
+                BATCH_ENDPOINT,
+                dataset
+            );
+            let res = self.post(&url, api_key, batch.finish());
+            self.stats.record_latency(sent_at.elapsed());
+            if let Err((status, message)) = res {
+                all_sent = false;
+                self.stats.record_send_errors(events as u64);
+                let is_server_error = |status: u16| (500..600).contains(&status);
+                // honeycomb.io was unreachable or overloaded, as opposed to rejecting the batch
+                let retryable =
+                    status.is_none_or(|status| is_server_error(status) || status == 429);
+                if retryable {
+                    self.failing.store(true, Ordering::Relaxed);
+                }
+                let unavailable = status.is_none_or(is_server_error);
+                if let (true, Some(circuit_breaker)) = (unavailable, &self.circuit_breaker) {
+                    circuit_breaker.record_failure();
+                }
+                if let (true, Some(spool)) = (retryable, &self.spool) {
+                    spool.spill(&records);
+                }
+                self.on_error.report(TelemetryError::Send {
+                    dataset,
+                    events,
+                    status,
+                    message,
+                });
+            } else {
+                self.stats.record_sent(events as u64);
+                if let Some(circuit_breaker) = &self.circuit_breaker {
+                    circuit_breaker.record_success();
+                }
+                if self.failing.swap(false, Ordering::Relaxed) {
+                    self.internal_log_mode.emit(&Diagnostic::Recovered);
+                }
+            }
+        }
+
+        let spooled = match (&self.spool, all_sent) {
+            (Some(spool), true) => spool.take(),
+            _ => return Vec::new(),
+        };
+        if !spooled.is_empty() {
+            self.internal_log_mode.emit(&Diagnostic::Replaying {
+                events: spooled.len(),
+            });
+        }
+        spooled
+            .into_iter()
+            .map(|event| PendingEvent {
+                options: event.options,
+                time: event.time,
+                data: event.data,
+                bytes: 0,
+            })
+            .collect()
+    }
+
+    // sends a batch, failing with the status honeycomb.io responded with, or `None` if it was
+    // unreachable, and a description of the failure
+    fn post(&self, url: &str, api_key: String, body: Vec<u8>) -> Result<(), (Option<u16>, String)> {
+        #[cfg(feature = "test-support")]
+        match self.faults.as_ref().and_then(FaultInjector::next) {
+            Some(Fault::Unreachable) => {
+                return Err((None, "injected fault: honeycomb.io unreachable".to_string()));
+            }
+            Some(Fault::Status(status)) if (200..300).contains(&status) => return Ok(()),
+            Some(Fault::Status(status)) => {
+                return Err((
+                    Some(status),
+                    format!("injected fault: HTTP status {}", status),
+                ));
+            }
+            Some(Fault::Delay(delay)) if delay >= self.deadline => {
+                std::thread::sleep(self.deadline);
+                return Err((None, "injected fault: operation timed out".to_string()));
+            }
+            Some(Fault::Delay(delay)) => std::thread::sleep(delay),
+            None => {}
+        }
+
+        self.client
+            .post(url)
+            .header("X-Honeycomb-Team", api_key)
+            .header("Content-Type", "application/json")
+            .body(body)
+            .send()
+            .and_then(|response| response.error_for_status())
+            .map(|_| ())
+            .map_err(|err| (err.status().map(|status| status.as_u16()), err.to_string()))
+    }
+
+    /// Get the counters of this transmission, with the number of pending events as queue depth.
+    pub(crate) fn stats(&self) -> TelemetryStats {
+        let pending = self.pending().len();
+        let mut stats = self.stats.snapshot(pending);
+        stats.buffered_bytes = self.budget.used();
+        stats
+    }
+
+    fn recycle(&self, data: HashMap<String, Value>) {
+        if let Some(buffers) = &self.buffers {
+            buffers.recycle(data);
+        }
+    }
+
+    #[cfg(feature = "tokio-transmission")]
+    pub(crate) fn record_dropped(&self, events: u64) {
+        self.stats.record_dropped(events);
+    }
+
+    /// Charge an event with the given fields, about to be sent with `send_pending`, to the
+    /// memory budget, returning its size, or `None` if it doesn't fit, in which case it is
+    /// counted as shed.
+    #[cfg(feature = "tokio-transmission")]
+    pub(crate) fn charge(&self, data: &HashMap<String, Value>) -> Option<usize> {
+        let bytes = self.budget.size_of(data);
+        if self.budget.exceeded_by(bytes) {
+            self.stats.record_shed(1);
+            return None;
+        }
+        self.budget.charge(bytes);
+        Some(bytes)
+    }
+
+    /// Release the bytes charged for an event that won't be sent, see `charge`.
+    #[cfg(feature = "tokio-transmission")]
+    pub(crate) fn release(&self, bytes: usize) {
+        self.budget.release(bytes);
+    }
+
+    pub(crate) fn record_rate_limited(&self, events: u64) {
+        self.stats.record_rate_limited(events);
+    }
+
+    pub(crate) fn record_circuit_open(&self, events: u64) {
+        self.stats.record_circuit_open(events);
+    }
+}
+
+/// Events sent to the same dataset in one request, see honeycomb.io's batch API.
+#[derive(Debug, Default)]
+struct Batch {
+    events: usize,
+    body: Vec<u8>,
+    // the events as spooled if the batch fails to send
+    records: Vec<Value>,
+}
+
+impl Batch {
+    fn push(&mut self, data: &HashMap<String, Value>, time: &str, sample_rate: usize) {
+        self.body
+            .extend_from_slice(if self.events == 0 { b"[" } else { b"," });
+        self.body.extend_from_slice(br#"{"data":"#);
+        serde_json::to_writer(&mut self.body, data).expect("json values always serialize");
+        // writing to a vec can't fail
+        let _ = write!(
+            self.body,
+            r#","time":"{}","samplerate":{}}}"#,
+            time, sample_rate
+        );
+        self.events += 1;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        self.body.push(b']');
+        self.body
+    }
+}
+
+impl Drop for BlockingTransmission {
+    fn drop(&mut self) {
+        self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn sends_pending_events_in_one_call_per_dataset() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = libhoney::client::Options {
+            api_host: format!("http://{}/", listener.local_addr().unwrap()),
+            dataset: "cli".to_string(),
+            ..Default::default()
+        };
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // the body is the last thing sent, a json array
+            while !request.ends_with(b"]") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n[]")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let transmission = BlockingTransmission::new(
+            Duration::from_secs(5),
+            10,
+            ErrorHandler::default(),
+            InternalLogMode::Silent,
+        );
+        for i in 0..2 {
+            let mut data = HashMap::new();
+            data.insert("i".to_string(), json!(i));
+            transmission.send(&options, data);
+        }
+        transmission.flush();
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /1/batch/cli HTTP/1.1"));
+        assert!(request.contains(r#"{"i":0}"#) && request.contains(r#"{"i":1}"#));
+        assert!(transmission.pending().is_empty());
+        assert_eq!(transmission.stats().events_sent, 2);
+    }
+
+    #[test]
+    fn sheds_the_oldest_pending_events_over_the_memory_budget() {
+        let event = |i: u64| {
+            let mut data = HashMap::new();
+            data.insert("i".to_string(), json!(i));
+            data
+        };
+        // sizes are only estimated once the budget is limited
+        let budget = MemoryBudget::default();
+        budget.set_limit(0);
+        let bytes = budget.size_of(&event(0));
+        let transmission = BlockingTransmission::new(
+            Duration::from_secs(5),
+            10,
+            ErrorHandler::default(),
+            InternalLogMode::Silent,
+        )
+        .with_memory_budget(2 * bytes);
+        for i in 0..3 {
+            transmission.send(&Default::default(), event(i));
+        }
+
+        let pending: Vec<_> = transmission
+            .pending()
+            .iter()
+            .map(|event| event.data["i"].clone())
+            .collect();
+        assert_eq!(pending, vec![json!(1), json!(2)]);
+        let stats = transmission.stats();
+        assert_eq!(stats.buffered_bytes, 2 * bytes);
+        assert_eq!(stats.dropped_by_memory_budget, 1);
+        assert_eq!(stats.events_dropped, 1);
+    }
+
+    #[test]
+    fn batches_serialize_to_the_batch_api_format() {
+        let mut batch = Batch::default();
+        let mut data = HashMap::new();
+        data.insert("name".to_string(), json!("quoted \"name\""));
+        data.insert("ids".to_string(), json!([1, 2]));
+        batch.push(&data, "2020-01-01T00:00:00+00:00", 1);
+        batch.push(&HashMap::new(), "2020-01-01T00:00:01+00:00", 10);
+
+        let body: Value = serde_json::from_slice(&batch.finish()).unwrap();
+        assert_eq!(
+            body,
+            json!([
+                {
+                    "data": { "name": "quoted \"name\"", "ids": [1, 2] },
+                    "time": "2020-01-01T00:00:00+00:00",
+                    "samplerate": 1,
+                },
+                { "data": {}, "time": "2020-01-01T00:00:01+00:00", "samplerate": 10 },
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_failed_batches_to_error_handler() {
+        // nothing listens on the port once the listener is dropped
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = libhoney::client::Options {
+            api_host: format!("http://{}", listener.local_addr().unwrap()),
+            dataset: "cli".to_string(),
+            ..Default::default()
+        };
+        drop(listener);
+
+        let errors = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = errors.clone();
+        let on_error = ErrorHandler::new(move |err| recorded.lock().unwrap().push(err));
+        let transmission = BlockingTransmission::new(
+            Duration::from_secs(5),
+            10,
+            on_error,
+            InternalLogMode::Silent,
+        );
+        transmission.send(&options, HashMap::new());
+        transmission.flush();
+
+        let errors = errors.lock().unwrap();
+        match errors.as_slice() {
+            [TelemetryError::Send {
+                dataset, events, ..
+            }] => assert_eq!((dataset.as_str(), *events), ("cli", 1)),
+            errors => panic!("unexpected errors {:?}", errors),
+        }
+        assert_eq!(transmission.stats().send_errors, 1);
+    }
+
+    #[test]
+    fn spools_batches_while_unavailable_and_replays_them() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = libhoney::client::Options {
+            api_host: format!("http://{}", listener.local_addr().unwrap()),
+            dataset: "cli".to_string(),
+            ..Default::default()
+        };
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in &["503 Service Unavailable", "200 OK", "200 OK"] {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                while !request.ends_with(b"]") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let response = format!(
+                    "HTTP/1.1 {}\r\nconnection: close\r\ncontent-length: 2\r\n\r\n[]",
+                    status
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+
+        let dir = std::env::temp_dir().join(format!("dead-letter-{}", uuid::Uuid::new_v4()));
+        let spool = DeadLetterSpool::new(
+            dir.clone(),
+            1024,
+            crate::ApiKeyHandle::new("key".to_string()),
+            ErrorHandler::default(),
+        );
+        let transmission = BlockingTransmission::new(
+            Duration::from_secs(5),
+            10,
+            ErrorHandler::default(),
+            InternalLogMode::Silent,
+        )
+        .with_spool(Arc::new(spool));
+        for i in 0..2 {
+            let mut data = HashMap::new();
+            data.insert("i".to_string(), json!(i));
+            transmission.send(&options, data);
+            transmission.flush();
+        }
+
+        // the spooled event is sent right after the first successful batch
+        let requests = server.join().unwrap();
+        assert!(requests[1].contains(r#"{"i":1}"#));
+        assert!(requests[2].contains(r#"{"i":0}"#));
+        assert!(requests[2].contains("x-honeycomb-team: key"));
+        assert_eq!(transmission.stats().events_sent, 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
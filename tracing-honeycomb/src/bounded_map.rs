@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+/// Map holding state attached to in-flight traces or open spans, e.g. their experiment
+/// assignments, shared by all threads reporting spans and events.
+///
+/// Holds at most `max_len` entries: insertions of new keys are dropped once it is full, so that
+/// state that is never removed cannot grow unbounded. Lookups and removals skip the lock while
+/// the map is empty, which is the common case on the hot path of reporting spans and events.
+#[derive(Debug)]
+pub(crate) struct BoundedMap<K, V> {
+    entries: Mutex<HashMap<K, V>>,
+    // number of entries, updated while holding the lock
+    len: AtomicUsize,
+    max_len: usize,
+}
+
+impl<K: Clone + Eq + Hash, V> BoundedMap<K, V> {
+    pub(crate) fn new(max_len: usize) -> Self {
+        BoundedMap {
+            entries: Mutex::new(HashMap::new()),
+            len: AtomicUsize::new(0),
+            max_len,
+        }
+    }
+
+    fn lock(&self) -> impl std::ops::DerefMut<Target = HashMap<K, V>> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let entries = self.entries.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let entries = self.entries.lock();
+
+        entries
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.len.load(Ordering::Acquire) == 0
+    }
+
+    /// Set the value of `key`, replacing any previous value.
+    pub(crate) fn insert(&self, key: &K, value: V) {
+        let mut entries = self.lock();
+        if entries.len() < self.max_len || entries.contains_key(key) {
+            entries.insert(key.clone(), value);
+            self.len.store(entries.len(), Ordering::Release);
+        }
+    }
+
+    /// Apply `f` to the value of `key`, starting from the default value if there is none.
+    pub(crate) fn update(&self, key: &K, f: impl FnOnce(&mut V))
+    where
+        V: Default,
+    {
+        let mut entries = self.lock();
+        if entries.len() < self.max_len || entries.contains_key(key) {
+            f(entries.entry(key.clone()).or_default());
+            self.len.store(entries.len(), Ordering::Release);
+        }
+    }
+
+    /// Apply `f` to the value of `key`, if any.
+    pub(crate) fn get<R>(&self, key: &K, f: impl FnOnce(&V) -> R) -> Option<R> {
+        if self.is_empty() {
+            return None;
+        }
+        self.lock().get(key).map(f)
+    }
+
+    pub(crate) fn remove(&self, key: &K) -> Option<V> {
+        if self.is_empty() {
+            return None;
+        }
+        let mut entries = self.lock();
+        let value = entries.remove(key);
+        self.len.store(entries.len(), Ordering::Release);
+        value
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn new_keys_are_dropped_once_full() {
+        let map = BoundedMap::new(2);
+        assert!(map.is_empty());
+        map.insert(&"a", 1);
+        map.update(&"b", |value: &mut i32| *value += 2);
+        map.insert(&"c", 3);
+        map.update(&"a", |value| *value += 10);
+
+        assert_eq!(map.get(&"a", |value| *value), Some(11));
+        assert_eq!(map.get(&"b", |value| *value), Some(2));
+        assert_eq!(map.get(&"c", |value| *value), None);
+
+        assert_eq!(map.remove(&"a"), Some(11));
+        assert_eq!(map.remove(&"b"), Some(2));
+        assert_eq!(map.remove(&"b"), None);
+        assert!(map.is_empty());
+    }
+}
@@ -0,0 +1,123 @@
+use libhoney::Value;
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Bytes of telemetry buffered by a transmission, bounded by `Builder::memory_budget`.
+///
+/// Sizes are estimates of the memory held by the fields of buffered events, not counting
+/// allocator overhead. The budget is unlimited by default, in which case sizes are not
+/// estimated at all.
+#[derive(Debug)]
+pub(crate) struct MemoryBudget {
+    limit: AtomicUsize,
+    used: AtomicUsize,
+}
+
+impl Default for MemoryBudget {
+    fn default() -> Self {
+        MemoryBudget {
+            limit: AtomicUsize::new(usize::MAX),
+            used: AtomicUsize::new(0),
+        }
+    }
+}
+
+impl MemoryBudget {
+    pub(crate) fn set_limit(&self, bytes: usize) {
+        self.limit.store(bytes, Ordering::Relaxed);
+    }
+
+    fn is_limited(&self) -> bool {
+        self.limit.load(Ordering::Relaxed) != usize::MAX
+    }
+
+    /// Estimated size of an event with the given fields, or 0 if the budget is unlimited.
+    pub(crate) fn size_of(&self, fields: &HashMap<String, Value>) -> usize {
+        if !self.is_limited() {
+            return 0;
+        }
+        fields
+            .iter()
+            .map(|(name, value)| field_size(name, value))
+            .sum()
+    }
+
+    /// Account for `bytes` more buffered bytes.
+    pub(crate) fn charge(&self, bytes: usize) {
+        self.used.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    /// Account for `bytes` buffered bytes that are no longer buffered.
+    pub(crate) fn release(&self, bytes: usize) {
+        // saturating, as the limit may have been set while events were buffered
+        let _ = self
+            .used
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |used| {
+                Some(used.saturating_sub(bytes))
+            });
+    }
+
+    /// Whether more bytes are buffered than the budget allows.
+    pub(crate) fn exceeded(&self) -> bool {
+        self.used() > self.limit.load(Ordering::Relaxed)
+    }
+
+    /// Whether buffering `bytes` more bytes would exceed the budget.
+    pub(crate) fn exceeded_by(&self, bytes: usize) -> bool {
+        self.used().saturating_add(bytes) > self.limit.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn used(&self) -> usize {
+        self.used.load(Ordering::Relaxed)
+    }
+}
+
+fn field_size(name: &str, value: &Value) -> usize {
+    size_of::<(String, Value)>() + name.len() + value_size(value)
+}
+
+// heap memory held by `value`
+fn value_size(value: &Value) -> usize {
+    match value {
+        Value::String(s) => s.len(),
+        Value::Array(values) => values
+            .iter()
+            .map(|value| size_of::<Value>() + value_size(value))
+            .sum(),
+        Value::Object(fields) => fields
+            .iter()
+            .map(|(name, value)| field_size(name, value))
+            .sum(),
+        Value::Null | Value::Bool(_) | Value::Number(_) => 0,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libhoney::json;
+
+    #[test]
+    fn sizes_are_estimated_once_limited() {
+        let mut fields = HashMap::new();
+        fields.insert("name".to_string(), json!("request"));
+        fields.insert("tags".to_string(), json!(["a", "bc"]));
+
+        let budget = MemoryBudget::default();
+        assert_eq!(budget.size_of(&fields), 0);
+
+        budget.set_limit(1024);
+        let entry = size_of::<(String, Value)>();
+        let expected = (entry + 4 + 7) + (entry + 4 + 2 * size_of::<Value>() + 3);
+        assert_eq!(budget.size_of(&fields), expected);
+
+        budget.charge(1000);
+        assert!(!budget.exceeded());
+        assert!(budget.exceeded_by(25));
+        budget.charge(25);
+        assert!(budget.exceeded());
+        budget.release(2000);
+        assert_eq!(budget.used(), 0);
+    }
+}
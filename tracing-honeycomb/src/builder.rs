@@ -0,0 +1,973 @@
+use eaze_tracing_distributed as tracing_distributed;
+
+use crate::api_key::ApiKeyHandle;
+use crate::connection::ConnectionStatus;
+use crate::diagnostics::InternalLogMode;
+use crate::export_filter::ExportFilter;
+use crate::honeycomb::HoneycombTelemetry;
+use crate::markers::MarkersClient;
+use crate::metrics::MetricsHandle;
+use crate::pool;
+use crate::reload::ReloadHandle;
+use crate::routing::DatasetRouter;
+use crate::sampling::SampleRateHandle;
+use crate::span_id::{SpanIdFormat, SpanIdGenerator};
+use crate::telemetry_error::ErrorHandler;
+#[cfg(feature = "test-support")]
+use crate::test_support::{FaultInjector, TelemetryRecorder};
+use crate::trace_id::BoxedTraceIdGenerator;
+use crate::transmission::SharedTransmission;
+use crate::validation::{normalize_api_host, ConfigError, ConfigReport};
+use crate::visitor::{
+    FieldAction, FieldNaming, FieldOptions, HoneycombValues, HoneycombVisitor, TraceFieldNames,
+};
+use crate::{FieldUnit, SpanId, TelemetryError, TraceId, TraceIdFormat, TraceIdGenerator};
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::Duration;
+use tracing_distributed::{Clock, RedundantRootPolicy, TelemetryLayer};
+
+/// Builder for a `TelemetryLayer` that publishes telemetry to honeycomb.io.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+#[derive(Debug)]
+pub struct Builder {
+    pub(crate) service_name: &'static str,
+    pub(crate) honeycomb_config: libhoney::Config,
+    pub(crate) api_key: ApiKeyHandle,
+    pub(crate) transmission: Option<SharedTransmission>,
+    pub(crate) transmission_shards: usize,
+    pub(crate) buffer_pool: usize,
+    pub(crate) memory_budget: Option<usize>,
+    pub(crate) dataset_shards: Option<u32>,
+    pub(crate) dataset_router: DatasetRouter,
+    pub(crate) send_now: Option<Duration>,
+    pub(crate) on_error: Option<ErrorHandler>,
+    pub(crate) internal_log_mode: InternalLogMode,
+    pub(crate) dead_letter: Option<(PathBuf, u64)>,
+    pub(crate) circuit_breaker: Option<(u32, Duration)>,
+    #[cfg(feature = "otlp")]
+    pub(crate) otlp_endpoint: Option<String>,
+    #[cfg(feature = "zipkin")]
+    pub(crate) zipkin_endpoint: Option<String>,
+    #[cfg(feature = "test-support")]
+    pub(crate) recorder: Option<TelemetryRecorder>,
+    #[cfg(feature = "test-support")]
+    pub(crate) faults: Option<FaultInjector>,
+    #[cfg(feature = "tokio-transmission")]
+    pub(crate) tokio_runtime: Option<tokio1::runtime::Handle>,
+    pub(crate) enabled: bool,
+    pub(crate) static_fields: HashMap<String, libhoney::Value>,
+    pub(crate) sample_rate: SampleRateHandle,
+    pub(crate) reload: ReloadHandle,
+    pub(crate) rate_limit: Option<u32>,
+    pub(crate) rollup_interval: Option<Duration>,
+    pub(crate) data_loss_interval: Option<Duration>,
+    pub(crate) metrics: MetricsHandle,
+    pub(crate) metrics_report: Option<(Duration, String)>,
+    pub(crate) keep_errored_traces: bool,
+    pub(crate) span_id_format: SpanIdFormat,
+    pub(crate) instance_id: u64,
+    pub(crate) trace_id_generator: BoxedTraceIdGenerator,
+    pub(crate) redundant_root_policy: RedundantRootPolicy,
+    pub(crate) clock: Option<Arc<dyn Clock>>,
+    pub(crate) field_options: FieldOptions,
+    pub(crate) export_filter: ExportFilter,
+    pub(crate) clamp_to_parent: bool,
+    pub(crate) span_transition_events: bool,
+    pub(crate) span_start_events: bool,
+    pub(crate) orphan_events: bool,
+    pub(crate) max_trace_duration: Option<Duration>,
+    pub(crate) inherited_fields: HashSet<String>,
+    // environment variables read by `Builder::from_env`, reported by `validate`
+    pub(crate) env_vars: Vec<&'static str>,
+}
+
+impl Builder {
+    /// Create a new builder that will publish telemetry to honeycomb.io using the provided
+    /// service name and honeycomb config.
+    pub fn new(service_name: &'static str, honeycomb_config: libhoney::Config) -> Self {
+        let sample_rate = SampleRateHandle::new(1);
+        Builder {
+            service_name,
+            api_key: ApiKeyHandle::new(honeycomb_config.options.api_key.clone()),
+            reload: ReloadHandle::new(
+                honeycomb_config.options.dataset.clone(),
+                sample_rate.clone(),
+            ),
+            honeycomb_config,
+            transmission: None,
+            transmission_shards: 1,
+            buffer_pool: pool::DEFAULT_POOL_SIZE,
+            memory_budget: None,
+            dataset_shards: None,
+            dataset_router: DatasetRouter::default(),
+            send_now: None,
+            on_error: None,
+            internal_log_mode: InternalLogMode::default(),
+            dead_letter: None,
+            circuit_breaker: None,
+            #[cfg(feature = "otlp")]
+            otlp_endpoint: None,
+            #[cfg(feature = "zipkin")]
+            zipkin_endpoint: None,
+            #[cfg(feature = "test-support")]
+            recorder: None,
+            #[cfg(feature = "test-support")]
+            faults: None,
+            #[cfg(feature = "tokio-transmission")]
+            tokio_runtime: None,
+            enabled: true,
+            static_fields: HashMap::new(),
+            sample_rate,
+            rate_limit: None,
+            rollup_interval: None,
+            data_loss_interval: None,
+            metrics: MetricsHandle::default(),
+            metrics_report: None,
+            keep_errored_traces: false,
+            span_id_format: SpanIdFormat::default(),
+            instance_id: rand::random(),
+            trace_id_generator: BoxedTraceIdGenerator::default(),
+            redundant_root_policy: RedundantRootPolicy::default(),
+            clock: None,
+            field_options: FieldOptions::default(),
+            export_filter: ExportFilter::default(),
+            clamp_to_parent: false,
+            span_transition_events: false,
+            span_start_events: false,
+            orphan_events: false,
+            max_trace_duration: None,
+            inherited_fields: HashSet::new(),
+            env_vars: Vec::new(),
+        }
+    }
+
+    /// Set the name of the service on which spans and events occur.
+    pub fn service_name(mut self, service_name: &'static str) -> Self {
+        self.service_name = service_name;
+        self
+    }
+
+    /// Set the honeycomb.io API key used to publish telemetry.
+    pub fn api_key(mut self, api_key: impl Into<String>) -> Self {
+        self.honeycomb_config.options.api_key = api_key.into();
+        self.api_key
+            .set(self.honeycomb_config.options.api_key.clone());
+        self
+    }
+
+    /// Get a handle that can be used to replace the API key of the layer constructed by this
+    /// builder at runtime, e.g. when it is rotated by a secrets manager.
+    pub fn api_key_handle(&self) -> ApiKeyHandle {
+        self.api_key.clone()
+    }
+
+    /// Set the honeycomb.io dataset to which telemetry is published.
+    pub fn dataset(mut self, dataset: impl Into<String>) -> Self {
+        self.honeycomb_config.options.dataset = dataset.into();
+        self.reload
+            .set_dataset(self.honeycomb_config.options.dataset.clone());
+        self
+    }
+
+    /// Set the URL of the honeycomb.io API, e.g. `https://api.eu1.honeycomb.io` for the EU
+    /// region or the URL of an egress proxy. Defaults to `https://api.honeycomb.io`.
+    ///
+    /// The URL must use the `http` or `https` scheme and may include a path, e.g. for proxies.
+    /// It is checked by `validate`, and `try_build` fails if it is invalid.
+    pub fn api_host(mut self, api_host: impl Into<String>) -> Self {
+        self.honeycomb_config.options.api_host = api_host.into();
+        self
+    }
+
+    /// Set the options tuning libhoney's background transmission, e.g. batch sizes, timeouts
+    /// and the number of concurrent batches.
+    pub fn transmission_options(mut self, options: libhoney::transmission::Options) -> Self {
+        self.honeycomb_config.transmission_options = options;
+        self
+    }
+
+    /// Enable or disable publishing telemetry. A disabled layer still tracks distributed
+    /// trace context, but does not send any spans or events to honeycomb.io.
+    pub fn enabled(mut self, enabled: bool) -> Self {
+        self.enabled = enabled;
+        self.reload.set_enabled(enabled);
+        self
+    }
+
+    /// Publish telemetry using the provided transmission, shared with other telemetry layers,
+    /// instead of starting a new transmission for this layer.
+    ///
+    /// The `transmission_options` of the honeycomb config are ignored when a shared
+    /// transmission is used.
+    pub fn shared_transmission(mut self, transmission: SharedTransmission) -> Self {
+        self.transmission = Some(transmission);
+        self
+    }
+
+    /// Send spans and events through `shards` independent libhoney clients, each with its own
+    /// queue and background threads, instead of a single one that can become a bottleneck
+    /// under high concurrency. Traces are routed to a shard by a hash of their trace id, and
+    /// flushing flushes all shards. Defaults to 1.
+    ///
+    /// Each shard queues up to `pending_work_capacity` events (see the transmission options).
+    /// Ignored when a shared transmission is used, see `SharedTransmission::sharded`.
+    pub fn transmission_shards(mut self, shards: usize) -> Self {
+        self.transmission_shards = shards.max(1);
+        self
+    }
+
+    /// Keep up to `maps` field maps for reuse across spans and events, instead of allocating
+    /// (and growing) a fresh map for each of them, to reduce allocator pressure in services
+    /// reporting many spans per second. Defaults to 64, 0 disables reuse.
+    ///
+    /// Maps are reused once their events are serialized, so only the queued transmission,
+    /// `send_now` and `tokio_transmission` reuse them. Maps holding unusually many fields are
+    /// not kept. When a shared transmission is used, its maps are shared by the layers using
+    /// it, and the size set by the last layer built applies.
+    pub fn buffer_pool(mut self, maps: usize) -> Self {
+        self.buffer_pool = maps;
+        self
+    }
+
+    /// Bound the memory held by telemetry buffered for sending to about `bytes`, so an outage
+    /// or a burst of spans can't exhaust the memory of the application. Unlimited by default.
+    ///
+    /// Once the estimated size of the buffered events exceeds the budget, the oldest of them
+    /// are shed to make room for new ones. Shed events are counted in
+    /// `TelemetryStats::dropped_by_memory_budget`, and the bytes buffered are reported as
+    /// `TelemetryStats::buffered_bytes`. The budget covers the events queued for libhoney by the
+    /// queued transmission, whose own queue is bounded by `pending_work_capacity` (see the
+    /// transmission options), the events pending with `send_now`, and the events queued with
+    /// `tokio_transmission`, which drops new events instead as queued ones can't be shed.
+    /// Events spooled to disk (see `dead_letter_spool`) are replayed only while they fit.
+    ///
+    /// When a shared transmission is used, its budget is shared by the layers using it, and
+    /// the budget set by the last layer built applies.
+    pub fn memory_budget(mut self, bytes: usize) -> Self {
+        self.memory_budget = Some(bytes);
+        self
+    }
+
+    /// Send spans and events synchronously, on the thread reporting them, instead of queueing
+    /// them for libhoney's background threads. Meant for short-lived CLI tools that emit a
+    /// handful of spans and must exit right away without losing them.
+    ///
+    /// Events are sent in a single HTTP call once the local root span of their trace closes,
+    /// or once `max_batch_size` events (see the transmission options) are pending, blocking
+    /// for at most `deadline`. Other transmission options, and any shared transmission, are
+    /// ignored. Must not be used from within an async runtime.
+    pub fn send_now(mut self, deadline: Duration) -> Self {
+        self.send_now = Some(deadline);
+        self
+    }
+
+    /// Send spans and events from a task spawned on the tokio 1.x runtime `runtime`, e.g.
+    /// `tokio::runtime::Handle::current()`, instead of libhoney's dedicated background threads,
+    /// for async services that avoid extra threads. Requires the `tokio-transmission` feature
+    /// and a runtime with the time driver enabled.
+    ///
+    /// Events are queued without blocking, and sent in batches per the transmission options,
+    /// on the runtime's blocking thread pool, one batch at a time. The dead-letter spool, the
+    /// circuit breaker and fault injection apply as with `send_now`, which takes precedence,
+    /// while any shared transmission is ignored. Events still queued when the runtime shuts
+    /// down are lost.
+    #[cfg(feature = "tokio-transmission")]
+    pub fn tokio_transmission(mut self, runtime: tokio1::runtime::Handle) -> Self {
+        self.tokio_runtime = Some(runtime);
+        self
+    }
+
+    /// Export spans and events via OTLP/HTTP, using the JSON encoding, instead of honeycomb.io's
+    /// Events API, e.g. to honeycomb.io's OTLP endpoint (`https://api.honeycomb.io`) or to an
+    /// OpenTelemetry collector (`http://localhost:4318`). Spans are posted to `/v1/traces`
+    /// under `endpoint`, along with the API key and dataset as `x-honeycomb-team` and
+    /// `x-honeycomb-dataset` headers. Requires the `otlp` feature.
+    ///
+    /// Spans and events are published with the same ids, names and fields as through the
+    /// Events API, so instrumentation needs no changes. Events are exported as spans without
+    /// duration, and trace and span ids are converted to the W3C format OTLP requires (see
+    /// `TraceId::to_w3c`). Batching follows the transmission options, while `send_now`, shared
+    /// transmissions and the dead-letter spool do not apply.
+    #[cfg(feature = "otlp")]
+    pub fn otlp_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.otlp_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Mirror spans to a Zipkin-compatible collector, e.g. a local Jaeger
+    /// (`http://localhost:9411` with the Zipkin collector enabled) or Zipkin, so developers can
+    /// view traces locally. Spans are posted to `/api/v2/spans` under `endpoint`, using
+    /// Zipkin's v2 JSON encoding, with their fields as tags. Events are not mirrored. Requires
+    /// the `zipkin` feature.
+    ///
+    /// Spans are still published to honeycomb.io, unless the API key is empty, so the same
+    /// configuration can run in production and in development without a honeycomb.io account.
+    /// Mirroring is best effort: spans are dropped if the collector can't keep up, and a
+    /// failure to reach it is reported once (see `Builder::internal_log_mode`) until it is
+    /// reachable again.
+    #[cfg(feature = "zipkin")]
+    pub fn mirror_to_zipkin(mut self, endpoint: impl Into<String>) -> Self {
+        self.zipkin_endpoint = Some(endpoint.into());
+        self
+    }
+
+    /// Record the spans and events that would be published to honeycomb.io in `recorder`
+    /// instead of publishing them, e.g. to test instrumentation, see `test_support`. Takes
+    /// precedence over the other transports. Requires the `test-support` feature.
+    #[cfg(feature = "test-support")]
+    pub fn record_to(mut self, recorder: &TelemetryRecorder) -> Self {
+        self.recorder = Some(recorder.clone());
+        self
+    }
+
+    /// Inject the failures scripted by `faults` into the batches sent by `send_now`, e.g. to
+    /// test error callbacks, circuit breaking or dead-letter spooling without network
+    /// failures, see `FaultInjector`. Requires the `test-support` feature.
+    #[cfg(feature = "test-support")]
+    pub fn inject_faults(mut self, faults: &FaultInjector) -> Self {
+        self.faults = Some(faults.clone());
+        self
+    }
+
+    /// Call `callback` when spans and events cannot be published, e.g. because libhoney's queue
+    /// is full or honeycomb.io rejected them, to route exporter failures into the application's
+    /// own logging or alerting. Rejected API keys and rate limiting are worth alerting on, see
+    /// `TelemetryError::is_unauthorized` and `TelemetryError::is_rate_limited`. Failures are
+    /// logged according to `internal_log_mode` by default.
+    ///
+    /// The callback runs on the thread reporting the span or event, or on the transmission's
+    /// response thread, so it should be cheap and must not block. It must not emit spans or
+    /// events to the subscriber this layer is part of, as those may fail in turn.
+    pub fn on_error<F>(mut self, callback: F) -> Self
+    where
+        F: Fn(TelemetryError) + Send + Sync + 'static,
+    {
+        self.on_error = Some(ErrorHandler::new(callback));
+        self
+    }
+
+    /// Select where the exporter reports its own health and errors: failures to send
+    /// telemetry (unless handled by `on_error`), recovery from outages, queue pressure and the
+    /// circuit breaker opening. Defaults to `InternalLogMode::Stderr`, which is unsuitable for
+    /// services whose stderr is structured, e.g. JSON logs.
+    pub fn internal_log_mode(mut self, mode: InternalLogMode) -> Self {
+        self.internal_log_mode = mode;
+        self.field_options.internal_log_mode = mode;
+        self
+    }
+
+    /// Stop sending spans and events for `cool_down` once `max_failures` consecutive events
+    /// (or batches, see `send_now`) failed to send because honeycomb.io was unreachable, timed
+    /// out or responded with a server error, instead of spending CPU, memory and connections
+    /// on sends bound to fail. Spans and events reported meanwhile are dropped, and counted in
+    /// `TelemetryStats::dropped_by_circuit_breaker`. They are not spooled (see
+    /// `dead_letter_spool`).
+    ///
+    /// Once the cool-down elapses, a single event is sent to probe honeycomb.io: the circuit
+    /// closes if it is accepted, and opens for another cool-down otherwise.
+    pub fn circuit_breaker(mut self, max_failures: u32, cool_down: Duration) -> Self {
+        self.circuit_breaker = Some((max_failures, cool_down));
+        self
+    }
+
+    /// Spool spans and events that cannot be sent because honeycomb.io is unreachable or
+    /// overloaded to a file in `dir`, holding at most `max_bytes`, and send them again once
+    /// honeycomb.io accepts events, so extended outages don't lose traces. Events that do not
+    /// fit are dropped, and events rejected by honeycomb.io (e.g. with an invalid API key) are
+    /// not spooled.
+    ///
+    /// Spooled events are kept across restarts, and are sent with the API key in effect when
+    /// they are replayed: API keys are never written to disk. Replays happen at most every ten
+    /// seconds. Spooling applies to both libhoney's transmission and `send_now`, and errors
+    /// accessing the spool are reported to `on_error`.
+    pub fn dead_letter_spool(mut self, dir: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        self.dead_letter = Some((dir.into(), max_bytes));
+        self
+    }
+
+    /// Spread spans and events across `shards` datasets, named `{dataset}-1` to
+    /// `{dataset}-{shards}` after the dataset of the honeycomb config, to stay below
+    /// per-dataset ingest limits.
+    ///
+    /// Traces are routed by a hash of their trace id, so all of a trace's spans and events land
+    /// in the same dataset. Sampled-out rollups are sent to the first shard.
+    pub fn shard_datasets(mut self, shards: u32) -> Self {
+        self.dataset_shards = Some(shards.max(1));
+        self
+    }
+
+    /// Publish spans and events whose target is `target` or one of its submodules, e.g.
+    /// `my_app::infra`, to `dataset` instead of the configured dataset.
+    ///
+    /// Routing rules are evaluated in the order they were added, the first matching rule
+    /// wins, and `dataset_router` is only called if no rule matches. Routed datasets are
+    /// sharded like the configured dataset, see `shard_datasets`.
+    pub fn route_target_to_dataset(
+        mut self,
+        target: impl Into<String>,
+        dataset: impl Into<String>,
+    ) -> Self {
+        self.dataset_router
+            .route_target(target.into(), dataset.into());
+        self
+    }
+
+    /// Publish spans and events whose field `name` is equal to `value` to `dataset` instead
+    /// of the configured dataset. Fields are matched as published, i.e. after renames, and
+    /// static fields are not matched. See `route_target_to_dataset`.
+    pub fn route_field_to_dataset(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<libhoney::Value>,
+        dataset: impl Into<String>,
+    ) -> Self {
+        self.dataset_router
+            .route_field(name.into(), value.into(), dataset.into());
+        self
+    }
+
+    /// Choose the dataset of spans and events not matched by a routing rule using the provided
+    /// callback, which is passed their published fields and returns the dataset to publish
+    /// them to, or `None` to use the configured dataset. See `route_target_to_dataset`.
+    pub fn dataset_router<F>(mut self, router: F) -> Self
+    where
+        F: Fn(&HashMap<String, libhoney::Value>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.dataset_router.set_router(router);
+        self
+    }
+
+    /// Attach a constant field to every span and event published by this layer, e.g. the
+    /// deployment environment or the git sha of the running build.
+    ///
+    /// Fields recorded on individual spans and events take precedence over static fields
+    /// with the same name.
+    pub fn with_static_field(
+        mut self,
+        name: impl Into<String>,
+        value: impl Into<libhoney::Value>,
+    ) -> Self {
+        self.static_fields.insert(name.into(), value.into());
+        self.reload.set_static_fields(self.static_fields.clone());
+        self
+    }
+
+    /// Enable trace-level sampling, keeping one out of every `sample_rate` traces.
+    ///
+    /// See `new_honeycomb_telemetry_layer_with_trace_sampling` for how this differs from
+    /// the `sample_rate` on the `libhoney::Config`.
+    pub fn sample_rate(self, sample_rate: u32) -> Self {
+        self.sample_rate.set(sample_rate);
+        self
+    }
+
+    /// Get a handle that can be used to adjust the trace-level sample rate of the layer
+    /// constructed by this builder at runtime, e.g. from an admin endpoint.
+    pub fn sample_rate_handle(&self) -> SampleRateHandle {
+        self.sample_rate.clone()
+    }
+
+    /// Get a handle that can be used to change the enabled flag, dataset, static fields and
+    /// sample rate of the layer constructed by this builder at runtime, without rebuilding
+    /// the subscriber. Changes made through the handle take precedence over the builder's
+    /// configuration until the corresponding builder method is called again.
+    pub fn reload_handle(&self) -> ReloadHandle {
+        self.reload.clone()
+    }
+
+    /// Limit the number of spans and events sent to honeycomb.io to `max_events_per_sec`.
+    ///
+    /// Short bursts of up to one second's worth of events are allowed. Anything beyond that
+    /// is dropped, and the number of dropped events is reported in the
+    /// `meta.dropped_by_rate_limit` field of the next event that is sent.
+    pub fn rate_limit(mut self, max_events_per_sec: u32) -> Self {
+        self.rate_limit = Some(max_events_per_sec);
+        self
+    }
+
+    /// Accumulate statistics about traces that were sampled out and report them as
+    /// aggregate events once every `interval`.
+    ///
+    /// One event is emitted per local root span name observed during the interval, marked
+    /// with `meta.sampled_out_rollup = true`. It holds the number of sampled-out traces
+    /// (`rollup.count`), the number of those that recorded an error-level event
+    /// (`rollup.errors`) and a cumulative histogram of root span durations
+    /// (`rollup.duration_ms.le_*`). Aggregates are emitted the first time a span or event is
+    /// reported after the interval has elapsed.
+    pub fn sampled_out_rollup(mut self, interval: Duration) -> Self {
+        self.rollup_interval = Some(interval);
+        self
+    }
+
+    /// Report the loss of spans and events as summary events once every `interval`, so data
+    /// loss is itself visible in honeycomb.io, next to the data that made it.
+    ///
+    /// One event named `data_loss` and marked with `meta.data_loss = true` is emitted per
+    /// reason for which events were lost during the interval, holding the reason
+    /// (`data_loss.reason`: `rate_limit`, `circuit_open`, `memory_budget`, `queue_full` or
+    /// `send_failed`), the number of lost events (`data_loss.count`) and the length of the
+    /// interval (`data_loss.window_secs`).
+    /// Summaries are emitted the first time a span or event is reported after the interval has
+    /// elapsed, and cover all layers using the same `SharedTransmission`. See `TelemetryStats`.
+    pub fn report_data_loss(mut self, interval: Duration) -> Self {
+        self.data_loss_interval = Some(interval);
+        self
+    }
+
+    /// Publish the metrics recorded through `metrics_handle` (or `current_metrics`) to
+    /// `dataset` once every `interval`, so basic service metrics don't need a separate
+    /// pipeline.
+    ///
+    /// Metrics are aggregated in-process and published as one event named `metrics` and
+    /// marked with `meta.metrics = true`, holding one field per counter and gauge, the fields
+    /// summarizing each histogram (see `MetricsHandle::histogram`) and the length of the
+    /// interval (`metrics.window_secs`). Metrics are published the first time a span or event
+    /// is reported after the interval has elapsed, and only if any were recorded.
+    pub fn report_metrics(mut self, interval: Duration, dataset: impl Into<String>) -> Self {
+        self.metrics_report = Some((interval, dataset.into()));
+        self
+    }
+
+    /// Get a handle that can be used to record counters, gauges and histograms published by
+    /// the layer constructed by this builder, see `report_metrics`.
+    pub fn metrics_handle(&self) -> MetricsHandle {
+        self.metrics.clone()
+    }
+
+    /// Override the sampling decision for traces that record an `ERROR` level event.
+    ///
+    /// Error events are always reported. The trace they belong to is then kept, so spans of
+    /// that trace that close afterwards (including all of the error's ancestors) are reported
+    /// even if the trace was sampled out. Spans that closed before the error was recorded
+    /// have already been discarded and cannot be recovered.
+    pub fn keep_errored_traces(mut self, keep_errored_traces: bool) -> Self {
+        self.keep_errored_traces = keep_errored_traces;
+        self
+    }
+
+    /// Only export spans and events at `level` or above to honeycomb.io, e.g. `Level::INFO` to
+    /// leave `DEBUG` and `TRACE` data to other layers, such as local logging, without changing
+    /// the subscriber's filters.
+    ///
+    /// Spans and events below the level are still tracked: errors recorded on them mark their
+    /// trace as errored, and local root spans still end their trace. Spans whose parent was
+    /// not exported are shown without a parent by honeycomb.io.
+    pub fn min_export_level(mut self, level: tracing::Level) -> Self {
+        self.export_filter.min_level = Some(level);
+        self
+    }
+
+    /// Do not export spans and events whose target is `target` or one of its submodules, e.g.
+    /// `hyper` for `hyper::client::pool`, to keep noisy dependencies out of the dataset.
+    ///
+    /// Excluded targets take precedence over `include_target_prefix`. Spans and events that
+    /// are not exported are still tracked, see `min_export_level`.
+    pub fn exclude_target(mut self, target: impl Into<String>) -> Self {
+        self.export_filter.excluded_targets.push(target.into());
+        self
+    }
+
+    /// Only export spans and events whose target starts with `prefix`, e.g. `myapp::`. May be
+    /// called several times to export several prefixes. All targets are exported by default.
+    ///
+    /// Spans and events that are not exported are still tracked, see `min_export_level`.
+    pub fn include_target_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.export_filter.included_prefixes.push(prefix.into());
+        self
+    }
+
+    /// Rename the field `from` to `to` on every span and event published by this layer,
+    /// e.g. `trace.trace_id` to `trace-id` to keep existing boards and derived columns
+    /// working.
+    ///
+    /// Renames apply to both the fields provided by this crate and the fields recorded on
+    /// spans and events (after reserved names are prefixed with `tracing.`), but not to
+    /// static fields.
+    pub fn rename_field(mut self, from: impl Into<String>, to: impl Into<String>) -> Self {
+        self.field_options
+            .key_mapping
+            .rename(from.into(), to.into());
+        self
+    }
+
+    /// Rewrite field names using the provided callback, which returns the new name for a
+    /// field or `None` to leave it unchanged. Fields renamed with `rename_field` are not
+    /// passed to the callback.
+    pub fn map_field_names<F>(mut self, mapper: F) -> Self
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.field_options.key_mapping.set_mapper(mapper);
+        self
+    }
+
+    /// Declare the unit of the numeric field `name` on every span and event published by this
+    /// layer. The unit suffix is appended to the field name, e.g. `latency` is published as
+    /// `latency_ms`, so columns are self-describing. Fields already recorded with the suffix
+    /// are left as is, and fields recorded in another unit of time are converted, e.g.
+    /// `latency_s = 1.5` is published as `latency_ms = 1500.0`.
+    ///
+    /// Units apply to the fields recorded on spans and events before `rename_field`.
+    pub fn field_unit(mut self, name: impl Into<String>, unit: FieldUnit) -> Self {
+        self.field_options.units.set_unit(name.into(), unit);
+        self
+    }
+
+    /// Replace the value of fields named `name` (case-insensitively) with `"[REDACTED]"` on
+    /// every span and event published by this layer, e.g. `password` or `authorization`.
+    ///
+    /// Redaction applies to the fields recorded on spans and events and to fields added with
+    /// `add_trace_field`, using their names as recorded, before any renaming. Lazy fields that
+    /// are redacted are never evaluated.
+    pub fn redact_field(mut self, name: &str) -> Self {
+        self.field_options
+            .redaction
+            .set_action(name, FieldAction::Mask);
+        self
+    }
+
+    /// Replace the value of fields named `name` (case-insensitively) with a consistent hash of
+    /// the value on every span and event published by this layer, e.g. `email` or
+    /// `customer_id`, so captured traces can be shared in bug reports without leaking customer
+    /// data. See `FieldAction::Anonymize`.
+    pub fn anonymize_field(mut self, name: &str) -> Self {
+        self.field_options
+            .redaction
+            .set_action(name, FieldAction::Anonymize);
+        self
+    }
+
+    /// Set the key mixed into the hashes of anonymized fields. Values anonymized with the same
+    /// key hash to the same value, e.g. across processes or restarts. Defaults to a random key
+    /// per layer, so hashes cannot be reversed by hashing candidate values.
+    pub fn anonymization_key(mut self, key: impl Into<String>) -> Self {
+        self.field_options
+            .redaction
+            .set_anonymization_key(key.into());
+        self
+    }
+
+    /// Do not send fields named `name` (case-insensitively) to honeycomb.io.
+    pub fn drop_field(mut self, name: &str) -> Self {
+        self.field_options
+            .redaction
+            .set_action(name, FieldAction::Drop);
+        self
+    }
+
+    /// Decide what to do with recorded fields not covered by `redact_field` or `drop_field`
+    /// using the provided callback, which is given the field's name.
+    pub fn filter_fields<F>(mut self, filter: F) -> Self
+    where
+        F: Fn(&str) -> FieldAction + Send + Sync + 'static,
+    {
+        self.field_options.redaction.set_filter(filter);
+        self
+    }
+
+    /// Clamp the start of each span to the start of its parent, and its end to its start,
+    /// to clean up waterfalls distorted by coarse or adjusted system clocks.
+    ///
+    /// Only parents within the same process are considered. The original offsets are
+    /// recorded, in milliseconds, in `meta.clamped_start_offset_ms` and
+    /// `meta.clamped_end_offset_ms`.
+    pub fn clamp_to_parent(mut self, clamp_to_parent: bool) -> Self {
+        self.clamp_to_parent = clamp_to_parent;
+        self
+    }
+
+    /// Emit a lightweight event each time a span is entered or exited, e.g. each time an
+    /// instrumented future is polled, to diagnose how async spans migrate between threads.
+    ///
+    /// Events are attached to the span, with `meta.span_transition` set to `enter` or `exit`
+    /// and the `thread.id` and `thread.name` of the thread the transition occurred on. This
+    /// significantly increases event volume and is disabled by default.
+    pub fn span_transition_events(mut self, span_transition_events: bool) -> Self {
+        self.span_transition_events = span_transition_events;
+        self
+    }
+
+    /// Emit a lightweight event when a span is first entered, in addition to the span reported
+    /// when it closes, so long-running work shows up in honeycomb.io while still in progress.
+    ///
+    /// Events have `meta.annotation_type` set to `span_start`, the span's `name`, `target` and
+    /// `Timestamp`, and `meta.started_span_id` set to the id of the span. They are attached to
+    /// the span's parent rather than being spans themselves, so waterfalls don't show the span
+    /// twice once it closes. Disabled by default.
+    pub fn span_start_events(mut self, span_start_events: bool) -> Self {
+        self.span_start_events = span_start_events;
+        self
+    }
+
+    /// Publish events recorded outside of any distributed trace, which are dropped by default,
+    /// each in a new trace of its own with an `orphan = true` field. Events recorded in spans
+    /// that are not part of a trace count as such.
+    ///
+    /// Orphan events are sampled like other traces. Exclude the targets of the HTTP stack
+    /// (e.g. `hyper` and `reqwest`, see `exclude_target`) if their events are bridged to
+    /// `tracing`, as sending telemetry would otherwise record more telemetry to send.
+    pub fn orphan_events(mut self, orphan_events: bool) -> Self {
+        self.orphan_events = orphan_events;
+        self
+    }
+
+    /// Finalize traces whose local root span has not closed within `max_trace_duration` of
+    /// their first reported span or event, e.g. because of a bug that leaks the root span.
+    ///
+    /// Per-trace state (propagated sampling decisions, errored traces and sampled-out rollup
+    /// state) is dropped, and an event named `trace_timed_out` marked with
+    /// `meta.timed_out = true` is reported for traces that were kept. Root registration is
+    /// owned by the root span itself and is released when it closes.
+    pub fn max_trace_duration(mut self, max_trace_duration: Duration) -> Self {
+        self.max_trace_duration = Some(max_trace_duration);
+        self
+    }
+
+    /// Limit the number of fields recorded on a single span or event to `max_fields`.
+    ///
+    /// Fields recorded beyond the limit are dropped, and the span or event is marked with
+    /// `meta.truncated = true`. Fields added by this crate do not count towards the limit.
+    pub fn max_fields(mut self, max_fields: usize) -> Self {
+        self.field_options.limits.max_fields = Some(max_fields);
+        self
+    }
+
+    /// Truncate string field values to at most `max_string_len` bytes, marking spans and
+    /// events with truncated values with `meta.truncated = true`.
+    pub fn max_string_len(mut self, max_string_len: usize) -> Self {
+        self.field_options.limits.max_string_len = Some(max_string_len);
+        self
+    }
+
+    /// Set the prefix added to the names of recorded fields that collide with the fields
+    /// provided by this crate (`name`, `level`, `target`, `service_name`, `duration_ms`,
+    /// `Timestamp` and the trace fields named by `trace_field_names`), e.g. `app.` to publish a
+    /// recorded `name` as `app.name`. Defaults to `tracing.`.
+    ///
+    /// With an empty prefix, colliding fields are recorded as is and replaced by the fields
+    /// provided by this crate, avoiding extra columns at the cost of the recorded values.
+    pub fn reserved_field_prefix(mut self, prefix: impl Into<String>) -> Self {
+        self.field_options.prefixing.set_prefix(prefix.into());
+        self
+    }
+
+    /// Publish the recorded field `name` without prefix (see `reserved_field_prefix`), its
+    /// value replacing the one provided by this crate, e.g. `service_name` to publish spans of
+    /// a shared library under the name of the calling service.
+    pub fn unprefixed_field(mut self, name: impl Into<String>) -> Self {
+        self.field_options.prefixing.exempt(name.into());
+        self
+    }
+
+    /// Copy the field `name`, when recorded on a span, onto its descendant spans and events
+    /// that do not record it themselves, e.g. `user_id`, so queries don't need to join
+    /// across spans of a trace.
+    ///
+    /// Fields are copied when the descendant is reported, from the nearest ancestor that
+    /// recorded them.
+    pub fn inherit_field(mut self, name: impl Into<String>) -> Self {
+        self.inherited_fields.insert(name.into());
+        self
+    }
+
+    /// Set the format used to derive `SpanId`s from `tracing::span::Id`s.
+    ///
+    /// Defaults to `SpanIdFormat::Salted`. Use `SpanIdFormat::Legacy` if downstream tooling
+    /// depends on the span id format produced by previous versions of this crate, or
+    /// `SpanIdFormat::Hex64` to exchange span ids with OpenTelemetry-instrumented services.
+    pub fn span_id_format(mut self, span_id_format: SpanIdFormat) -> Self {
+        self.span_id_format = span_id_format;
+        self
+    }
+
+    /// Set the instance id salting the span ids generated by this layer (see
+    /// `SpanIdFormat::Salted`), e.g. to keep span ids stable across restarts for replay and
+    /// debugging tools. Defaults to a random value.
+    ///
+    /// Span ids are only unique across processes if each process uses a distinct instance id.
+    pub fn instance_id(mut self, instance_id: u64) -> Self {
+        self.instance_id = instance_id;
+        self
+    }
+
+    /// Derive the instance id salting the span ids generated by this layer from the host name
+    /// and the process id, see `instance_id`.
+    pub fn host_instance_id(mut self) -> Self {
+        self.instance_id = crate::span_id::host_instance_id();
+        self
+    }
+
+    /// Generate the `TraceId`s of new traces, as returned by `new_trace_id`, using the
+    /// provided generator instead of `TraceId::new()`.
+    pub fn trace_id_generator<G>(mut self, generator: G) -> Self
+    where
+        G: TraceIdGenerator + 'static,
+    {
+        self.trace_id_generator = BoxedTraceIdGenerator::new(generator);
+        self
+    }
+
+    /// Set what to do when `register_dist_tracing_root` is called within a trace that was
+    /// already registered with a different `TraceId`, e.g. by both an HTTP middleware and the
+    /// request handler it wraps. Defaults to `RedundantRootPolicy::LastWins`.
+    pub fn redundant_root_policy(mut self, policy: RedundantRootPolicy) -> Self {
+        self.redundant_root_policy = policy;
+        self
+    }
+
+    /// Set the clock used to time spans and events, e.g. a `MockClock` so tests can assert
+    /// exact `duration_ms` values. Defaults to `SystemClock`.
+    pub fn clock<C: Clock>(mut self, clock: C) -> Self {
+        self.clock = Some(Arc::new(clock));
+        self
+    }
+
+    /// Set the format of the trace ids reported to honeycomb.io and propagated downstream by
+    /// `PropagationContext::current`.
+    ///
+    /// Defaults to `TraceIdFormat::Opaque`. Use `TraceIdFormat::W3c` when exchanging trace
+    /// context with services using W3C Trace Context. Trace ids generated by `TraceId::new()`
+    /// are already in that format. Other trace ids are converted, so services exchanging them
+    /// must all use the same format.
+    pub fn trace_id_format(mut self, trace_id_format: TraceIdFormat) -> Self {
+        self.field_options.trace_id_format = trace_id_format;
+        self
+    }
+
+    /// Set the names of the trace id, span id and parent span id columns of published spans
+    /// and events, e.g. to match the trace settings of an existing dataset. Defaults to
+    /// honeycomb.io's standard `trace.trace_id`, `trace.span_id` and `trace.parent_id`.
+    pub fn trace_field_names(mut self, trace_field_names: TraceFieldNames) -> Self {
+        self.field_options.trace_fields = trace_field_names;
+        self
+    }
+
+    /// Use a preset of field names and formats, e.g. `FieldNaming::Beeline` so traces from
+    /// this service merge cleanly with traces from services instrumented with honeycomb.io's
+    /// beelines. Defaults to `FieldNaming::Default`.
+    ///
+    /// Resets the trace field names to honeycomb.io's standard names, call
+    /// `Builder::trace_field_names` afterwards to override them.
+    pub fn field_naming(mut self, naming: FieldNaming) -> Self {
+        self.field_options.naming = naming;
+        self.field_options.trace_fields = TraceFieldNames::honeycomb();
+        self
+    }
+
+    /// Report the effective configuration of this builder, including applied defaults, along
+    /// with detected misconfigurations such as conflicting sampling settings. Meant to be
+    /// logged at startup, so telemetry misconfiguration can be diagnosed from boot logs.
+    pub fn validate(&self) -> ConfigReport {
+        crate::validation::validate(self)
+    }
+
+    /// Check that honeycomb.io is reachable and accepts the configured API key, e.g. to fail
+    /// fast at startup before installing the subscriber. Blocks the current thread for at
+    /// most ten seconds, see `HoneycombTelemetry::check_connection`.
+    pub fn check_connection(&self) -> ConnectionStatus {
+        let api_key = self.api_key.read().clone();
+        crate::connection::check_connection(&self.honeycomb_config.options.api_host, &api_key)
+    }
+
+    /// Get a client for honeycomb.io's Markers API, e.g. to mark deploys, using the API host,
+    /// API key and dataset configured on this builder. See `HoneycombTelemetry::markers_client`.
+    pub fn markers_client(&self) -> MarkersClient {
+        MarkersClient::new(
+            self.honeycomb_config.options.api_host.clone(),
+            self.api_key.clone(),
+            self.reload.settings().dataset,
+        )
+    }
+
+    /// Construct a `TelemetryLayer` using the configuration provided to this builder.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, see `try_build`.
+    pub fn build(self) -> TelemetryLayer<HoneycombTelemetry, SpanId, TraceId> {
+        self.try_build().unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Construct a `TelemetryLayer` using the configuration provided to this builder, or fail
+    /// if the configuration is invalid, e.g. if the API host is not an `http(s)` URL.
+    pub fn try_build(
+        mut self,
+    ) -> Result<TelemetryLayer<HoneycombTelemetry, SpanId, TraceId>, ConfigError> {
+        self.reserve_trace_field_names();
+        let limits = self.field_options.limits;
+        let prefixing = self.field_options.prefixing.clone();
+        self.try_build_with_visitor(move || HoneycombVisitor::new(limits, prefixing.clone()))
+    }
+
+    /// Construct a `TelemetryLayer` using the configuration provided to this builder. Same as
+    /// `build`.
+    pub fn build_layer(self) -> TelemetryLayer<HoneycombTelemetry, SpanId, TraceId> {
+        self.build()
+    }
+
+    /// Construct a `TelemetryLayer` using the configuration provided to this builder, recording
+    /// fields using visitors created by `mk_visitor` instead of the default `HoneycombVisitor`.
+    ///
+    /// Field limits (`max_fields`, `max_string_len`) and the prefixing of recorded fields
+    /// (`reserved_field_prefix`, `unprefixed_field`) only apply to `HoneycombVisitor`, and
+    /// `inherit_field` only to visitors implementing `HoneycombValues::inherit`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid, see `try_build_with_visitor`.
+    pub fn build_with_visitor<V, F>(
+        self,
+        mk_visitor: F,
+    ) -> TelemetryLayer<HoneycombTelemetry<V>, SpanId, TraceId>
+    where
+        V: HoneycombValues,
+        F: Fn() -> V + Send + Sync + 'static,
+    {
+        self.try_build_with_visitor(mk_visitor)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+
+    /// Same as `build_with_visitor`, but fails instead of panicking if the configuration is
+    /// invalid.
+    pub fn try_build_with_visitor<V, F>(
+        mut self,
+        mk_visitor: F,
+    ) -> Result<TelemetryLayer<HoneycombTelemetry<V>, SpanId, TraceId>, ConfigError>
+    where
+        V: HoneycombValues,
+        F: Fn() -> V + Send + Sync + 'static,
+    {
+        let api_host = &self.honeycomb_config.options.api_host;
+        if normalize_api_host(api_host).is_none() {
+            return Err(ConfigError::InvalidApiHost(api_host.clone()));
+        }
+        self.reserve_trace_field_names();
+        let service_name = self.service_name;
+        let span_id_format = self.span_id_format;
+        let instance_id = self.instance_id;
+        let redundant_root_policy = self.redundant_root_policy;
+        let clock = self.clock.clone();
+        let telemetry = HoneycombTelemetry::new(self, mk_visitor);
+
+        let span_ids = SpanIdGenerator::new(span_id_format, instance_id);
+
+        let layer = TelemetryLayer::new(service_name, telemetry, move |tracing_id| {
+            span_ids.promote(tracing_id)
+        })
+        .redundant_root_policy(redundant_root_policy);
+        Ok(match clock {
+            Some(clock) => layer.clock(clock),
+            None => layer,
+        })
+    }
+
+    // recorded fields are prefixed if they collide with the trace field names actually
+    // published, which may have been customized after setting the prefix
+    fn reserve_trace_field_names(&mut self) {
+        let trace_fields = &self.field_options.trace_fields;
+        self.field_options.prefixing.set_trace_fields(trace_fields);
+    }
+}
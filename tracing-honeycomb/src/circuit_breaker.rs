@@ -0,0 +1,156 @@
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+use crate::diagnostics::{Diagnostic, InternalLogMode};
+
+/// Stops sending events while honeycomb.io is unavailable, see `Builder::circuit_breaker`.
+///
+/// Closed, the circuit lets all events through. It opens once `max_failures` consecutive
+/// sends failed, dropping all events for `cool_down`. A single event is then let through as
+/// a probe: the circuit closes if it is sent, and opens again otherwise.
+#[derive(Debug)]
+pub(crate) struct CircuitBreaker {
+    max_failures: u32,
+    cool_down: Duration,
+    internal_log_mode: InternalLogMode,
+    consecutive_failures: AtomicU32,
+    // checked for every event, so the lock is only taken while the circuit is not closed
+    open: AtomicBool,
+    state: Mutex<State>,
+}
+
+#[derive(Debug, PartialEq)]
+enum State {
+    Closed,
+    Open { until: Instant },
+    // a probe was let through, the circuit opens again if it gets no response within the
+    // cool-down, e.g. because it was dropped
+    Probing { until: Instant },
+}
+
+impl CircuitBreaker {
+    pub(crate) fn new(
+        max_failures: u32,
+        cool_down: Duration,
+        internal_log_mode: InternalLogMode,
+    ) -> Self {
+        CircuitBreaker {
+            max_failures: max_failures.max(1),
+            cool_down,
+            internal_log_mode,
+            consecutive_failures: AtomicU32::new(0),
+            open: AtomicBool::new(false),
+            state: Mutex::new(State::Closed),
+        }
+    }
+
+    fn state(&self) -> impl std::ops::DerefMut<Target = State> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let state = self.state.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let state = self.state.lock();
+
+        state
+    }
+
+    /// Whether an event may be sent. Returns `false` if it should be dropped.
+    pub(crate) fn allow(&self) -> bool {
+        self.allow_at(Instant::now())
+    }
+
+    fn allow_at(&self, now: Instant) -> bool {
+        if !self.open.load(Ordering::Relaxed) {
+            return true;
+        }
+
+        let mut state = self.state();
+        match *state {
+            State::Closed => true,
+            State::Open { until } | State::Probing { until } if now >= until => {
+                *state = State::Probing {
+                    until: now + self.cool_down,
+                };
+                true
+            }
+            State::Open { .. } | State::Probing { .. } => false,
+        }
+    }
+
+    /// Record that an event or batch was sent.
+    pub(crate) fn record_success(&self) {
+        self.consecutive_failures.store(0, Ordering::Relaxed);
+        if self.open.load(Ordering::Relaxed) {
+            *self.state() = State::Closed;
+            self.open.store(false, Ordering::Relaxed);
+        }
+    }
+
+    /// Record that an event or batch could not be sent because honeycomb.io was unreachable,
+    /// timed out or responded with a server error.
+    pub(crate) fn record_failure(&self) {
+        self.record_failure_at(Instant::now())
+    }
+
+    fn record_failure_at(&self, now: Instant) {
+        let failures = self.consecutive_failures.fetch_add(1, Ordering::Relaxed) + 1;
+        let mut state = self.state();
+        let opens = match *state {
+            State::Closed => failures >= self.max_failures,
+            State::Probing { .. } => true,
+            // failures of events sent before the circuit opened
+            State::Open { .. } => false,
+        };
+        if opens {
+            *state = State::Open {
+                until: now + self.cool_down,
+            };
+            self.open.store(true, Ordering::Relaxed);
+            drop(state);
+            self.internal_log_mode.emit(&Diagnostic::CircuitOpened {
+                cool_down: self.cool_down,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn opens_after_failures_and_closes_after_probe() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(30), InternalLogMode::Silent);
+        let start = Instant::now();
+
+        for _ in 0..2 {
+            breaker.record_failure_at(start);
+        }
+        assert!(breaker.allow_at(start));
+        breaker.record_success();
+        for _ in 0..3 {
+            breaker.record_failure_at(start);
+        }
+        assert!(!breaker.allow_at(start));
+
+        // a single probe is let through once the cool-down elapsed, and reopens the circuit
+        let probe_at = start + Duration::from_secs(30);
+        assert!(breaker.allow_at(probe_at));
+        assert!(!breaker.allow_at(probe_at));
+        breaker.record_failure_at(probe_at);
+        assert!(!breaker.allow_at(probe_at + Duration::from_secs(29)));
+
+        // a probe without response is retried after another cool-down
+        let probe_at = probe_at + Duration::from_secs(30);
+        assert!(breaker.allow_at(probe_at));
+        assert!(breaker.allow_at(probe_at + Duration::from_secs(30)));
+        breaker.record_success();
+        assert!(breaker.allow_at(probe_at));
+        assert_eq!(*breaker.state(), State::Closed);
+    }
+}
@@ -0,0 +1,60 @@
+use eaze_tracing_distributed as tracing_distributed;
+
+use libhoney::{json, Value};
+use std::time::SystemTime;
+use tracing_distributed::Span;
+
+use crate::{SpanId, TraceId};
+
+/// Clamp the span's start and end times so that it starts no earlier than its local parent
+/// and ends no earlier than it starts, recording the original offsets (in milliseconds,
+/// negative) in `meta.clamped_start_offset_ms` and `meta.clamped_end_offset_ms`.
+///
+/// Parents always close after their children, so the end of a span only needs clamping
+/// against its (possibly clamped) start. Returns the meta fields to add to the span.
+pub(crate) fn clamp_to_parent<V>(span: &mut Span<V, SpanId, TraceId>) -> Vec<(String, Value)> {
+    let mut meta = Vec::new();
+
+    if let Some(parent_initialized_at) = span.parent_initialized_at {
+        if let Some(offset_ms) = negative_offset_ms(span.initialized_at, parent_initialized_at) {
+            meta.push(("meta.clamped_start_offset_ms".to_string(), json!(offset_ms)));
+            span.initialized_at = parent_initialized_at;
+        }
+    }
+
+    if let Some(offset_ms) = negative_offset_ms(span.completed_at, span.initialized_at) {
+        meta.push(("meta.clamped_end_offset_ms".to_string(), json!(offset_ms)));
+        span.completed_at = span.initialized_at;
+    }
+
+    meta
+}
+
+// `Some(time - bound)`, in milliseconds, if `time` is before `bound`
+fn negative_offset_ms(time: SystemTime, bound: SystemTime) -> Option<f64> {
+    bound
+        .duration_since(time)
+        .ok()
+        .filter(|offset| !offset.is_zero())
+        .map(|offset| -offset.as_secs_f64() * 1000.0)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn only_times_before_their_bound_are_offset() {
+        let bound = SystemTime::now();
+        assert_eq!(
+            negative_offset_ms(bound - Duration::from_micros(1500), bound),
+            Some(-1.5)
+        );
+        assert_eq!(negative_offset_ms(bound, bound), None);
+        assert_eq!(
+            negative_offset_ms(bound + Duration::from_millis(1), bound),
+            None
+        );
+    }
+}
@@ -0,0 +1,102 @@
+use crate::{Builder, HoneycombTelemetry};
+
+/// Command line arguments for configuring honeycomb.io telemetry, for use with `clap`.
+///
+/// Flatten these into your own arguments and convert them into a `Builder`:
+///
+/// ```ignore
+/// #[derive(clap::Parser)]
+/// struct Args {
+///     #[command(flatten)]
+///     honeycomb: tracing_honeycomb::HoneycombArgs,
+/// }
+///
+/// let args = Args::parse();
+/// let telemetry_layer = tracing_honeycomb::Builder::from(args.honeycomb)
+///     .service_name("my-service-name")
+///     .build();
+/// ```
+///
+/// Each flag can also be provided via the environment variable listed in its help text.
+/// Telemetry is disabled if no API key is provided.
+#[derive(Clone, Debug, clap::Args)]
+pub struct HoneycombArgs {
+    /// Honeycomb API key used to publish telemetry
+    #[arg(
+        long = "honeycomb-api-key",
+        env = "HONEYCOMB_API_KEY",
+        hide_env_values = true
+    )]
+    pub api_key: Option<String>,
+
+    /// Honeycomb dataset to which telemetry is published
+    #[arg(long = "honeycomb-dataset", env = "HONEYCOMB_DATASET")]
+    pub dataset: Option<String>,
+
+    /// Keep one out of every N traces
+    #[arg(
+        long = "honeycomb-sample-rate",
+        env = "HONEYCOMB_SAMPLE_RATE",
+        default_value_t = 1
+    )]
+    pub sample_rate: u32,
+
+    /// Do not publish telemetry to Honeycomb
+    #[arg(long = "honeycomb-disable", env = "HONEYCOMB_DISABLE")]
+    pub disable: bool,
+}
+
+impl From<HoneycombArgs> for Builder {
+    /// Uses `unknown_service` as the service name, override it via `Builder::service_name`.
+    fn from(args: HoneycombArgs) -> Self {
+        let enabled = !args.disable && args.api_key.is_some();
+        let mut builder = HoneycombTelemetry::builder();
+        if let Some(api_key) = args.api_key {
+            builder = builder.api_key(api_key);
+        }
+        if let Some(dataset) = args.dataset {
+            builder = builder.dataset(dataset);
+        }
+
+        builder.enabled(enabled).sample_rate(args.sample_rate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use clap::Parser;
+
+    #[derive(Parser)]
+    struct Args {
+        #[command(flatten)]
+        honeycomb: HoneycombArgs,
+    }
+
+    #[test]
+    fn parses_flags_into_builder() {
+        let args = Args::parse_from([
+            "cli",
+            "--honeycomb-api-key",
+            "key",
+            "--honeycomb-dataset",
+            "my-dataset",
+            "--honeycomb-sample-rate",
+            "10",
+        ]);
+        let builder = Builder::from(args.honeycomb);
+
+        assert!(builder.enabled);
+        assert_eq!(builder.honeycomb_config.options.api_key, "key");
+        assert_eq!(builder.honeycomb_config.options.dataset, "my-dataset");
+        assert_eq!(builder.sample_rate.get(), 10);
+    }
+
+    #[test]
+    fn disabled_without_api_key() {
+        let args = Args::parse_from(["cli"]);
+        let builder = Builder::from(args.honeycomb);
+
+        assert!(!builder.enabled);
+    }
+}
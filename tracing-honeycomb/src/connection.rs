@@ -0,0 +1,139 @@
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+use crate::validation::normalize_api_host;
+
+const AUTH_ENDPOINT: &str = "/1/auth";
+
+// bounds startup delays caused by unreachable hosts
+const CHECK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Outcome of checking that telemetry can be published to honeycomb.io, see
+/// `HoneycombTelemetry::check_connection`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConnectionStatus {
+    /// honeycomb.io is reachable and accepted the API key.
+    Ok,
+    /// honeycomb.io rejected the API key, e.g. because it is invalid or was revoked.
+    InvalidKey,
+    /// honeycomb.io could not be reached, or responded unexpectedly, e.g. because the API host
+    /// is wrong. Holds a description of the failure.
+    NetworkError(String),
+}
+
+impl ConnectionStatus {
+    /// Whether telemetry can be published.
+    pub fn is_ok(&self) -> bool {
+        *self == ConnectionStatus::Ok
+    }
+}
+
+impl Display for ConnectionStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionStatus::Ok => f.write_str("connected to honeycomb"),
+            ConnectionStatus::InvalidKey => f.write_str("honeycomb rejected the api key"),
+            ConnectionStatus::NetworkError(message) => {
+                write!(f, "error connecting to honeycomb, {}", message)
+            }
+        }
+    }
+}
+
+/// Check the API key against honeycomb.io's auth endpoint, blocking the current thread.
+pub(crate) fn check_connection(api_host: &str, api_key: &str) -> ConnectionStatus {
+    let api_host = match normalize_api_host(api_host) {
+        Some(api_host) => api_host,
+        None => return ConnectionStatus::NetworkError(format!("invalid api host {:?}", api_host)),
+    };
+    let client = match reqwest::blocking::Client::builder()
+        .timeout(CHECK_TIMEOUT)
+        .build()
+    {
+        Ok(client) => client,
+        Err(err) => return ConnectionStatus::NetworkError(err.to_string()),
+    };
+
+    let res = client
+        .get(&format!("{}{}", api_host, AUTH_ENDPOINT))
+        .header("X-Honeycomb-Team", api_key)
+        .send();
+    match res {
+        Ok(response) if response.status().is_success() => ConnectionStatus::Ok,
+        Ok(response)
+            if response.status() == reqwest::StatusCode::UNAUTHORIZED
+                || response.status() == reqwest::StatusCode::FORBIDDEN =>
+        {
+            ConnectionStatus::InvalidKey
+        }
+        Ok(response) => ConnectionStatus::NetworkError(format!(
+            "unexpected response from {}, {}",
+            api_host,
+            response.status()
+        )),
+        Err(err) => ConnectionStatus::NetworkError(err.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    // serves one request per status, returning the requests
+    fn serve(statuses: &'static [&'static str]) -> (String, std::thread::JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_host = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let mut requests = Vec::new();
+            for status in statuses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = Vec::new();
+                let mut buf = [0; 4096];
+                while !request.ends_with(b"\r\n\r\n") {
+                    let n = stream.read(&mut buf).unwrap();
+                    request.extend_from_slice(&buf[..n]);
+                }
+                let response = format!(
+                    "HTTP/1.1 {}\r\nconnection: close\r\ncontent-length: 2\r\n\r\n{{}}",
+                    status
+                );
+                stream.write_all(response.as_bytes()).unwrap();
+                requests.push(String::from_utf8(request).unwrap());
+            }
+            requests
+        });
+        (api_host, server)
+    }
+
+    #[test]
+    fn classifies_responses() {
+        let (api_host, server) = serve(&["200 OK", "401 Unauthorized", "404 Not Found"]);
+
+        assert_eq!(check_connection(&api_host, "key"), ConnectionStatus::Ok);
+        assert_eq!(
+            check_connection(&api_host, "revoked"),
+            ConnectionStatus::InvalidKey
+        );
+        assert!(matches!(
+            check_connection(&api_host, "key"),
+            ConnectionStatus::NetworkError(_)
+        ));
+
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /1/auth HTTP/1.1"));
+        assert!(requests[1].contains("x-honeycomb-team: revoked"));
+    }
+
+    #[test]
+    fn unreachable_hosts_are_network_errors() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_host = format!("http://{}", listener.local_addr().unwrap());
+        // nothing listens on the port once the listener is dropped
+        drop(listener);
+
+        assert!(!check_connection(&api_host, "key").is_ok());
+        assert!(!check_connection("ftp://example.com", "key").is_ok());
+    }
+}
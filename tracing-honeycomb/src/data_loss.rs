@@ -0,0 +1,194 @@
+use chrono::{DateTime, Utc};
+use libhoney::{json, Value};
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+use crate::stats::TelemetryStats;
+
+/// Periodically turns the loss counters of `TelemetryStats` into summary events, so the loss
+/// of telemetry is itself observable in honeycomb.io. See `Builder::report_data_loss`.
+#[derive(Debug)]
+pub(crate) struct DataLoss {
+    interval: Duration,
+    state: Mutex<DataLossState>,
+}
+
+#[derive(Debug)]
+struct DataLossState {
+    window_started_at: SystemTime,
+    window_started: Instant,
+    // counters as of the start of the window
+    reported: LossCounts,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+struct LossCounts {
+    rate_limit: u64,
+    circuit_open: u64,
+    memory_budget: u64,
+    queue_full: u64,
+    send_failed: u64,
+}
+
+impl LossCounts {
+    fn of(stats: &TelemetryStats) -> Self {
+        LossCounts {
+            rate_limit: stats.dropped_by_rate_limit,
+            circuit_open: stats.dropped_by_circuit_breaker,
+            memory_budget: stats.dropped_by_memory_budget,
+            queue_full: stats.events_dropped
+                - stats.dropped_by_rate_limit
+                - stats.dropped_by_circuit_breaker
+                - stats.dropped_by_memory_budget,
+            send_failed: stats.send_errors,
+        }
+    }
+}
+
+impl DataLoss {
+    pub(crate) fn new(interval: Duration, stats: &TelemetryStats) -> Self {
+        DataLoss {
+            interval,
+            state: Mutex::new(DataLossState {
+                window_started_at: SystemTime::now(),
+                window_started: Instant::now(),
+                reported: LossCounts::of(stats),
+            }),
+        }
+    }
+
+    fn lock(&self) -> impl std::ops::DerefMut<Target = DataLossState> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let state = self.state.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let state = self.state.lock();
+
+        state
+    }
+
+    /// If the current window has elapsed, reset it and return one summary event per reason
+    /// for which events were lost during the window. `stats` is only called once the window
+    /// has elapsed.
+    pub(crate) fn take_due<F>(
+        &self,
+        service_name: &'static str,
+        stats: F,
+    ) -> Vec<HashMap<String, Value>>
+    where
+        F: FnOnce() -> TelemetryStats,
+    {
+        self.take_due_at(service_name, stats, Instant::now(), SystemTime::now())
+    }
+
+    fn take_due_at<F>(
+        &self,
+        service_name: &'static str,
+        stats: F,
+        now: Instant,
+        now_utc: SystemTime,
+    ) -> Vec<HashMap<String, Value>>
+    where
+        F: FnOnce() -> TelemetryStats,
+    {
+        let mut state = self.lock();
+        let elapsed = now.saturating_duration_since(state.window_started);
+        if elapsed < self.interval {
+            return Vec::new();
+        }
+
+        let window_started_at: DateTime<Utc> = state.window_started_at.into();
+        let previous = state.reported;
+        let current = LossCounts::of(&stats());
+        state.window_started_at = now_utc;
+        state.window_started = now;
+        state.reported = current;
+
+        let losses = [
+            ("rate_limit", current.rate_limit - previous.rate_limit),
+            ("circuit_open", current.circuit_open - previous.circuit_open),
+            (
+                "memory_budget",
+                current.memory_budget - previous.memory_budget,
+            ),
+            ("queue_full", current.queue_full - previous.queue_full),
+            ("send_failed", current.send_failed - previous.send_failed),
+        ];
+        losses
+            .iter()
+            .filter(|(_, count)| *count > 0)
+            .map(|(reason, count)| {
+                let mut values = HashMap::new();
+                values.insert("name".to_string(), json!("data_loss"));
+                values.insert("service_name".to_string(), json!(service_name));
+                values.insert(
+                    "Timestamp".to_string(),
+                    json!(window_started_at.to_rfc3339()),
+                );
+                values.insert("meta.data_loss".to_string(), json!(true));
+                values.insert(
+                    "data_loss.window_secs".to_string(),
+                    json!(elapsed.as_secs_f64()),
+                );
+                values.insert("data_loss.reason".to_string(), json!(reason));
+                values.insert("data_loss.count".to_string(), json!(count));
+                values
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn summarizes_losses_per_window_and_reason() {
+        let mut stats = TelemetryStats {
+            events_dropped: 5,
+            dropped_by_rate_limit: 2,
+            ..Default::default()
+        };
+        let data_loss = DataLoss::new(Duration::from_secs(60), &stats);
+        let start = Instant::now();
+
+        stats.events_dropped += 4;
+        stats.dropped_by_rate_limit += 1;
+        stats.send_errors += 2;
+        assert!(data_loss
+            .take_due_at("svc", || stats.clone(), start, SystemTime::now())
+            .is_empty());
+
+        let later = start + Duration::from_secs(61);
+        let mut events = data_loss.take_due_at("svc", || stats.clone(), later, SystemTime::now());
+        events.sort_by_key(|event| event["data_loss.reason"].to_string());
+        let counts: Vec<_> = events
+            .iter()
+            .map(|event| {
+                (
+                    event["data_loss.reason"].clone(),
+                    event["data_loss.count"].clone(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                (json!("queue_full"), json!(3)),
+                (json!("rate_limit"), json!(1)),
+                (json!("send_failed"), json!(2)),
+            ]
+        );
+        assert_eq!(events[0]["meta.data_loss"], json!(true));
+
+        let much_later = later + Duration::from_secs(61);
+        assert!(data_loss
+            .take_due_at("svc", || stats.clone(), much_later, SystemTime::now())
+            .is_empty());
+    }
+}
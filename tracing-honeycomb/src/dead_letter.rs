@@ -0,0 +1,235 @@
+use chrono::{DateTime, Utc};
+use libhoney::{json, Value};
+use std::collections::HashMap;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+use crate::api_key::ApiKeyHandle;
+use crate::telemetry_error::{ErrorHandler, TelemetryError};
+
+const SPOOL_FILE: &str = "honeycomb-dead-letter.jsonl";
+
+// minimum time between replays, so events that keep failing (e.g. for a single dataset) are
+// not resent on every successful response
+const REPLAY_INTERVAL: Duration = Duration::from_secs(10);
+
+/// An event read back from the spool, to be sent again.
+#[derive(Debug)]
+pub(crate) struct SpooledEvent {
+    pub(crate) options: libhoney::client::Options,
+    pub(crate) time: DateTime<Utc>,
+    pub(crate) data: HashMap<String, Value>,
+}
+
+/// Events that could not be delivered because honeycomb.io was unreachable, spooled to a local
+/// file until it is reachable again, see `Builder::dead_letter_spool`.
+///
+/// Events are stored one per line, in the format of honeycomb.io's batch API along with their
+/// API host and dataset. API keys are never written to disk: replayed events are sent with the
+/// layer's current API key.
+#[derive(Debug)]
+pub(crate) struct DeadLetterSpool {
+    path: PathBuf,
+    max_bytes: u64,
+    api_key: ApiKeyHandle,
+    on_error: ErrorHandler,
+    // serializes writes to, and replays of, the spool file. holds the time of the last replay
+    file: Mutex<Option<Instant>>,
+    // set when events are spilled, cleared when they are taken for replay, so successful
+    // responses don't hit the filesystem while the spool is empty
+    pending: AtomicBool,
+}
+
+impl DeadLetterSpool {
+    pub(crate) fn new(
+        dir: PathBuf,
+        max_bytes: u64,
+        api_key: ApiKeyHandle,
+        on_error: ErrorHandler,
+    ) -> Self {
+        let path = dir.join(SPOOL_FILE);
+        // events spooled by a previous run are replayed once honeycomb.io responds
+        let pending = path.exists();
+        DeadLetterSpool {
+            path,
+            max_bytes,
+            api_key,
+            on_error,
+            file: Mutex::new(None),
+            pending: AtomicBool::new(pending),
+        }
+    }
+
+    fn lock(&self) -> impl std::ops::DerefMut<Target = Option<Instant>> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let file = self.file.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let file = self.file.lock();
+
+        file
+    }
+
+    /// The spool record of an event: the batch API representation of the event, along with
+    /// the API host and dataset it is sent to.
+    pub(crate) fn record(
+        options: &libhoney::client::Options,
+        time: DateTime<Utc>,
+        data: &HashMap<String, Value>,
+    ) -> Value {
+        json!({
+            "api_host": options.api_host,
+            "dataset": options.dataset,
+            "samplerate": options.sample_rate,
+            "time": time.to_rfc3339(),
+            "data": data,
+        })
+    }
+
+    /// Append events to the spool. Events that do not fit within the size bound are dropped.
+    pub(crate) fn spill(&self, records: &[Value]) {
+        let _file = self.lock();
+        if let Err(err) = self.append(records) {
+            self.on_error.report(TelemetryError::DeadLetter(err));
+        }
+    }
+
+    fn append(&self, records: &[Value]) -> io::Result<()> {
+        let mut size = match fs::metadata(&self.path) {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+
+        let mut lines = String::new();
+        for record in records {
+            let line = format!("{}\n", record);
+            if size + line.len() as u64 > self.max_bytes {
+                break;
+            }
+            size += line.len() as u64;
+            lines.push_str(&line);
+        }
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        file.write_all(lines.as_bytes())?;
+        self.pending.store(true, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// Remove all events from the spool, to be sent again. Returns nothing if events were
+    /// already taken within the last few seconds.
+    pub(crate) fn take(&self) -> Vec<SpooledEvent> {
+        if !self.pending.load(Ordering::Relaxed) {
+            return Vec::new();
+        }
+
+        let mut last_replay = self.lock();
+        if last_replay.is_some_and(|at| at.elapsed() < REPLAY_INTERVAL) {
+            return Vec::new();
+        }
+        *last_replay = Some(Instant::now());
+        self.pending.store(false, Ordering::Relaxed);
+        let contents = match fs::read_to_string(&self.path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Vec::new(),
+            Err(err) => {
+                self.on_error.report(TelemetryError::DeadLetter(err));
+                return Vec::new();
+            }
+        };
+        if let Err(err) = fs::remove_file(&self.path) {
+            // keep the events rather than replaying them twice
+            self.on_error.report(TelemetryError::DeadLetter(err));
+            return Vec::new();
+        }
+
+        let api_key = self.api_key.read().clone();
+        contents
+            .lines()
+            // skips lines that were partially written, e.g. when the process was killed
+            .filter_map(|line| line.parse::<Value>().ok())
+            .filter_map(|record| parse_record(record, &api_key))
+            .collect()
+    }
+}
+
+fn parse_record(record: Value, api_key: &str) -> Option<SpooledEvent> {
+    let time = DateTime::parse_from_rfc3339(record["time"].as_str()?).ok()?;
+    let data = match &record["data"] {
+        Value::Object(data) => data.clone().into_iter().collect(),
+        _ => return None,
+    };
+    Some(SpooledEvent {
+        options: libhoney::client::Options {
+            api_key: api_key.to_string(),
+            api_host: record["api_host"].as_str()?.to_string(),
+            dataset: record["dataset"].as_str()?.to_string(),
+            sample_rate: record["samplerate"].as_u64()? as usize,
+        },
+        time: time.with_timezone(&Utc),
+        data,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn spool(dir: &std::path::Path, max_bytes: u64) -> DeadLetterSpool {
+        DeadLetterSpool::new(
+            dir.to_path_buf(),
+            max_bytes,
+            ApiKeyHandle::new("key".to_string()),
+            ErrorHandler::default(),
+        )
+    }
+
+    #[test]
+    fn spills_and_replays_events_within_bound() {
+        let dir = std::env::temp_dir().join(format!("dead-letter-{}", uuid::Uuid::new_v4()));
+        let options = libhoney::client::Options {
+            dataset: "spooled".to_string(),
+            ..Default::default()
+        };
+        let record = |i: i32| {
+            let mut data = HashMap::new();
+            data.insert("i".to_string(), json!(i));
+            DeadLetterSpool::record(&options, Utc::now(), &data)
+        };
+        let line_len = format!("{}\n", record(0)).len() as u64;
+
+        let spool = spool(&dir, line_len * 2);
+        assert!(spool.take().is_empty());
+        spool.spill(&[record(0), record(1)]);
+        spool.spill(&[record(2)]);
+
+        // spooled events survive restarts
+        let spool = self::spool(&dir, line_len * 2);
+        let events = spool.take();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[1].data["i"], json!(1));
+        assert_eq!(events[1].options.dataset, "spooled");
+        assert_eq!(events[1].options.api_key, "key");
+        assert!(spool.take().is_empty());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
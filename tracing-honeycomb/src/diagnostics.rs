@@ -0,0 +1,269 @@
+use std::cell::Cell;
+use std::fmt::{self, Display};
+use std::time::{Duration, SystemTimeError};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+use crate::telemetry_error::TelemetryError;
+
+/// Target of the events and log records reporting the health of the exporter itself: failures
+/// to send telemetry, recovery from outages and queue pressure, see `InternalLogMode`. Events
+/// with this target are never published to honeycomb.io.
+pub const INTERNAL_TARGET: &str = "tracing_honeycomb::internal";
+
+// fraction of the queue's capacity above which the queue is reported to be under pressure,
+// and below which it is reported to have recovered
+const QUEUE_PRESSURE_HIGH: f64 = 0.9;
+const QUEUE_PRESSURE_LOW: f64 = 0.5;
+
+/// Where the exporter reports its own health and errors, see `Builder::internal_log_mode`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum InternalLogMode {
+    /// Write messages to stderr. The default.
+    #[default]
+    Stderr,
+    /// Emit records with the `log` crate, under the `INTERNAL_TARGET` target.
+    Log,
+    /// Emit `tracing` events under the `INTERNAL_TARGET` target, captured by the other layers of
+    /// the subscriber (e.g. logging) but not published to honeycomb.io. Events emitted while
+    /// the layer handles an event are dropped by `tracing`.
+    Tracing,
+    /// Discard messages. Errors are still passed to the callback set with `Builder::on_error`.
+    Silent,
+}
+
+/// A message about the health of the exporter.
+#[derive(Debug)]
+pub(crate) enum Diagnostic<'a> {
+    /// Telemetry could not be published, see `Builder::on_error`.
+    Error(&'a TelemetryError),
+    /// honeycomb.io accepted events again after failing to.
+    Recovered,
+    /// Events spooled while honeycomb.io was unreachable are sent again.
+    Replaying { events: usize },
+    /// The queue crossed the pressure thresholds, upwards if `rising`.
+    QueuePressure {
+        depth: usize,
+        capacity: usize,
+        rising: bool,
+    },
+    /// Too many sends failed, events are dropped for `cool_down`, see
+    /// `Builder::circuit_breaker`.
+    CircuitOpened { cool_down: Duration },
+    /// The end of a span preceded its start.
+    ClockSkew(&'a SystemTimeError),
+}
+
+impl Diagnostic<'_> {
+    fn level(&self) -> tracing::Level {
+        match self {
+            Diagnostic::Error(_) => tracing::Level::ERROR,
+            Diagnostic::QueuePressure { rising: true, .. }
+            | Diagnostic::CircuitOpened { .. }
+            | Diagnostic::ClockSkew(_) => tracing::Level::WARN,
+            Diagnostic::Recovered
+            | Diagnostic::Replaying { .. }
+            | Diagnostic::QueuePressure { rising: false, .. } => tracing::Level::INFO,
+        }
+    }
+}
+
+impl Display for Diagnostic<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Diagnostic::Error(err) => err.fmt(f),
+            Diagnostic::Recovered => f.write_str("sending events to honeycomb.io again"),
+            Diagnostic::Replaying { events } => write!(
+                f,
+                "sending {} events spooled while honeycomb.io was unreachable",
+                events
+            ),
+            Diagnostic::QueuePressure {
+                depth,
+                capacity,
+                rising: true,
+            } => write!(
+                f,
+                "honeycomb.io event queue is almost full ({}/{}), events may be dropped",
+                depth, capacity
+            ),
+            Diagnostic::QueuePressure {
+                depth,
+                capacity,
+                rising: false,
+            } => write!(
+                f,
+                "honeycomb.io event queue recovered ({}/{})",
+                depth, capacity
+            ),
+            Diagnostic::CircuitOpened { cool_down } => write!(
+                f,
+                "honeycomb.io is unavailable, dropping events for {:?}",
+                cool_down
+            ),
+            Diagnostic::ClockSkew(err) => write!(
+                f,
+                "error comparing system times in tracing-honeycomb, indicates possible clock skew: {:?}",
+                err
+            ),
+        }
+    }
+}
+
+thread_local! {
+    // set while emitting a diagnostic, so that diagnostics caused by emitting one (e.g. by a
+    // layer exporting it) are dropped instead of recursing
+    static EMITTING: Cell<bool> = const { Cell::new(false) };
+}
+
+impl InternalLogMode {
+    /// Report `diagnostic`, unless already reporting one on this thread.
+    pub(crate) fn emit(self, diagnostic: &Diagnostic<'_>) {
+        if self == InternalLogMode::Silent {
+            return;
+        }
+        let reentered = EMITTING
+            .try_with(|emitting| emitting.replace(true))
+            .unwrap_or(true);
+        if reentered {
+            return;
+        }
+        match self {
+            InternalLogMode::Stderr => eprintln!("{}", diagnostic),
+            InternalLogMode::Log => log::log!(
+                target: INTERNAL_TARGET,
+                log_level(diagnostic.level()),
+                "{}",
+                diagnostic
+            ),
+            InternalLogMode::Tracing => emit_event(diagnostic),
+            InternalLogMode::Silent => {}
+        }
+        EMITTING.with(|emitting| emitting.set(false));
+    }
+}
+
+fn log_level(level: tracing::Level) -> log::Level {
+    match level {
+        tracing::Level::ERROR => log::Level::Error,
+        tracing::Level::WARN => log::Level::Warn,
+        _ => log::Level::Info,
+    }
+}
+
+fn emit_event(diagnostic: &Diagnostic<'_>) {
+    match diagnostic {
+        Diagnostic::Error(err) => tracing::error!(target: INTERNAL_TARGET, error = %err),
+        Diagnostic::Recovered => tracing::info!(target: INTERNAL_TARGET, "{}", diagnostic),
+        Diagnostic::Replaying { events } => {
+            tracing::info!(target: INTERNAL_TARGET, events, "{}", diagnostic)
+        }
+        Diagnostic::QueuePressure {
+            depth,
+            capacity,
+            rising: true,
+        } => tracing::warn!(target: INTERNAL_TARGET, depth, capacity, "{}", diagnostic),
+        Diagnostic::QueuePressure {
+            depth,
+            capacity,
+            rising: false,
+        } => tracing::info!(target: INTERNAL_TARGET, depth, capacity, "{}", diagnostic),
+        Diagnostic::CircuitOpened { cool_down } => tracing::warn!(
+            target: INTERNAL_TARGET,
+            cool_down_secs = cool_down.as_secs_f64(),
+            "{}",
+            diagnostic
+        ),
+        Diagnostic::ClockSkew(_) => tracing::warn!(target: INTERNAL_TARGET, "{}", diagnostic),
+    }
+}
+
+/// Modes of the layers sharing a transmission, for diagnostics concerning all of them. Each
+/// diagnostic is reported once per distinct mode.
+#[derive(Debug, Default)]
+pub(crate) struct SharedDiagnostics(Mutex<Vec<InternalLogMode>>);
+
+impl SharedDiagnostics {
+    fn modes(&self) -> impl std::ops::DerefMut<Target = Vec<InternalLogMode>> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let modes = self.0.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let modes = self.0.lock();
+
+        modes
+    }
+
+    pub(crate) fn add(&self, mode: InternalLogMode) {
+        let mut modes = self.modes();
+        if !modes.contains(&mode) {
+            modes.push(mode);
+        }
+    }
+
+    pub(crate) fn emit(&self, diagnostic: &Diagnostic<'_>) {
+        let modes = self.modes().clone();
+        for mode in modes {
+            mode.emit(diagnostic);
+        }
+    }
+}
+
+/// Whether the queue is under pressure given its `depth`, accounting for hysteresis, if that
+/// changed since it was `under_pressure`.
+pub(crate) fn queue_pressure(depth: usize, capacity: usize, under_pressure: bool) -> Option<bool> {
+    let fraction = depth as f64 / capacity.max(1) as f64;
+    match under_pressure {
+        false if fraction >= QUEUE_PRESSURE_HIGH => Some(true),
+        true if fraction < QUEUE_PRESSURE_LOW => Some(false),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Arc;
+    use tracing_subscriber::layer::{Context, Layer};
+
+    struct Recorder(Arc<std::sync::Mutex<Vec<String>>>);
+
+    impl<S: tracing::Subscriber> Layer<S> for Recorder {
+        fn on_event(&self, event: &tracing::Event<'_>, _ctx: Context<'_, S>) {
+            self.0
+                .lock()
+                .unwrap()
+                .push(event.metadata().target().to_string());
+            // diagnostics emitted while a diagnostic is being handled are dropped
+            InternalLogMode::Tracing.emit(&Diagnostic::Recovered);
+        }
+    }
+
+    #[test]
+    fn diagnostics_are_emitted_without_recursion() {
+        let targets = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let subscriber = Recorder(targets.clone())
+            .with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        assert_eq!(queue_pressure(95, 100, false), Some(true));
+        assert_eq!(queue_pressure(60, 100, true), None);
+        assert_eq!(queue_pressure(10, 100, true), Some(false));
+
+        let shared = SharedDiagnostics::default();
+        shared.add(InternalLogMode::Tracing);
+        shared.add(InternalLogMode::Silent);
+        shared.add(InternalLogMode::Tracing);
+        tracing::subscriber::with_default(subscriber, || {
+            shared.emit(&Diagnostic::Replaying { events: 3 });
+            InternalLogMode::Silent.emit(&Diagnostic::Recovered);
+            InternalLogMode::Tracing.emit(&Diagnostic::CircuitOpened {
+                cool_down: Duration::from_secs(30),
+            });
+        });
+
+        assert_eq!(*targets.lock().unwrap(), vec![INTERNAL_TARGET; 2]);
+    }
+}
@@ -0,0 +1,61 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::links::SpanLink;
+use crate::{SamplingDecision, SpanId, SpanStatus, TraceId};
+
+/// Stands in for the reporting path of the telemetry layer when the crate is built without the
+/// `honeycomb` feature, see the `disabled` feature in the crate docs.
+///
+/// No value of this type can exist, so functions looking up the telemetry layer of the current
+/// subscriber behave as if none was installed, e.g. `set_trace_experiment` fails with
+/// `TraceCtxError::TelemetryLayerNotRegistered` and `new_trace_id` falls back to
+/// `TraceId::new`.
+#[derive(Debug)]
+pub(crate) enum Reporter {}
+
+impl Reporter {
+    pub(crate) fn record_sampling_decision(&self, _trace_id: TraceId, _decision: SamplingDecision) {
+        match *self {}
+    }
+
+    pub(crate) fn record_experiment(&self, _trace_id: &TraceId, _flag: String, _variant: String) {
+        match *self {}
+    }
+
+    pub(crate) fn experiments(&self, _trace_id: &TraceId) -> BTreeMap<String, String> {
+        match *self {}
+    }
+
+    pub(crate) fn add_trace_field(&self, _trace_id: &TraceId, _key: String, _value: Value) {
+        match *self {}
+    }
+
+    pub(crate) fn report_link(&self, _link: SpanLink) {
+        match *self {}
+    }
+
+    pub(crate) fn set_span_status(&self, _span_id: &SpanId, _status: SpanStatus) {
+        match *self {}
+    }
+
+    pub(crate) fn set_enabled(&self, _enabled: bool) {
+        match *self {}
+    }
+
+    pub(crate) fn instance_id(&self) -> u64 {
+        match *self {}
+    }
+
+    pub(crate) fn new_trace_id(&self) -> TraceId {
+        match *self {}
+    }
+
+    pub(crate) fn propagated_trace_id(&self, _trace_id: TraceId) -> TraceId {
+        match *self {}
+    }
+
+    pub(crate) fn sampling_decision(&self, _trace_id: &TraceId) -> SamplingDecision {
+        match *self {}
+    }
+}
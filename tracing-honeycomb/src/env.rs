@@ -0,0 +1,168 @@
+use std::fmt::{self, Display};
+
+use crate::validation::normalize_api_host;
+use crate::{Builder, HoneycombTelemetry};
+
+/// Environment variable holding the honeycomb.io API key. Required.
+pub const HONEYCOMB_API_KEY: &str = "HONEYCOMB_API_KEY";
+/// Environment variable holding the honeycomb.io dataset. Required.
+pub const HONEYCOMB_DATASET: &str = "HONEYCOMB_DATASET";
+/// Environment variable holding the honeycomb.io API host, e.g. `https://api.eu1.honeycomb.io`.
+/// Defaults to `https://api.honeycomb.io`.
+pub const HONEYCOMB_API_HOST: &str = "HONEYCOMB_API_HOST";
+/// Environment variable holding the trace-level sample rate, see `Builder::sample_rate`.
+/// Defaults to 1, keeping every trace.
+pub const HONEYCOMB_SAMPLE_RATE: &str = "HONEYCOMB_SAMPLE_RATE";
+
+/// Error returned when the telemetry layer cannot be configured from environment variables,
+/// listing every missing or invalid variable.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EnvConfigError {
+    /// Required variables that are unset or empty.
+    pub missing: Vec<&'static str>,
+    /// Variables whose value is invalid, e.g. a sample rate that is not a positive integer or
+    /// an API host that is not an `http(s)` URL, along with that value.
+    pub invalid: Vec<(&'static str, String)>,
+}
+
+impl Display for EnvConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid honeycomb configuration")?;
+        if !self.missing.is_empty() {
+            write!(
+                f,
+                ", missing environment variables: {}",
+                self.missing.join(", ")
+            )?;
+        }
+        for (name, value) in &self.invalid {
+            write!(f, ", {} is invalid: {:?}", name, value)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for EnvConfigError {}
+
+impl Builder {
+    /// Create a builder configured from the `HONEYCOMB_API_KEY`, `HONEYCOMB_DATASET`,
+    /// `HONEYCOMB_API_HOST` and `HONEYCOMB_SAMPLE_RATE` environment variables, using the
+    /// provided service name. Other options can be set on the returned builder.
+    ///
+    /// Returns an error listing all missing and invalid variables, if any.
+    pub fn from_env(service_name: &'static str) -> Result<Self, EnvConfigError> {
+        from_lookup(service_name, |name| std::env::var(name).ok())
+    }
+}
+
+fn from_lookup<F>(service_name: &'static str, lookup: F) -> Result<Builder, EnvConfigError>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let lookup = |name| lookup(name).filter(|value| !value.trim().is_empty());
+    let mut error = EnvConfigError {
+        missing: Vec::new(),
+        invalid: Vec::new(),
+    };
+
+    let api_key = lookup(HONEYCOMB_API_KEY);
+    let dataset = lookup(HONEYCOMB_DATASET);
+    for (name, value) in &[(HONEYCOMB_API_KEY, &api_key), (HONEYCOMB_DATASET, &dataset)] {
+        if value.is_none() {
+            error.missing.push(name);
+        }
+    }
+    let sample_rate = match lookup(HONEYCOMB_SAMPLE_RATE) {
+        Some(value) => match value.trim().parse::<u32>() {
+            Ok(sample_rate) if sample_rate > 0 => Some(sample_rate),
+            _ => {
+                error.invalid.push((HONEYCOMB_SAMPLE_RATE, value));
+                None
+            }
+        },
+        None => None,
+    };
+    let api_host = lookup(HONEYCOMB_API_HOST);
+    if let Some(api_host) = &api_host {
+        if normalize_api_host(api_host).is_none() {
+            error.invalid.push((HONEYCOMB_API_HOST, api_host.clone()));
+        }
+    }
+    if !error.missing.is_empty() || !error.invalid.is_empty() {
+        return Err(error);
+    }
+
+    let mut builder = HoneycombTelemetry::builder()
+        .service_name(service_name)
+        .api_key(api_key.unwrap_or_default())
+        .dataset(dataset.unwrap_or_default());
+    builder.env_vars.push(HONEYCOMB_API_KEY);
+    builder.env_vars.push(HONEYCOMB_DATASET);
+    if let Some(api_host) = api_host {
+        builder = builder.api_host(api_host);
+        builder.env_vars.push(HONEYCOMB_API_HOST);
+    }
+    if let Some(sample_rate) = sample_rate {
+        builder = builder.sample_rate(sample_rate);
+        builder.env_vars.push(HONEYCOMB_SAMPLE_RATE);
+    }
+    Ok(builder)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        move |name| vars.get(name).cloned()
+    }
+
+    #[test]
+    fn reads_configuration_from_env() {
+        let builder = from_lookup(
+            "svc",
+            lookup(&[
+                (HONEYCOMB_API_KEY, "key"),
+                (HONEYCOMB_DATASET, "prod"),
+                (HONEYCOMB_API_HOST, "https://api.eu1.honeycomb.io"),
+                (HONEYCOMB_SAMPLE_RATE, "20"),
+            ]),
+        )
+        .unwrap();
+
+        let options = &builder.honeycomb_config.options;
+        assert_eq!(options.api_key, "key");
+        assert_eq!(options.dataset, "prod");
+        assert_eq!(options.api_host, "https://api.eu1.honeycomb.io");
+        assert_eq!(builder.sample_rate.get(), 20);
+        assert_eq!(
+            builder.validate().get("sample_rate").unwrap().env_var,
+            Some(HONEYCOMB_SAMPLE_RATE)
+        );
+    }
+
+    #[test]
+    fn lists_missing_and_invalid_variables() {
+        let error = from_lookup(
+            "svc",
+            lookup(&[(HONEYCOMB_DATASET, " "), (HONEYCOMB_SAMPLE_RATE, "0")]),
+        )
+        .unwrap_err();
+
+        assert_eq!(error.missing, vec![HONEYCOMB_API_KEY, HONEYCOMB_DATASET]);
+        assert_eq!(
+            error.invalid,
+            vec![(HONEYCOMB_SAMPLE_RATE, "0".to_string())]
+        );
+        assert_eq!(
+            error.to_string(),
+            "invalid honeycomb configuration, missing environment variables: \
+             HONEYCOMB_API_KEY, HONEYCOMB_DATASET, HONEYCOMB_SAMPLE_RATE is invalid: \"0\""
+        );
+    }
+}
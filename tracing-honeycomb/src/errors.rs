@@ -0,0 +1,186 @@
+use libhoney::{json, Value};
+use std::error::Error;
+
+use crate::bounded_map::BoundedMap;
+#[cfg(feature = "tracing-error")]
+use crate::visitor::HoneycombValues;
+use crate::{SpanId, SpanStatus};
+
+// bounds the number of open spans with a status
+const MAX_SPAN_STATUSES: usize = 10_000;
+
+/// Field holding the `tracing_error::SpanTrace` of an error, see the `tracing-error` feature.
+#[cfg(feature = "tracing-error")]
+pub(crate) const SPAN_TRACE_FIELD: &str = "error.span_trace";
+
+/// Honeycomb-conventional fields describing an error recorded on a span or event: `error`,
+/// `error.message`, `error.type` and `error.source_chain`, along with `error.span_trace` if
+/// the error or one of its sources carries a span trace (see the `tracing-error` feature).
+pub(crate) fn error_values(err: &(dyn Error + 'static)) -> Vec<(&'static str, Value)> {
+    let mut source_chain = Vec::new();
+    let mut source = err.source();
+    while let Some(err) = source {
+        source_chain.push(err.to_string());
+        source = err.source();
+    }
+
+    let mut values = vec![
+        ("error", json!(true)),
+        ("error.message", json!(err.to_string())),
+        ("error.type", json!(error_type(err))),
+    ];
+    if !source_chain.is_empty() {
+        values.push(("error.source_chain", json!(source_chain)));
+    }
+    #[cfg(feature = "tracing-error")]
+    {
+        if let Some(span_trace) = recorded_span_trace(err) {
+            values.push((SPAN_TRACE_FIELD, json!(span_trace)));
+        }
+    }
+    values
+}
+
+// the span trace carried by the error or its sources, e.g. a `tracing_error::TracedError`
+#[cfg(feature = "tracing-error")]
+fn recorded_span_trace(err: &(dyn Error + 'static)) -> Option<String> {
+    use tracing_error::{ExtractSpanTrace, SpanTraceStatus};
+
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(span_trace) = err.span_trace() {
+            if span_trace.status() == SpanTraceStatus::CAPTURED {
+                return Some(span_trace.to_string());
+            }
+        }
+        source = err.source();
+    }
+    None
+}
+
+/// Add the span trace of the current span to the fields of an error event, unless an error
+/// recorded on the event already carried one. Requires `tracing_error::ErrorLayer` to be part
+/// of the default subscriber.
+///
+/// Nothing is captured while an event is dispatched to a scoped default subscriber (see
+/// `tracing::subscriber::with_default`), as the subscriber is not reachable from within its
+/// own callbacks.
+#[cfg(feature = "tracing-error")]
+pub(crate) fn with_span_trace<V: HoneycombValues>(
+    mut extra: Vec<(String, Value)>,
+    values: &V,
+) -> Vec<(String, Value)> {
+    if values.get(SPAN_TRACE_FIELD).is_some() {
+        return extra;
+    }
+    let span_trace = tracing_error::SpanTrace::capture();
+    if span_trace.status() == tracing_error::SpanTraceStatus::CAPTURED {
+        extra.push((SPAN_TRACE_FIELD.to_string(), json!(span_trace.to_string())));
+    }
+    extra
+}
+
+// `dyn Error` does not expose the name of the underlying type, so use the leading identifier
+// of its `Debug` representation, which is the type (or variant) name for derived impls
+fn error_type(err: &dyn Error) -> String {
+    let debug = format!("{:?}", err);
+    let end = debug
+        .find(|c: char| !(c.is_alphanumeric() || c == '_' || c == ':'))
+        .unwrap_or(debug.len());
+    debug[..end].to_string()
+}
+
+/// Status of open spans, set via `set_span_status` or by an event recording an error, so it can
+/// be published once they close. The latest status set wins.
+#[derive(Debug)]
+pub(crate) struct SpanStatuses(BoundedMap<SpanId, SpanStatus>);
+
+impl Default for SpanStatuses {
+    fn default() -> Self {
+        SpanStatuses(BoundedMap::new(MAX_SPAN_STATUSES))
+    }
+}
+
+impl SpanStatuses {
+    pub(crate) fn insert(&self, span_id: &SpanId, status: SpanStatus) {
+        self.0.insert(span_id, status);
+    }
+
+    /// Stop tracking the span, returning its status if one was set. Called once it has closed.
+    /// Does not lock while no status is pending, which is the common case.
+    pub(crate) fn remove(&self, span_id: &SpanId) -> Option<SpanStatus> {
+        self.0.remove(span_id)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::fmt;
+
+    #[derive(Debug)]
+    struct Outer(std::num::ParseIntError);
+
+    impl fmt::Display for Outer {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "failed to parse config")
+        }
+    }
+
+    impl Error for Outer {
+        fn source(&self) -> Option<&(dyn Error + 'static)> {
+            Some(&self.0)
+        }
+    }
+
+    #[cfg(feature = "tracing-error")]
+    #[test]
+    fn error_values_include_span_trace() {
+        use tracing_error::InstrumentError;
+        use tracing_subscriber::layer::SubscriberExt;
+
+        let subscriber = tracing_subscriber::registry::Registry::default()
+            .with(tracing_error::ErrorLayer::default());
+        let err = tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("load_config", path = "app.toml");
+            let _guard = span.enter();
+            Outer("x".parse::<u32>().unwrap_err()).in_current_span()
+        });
+        let values: std::collections::HashMap<_, _> = error_values(&err).into_iter().collect();
+
+        let span_trace = values[SPAN_TRACE_FIELD].as_str().unwrap();
+        assert!(span_trace.contains("load_config"));
+        assert!(span_trace.contains("app.toml"));
+    }
+
+    #[test]
+    fn error_values_include_type_and_source_chain() {
+        let err = Outer("x".parse::<u32>().unwrap_err());
+        let values: std::collections::HashMap<_, _> = error_values(&err).into_iter().collect();
+
+        assert_eq!(values["error"], json!(true));
+        assert_eq!(values["error.message"], json!("failed to parse config"));
+        assert_eq!(values["error.type"], json!("Outer"));
+        assert_eq!(
+            values["error.source_chain"],
+            json!(["invalid digit found in string"])
+        );
+    }
+
+    #[test]
+    fn span_statuses_are_forgotten_once_removed() {
+        use std::str::FromStr;
+
+        let statuses = SpanStatuses::default();
+        let span_id = SpanId::from_str("1-1").unwrap();
+        let other = SpanId::from_str("2-1").unwrap();
+        assert_eq!(statuses.remove(&span_id), None);
+
+        statuses.insert(&span_id, SpanStatus::Error);
+        statuses.insert(&span_id, SpanStatus::Ok);
+        assert_eq!(statuses.remove(&other), None);
+        assert_eq!(statuses.remove(&span_id), Some(SpanStatus::Ok));
+        assert_eq!(statuses.remove(&span_id), None);
+        assert!(statuses.0.is_empty());
+    }
+}
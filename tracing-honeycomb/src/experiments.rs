@@ -0,0 +1,82 @@
+use serde_json::{json, Value};
+use std::collections::BTreeMap;
+
+use crate::bounded_map::BoundedMap;
+use crate::TraceId;
+
+/// Prefix of the fields holding experiment and feature flag assignments, e.g.
+/// `experiment.new_checkout = "variant_b"`.
+pub(crate) const EXPERIMENT_FIELD_PREFIX: &str = "experiment.";
+
+// bounds the number of traces with assignments whose local root span has not yet closed
+const MAX_TRACE_EXPERIMENTS: usize = 10_000;
+
+/// Experiment and feature flag assignments of in-flight traces, stamped on all of their spans
+/// and events until the local root span of the trace closes.
+#[derive(Debug)]
+pub(crate) struct TraceExperiments(BoundedMap<TraceId, BTreeMap<String, String>>);
+
+impl Default for TraceExperiments {
+    fn default() -> Self {
+        TraceExperiments(BoundedMap::new(MAX_TRACE_EXPERIMENTS))
+    }
+}
+
+impl TraceExperiments {
+    pub(crate) fn insert(&self, trace_id: &TraceId, flag: String, variant: String) {
+        self.0.update(trace_id, |assignments| {
+            assignments.insert(flag, variant);
+        });
+    }
+
+    pub(crate) fn get(&self, trace_id: &TraceId) -> BTreeMap<String, String> {
+        self.0.get(trace_id, Clone::clone).unwrap_or_default()
+    }
+
+    /// Fields to stamp on spans and events belonging to the given trace.
+    pub(crate) fn fields(&self, trace_id: &TraceId) -> Vec<(String, Value)> {
+        self.0
+            .get(trace_id, |assignments| {
+                assignments
+                    .iter()
+                    .map(|(flag, variant)| {
+                        (
+                            format!("{}{}", EXPERIMENT_FIELD_PREFIX, flag),
+                            json!(variant),
+                        )
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn remove(&self, trace_id: &TraceId) {
+        self.0.remove(trace_id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn later_assignments_replace_earlier_ones() {
+        let experiments = TraceExperiments::default();
+        let trace_id = TraceId::from("abc");
+        experiments.insert(&trace_id, "checkout".to_string(), "a".to_string());
+        experiments.insert(&trace_id, "checkout".to_string(), "b".to_string());
+        experiments.insert(&trace_id, "search".to_string(), "control".to_string());
+
+        assert_eq!(
+            experiments.fields(&trace_id),
+            vec![
+                ("experiment.checkout".to_string(), json!("b")),
+                ("experiment.search".to_string(), json!("control")),
+            ]
+        );
+        assert!(experiments.fields(&TraceId::from("other")).is_empty());
+
+        experiments.remove(&trace_id);
+        assert!(experiments.get(&trace_id).is_empty());
+    }
+}
@@ -0,0 +1,76 @@
+use tracing::Level;
+
+/// Decides which spans and events are exported to honeycomb.io, independently of the filters
+/// applied by the subscriber, see `Builder::min_export_level`, `Builder::exclude_target` and
+/// `Builder::include_target_prefix`.
+///
+/// Spans and events that are not exported are still tracked, e.g. errors recorded on them
+/// still mark their trace as errored.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ExportFilter {
+    pub(crate) min_level: Option<Level>,
+    pub(crate) excluded_targets: Vec<String>,
+    pub(crate) included_prefixes: Vec<String>,
+}
+
+impl ExportFilter {
+    pub(crate) fn exports(&self, level: &Level, target: &str) -> bool {
+        // more verbose levels compare greater, e.g. `Level::TRACE > Level::DEBUG`
+        if self.min_level.is_some_and(|min_level| *level > min_level) {
+            return false;
+        }
+        if self
+            .excluded_targets
+            .iter()
+            .any(|excluded| is_within(target, excluded))
+        {
+            return false;
+        }
+        self.included_prefixes.is_empty()
+            || self
+                .included_prefixes
+                .iter()
+                .any(|prefix| target.starts_with(prefix.as_str()))
+    }
+}
+
+// whether `target` is `module` or one of its submodules
+pub(crate) fn is_within(target: &str, module: &str) -> bool {
+    target
+        .strip_prefix(module)
+        .is_some_and(|rest| rest.is_empty() || rest.starts_with("::"))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn filters_levels_more_verbose_than_the_minimum() {
+        let filter = ExportFilter {
+            min_level: Some(Level::INFO),
+            ..Default::default()
+        };
+        assert!(filter.exports(&Level::ERROR, "app"));
+        assert!(filter.exports(&Level::INFO, "app"));
+        assert!(!filter.exports(&Level::DEBUG, "app"));
+        assert!(!filter.exports(&Level::TRACE, "app"));
+
+        assert!(ExportFilter::default().exports(&Level::TRACE, "app"));
+    }
+
+    #[test]
+    fn excluded_targets_take_precedence_over_included_prefixes() {
+        let filter = ExportFilter {
+            excluded_targets: vec!["hyper".to_string(), "myapp::noisy".to_string()],
+            included_prefixes: vec!["myapp::".to_string(), "hyper".to_string()],
+            ..Default::default()
+        };
+        assert!(filter.exports(&Level::INFO, "myapp::db"));
+        assert!(filter.exports(&Level::INFO, "myapp::noisy_neighbor"));
+        assert!(!filter.exports(&Level::INFO, "myapp::noisy::inner"));
+        assert!(!filter.exports(&Level::INFO, "hyper::client"));
+        assert!(filter.exports(&Level::INFO, "hyperlocal"));
+        assert!(!filter.exports(&Level::INFO, "tokio"));
+    }
+}
@@ -0,0 +1,161 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+/// Scripts failures of the batches sent by a layer built with `Builder::inject_faults`, to
+/// test how the exporter and the application handle an unreliable honeycomb.io (error
+/// callbacks, circuit breaking, dead-letter spooling) without depending on real network
+/// failures.
+///
+/// Faults are consumed in the order they were scripted, one per batch. Batches sent once the
+/// script is exhausted are sent to honeycomb.io as usual, e.g. to a `MockHoneycomb` server.
+///
+/// Handles are cheap to clone, clones share the same script.
+#[derive(Clone, Debug, Default)]
+pub struct FaultInjector(Arc<Mutex<Script>>);
+
+/// A failure injected into the sending of a batch, see `FaultInjector`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Fault {
+    /// The batch is not sent, as if honeycomb.io was unreachable.
+    Unreachable,
+    /// The batch is not sent, as if honeycomb.io responded with the given HTTP status.
+    /// Success statuses (2xx) accept the batch, e.g. to test recovery without a server.
+    Status(u16),
+    /// The batch is sent after the given delay, as if honeycomb.io was slow to respond. Delays
+    /// exceeding the deadline of `Builder::send_now` time out instead.
+    Delay(Duration),
+}
+
+#[derive(Debug, Default)]
+struct Script {
+    faults: VecDeque<Fault>,
+    injected: usize,
+}
+
+impl FaultInjector {
+    /// Create an injector without scripted faults.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn lock(&self) -> impl std::ops::DerefMut<Target = Script> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let script = self.0.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let script = self.0.lock();
+
+        script
+    }
+
+    /// Inject `fault` into the next `batches` batches, after the faults already scripted.
+    pub fn inject(&self, batches: usize, fault: Fault) -> &Self {
+        self.lock()
+            .faults
+            .extend(std::iter::repeat_n(fault, batches));
+        self
+    }
+
+    /// Fail the next `batches` batches as if honeycomb.io was unreachable.
+    pub fn fail_next(&self, batches: usize) -> &Self {
+        self.inject(batches, Fault::Unreachable)
+    }
+
+    /// Respond to the next `batches` batches with `status` instead of sending them, e.g. 429
+    /// to simulate rate limiting or 503 an outage.
+    pub fn respond_next(&self, batches: usize, status: u16) -> &Self {
+        self.inject(batches, Fault::Status(status))
+    }
+
+    /// Delay the next `batches` batches by `delay`.
+    pub fn delay_next(&self, batches: usize, delay: Duration) -> &Self {
+        self.inject(batches, Fault::Delay(delay))
+    }
+
+    /// Forget the faults not injected yet.
+    pub fn clear(&self) {
+        self.lock().faults.clear();
+    }
+
+    /// Number of scripted faults not injected yet.
+    pub fn remaining(&self) -> usize {
+        self.lock().faults.len()
+    }
+
+    /// Number of faults injected so far.
+    pub fn injected(&self) -> usize {
+        self.lock().injected
+    }
+
+    /// Take the fault to inject into the next batch, if any.
+    pub(crate) fn next(&self) -> Option<Fault> {
+        let mut script = self.lock();
+        let fault = script.faults.pop_front()?;
+        script.injected += 1;
+        Some(fault)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{register_dist_tracing_root, HoneycombTelemetry, TelemetryError, TraceId};
+    use tracing_subscriber::layer::Layer;
+
+    #[test]
+    fn injects_scripted_faults() {
+        let faults = FaultInjector::new();
+        faults
+            .fail_next(1)
+            .respond_next(1, 429)
+            .delay_next(1, Duration::from_secs(60))
+            .respond_next(1, 202);
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let layer = {
+            let errors = errors.clone();
+            HoneycombTelemetry::builder()
+                .api_key("key")
+                .send_now(Duration::from_millis(50))
+                .inject_faults(&faults)
+                .on_error(move |err: TelemetryError| lock_errors(&errors).push(err))
+                .build()
+        };
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            for _ in 0..4 {
+                let _span = tracing::info_span!("request").entered();
+                register_dist_tracing_root(TraceId::new(), None).unwrap();
+            }
+        });
+
+        assert_eq!(faults.injected(), 4);
+        assert_eq!(faults.remaining(), 0);
+        let statuses: Vec<_> = lock_errors(&errors)
+            .iter()
+            .map(|err| match err {
+                TelemetryError::Send { status, .. } => *status,
+                err => panic!("unexpected error {}", err),
+            })
+            .collect();
+        // the delayed batch timed out
+        assert_eq!(statuses, vec![None, Some(429), None]);
+    }
+
+    fn lock_errors(
+        errors: &Mutex<Vec<TelemetryError>>,
+    ) -> impl std::ops::DerefMut<Target = Vec<TelemetryError>> + '_ {
+        #[cfg(not(feature = "use_parking_lot"))]
+        let errors = errors.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let errors = errors.lock();
+
+        errors
+    }
+}
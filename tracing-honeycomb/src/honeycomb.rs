@@ -1,84 +1,995 @@
 use eaze_tracing_distributed as tracing_distributed;
 
-use crate::visitor::{event_to_values, span_to_values, HoneycombVisitor};
-use libhoney::FieldHolder;
-use std::collections::HashMap;
-use tracing_distributed::{Event, Span, Telemetry};
+use crate::api_key::ApiKeyHandle;
+use crate::blocking::BlockingTransmission;
+use crate::builder::Builder;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::clamp::clamp_to_parent;
+use crate::connection::ConnectionStatus;
+use crate::data_loss::DataLoss;
+use crate::dead_letter::DeadLetterSpool;
+use crate::diagnostics::INTERNAL_TARGET;
+#[cfg(feature = "tracing-error")]
+use crate::errors::with_span_trace;
+use crate::errors::SpanStatuses;
+use crate::experiments::TraceExperiments;
+use crate::export_filter::ExportFilter;
+use crate::links::SpanLink;
+use crate::log_bridge::log_record_target;
+use crate::markers::MarkersClient;
+use crate::metrics::{Metrics, MetricsHandle};
+#[cfg(feature = "otlp")]
+use crate::otlp::OtlpTransmission;
+use crate::pool::BufferPool;
+use crate::rate_limiter::RateLimiter;
+use crate::reload::ReloadHandle;
+use crate::rollup::Rollup;
+use crate::routing::DatasetRouter;
+use crate::sampling::{ErroredTraces, PropagatedDecisions, SampleRateHandle, SamplingDecision};
+use crate::sharding::DatasetShards;
+use crate::stats::TelemetryStats;
+use crate::telemetry_error::{ErrorHandler, TelemetryError};
+#[cfg(feature = "test-support")]
+use crate::test_support::RecordingTransmission;
+#[cfg(feature = "tokio-transmission")]
+use crate::tokio_transmission::{self, TokioTransmission};
+use crate::trace_fields::TraceFields;
+use crate::trace_id::BoxedTraceIdGenerator;
+use crate::trace_timeout::TraceTimeouts;
+use crate::transmission::SharedTransmission;
+use crate::validation::normalize_api_host;
+use crate::visitor::{
+    event_to_values, link_to_values, span_start_to_values, span_to_values, transition_to_values,
+    FieldOptions, HoneycombValues, HoneycombVisitor,
+};
+#[cfg(feature = "zipkin")]
+use crate::zipkin::ZipkinMirror;
+use chrono::{DateTime, Utc};
+use libhoney::json;
+use rand::Rng;
+use std::any::TypeId;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tracing_distributed::{Event, FollowsFrom, Span, SpanStart, Telemetry, Transition};
 
-#[cfg(feature = "use_parking_lot")]
-use parking_lot::Mutex;
-#[cfg(not(feature = "use_parking_lot"))]
-use std::sync::Mutex;
-
-use crate::{SpanId, TraceId};
+use crate::{SpanId, SpanStatus, TraceId};
 
 /// Telemetry capability that publishes events and spans to Honeycomb.io.
-#[derive(Debug)]
-pub struct HoneycombTelemetry {
-    honeycomb_client: Mutex<libhoney::Client<libhoney::transmission::Transmission>>,
-    sample_rate: Option<u32>,
+///
+/// Generic over the visitor used to record fields, `HoneycombVisitor` by default. See
+/// `Builder::build_with_visitor` to use a custom visitor.
+pub struct HoneycombTelemetry<V = HoneycombVisitor> {
+    reporter: Reporter,
+    mk_visitor: Box<dyn Fn() -> V + Send + Sync>,
 }
 
-impl HoneycombTelemetry {
-    pub(crate) fn new(cfg: libhoney::Config, sample_rate: Option<u32>) -> Self {
-        let honeycomb_client = libhoney::init(cfg);
+impl<V> fmt::Debug for HoneycombTelemetry<V> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HoneycombTelemetry")
+            .field("reporter", &self.reporter)
+            .finish()
+    }
+}
 
-        // publishing requires &mut so just mutex-wrap it
-        // FIXME: may not be performant, investigate options (eg mpsc)
-        let honeycomb_client = Mutex::new(honeycomb_client);
+impl HoneycombTelemetry {
+    /// Create a builder for a `TelemetryLayer` publishing telemetry to honeycomb.io, starting
+    /// from libhoney's default config and the `unknown_service` service name, e.g.
+    ///
+    /// ```ignore
+    /// let layer = HoneycombTelemetry::builder()
+    ///     .service_name("my-service")
+    ///     .api_key("...")
+    ///     .dataset("my-dataset")
+    ///     .sample_rate(10)
+    ///     .build_layer();
+    /// ```
+    pub fn builder() -> Builder {
+        let honeycomb_config = libhoney::Config {
+            options: libhoney::client::Options::default(),
+            transmission_options: libhoney::transmission::Options::default(),
+        };
+        Builder::new("unknown_service", honeycomb_config)
+    }
+}
 
+impl<V> HoneycombTelemetry<V> {
+    pub(crate) fn new<F>(builder: Builder, mk_visitor: F) -> Self
+    where
+        F: Fn() -> V + Send + Sync + 'static,
+    {
         HoneycombTelemetry {
-            honeycomb_client,
-            sample_rate,
+            reporter: Reporter::new(builder),
+            mk_visitor: Box::new(mk_visitor),
+        }
+    }
+
+    /// Get a handle that can be used to adjust the trace-level sample rate at runtime.
+    pub fn sample_rate_handle(&self) -> SampleRateHandle {
+        self.reporter.sample_rate.clone()
+    }
+
+    /// Get a handle that can be used to change the enabled flag, dataset, static fields and
+    /// sample rate at runtime.
+    pub fn reload_handle(&self) -> ReloadHandle {
+        self.reporter.reload.clone()
+    }
+
+    /// Enable or disable publishing telemetry at runtime, e.g. to shed telemetry load during an
+    /// incident, without tearing down the subscriber.
+    ///
+    /// While disabled, the layer is a no-op: fields are not recorded and no spans or events are
+    /// reported, but trace ids registered with `register_dist_tracing_root` are still tracked.
+    /// Spans opened while disabled are not reported once publishing is enabled again, so traces
+    /// in flight when the switch is flipped are incomplete.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.reporter.set_enabled(enabled);
+    }
+
+    /// Whether publishing telemetry is currently enabled, see `set_enabled`.
+    pub fn is_enabled(&self) -> bool {
+        self.reporter.enabled()
+    }
+
+    /// Get a handle that can be used to replace the API key at runtime.
+    pub fn api_key_handle(&self) -> ApiKeyHandle {
+        self.reporter.api_key.clone()
+    }
+
+    /// Get the counters maintained by the reporting path of this layer, e.g. the number of
+    /// events sent and dropped.
+    pub fn stats(&self) -> TelemetryStats {
+        self.reporter.stats()
+    }
+
+    /// Get the instance id used to salt the span ids generated by this layer, see
+    /// `Builder::instance_id`.
+    pub fn instance_id(&self) -> u64 {
+        self.reporter.instance_id
+    }
+
+    /// Check that honeycomb.io is reachable and accepts the current API key, so services can
+    /// fail fast when telemetry is misconfigured. Sends a request to honeycomb.io's auth
+    /// endpoint, blocking the current thread for at most ten seconds: must not be used from
+    /// within an async runtime. See also `Builder::check_connection`.
+    pub fn check_connection(&self) -> ConnectionStatus {
+        let api_key = self.reporter.api_key.read().clone();
+        crate::connection::check_connection(&self.reporter.options.api_host, &api_key)
+    }
+
+    /// Get a client for honeycomb.io's Markers API, e.g. to mark deploys, using the API host,
+    /// current API key and current dataset of this layer. See also `Builder::markers_client`.
+    pub fn markers_client(&self) -> MarkersClient {
+        MarkersClient::new(
+            self.reporter.options.api_host.clone(),
+            self.reporter.api_key.clone(),
+            self.reporter.reload.load().dataset.clone(),
+        )
+    }
+}
+
+#[derive(Debug)]
+enum Transport {
+    /// queued and sent by libhoney's background threads
+    Queued(SharedTransmission),
+    /// sent by the reporting thread, see `Builder::send_now`
+    Blocking(BlockingTransmission),
+    /// exported via OTLP, see `Builder::otlp_endpoint`
+    #[cfg(feature = "otlp")]
+    Otlp(OtlpTransmission),
+    /// queued and sent by a task on a tokio runtime, see `Builder::tokio_transmission`
+    #[cfg(feature = "tokio-transmission")]
+    Tokio(TokioTransmission),
+    /// recorded instead of published, see `Builder::record_to`
+    #[cfg(feature = "test-support")]
+    Recording(RecordingTransmission),
+}
+
+impl Transport {
+    fn stats(&self) -> TelemetryStats {
+        match self {
+            Transport::Queued(transmission) => transmission.stats(),
+            Transport::Blocking(transmission) => transmission.stats(),
+            #[cfg(feature = "otlp")]
+            Transport::Otlp(transmission) => transmission.stats(),
+            #[cfg(feature = "tokio-transmission")]
+            Transport::Tokio(transmission) => transmission.stats(),
+            #[cfg(feature = "test-support")]
+            Transport::Recording(transmission) => transmission.stats(),
+        }
+    }
+
+    fn record_rate_limited(&self, events: u64) {
+        match self {
+            Transport::Queued(transmission) => transmission.record_rate_limited(events),
+            Transport::Blocking(transmission) => transmission.record_rate_limited(events),
+            #[cfg(feature = "otlp")]
+            Transport::Otlp(transmission) => transmission.record_rate_limited(events),
+            #[cfg(feature = "tokio-transmission")]
+            Transport::Tokio(transmission) => transmission.record_rate_limited(events),
+            #[cfg(feature = "test-support")]
+            Transport::Recording(transmission) => transmission.record_rate_limited(events),
+        }
+    }
+
+    fn record_circuit_open(&self, events: u64) {
+        match self {
+            Transport::Queued(transmission) => transmission.record_circuit_open(events),
+            Transport::Blocking(transmission) => transmission.record_circuit_open(events),
+            #[cfg(feature = "otlp")]
+            Transport::Otlp(transmission) => transmission.record_circuit_open(events),
+            #[cfg(feature = "tokio-transmission")]
+            Transport::Tokio(transmission) => transmission.record_circuit_open(events),
+            #[cfg(feature = "test-support")]
+            Transport::Recording(transmission) => transmission.record_circuit_open(events),
         }
     }
+}
 
-    fn report_data(&self, data: HashMap<String, libhoney::Value>) {
-        // succeed or die. failure is unrecoverable (mutex poisoned)
-        #[cfg(not(feature = "use_parking_lot"))]
-        let mut client = self.honeycomb_client.lock().unwrap();
-        #[cfg(feature = "use_parking_lot")]
-        let mut client = self.honeycomb_client.lock();
+/// Publishes events and spans to Honeycomb.io, independently of the visitor used to record
+/// their fields.
+#[derive(Debug)]
+pub(crate) struct Reporter {
+    service_name: &'static str,
+    transport: Transport,
+    #[cfg(feature = "zipkin")]
+    zipkin: Option<ZipkinMirror>,
+    options: libhoney::client::Options,
+    api_key: ApiKeyHandle,
+    dataset_shards: Option<DatasetShards>,
+    dataset_router: DatasetRouter,
+    reload: ReloadHandle,
+    sample_rate: SampleRateHandle,
+    rate_limiter: Option<RateLimiter>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    rollup: Option<Rollup>,
+    data_loss: Option<DataLoss>,
+    metrics: Option<Metrics>,
+    errored_traces: Option<ErroredTraces>,
+    propagated_decisions: PropagatedDecisions,
+    experiments: TraceExperiments,
+    trace_fields: TraceFields,
+    trace_id_generator: BoxedTraceIdGenerator,
+    instance_id: u64,
+    field_options: FieldOptions,
+    export_filter: ExportFilter,
+    on_error: ErrorHandler,
+    // index of the layer registered with the queued transmission, see
+    // `SharedTransmission::register_layer`
+    registered: Option<usize>,
+    dead_letter: bool,
+    span_statuses: SpanStatuses,
+    clamp_to_parent: bool,
+    span_transition_events: bool,
+    span_start_events: bool,
+    orphan_events: bool,
+    trace_timeouts: Option<TraceTimeouts>,
+    inherited_fields: HashSet<String>,
+    // field maps reused across reports, see `Builder::buffer_pool`
+    buffers: Arc<BufferPool>,
+}
 
-        let mut ev = client.new_event();
-        ev.add(data);
-        let res = ev.send(&mut client);
+impl Reporter {
+    fn new(builder: Builder) -> Self {
+        let libhoney::Config {
+            mut options,
+            transmission_options,
+        } = builder.honeycomb_config;
+        // checked by `Builder::try_build_with_visitor`
+        options.api_host = normalize_api_host(&options.api_host).unwrap_or(options.api_host);
+        let api_key = &builder.api_key;
+        let internal_log_mode = builder.internal_log_mode;
+        let on_error = builder
+            .on_error
+            .unwrap_or_else(|| ErrorHandler::logging(internal_log_mode));
+        let spool = builder.dead_letter.map(|(dir, max_bytes)| {
+            Arc::new(DeadLetterSpool::new(
+                dir,
+                max_bytes,
+                api_key.clone(),
+                on_error.clone(),
+            ))
+        });
+        let circuit_breaker = builder.circuit_breaker.map(|(max_failures, cool_down)| {
+            Arc::new(CircuitBreaker::new(
+                max_failures,
+                cool_down,
+                internal_log_mode,
+            ))
+        });
+        let dead_letter = spool.is_some();
+        let mut registered = None;
+        let mut buffers = Arc::new(BufferPool::new(builder.buffer_pool));
+        let memory_budget = builder.memory_budget;
+        #[cfg(feature = "zipkin")]
+        let zipkin = {
+            let trace_fields = builder.field_options.trace_fields.clone();
+            builder
+                .zipkin_endpoint
+                .map(|endpoint| ZipkinMirror::new(endpoint, trace_fields, internal_log_mode))
+        };
+        #[cfg(feature = "otlp")]
+        let trace_fields = builder.field_options.trace_fields.clone();
+        #[cfg(feature = "otlp")]
+        let exporter = builder.otlp_endpoint.map(|endpoint| {
+            Transport::Otlp(OtlpTransmission::new(
+                endpoint,
+                &transmission_options,
+                trace_fields,
+                on_error.clone(),
+                internal_log_mode,
+                circuit_breaker.clone(),
+            ))
+        });
+        #[cfg(not(feature = "otlp"))]
+        let exporter = None;
+        #[cfg(feature = "test-support")]
+        let exporter = match builder.recorder {
+            Some(recorder) => Some(Transport::Recording(RecordingTransmission::new(
+                recorder,
+                builder.field_options.trace_fields.clone(),
+            ))),
+            None => exporter,
+        };
+        #[cfg(feature = "test-support")]
+        let faults = builder.faults;
+        let blocking_transmission = |deadline| {
+            let transmission = BlockingTransmission::new(
+                deadline,
+                transmission_options.max_batch_size,
+                on_error.clone(),
+                internal_log_mode,
+            );
+            let transmission = match &spool {
+                Some(spool) => transmission.with_spool(spool.clone()),
+                None => transmission,
+            };
+            #[cfg(feature = "test-support")]
+            let transmission = match &faults {
+                Some(faults) => transmission.with_faults(faults.clone()),
+                None => transmission,
+            };
+            let transmission = transmission.with_buffers(buffers.clone());
+            let transmission = match memory_budget {
+                Some(bytes) => transmission.with_memory_budget(bytes),
+                None => transmission,
+            };
+            match &circuit_breaker {
+                Some(circuit_breaker) => transmission.with_circuit_breaker(circuit_breaker.clone()),
+                None => transmission,
+            }
+        };
+        #[cfg(feature = "tokio-transmission")]
+        let exporter = match (exporter, builder.tokio_runtime) {
+            (None, Some(runtime)) if builder.send_now.is_none() => {
+                Some(Transport::Tokio(TokioTransmission::new(
+                    runtime,
+                    blocking_transmission(tokio_transmission::SEND_TIMEOUT),
+                    &transmission_options,
+                    on_error.clone(),
+                )))
+            }
+            (exporter, _) => exporter,
+        };
+        let transport = match (exporter, builder.send_now) {
+            (Some(exporter), _) => exporter,
+            (None, Some(deadline)) => Transport::Blocking(blocking_transmission(deadline)),
+            (None, None) => {
+                let shards = builder.transmission_shards;
+                let transmission = builder
+                    .transmission
+                    .unwrap_or_else(|| SharedTransmission::sharded(transmission_options, shards));
+                registered = Some(transmission.register_layer(
+                    on_error.clone(),
+                    spool,
+                    circuit_breaker.clone(),
+                    internal_log_mode,
+                ));
+                // maps are recycled by the transmission, shared with the layers using it
+                buffers = transmission.buffers().clone();
+                buffers.set_size(builder.buffer_pool);
+                if let Some(bytes) = memory_budget {
+                    transmission.set_memory_budget(bytes);
+                }
+                Transport::Queued(transmission)
+            }
+        };
+
+        let mut dataset_router = builder.dataset_router;
+        let metrics_handle = builder.metrics;
+        let metrics = builder.metrics_report.map(|(interval, dataset)| {
+            dataset_router.route_field_first("meta.metrics".to_string(), json!(true), dataset);
+            Metrics::new(interval, metrics_handle)
+        });
+        let data_loss = builder
+            .data_loss_interval
+            .map(|interval| DataLoss::new(interval, &transport.stats()));
+
+        Reporter {
+            service_name: builder.service_name,
+            transport,
+            #[cfg(feature = "zipkin")]
+            zipkin,
+            dataset_shards: builder
+                .dataset_shards
+                .map(|shards| DatasetShards::new(&options, shards)),
+            options,
+            dataset_router,
+            api_key: builder.api_key,
+            reload: builder.reload,
+            sample_rate: builder.sample_rate,
+            rate_limiter: builder.rate_limit.map(RateLimiter::new),
+            circuit_breaker,
+            rollup: builder.rollup_interval.map(Rollup::new),
+            data_loss,
+            metrics,
+            errored_traces: if builder.keep_errored_traces {
+                Some(ErroredTraces::default())
+            } else {
+                None
+            },
+            propagated_decisions: PropagatedDecisions::default(),
+            experiments: TraceExperiments::default(),
+            trace_fields: TraceFields::default(),
+            trace_id_generator: builder.trace_id_generator,
+            instance_id: builder.instance_id,
+            field_options: builder.field_options,
+            export_filter: builder.export_filter,
+            on_error,
+            registered,
+            dead_letter,
+            span_statuses: SpanStatuses::default(),
+            clamp_to_parent: builder.clamp_to_parent,
+            span_transition_events: builder.span_transition_events,
+            span_start_events: builder.span_start_events,
+            orphan_events: builder.orphan_events,
+            trace_timeouts: builder.max_trace_duration.map(TraceTimeouts::new),
+            inherited_fields: builder.inherited_fields,
+            buffers,
+        }
+    }
+
+    fn report_data(&self, mut data: HashMap<String, libhoney::Value>, trace_id: Option<&TraceId>) {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            match rate_limiter.try_acquire() {
+                None => {
+                    self.transport.record_rate_limited(1);
+                    self.buffers.recycle(data);
+                    return;
+                }
+                Some(0) => {}
+                Some(dropped) => {
+                    data.insert("meta.dropped_by_rate_limit".to_string(), json!(dropped));
+                }
+            }
+        }
+
+        // libhoney-level sampling, see `new_honeycomb_telemetry_layer_with_trace_sampling`
+        let sample_rate = self.options.sample_rate;
+        if sample_rate > 1 && rand::thread_rng().gen_range(0, sample_rate) != 0 {
+            self.buffers.recycle(data);
+            return;
+        }
+
+        let settings = self.reload.load();
+        #[cfg(feature = "zipkin")]
+        if let Some(zipkin) = &self.zipkin {
+            zipkin.send(&data, &settings.static_fields);
+            // developing without a honeycomb.io account, see `Builder::mirror_to_zipkin`
+            if self.api_key.read().is_empty() {
+                self.buffers.recycle(data);
+                return;
+            }
+        }
+
+        if let Some(circuit_breaker) = &self.circuit_breaker {
+            if !circuit_breaker.allow() {
+                self.transport.record_circuit_open(1);
+                self.buffers.recycle(data);
+                return;
+            }
+        }
+
+        let routed = self.dataset_router.route(&data);
+        let dataset = routed.as_deref().unwrap_or(&settings.dataset);
+        let overridden;
+        let options = {
+            let api_key = self.api_key.read();
+            if dataset == self.options.dataset && *api_key == self.options.api_key {
+                match &self.dataset_shards {
+                    Some(dataset_shards) => dataset_shards.options(trace_id),
+                    None => &self.options,
+                }
+            } else {
+                // routed or reloaded dataset, or rotated api key
+                overridden = libhoney::client::Options {
+                    dataset: match &self.dataset_shards {
+                        Some(dataset_shards) => dataset_shards.shard_dataset(dataset, trace_id),
+                        None => dataset.to_string(),
+                    },
+                    api_key: api_key.clone(),
+                    ..self.options.clone()
+                };
+                &overridden
+            }
+        };
+        // recorded fields take precedence over static fields
+        for (name, value) in &settings.static_fields {
+            if !data.contains_key(name) {
+                data.insert(name.clone(), value.clone());
+            }
+        }
+        let transmission = match &self.transport {
+            Transport::Queued(transmission) => transmission,
+            Transport::Blocking(transmission) => {
+                transmission.send(options, data);
+                return;
+            }
+            #[cfg(feature = "otlp")]
+            Transport::Otlp(transmission) => {
+                transmission.send(options, data);
+                return;
+            }
+            #[cfg(feature = "tokio-transmission")]
+            Transport::Tokio(transmission) => {
+                transmission.send(options, data);
+                return;
+            }
+            #[cfg(feature = "test-support")]
+            Transport::Recording(transmission) => {
+                transmission.send(data);
+                return;
+            }
+        };
+
+        let mut ev = libhoney::Event::new(options);
+        // moved rather than copied into the event, to be recycled once it is sent
+        *ev.get_fields_mut() = data;
+        if let Some(layer) = self.registered {
+            // returned with the event's response, to report or spool the event if it fails to
+            // send
+            let mut metadata = json!({ "layer": layer, "dataset": options.dataset });
+            if self.dead_letter {
+                let time = Utc::now();
+                ev.set_timestamp(time);
+                metadata["record"] = DeadLetterSpool::record(options, time, &ev.fields());
+            }
+            ev.set_metadata(Some(metadata));
+        }
+        let res = transmission.send(ev, trace_id);
         if let Err(err) = res {
-            // unable to report telemetry (buffer full) so log msg to stderr
-            // TODO: figure out strategy for handling this (eg report data loss event)
-            eprintln!("error sending event to honeycomb, {:?}", err);
+            // counted as dropped, see `Builder::report_data_loss`
+            self.on_error.report(TelemetryError::Enqueue(err));
+        }
+    }
+
+    /// Record a sampling decision made upstream for the given trace. It takes precedence over
+    /// the local sampling decision until the trace's local root span closes.
+    pub(crate) fn record_sampling_decision(&self, trace_id: TraceId, decision: SamplingDecision) {
+        self.propagated_decisions.insert(trace_id, decision);
+    }
+
+    /// Set the status of an open span, published once it closes.
+    pub(crate) fn set_span_status(&self, span_id: &SpanId, status: SpanStatus) {
+        // spans closing while disabled are not reported, and would never clear their status
+        if self.enabled() {
+            self.span_statuses.insert(span_id, status);
         }
     }
 
+    /// Assign a variant of an experiment or feature flag to the given trace, stamped on its
+    /// spans and events until the trace's local root span closes.
+    pub(crate) fn record_experiment(&self, trace_id: &TraceId, flag: String, variant: String) {
+        self.experiments.insert(trace_id, flag, variant);
+    }
+
+    /// Get the experiment and feature flag assignments of the given trace, suitable for
+    /// propagating downstream.
+    pub(crate) fn experiments(&self, trace_id: &TraceId) -> BTreeMap<String, String> {
+        self.experiments.get(trace_id)
+    }
+
+    /// Add a field to the given trace, stamped on its spans and events until the trace's local
+    /// root span closes.
+    pub(crate) fn add_trace_field(&self, trace_id: &TraceId, key: String, value: libhoney::Value) {
+        self.trace_fields.insert(trace_id, key, value);
+    }
+
+    pub(crate) fn set_enabled(&self, enabled: bool) {
+        self.reload.set_enabled(enabled);
+    }
+
+    pub(crate) fn stats(&self) -> TelemetryStats {
+        self.transport.stats()
+    }
+
+    /// Send the events reported so far, waiting at most `timeout` for honeycomb.io to respond.
+    pub(crate) fn flush(&self, timeout: Duration) {
+        match &self.transport {
+            Transport::Queued(transmission) => transmission.flush(timeout),
+            // bounded by the deadline passed to `Builder::send_now`
+            Transport::Blocking(transmission) => transmission.flush(),
+            #[cfg(feature = "otlp")]
+            Transport::Otlp(transmission) => transmission.flush(timeout),
+            #[cfg(feature = "tokio-transmission")]
+            Transport::Tokio(transmission) => transmission.flush(timeout),
+            // recorded as they are reported
+            #[cfg(feature = "test-support")]
+            Transport::Recording(_) => {}
+        }
+        #[cfg(feature = "zipkin")]
+        if let Some(zipkin) = &self.zipkin {
+            zipkin.flush(timeout);
+        }
+    }
+
+    pub(crate) fn instance_id(&self) -> u64 {
+        self.instance_id
+    }
+
+    pub(crate) fn metrics_handle(&self) -> Option<MetricsHandle> {
+        self.metrics.as_ref().map(Metrics::handle)
+    }
+
+    /// Generate the trace id of a new trace.
+    pub(crate) fn new_trace_id(&self) -> TraceId {
+        self.trace_id_generator.generate()
+    }
+
+    /// Get the trace id to propagate downstream for the given trace.
+    pub(crate) fn propagated_trace_id(&self, trace_id: TraceId) -> TraceId {
+        self.field_options.trace_id_format.apply(&trace_id)
+    }
+
+    /// Get the sampling decision for the given trace, suitable for propagating downstream.
+    pub(crate) fn sampling_decision(&self, trace_id: &TraceId) -> SamplingDecision {
+        self.propagated_decisions.get(trace_id).unwrap_or_else(|| {
+            let sample_rate = self.sample_rate.get();
+            SamplingDecision {
+                sampled: crate::deterministic_sampler::sample(sample_rate, trace_id),
+                sample_rate,
+            }
+        })
+    }
+
     fn should_report(&self, trace_id: &TraceId) -> bool {
-        if let Some(sample_rate) = self.sample_rate {
-            crate::deterministic_sampler::sample(sample_rate, trace_id)
-        } else {
-            false
+        self.sampling_decision(trace_id).sampled
+            || self
+                .errored_traces
+                .as_ref()
+                .is_some_and(|errored_traces| errored_traces.contains(trace_id))
+    }
+
+    /// Finalize traces that have been in flight for longer than the maximum trace duration,
+    /// dropping their state and, if they were kept, reporting an event marked with
+    /// `meta.timed_out = true` in their stead.
+    fn finalize_timed_out_traces(&self) {
+        let trace_timeouts = match &self.trace_timeouts {
+            Some(trace_timeouts) => trace_timeouts,
+            None => return,
+        };
+
+        for (trace_id, started_at) in trace_timeouts.take_expired() {
+            let should_report = self.should_report(&trace_id);
+
+            self.propagated_decisions.remove(&trace_id);
+            self.experiments.remove(&trace_id);
+            self.trace_fields.remove(&trace_id);
+            if let Some(errored_traces) = &self.errored_traces {
+                errored_traces.remove(&trace_id);
+            }
+            if let Some(rollup) = &self.rollup {
+                rollup.forget(&trace_id);
+            }
+
+            if should_report {
+                let elapsed = SystemTime::now()
+                    .duration_since(started_at)
+                    .unwrap_or_default();
+                let started_at: DateTime<Utc> = started_at.into();
+
+                let mut values = HashMap::new();
+                let reported_id = self.field_options.trace_id_format.apply(&trace_id);
+                values.insert(
+                    self.field_options.trace_fields.trace_id.clone(),
+                    json!(reported_id.to_string()),
+                );
+                values.insert("service_name".to_string(), json!(self.service_name));
+                values.insert("name".to_string(), json!("trace_timed_out"));
+                values.insert("Timestamp".to_string(), json!(started_at.to_rfc3339()));
+                values.insert(
+                    "duration_ms".to_string(),
+                    json!(elapsed.as_secs_f64() * 1000.0),
+                );
+                values.insert("meta.timed_out".to_string(), json!(true));
+                let data = self.field_options.key_mapping.apply(values);
+                self.report_data(data, Some(&trace_id));
+            }
+        }
+    }
+
+    pub(crate) fn enabled(&self) -> bool {
+        self.reload.is_enabled()
+    }
+
+    fn exports(&self, meta: &tracing::Metadata<'_>) -> bool {
+        self.export_filter.exports(meta.level(), meta.target())
+    }
+
+    fn report_due_rollups(&self) {
+        if let Some(rollup) = &self.rollup {
+            for data in rollup.take_due() {
+                self.report_data(data, None);
+            }
+        }
+        if let Some(data_loss) = &self.data_loss {
+            for data in data_loss.take_due(self.service_name, || self.stats()) {
+                self.report_data(data, None);
+            }
+        }
+        if let Some(metrics) = &self.metrics {
+            if let Some(data) = metrics.take_due(self.service_name) {
+                self.report_data(data, None);
+            }
         }
     }
 }
 
-impl Telemetry for HoneycombTelemetry {
-    type Visitor = HoneycombVisitor;
+impl Reporter {
+    fn report_span<V: HoneycombValues>(&self, mut span: Span<V, SpanId, TraceId>) {
+        // forgotten even if the span is not reported, e.g. once the layer has been disabled
+        let explicit_status = self.span_statuses.remove(&span.id);
+        if !self.enabled() {
+            return;
+        }
+
+        let mut meta = self.field_options.redaction.redact(
+            self.trace_fields
+                .fields(&span.trace_id, |key| span.values.get(key).is_some()),
+        );
+        meta.extend(self.experiments.fields(&span.trace_id));
+        let status = match explicit_status {
+            Some(status) => status,
+            None if span.values.get("error") == Some(&json!(true)) => SpanStatus::Error,
+            None => SpanStatus::Ok,
+        };
+        if status == SpanStatus::Error {
+            meta.push(("error".to_string(), json!(true)));
+        }
+        meta.push((SpanStatus::field_name().to_string(), json!(status.as_str())));
+
+        let should_report = self.should_report(&span.trace_id);
+        let is_local_root = span.is_local_root;
+
+        if is_local_root {
+            // no further spans are expected once the trace's local root has closed
+            self.propagated_decisions.remove(&span.trace_id);
+            self.experiments.remove(&span.trace_id);
+            self.trace_fields.remove(&span.trace_id);
+            if let Some(errored_traces) = &self.errored_traces {
+                errored_traces.remove(&span.trace_id);
+            }
+            if let Some(trace_timeouts) = &self.trace_timeouts {
+                trace_timeouts.finish(&span.trace_id);
+            }
+        } else if let Some(trace_timeouts) = &self.trace_timeouts {
+            trace_timeouts.observe(&span.trace_id);
+        }
+
+        if should_report {
+            // spans that are not exported still go through the trace's bookkeeping above
+            if self.exports(span.meta) {
+                if self.clamp_to_parent {
+                    meta.extend(clamp_to_parent(&mut span));
+                }
+                let trace_id = span.trace_id.clone();
+                let data = span_to_values(span, &self.field_options, meta, self.buffers.take());
+                self.report_data(data, Some(&trace_id));
+            }
+        } else if let Some(rollup) = &self.rollup {
+            if span.is_local_root {
+                let duration = span
+                    .completed_at
+                    .duration_since(span.initialized_at)
+                    .unwrap_or_default();
+                rollup.record_root(
+                    span.meta.name(),
+                    span.service_name,
+                    &span.trace_id,
+                    duration,
+                );
+            }
+        }
+
+        self.finalize_timed_out_traces();
+        self.report_due_rollups();
+
+        if let Transport::Blocking(transmission) = &self.transport {
+            if is_local_root {
+                transmission.flush();
+            }
+        }
+    }
+
+    fn report_event<V: HoneycombValues>(&self, event: Event<V, SpanId, TraceId>) {
+        let log_target = log_record_target(event.meta, &event.values);
+        let target = log_target.unwrap_or_else(|| event.meta.target());
+        if !self.enabled() || target == INTERNAL_TARGET {
+            // publishing self-diagnostics could fail in turn, and report more of them
+            return;
+        }
+
+        if event.values.get("error") == Some(&json!(true)) {
+            // an error was recorded, mark the enclosing span as errored
+            if let Some(parent_id) = &event.parent_id {
+                self.span_statuses.insert(parent_id, SpanStatus::Error);
+            }
+        }
+
+        // alone in a trace of its own, which no local root span will finalize, see
+        // `Builder::orphan_events`
+        let is_orphan = event.parent_id.is_none();
+
+        if let Some(trace_timeouts) = &self.trace_timeouts {
+            if !is_orphan {
+                trace_timeouts.observe(&event.trace_id);
+            }
+        }
+
+        let is_error = *event.meta.level() == tracing::Level::ERROR;
+        let keep_error = match &self.errored_traces {
+            Some(errored_traces) if is_error => {
+                if !is_orphan {
+                    errored_traces.insert(&event.trace_id);
+                }
+                true
+            }
+            _ => false,
+        };
+
+        if keep_error || self.should_report(&event.trace_id) {
+            if self.export_filter.exports(event.meta.level(), target) {
+                let trace_id = event.trace_id.clone();
+                let mut meta = self.field_options.redaction.redact(
+                    self.trace_fields
+                        .fields(&trace_id, |key| event.values.get(key).is_some()),
+                );
+                meta.extend(self.experiments.fields(&trace_id));
+                if is_orphan {
+                    meta.push(("orphan".to_string(), json!(true)));
+                }
+                #[cfg(feature = "tracing-error")]
+                let meta = if is_error {
+                    with_span_trace(meta, &event.values)
+                } else {
+                    meta
+                };
+                let data = event_to_values(event, &self.field_options, meta, self.buffers.take());
+                self.report_data(data, Some(&trace_id));
+            }
+        } else if let Some(rollup) = &self.rollup {
+            if is_error && !is_orphan {
+                rollup.record_error(&event.trace_id);
+            }
+        }
+
+        self.finalize_timed_out_traces();
+        self.report_due_rollups();
+    }
+
+    fn report_transition(&self, transition: Transition<SpanId, TraceId>) {
+        if self.should_report(&transition.trace_id) && self.exports(transition.meta) {
+            let trace_id = transition.trace_id.clone();
+            let data = transition_to_values(transition, &self.field_options, self.buffers.take());
+            self.report_data(data, Some(&trace_id));
+        }
+    }
+
+    /// Report a link from a span to another span, see `add_span_link`.
+    pub(crate) fn report_link(&self, link: SpanLink) {
+        if self.enabled() && self.should_report(&link.trace_id) && self.exports(link.meta) {
+            let trace_id = link.trace_id.clone();
+            let data = link_to_values(
+                link,
+                self.service_name,
+                &self.field_options,
+                self.buffers.take(),
+            );
+            self.report_data(data, Some(&trace_id));
+        }
+    }
+
+    fn report_span_start(&self, start: SpanStart<SpanId, TraceId>) {
+        if self.should_report(&start.trace_id) && self.exports(start.meta) {
+            let trace_id = start.trace_id.clone();
+            let data = span_start_to_values(start, &self.field_options, self.buffers.take());
+            self.report_data(data, Some(&trace_id));
+        }
+    }
+}
+
+impl<V: HoneycombValues> Telemetry for HoneycombTelemetry<V> {
+    type Visitor = V;
     type TraceId = TraceId;
     type SpanId = SpanId;
 
     fn mk_visitor(&self) -> Self::Visitor {
-        Default::default()
+        (self.mk_visitor)()
     }
 
     fn report_span(&self, span: Span<Self::Visitor, Self::SpanId, Self::TraceId>) {
-        if self.should_report(&span.trace_id) {
-            let data = span_to_values(span);
-            self.report_data(data);
-        }
+        self.reporter.report_span(span);
     }
 
     fn report_event(&self, event: Event<Self::Visitor, Self::SpanId, Self::TraceId>) {
-        if self.should_report(&event.trace_id) {
-            let data = event_to_values(event);
-            self.report_data(data);
+        self.reporter.report_event(event);
+    }
+
+    fn is_enabled(&self) -> bool {
+        self.reporter.enabled()
+    }
+
+    fn samples_traces(&self) -> bool {
+        // sampled-out traces recording an error are kept, which is not known when spans are
+        // created
+        self.reporter.enabled() && self.reporter.errored_traces.is_none()
+    }
+
+    fn records_trace(&self, trace_id: &Self::TraceId) -> bool {
+        self.reporter.should_report(trace_id)
+    }
+
+    fn inherits_fields(&self) -> bool {
+        self.reporter.enabled() && !self.reporter.inherited_fields.is_empty()
+    }
+
+    fn inherit_fields(&self, ancestor: &Self::Visitor, visitor: &mut Self::Visitor) {
+        visitor.inherit(ancestor, &self.reporter.inherited_fields);
+    }
+
+    fn reports_transitions(&self) -> bool {
+        self.reporter.enabled() && self.reporter.span_transition_events
+    }
+
+    fn report_transition(&self, transition: Transition<Self::SpanId, Self::TraceId>) {
+        self.reporter.report_transition(transition);
+    }
+
+    fn reports_span_starts(&self) -> bool {
+        self.reporter.enabled() && self.reporter.span_start_events
+    }
+
+    fn report_span_start(&self, start: SpanStart<Self::SpanId, Self::TraceId>) {
+        self.reporter.report_span_start(start);
+    }
+
+    fn orphan_trace_id(&self) -> Option<Self::TraceId> {
+        if self.reporter.orphan_events {
+            Some(self.reporter.new_trace_id())
+        } else {
+            None
+        }
+    }
+
+    fn report_follows_from(&self, follows_from: FollowsFrom<Self::SpanId, Self::TraceId>) {
+        // published like links added via `add_span_link`
+        self.reporter.report_link(SpanLink {
+            trace_id: follows_from.trace_id,
+            span_id: follows_from.span_id,
+            linked_trace_id: follows_from.follows_trace_id,
+            linked_span_id: follows_from.follows_span_id,
+            meta: follows_from.meta,
+            linked_at: follows_from.occurred_at,
+        });
+    }
+
+    unsafe fn downcast_raw(&self, id: TypeId) -> Option<*const ()> {
+        // allows the crate's helpers to reach the reporter regardless of the visitor in use
+        if id == TypeId::of::<Reporter>() {
+            Some(&self.reporter as *const Reporter as *const ())
+        } else {
+            None
         }
     }
 }
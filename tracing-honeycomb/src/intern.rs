@@ -0,0 +1,54 @@
+use std::collections::HashSet;
+use std::sync::OnceLock;
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::RwLock;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::RwLock;
+
+// strings interned so far, shared by all layers
+static INTERNED: OnceLock<RwLock<HashSet<&'static str>>> = OnceLock::new();
+
+/// Get a `&'static str` equal to `s`, allocated the first time `s` is interned only, so field
+/// names that aren't `&'static str`s already (e.g. reserved field names with a configured
+/// prefix) can be recorded without allocating.
+///
+/// Interned strings are never freed: only intern strings derived from configuration, never
+/// strings derived from recorded values.
+pub(crate) fn intern(s: &str) -> &'static str {
+    let interned = INTERNED.get_or_init(Default::default);
+
+    #[cfg(not(feature = "use_parking_lot"))]
+    let read = interned.read().unwrap();
+    #[cfg(feature = "use_parking_lot")]
+    let read = interned.read();
+    if let Some(s) = read.get(s) {
+        return s;
+    }
+    drop(read);
+
+    #[cfg(not(feature = "use_parking_lot"))]
+    let mut write = interned.write().unwrap();
+    #[cfg(feature = "use_parking_lot")]
+    let mut write = interned.write();
+    // interned by another thread since the read lock was released
+    if let Some(s) = write.get(s) {
+        return s;
+    }
+    let s: &'static str = Box::leak(s.to_string().into_boxed_str());
+    write.insert(s);
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn interned_strings_are_shared() {
+        let first = intern(&format!("{}{}", "tracing.", "name"));
+        let second = intern("tracing.name");
+        assert_eq!(first, "tracing.name");
+        assert!(std::ptr::eq(first, second));
+    }
+}
@@ -0,0 +1,97 @@
+use serde_json::Value;
+use std::cell::RefCell;
+use std::fmt::{self, Write};
+use std::sync::Arc;
+
+thread_local! {
+    // set while `HoneycombVisitor` formats a field, so that `Lazy` values can hand over their
+    // closure instead of being evaluated
+    static CAPTURED: RefCell<Option<Option<Lazy>>> = const { RefCell::new(None) };
+}
+
+/// A field value computed only if the span or event it is recorded on is actually published
+/// to honeycomb.io, i.e. after sampling.
+///
+/// Record it as a `Debug` field: `tracing::info_span!("request", dump = ?Lazy::new(...))`.
+/// The closure must own everything it needs, as it may be called after the span was
+/// recorded. Other layers observing the same field evaluate it eagerly when formatting it.
+#[derive(Clone)]
+pub struct Lazy(Arc<dyn Fn() -> Value + Send + Sync>);
+
+impl Lazy {
+    /// Wrap a closure computing an expensive field value.
+    pub fn new<F, V>(f: F) -> Self
+    where
+        F: Fn() -> V + Send + Sync + 'static,
+        V: Into<Value>,
+    {
+        Lazy(Arc::new(move || f().into()))
+    }
+
+    pub(crate) fn evaluate(&self) -> Value {
+        (self.0)()
+    }
+}
+
+impl fmt::Debug for Lazy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let captured = CAPTURED.with(|captured| match captured.borrow_mut().as_mut() {
+            Some(slot) => {
+                *slot = Some(self.clone());
+                true
+            }
+            None => false,
+        });
+
+        if captured {
+            Ok(())
+        } else {
+            write!(f, "{}", self.evaluate())
+        }
+    }
+}
+
+/// Format a `Debug` field value, unless it is a `Lazy`, in which case it is returned as is.
+pub(crate) fn format_or_capture(value: &dyn fmt::Debug) -> Result<String, Lazy> {
+    CAPTURED.with(|captured| *captured.borrow_mut() = Some(None));
+    let mut s = String::new();
+    let _ = write!(s, "{:?}", value);
+    let lazy = CAPTURED.with(|captured| captured.borrow_mut().take().flatten());
+
+    match lazy {
+        Some(lazy) if s.is_empty() => Err(lazy),
+        // a `Lazy` nested in some other value, format it eagerly along with its parent
+        Some(_) => Ok(format!("{:?}", value)),
+        None => Ok(s),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    #[test]
+    fn lazy_values_are_captured_unevaluated() {
+        let evaluated = Arc::new(AtomicBool::new(false));
+        let lazy = {
+            let evaluated = evaluated.clone();
+            Lazy::new(move || {
+                evaluated.store(true, Ordering::SeqCst);
+                "expensive"
+            })
+        };
+
+        let captured = format_or_capture(&lazy).unwrap_err();
+        assert!(!evaluated.load(Ordering::SeqCst));
+        assert_eq!(captured.evaluate(), json!("expensive"));
+        assert!(evaluated.load(Ordering::SeqCst));
+
+        assert_eq!(
+            format_or_capture(&"plain").ok(),
+            Some("\"plain\"".to_string())
+        );
+        assert_eq!(format!("{:?}", lazy), "\"expensive\"");
+    }
+}
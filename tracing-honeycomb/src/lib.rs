@@ -4,31 +4,239 @@
     missing_copy_implementations,
     missing_docs
 )]
+// the reporting path is compiled out, leaving parts of the shared modules unused
+#![cfg_attr(not(feature = "honeycomb"), allow(dead_code))]
 
 //! This crate provides:
 //! - A tracing layer, `TelemetryLayer`, that can be used to publish trace data to honeycomb.io
 //! - Utilities for implementing distributed tracing against the honeycomb.io backend
 //!
 //! As a tracing layer, `TelemetryLayer` can be composed with other layers to provide stdout logging, filtering, etc.
+//!
+//! Telemetry is sent over TLS using rustls by default. Disable default features and enable the
+//! `native-tls` feature to use the platform's TLS implementation (OpenSSL on Linux) instead.
+//! The selected backend is used by both libhoney's transmission and `Builder::send_now`.
+//!
+//! With the `tracing-error` feature, ERROR events are published with the
+//! `tracing_error::SpanTrace` of the span they occurred in, as an `error.span_trace` field, so
+//! the origin of an error is visible even when most of its trace was sampled out. The span
+//! trace of an error recorded on the event (e.g. a `tracing_error::TracedError`) is used if
+//! there is one, otherwise the span trace is captured when the event is published, which
+//! requires `tracing_error::ErrorLayer` to be part of the global default subscriber.
+//!
+//! The exporter reports its own health (failures to send telemetry, recovery from outages,
+//! queue pressure) to stderr by default. `Builder::internal_log_mode` redirects these reports
+//! to the `log` crate or to `tracing` events with the `tracing_honeycomb::internal` target
+//! (`INTERNAL_TARGET`), which are not published to honeycomb.io but are captured by other
+//! layers, e.g. logging.
+//!
+//! With default features disabled and the `disabled` feature enabled, only the trace context
+//! API is compiled (`TraceId`, `SpanId`, `PropagationContext`, `register_dist_tracing_root`,
+//! `current_dist_trace_ctx`, `TracedFutureExt`, ...), without the honeycomb.io telemetry
+//! layer, libhoney or an HTTP client. Libraries can depend on this crate this way
+//! unconditionally, while binaries opt into exporting by enabling a TLS backend (or the
+//! `honeycomb` feature), which takes precedence over `disabled`, and installing the layer.
+//! Without the layer, the trace context API behaves as if no telemetry layer was installed,
+//! e.g. `set_trace_experiment` fails with `TraceCtxError::TelemetryLayerNotRegistered`, unless
+//! `new_blackhole_telemetry_layer` is used to keep track of trace context without publishing
+//! it.
+//!
+//! # Concurrent traces
+//!
+//! Any number of unrelated distributed traces can be in flight at once in a process, e.g. one
+//! per job of a scheduler. Traces are scoped to span subtrees: registering a span as the local
+//! root of a trace via `register_dist_tracing_root` associates the trace with that span and
+//! all of its descendants, regardless of the thread or task they are entered on, and no other
+//! span. A span belongs to the trace registered on its nearest ancestor (or itself), so
+//! registering a descendant of a root as the root of another trace (see
+//! `RedundantRootPolicy`) moves that descendant's subtree only. Spans outside of any
+//! registered subtree belong to no trace.
+//!
+//! `current_dist_trace_ctx` and `current_trace_id` look up the trace of the current span,
+//! while `dist_trace_ctx_of` and `trace_id_of` look up the trace of any open span by its
+//! `tracing::Id`, e.g. one stored alongside a scheduled job.
 
 use eaze_tracing_distributed as tracing_distributed;
 
+#[cfg(not(any(feature = "honeycomb", feature = "disabled")))]
+compile_error!(
+    "enable a TLS backend (`rustls-tls` or `native-tls`), the `honeycomb` feature to publish over \
+     plain HTTP, or the `disabled` feature"
+);
+
+#[cfg(feature = "honeycomb")]
+mod api_key;
+#[cfg(feature = "honeycomb")]
+mod blocking;
+mod bounded_map;
+#[cfg(feature = "honeycomb")]
+mod budget;
+#[cfg(feature = "honeycomb")]
+mod builder;
+#[cfg(feature = "honeycomb")]
+mod circuit_breaker;
+#[cfg(feature = "honeycomb")]
+mod clamp;
+#[cfg(all(feature = "clap", feature = "honeycomb"))]
+mod cli;
+#[cfg(feature = "honeycomb")]
+mod connection;
+#[cfg(feature = "honeycomb")]
+mod data_loss;
+#[cfg(feature = "honeycomb")]
+mod dead_letter;
+#[cfg(feature = "honeycomb")]
+mod diagnostics;
+#[cfg(feature = "honeycomb")]
+mod env;
+#[cfg(feature = "honeycomb")]
+mod errors;
+mod experiments;
+#[cfg(feature = "honeycomb")]
+mod export_filter;
+#[cfg(all(feature = "test-support", feature = "honeycomb"))]
+mod faults;
+#[cfg(feature = "honeycomb")]
 mod honeycomb;
+#[cfg(feature = "honeycomb")]
+mod intern;
+mod lazy;
+mod links;
+#[cfg(feature = "honeycomb")]
+mod log_bridge;
+#[cfg(feature = "honeycomb")]
+mod markers;
+#[cfg(feature = "honeycomb")]
+mod metrics;
+#[cfg(all(feature = "test-support", feature = "honeycomb"))]
+mod mock_server;
+#[cfg(all(feature = "opentelemetry", feature = "honeycomb"))]
+mod otel;
+#[cfg(all(feature = "otlp", feature = "honeycomb"))]
+mod otlp;
+#[cfg(feature = "honeycomb")]
+mod panic_hook;
+#[cfg(feature = "honeycomb")]
+mod pool;
+mod propagation;
+#[cfg(feature = "honeycomb")]
+mod rate_limiter;
+#[cfg(feature = "honeycomb")]
+mod reload;
+#[cfg(feature = "honeycomb")]
+mod rollup;
+#[cfg(feature = "honeycomb")]
+mod routing;
+mod sampling;
+#[cfg(feature = "honeycomb")]
+mod sharding;
 mod span_id;
+mod span_kind;
+mod span_status;
+mod spawn;
+#[cfg(feature = "honeycomb")]
+mod stats;
+#[cfg(feature = "proptest")]
+pub mod strategies;
+#[cfg(feature = "serde")]
+mod structured;
+#[cfg(feature = "honeycomb")]
+mod telemetry_error;
+#[cfg(all(feature = "test-support", feature = "honeycomb"))]
+pub mod test_support;
+#[cfg(all(feature = "tokio-transmission", feature = "honeycomb"))]
+mod tokio_transmission;
+#[cfg(feature = "honeycomb")]
+mod trace_fields;
 mod trace_id;
+#[cfg(feature = "honeycomb")]
+mod trace_timeout;
+#[cfg(feature = "honeycomb")]
+mod transmission;
+#[cfg(feature = "honeycomb")]
+mod units;
+#[cfg(feature = "honeycomb")]
+mod validation;
+#[cfg(feature = "honeycomb")]
 mod visitor;
+#[cfg(feature = "tungstenite")]
+pub mod websocket;
+#[cfg(all(feature = "zipkin", feature = "honeycomb"))]
+mod zipkin;
 
+#[cfg(feature = "honeycomb")]
+pub use api_key::ApiKeyHandle;
+#[cfg(feature = "honeycomb")]
+pub use builder::Builder;
+#[cfg(all(feature = "clap", feature = "honeycomb"))]
+pub use cli::HoneycombArgs;
+#[cfg(feature = "honeycomb")]
+pub use connection::ConnectionStatus;
+#[cfg(feature = "honeycomb")]
+pub use diagnostics::{InternalLogMode, INTERNAL_TARGET};
+#[cfg(feature = "honeycomb")]
+pub use env::{
+    EnvConfigError, HONEYCOMB_API_HOST, HONEYCOMB_API_KEY, HONEYCOMB_DATASET, HONEYCOMB_SAMPLE_RATE,
+};
+#[cfg(feature = "honeycomb")]
 pub use honeycomb::HoneycombTelemetry;
-pub use span_id::SpanId;
-pub use trace_id::TraceId;
+pub use lazy::Lazy;
+use links::SpanLink;
+#[cfg(all(feature = "tracing-log", feature = "honeycomb"))]
+pub use log_bridge::init_log_bridge;
+#[cfg(feature = "honeycomb")]
+pub use markers::{Marker, MarkerError, MarkersClient};
+#[cfg(feature = "honeycomb")]
+pub use metrics::MetricsHandle;
+#[cfg(feature = "honeycomb")]
+pub use panic_hook::install_panic_hook;
+pub use propagation::{
+    HeaderExtractor, ParsePropagationContextError, PropagationContext, TraceHeadersExt,
+    XrayTraceHeader, B3_TRACE_HEADER, HONEYCOMB_TRACE_HEADER, W3C_TRACE_HEADER, XRAY_TRACE_HEADER,
+};
+#[cfg(feature = "honeycomb")]
+pub use reload::{ReloadHandle, RuntimeSettings};
+pub use sampling::{ParseSamplingDecisionError, SampleRateHandle, SamplingDecision};
+use span_id::SpanIdGenerator;
+pub use span_id::{ParseSpanIdError, SpanId, SpanIdFormat};
+pub use span_kind::SpanKind;
+pub use span_status::SpanStatus;
+#[cfg(feature = "tokio")]
+pub use spawn::spawn_traced;
+pub use spawn::TracedFutureExt;
+#[cfg(feature = "honeycomb")]
+pub use stats::TelemetryStats;
+#[cfg(feature = "serde")]
+pub use structured::Structured;
+#[cfg(feature = "honeycomb")]
+pub use telemetry_error::TelemetryError;
+pub use trace_id::{ParseTraceIdError, TraceId, TraceIdFormat, TraceIdGenerator};
 #[doc(no_inline)]
-pub use tracing_distributed::{TelemetryLayer, TraceCtxError};
-pub use visitor::HoneycombVisitor;
+pub use tracing_distributed::{
+    check_subscriber, Clock, MockClock, RedundantRootPolicy, ReportedCounts, SubscriberDiagnostics,
+    SubscriberIssue, SystemClock, TelemetryLayer, TraceCtxError,
+};
+#[cfg(feature = "honeycomb")]
+pub use transmission::{QueueDepth, SharedTransmission};
+#[cfg(feature = "honeycomb")]
+pub use units::FieldUnit;
+#[cfg(feature = "honeycomb")]
+pub use validation::{ConfigError, ConfigIssue, ConfigReport, ConfigSetting};
+#[cfg(feature = "honeycomb")]
+pub use visitor::{FieldAction, FieldNaming, HoneycombValues, HoneycombVisitor, TraceFieldNames};
 
+#[cfg(feature = "honeycomb")]
 pub(crate) mod deterministic_sampler;
+#[cfg(not(feature = "honeycomb"))]
+mod disabled;
+#[cfg(not(feature = "honeycomb"))]
+use disabled as honeycomb;
 
 /// Register the current span as the local root of a distributed trace.
 ///
+/// The trace is scoped to the current span and its descendants, see "Concurrent traces" in
+/// the crate docs.
+///
 /// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
 pub fn register_dist_tracing_root(
     trace_id: TraceId,
@@ -37,6 +245,195 @@ pub fn register_dist_tracing_root(
     tracing_distributed::register_dist_tracing_root(trace_id, remote_parent_span)
 }
 
+/// Register the current span as the local root of a distributed trace, honoring the
+/// sampling decision made by the upstream service that initiated the trace.
+///
+/// Spans and events belonging to this trace are kept or dropped according to `sampling`
+/// instead of the locally configured sample rate, so traces are either fully kept or fully
+/// dropped across services.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn register_dist_tracing_root_with_sampling(
+    trace_id: TraceId,
+    remote_parent_span: Option<SpanId>,
+    sampling: SamplingDecision,
+) -> Result<(), TraceCtxError> {
+    tracing_distributed::register_dist_tracing_root(trace_id.clone(), remote_parent_span)?;
+
+    with_current_telemetry(|telemetry| telemetry.record_sampling_decision(trace_id, sampling))
+}
+
+/// Register the current span as the local root of the distributed trace propagated by the
+/// headers of an incoming request, e.g. at the start of an HTTP handler.
+///
+/// The remote trace context is extracted from the first header present and valid among
+/// `HONEYCOMB_TRACE_HEADER`, `W3C_TRACE_HEADER`, `B3_TRACE_HEADER`, the multi-header B3
+/// format (`X-B3-TraceId`, `X-B3-SpanId`, `X-B3-Sampled` and `X-B3-Flags`) and
+/// `XRAY_TRACE_HEADER`, honoring the caller's sampling decision, if any. Requests without a
+/// valid trace context start a new trace, see `new_trace_id`.
+pub fn register_dist_tracing_root_from<H: HeaderExtractor + ?Sized>(
+    headers: &H,
+) -> Result<(), TraceCtxError> {
+    match propagation::ExtractedContext::extract(headers) {
+        Some(ctx) => ctx.register_dist_tracing_root(),
+        None => register_dist_tracing_root(new_trace_id(), None),
+    }
+}
+
+/// Retrieve the sampling decision for the distributed trace associated with the current span,
+/// to be propagated to downstream services along with the `TraceId` and `SpanId`.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn current_sampling_decision() -> Result<SamplingDecision, TraceCtxError> {
+    let (trace_id, _) = current_dist_trace_ctx()?;
+
+    with_current_telemetry(|telemetry| telemetry.sampling_decision(&trace_id))
+}
+
+/// Assign `variant` of the experiment or feature flag `flag` to the distributed trace
+/// associated with the current span.
+///
+/// The assignment is recorded as an `experiment.<flag>` field on all spans and events of the
+/// trace reported from then on, and propagated to downstream services along with the trace
+/// context, so that latency and errors can be broken down by variant. Assign flags as soon as
+/// the trace starts: spans that closed before the assignment are not annotated.
+pub fn set_trace_experiment(
+    flag: impl Into<String>,
+    variant: impl Into<String>,
+) -> Result<(), TraceCtxError> {
+    let (trace_id, _) = current_dist_trace_ctx()?;
+
+    with_current_telemetry(|telemetry| {
+        telemetry.record_experiment(&trace_id, flag.into(), variant.into())
+    })
+}
+
+/// Add the field `key` to the distributed trace associated with the current span, e.g. a
+/// `user_id` or `feature_flag` known only once a request has been authenticated.
+///
+/// The field is recorded on all spans and events of the trace reported from then on, including
+/// the spans that are already open, such as the local root span, until the local root span
+/// closes. Spans and events that recorded a field of the same name keep their own value, and
+/// adding a field again replaces its value. Unlike experiments, trace fields are not propagated
+/// to downstream services. Mirrors beelines' `AddFieldToTrace`.
+pub fn add_trace_field(
+    key: impl Into<String>,
+    value: impl Into<serde_json::Value>,
+) -> Result<(), TraceCtxError> {
+    let (trace_id, _) = current_dist_trace_ctx()?;
+
+    with_current_telemetry(|telemetry| {
+        telemetry.add_trace_field(&trace_id, key.into(), value.into())
+    })
+}
+
+/// Set the status of the current span, published as its `span.status` column once it closes,
+/// see `SpanStatus`. Replaces any status set before, including the error status set by events
+/// recording an error.
+pub fn set_span_status(status: SpanStatus) -> Result<(), TraceCtxError> {
+    let (_, span_id) = current_dist_trace_ctx()?;
+
+    with_current_telemetry(|telemetry| telemetry.set_span_status(&span_id, status))
+}
+
+/// Mark the current span as errored, see `set_span_status`.
+pub fn set_span_error() -> Result<(), TraceCtxError> {
+    set_span_status(SpanStatus::Error)
+}
+
+/// Link the current span to the span `span_id` of the distributed trace `trace_id`, e.g. to
+/// connect a batch consumer's span to the traces of the producers of each message it handles.
+///
+/// The link is published right away as a link annotation (`meta.annotation_type = "link"`)
+/// attached to the current span, with `trace.link.trace_id` and `trace.link.span_id` set to the
+/// linked span. Spans can be linked any number of times, including to spans of their own trace.
+pub fn add_span_link(trace_id: TraceId, span_id: SpanId) -> Result<(), TraceCtxError> {
+    let (current_trace_id, current_span_id) = current_dist_trace_ctx()?;
+    let meta = tracing::Span::current()
+        .metadata()
+        .ok_or(TraceCtxError::NoEnabledSpan)?;
+
+    with_current_telemetry(|telemetry| {
+        telemetry.report_link(SpanLink {
+            trace_id: current_trace_id,
+            span_id: current_span_id,
+            linked_trace_id: trace_id,
+            linked_span_id: span_id,
+            meta,
+            linked_at: std::time::SystemTime::now(),
+        })
+    })
+}
+
+fn with_current_telemetry<F, R>(f: F) -> Result<R, TraceCtxError>
+where
+    F: FnOnce(&honeycomb::Reporter) -> R,
+{
+    tracing::Span::current()
+        .with_subscriber(|(_, dispatch)| {
+            dispatch
+                .downcast_ref::<honeycomb::Reporter>()
+                .map(f)
+                .ok_or(TraceCtxError::TelemetryLayerNotRegistered)
+        })
+        .ok_or(TraceCtxError::NoEnabledSpan)?
+}
+
+/// Generate the `TraceId` of a new trace, using the generator configured on the telemetry
+/// layer of the default subscriber (see `Builder::trace_id_generator`), or `TraceId::new()` if
+/// there is none.
+pub fn new_trace_id() -> TraceId {
+    tracing::dispatcher::get_default(|dispatch| {
+        dispatch
+            .downcast_ref::<honeycomb::Reporter>()
+            .map(honeycomb::Reporter::new_trace_id)
+    })
+    .unwrap_or_else(TraceId::new)
+}
+
+/// Enable or disable publishing telemetry from the telemetry layer of the default subscriber,
+/// see `HoneycombTelemetry::set_enabled`. Returns `false` if there is no such layer.
+pub fn set_telemetry_enabled(enabled: bool) -> bool {
+    tracing::dispatcher::get_default(|dispatch| {
+        dispatch
+            .downcast_ref::<honeycomb::Reporter>()
+            .map(|reporter| reporter.set_enabled(enabled))
+            .is_some()
+    })
+}
+
+/// Get the counters maintained by the reporting path of the telemetry layer of the default
+/// subscriber, if any. See `HoneycombTelemetry::stats`.
+#[cfg(feature = "honeycomb")]
+pub fn current_telemetry_stats() -> Option<TelemetryStats> {
+    tracing::dispatcher::get_default(|dispatch| {
+        dispatch
+            .downcast_ref::<honeycomb::Reporter>()
+            .map(honeycomb::Reporter::stats)
+    })
+}
+
+/// Get the handle used to record the metrics published by the telemetry layer of the default
+/// subscriber, if it reports metrics. See `Builder::report_metrics`.
+#[cfg(feature = "honeycomb")]
+pub fn current_metrics() -> Option<MetricsHandle> {
+    tracing::dispatcher::get_default(|dispatch| {
+        dispatch
+            .downcast_ref::<honeycomb::Reporter>()
+            .and_then(honeycomb::Reporter::metrics_handle)
+    })
+}
+
+/// Get the instance id used to salt the span ids generated by the telemetry layer of the
+/// default subscriber, if any. See `Builder::instance_id`.
+pub fn current_instance_id() -> Option<u64> {
+    tracing::dispatcher::get_default(|dispatch| {
+        dispatch
+            .downcast_ref::<honeycomb::Reporter>()
+            .map(honeycomb::Reporter::instance_id)
+    })
+}
+
 /// Retrieve the distributed trace context associated with the current span.
 ///
 /// Returns the `TraceId`, if any, that the current span is associated with along with
@@ -47,30 +444,86 @@ pub fn current_dist_trace_ctx() -> Result<(TraceId, SpanId), TraceCtxError> {
     tracing_distributed::current_dist_trace_ctx()
 }
 
+/// Get the `TraceId` of the distributed trace associated with the current span, if any, e.g.
+/// to return it to clients in error responses so their reports can be looked up.
+///
+/// The trace id is returned in the format it is reported in, see `Builder::trace_id_format`.
+pub fn current_trace_id() -> Option<TraceId> {
+    let (trace_id, _) = current_dist_trace_ctx().ok()?;
+    let reported =
+        with_current_telemetry(|telemetry| telemetry.propagated_trace_id(trace_id.clone()));
+    Some(reported.unwrap_or(trace_id))
+}
+
+/// Get the `SpanId` of the current span, if it belongs to a distributed trace.
+pub fn current_span_id() -> Option<SpanId> {
+    current_dist_trace_ctx().ok().map(|(_, span_id)| span_id)
+}
+
+/// Retrieve the distributed trace context associated with the open span `id` of the default
+/// subscriber, e.g. the span a job was scheduled with, see "Concurrent traces" in the crate
+/// docs.
+///
+/// Returns the `TraceId`, if any, that the span is associated with along with its `SpanId`.
+/// Fails with `TraceCtxError::NoEnabledSpan` once the span has closed.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn dist_trace_ctx_of(id: &tracing::Id) -> Result<(TraceId, SpanId), TraceCtxError> {
+    tracing_distributed::dist_trace_ctx_of(id)
+}
+
+/// Get the `TraceId` of the distributed trace the open span `id` of the default subscriber
+/// belongs to, if any, in the format it is reported in. See `dist_trace_ctx_of` and
+/// `current_trace_id`.
+pub fn trace_id_of(id: &tracing::Id) -> Option<TraceId> {
+    let (trace_id, _) = dist_trace_ctx_of(id).ok()?;
+    let reported = tracing::dispatcher::get_default(|dispatch| {
+        dispatch
+            .downcast_ref::<honeycomb::Reporter>()
+            .map(|reporter| reporter.propagated_trace_id(trace_id.clone()))
+    });
+    Some(reported.unwrap_or(trace_id))
+}
+
 /// Construct a TelemetryLayer that does not publish telemetry to any backend.
 ///
 /// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
 pub fn new_blackhole_telemetry_layer(
 ) -> TelemetryLayer<tracing_distributed::BlackholeTelemetry<SpanId, TraceId>, SpanId, TraceId> {
-    TelemetryLayer::new(
+    new_counting_blackhole_telemetry_layer().0
+}
+
+/// Construct a TelemetryLayer that does not publish telemetry to any backend, along with the
+/// number of spans and events it reported by level and target, e.g. to check in tests and
+/// benchmarks that instrumentation reports the expected data without network I/O.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+pub fn new_counting_blackhole_telemetry_layer() -> (
+    TelemetryLayer<tracing_distributed::BlackholeTelemetry<SpanId, TraceId>, SpanId, TraceId>,
+    ReportedCounts,
+) {
+    let span_ids = SpanIdGenerator::new(SpanIdFormat::default(), rand::random());
+    let telemetry = tracing_distributed::BlackholeTelemetry::default();
+    let counts = telemetry.counts();
+    let layer = TelemetryLayer::new(
         "honeycomb_blackhole_tracing_layer",
-        tracing_distributed::BlackholeTelemetry::default(),
-        move |tracing_id| SpanId { tracing_id },
-    )
+        telemetry,
+        move |tracing_id| span_ids.promote(tracing_id),
+    );
+    (layer, counts)
 }
 
 /// Construct a TelemetryLayer that publishes telemetry to honeycomb.io using the provided honeycomb config.
 ///
+/// Prefer `HoneycombTelemetry::builder()`, which exposes all configuration options.
+///
 /// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+#[cfg(feature = "honeycomb")]
 pub fn new_honeycomb_telemetry_layer(
     service_name: &'static str,
     honeycomb_config: libhoney::Config,
 ) -> TelemetryLayer<HoneycombTelemetry, SpanId, TraceId> {
-    TelemetryLayer::new(
-        service_name,
-        HoneycombTelemetry::new(honeycomb_config, None),
-        move |tracing_id| SpanId { tracing_id },
-    )
+    Builder::new(service_name, honeycomb_config).build()
 }
 
 /// Construct a TelemetryLayer that publishes telemetry to honeycomb.io using the
@@ -88,14 +541,25 @@ pub fn new_honeycomb_telemetry_layer(
 /// is the default.
 ///
 /// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+#[cfg(feature = "honeycomb")]
 pub fn new_honeycomb_telemetry_layer_with_trace_sampling(
     service_name: &'static str,
     honeycomb_config: libhoney::Config,
     sample_rate: u32,
 ) -> TelemetryLayer<HoneycombTelemetry, SpanId, TraceId> {
-    TelemetryLayer::new(
-        service_name,
-        HoneycombTelemetry::new(honeycomb_config, Some(sample_rate)),
-        move |tracing_id| SpanId { tracing_id },
-    )
+    Builder::new(service_name, honeycomb_config)
+        .sample_rate(sample_rate)
+        .build()
+}
+
+/// Construct a TelemetryLayer that publishes telemetry to honeycomb.io, configured from the
+/// `HONEYCOMB_API_KEY`, `HONEYCOMB_DATASET`, `HONEYCOMB_API_HOST` and
+/// `HONEYCOMB_SAMPLE_RATE` environment variables. See `Builder::from_env`.
+///
+/// Specialized to the honeycomb.io-specific SpanId and TraceId provided by this crate.
+#[cfg(feature = "honeycomb")]
+pub fn new_honeycomb_telemetry_layer_from_env(
+    service_name: &'static str,
+) -> Result<TelemetryLayer<HoneycombTelemetry, SpanId, TraceId>, EnvConfigError> {
+    Ok(Builder::from_env(service_name)?.build())
 }
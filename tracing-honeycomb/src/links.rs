@@ -0,0 +1,20 @@
+use std::time::SystemTime;
+
+use crate::{SpanId, TraceId};
+
+/// A link from a span to a span of another trace (or of the same trace), see `add_span_link`.
+#[derive(Clone, Debug)]
+pub(crate) struct SpanLink {
+    /// trace of the linking span
+    pub(crate) trace_id: TraceId,
+    /// the linking span
+    pub(crate) span_id: SpanId,
+    /// trace of the linked span
+    pub(crate) linked_trace_id: TraceId,
+    /// the linked span
+    pub(crate) linked_span_id: SpanId,
+    /// `tracing::Metadata` of the linking span
+    pub(crate) meta: &'static tracing::Metadata<'static>,
+    /// UTC time at which the link was added
+    pub(crate) linked_at: SystemTime,
+}
@@ -0,0 +1,103 @@
+use libhoney::Value;
+use std::collections::HashMap;
+
+use crate::visitor::HoneycombValues;
+
+// target of the callsites shared by all events converted from `log` records by
+// `tracing_log::LogTracer`, the record's own target is recorded as the `log.target` field
+const LOG_TARGET: &str = "log";
+const LOG_TARGET_FIELD: &str = "log.target";
+
+/// Forward records of the `log` crate, as still used by many dependencies, to `tracing`, so
+/// that they are published as events of the current span instead of disappearing.
+///
+/// Installs `tracing_log::LogTracer` as the global logger. Records logged within a span that
+/// belongs to a distributed trace are published like any other event: with their level, their
+/// message (`message`) and their target (`target`, e.g. `hyper::client`) rather than the
+/// `log` target shared by all converted records. The record's module path, file and line are
+/// kept as `log.module_path`, `log.file` and `log.line` fields. Records logged outside of a
+/// trace are only seen by other layers, e.g. logging.
+///
+/// Fails if a global logger is already installed. Requires the `tracing-log` feature.
+#[cfg(feature = "tracing-log")]
+pub fn init_log_bridge() -> Result<(), log::SetLoggerError> {
+    tracing_log::LogTracer::init()
+}
+
+/// Whether this event was converted from a `log` record, see `init_log_bridge`. Also holds for
+/// records forwarded by a `LogTracer` installed directly, e.g. by `tracing_subscriber::fmt`.
+pub(crate) fn is_log_record(meta: &tracing::Metadata<'_>) -> bool {
+    meta.target() == LOG_TARGET && meta.fields().field(LOG_TARGET_FIELD).is_some()
+}
+
+/// The target of the `log` record this event was converted from, if any.
+pub(crate) fn log_record_target<'a, V: HoneycombValues>(
+    meta: &tracing::Metadata<'_>,
+    values: &'a V,
+) -> Option<&'a str> {
+    if !is_log_record(meta) {
+        return None;
+    }
+    values.get(LOG_TARGET_FIELD).and_then(Value::as_str)
+}
+
+/// Remove the target of the `log` record these values were converted from, if any, so it
+/// replaces the `log` target of the event.
+pub(crate) fn take_log_record_target(
+    meta: &tracing::Metadata<'_>,
+    values: &mut HashMap<String, Value>,
+) -> Option<Value> {
+    if !is_log_record(meta) {
+        return None;
+    }
+    values.remove(LOG_TARGET_FIELD)
+}
+
+#[cfg(all(test, feature = "tracing-log"))]
+mod test {
+    use super::*;
+    use crate::{register_dist_tracing_root, HoneycombTelemetry, TraceId};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use std::time::Duration;
+    use tracing_subscriber::layer::Layer;
+
+    #[test]
+    fn log_records_are_published_as_events() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_host = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            while !request.ends_with(b"]") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n[]")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let layer = HoneycombTelemetry::builder()
+            .api_host(api_host)
+            .send_now(Duration::from_secs(5))
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+        init_log_bridge().unwrap();
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            let _guard = span.enter();
+            register_dist_tracing_root(TraceId::new(), None).unwrap();
+            log::warn!(target: "legacy::client", "connection reset");
+        });
+
+        let request = server.join().unwrap();
+        assert!(request.contains(r#""message":"connection reset""#));
+        assert!(request.contains(r#""target":"legacy::client""#));
+        assert!(request.contains(r#""level":"WARN""#));
+        assert!(!request.contains(r#""log.target""#));
+    }
+}
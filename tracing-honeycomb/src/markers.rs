@@ -0,0 +1,268 @@
+use libhoney::{json, Value};
+use std::fmt::{self, Display};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+use crate::api_key::ApiKeyHandle;
+use crate::validation::normalize_api_host;
+
+const MARKERS_ENDPOINT: &str = "/1/markers/";
+
+// bounds the time spent waiting for honeycomb.io, e.g. during a deploy script
+const MARKER_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Client for honeycomb.io's Markers API, used to drop deploy or incident markers onto
+/// boards and query results. Uses the API host, API key and dataset of the telemetry layer it
+/// was obtained from, see `HoneycombTelemetry::markers_client` and `Builder::markers_client`.
+///
+/// The API key is read when each marker is created, so rotated keys (see `ApiKeyHandle`) are
+/// picked up.
+#[derive(Clone, Debug)]
+pub struct MarkersClient {
+    api_host: String,
+    api_key: ApiKeyHandle,
+    dataset: String,
+}
+
+/// A marker created by `MarkersClient::create_marker`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Marker {
+    /// Id assigned to the marker by honeycomb.io.
+    pub id: String,
+    /// Message displayed along with the marker.
+    pub message: String,
+    /// Type of the marker, e.g. `deploy`, used to group and color markers.
+    pub marker_type: String,
+}
+
+/// Failure to create a marker, see `MarkersClient::create_marker`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum MarkerError {
+    /// honeycomb.io rejected the API key, e.g. because it is invalid, was revoked or lacks
+    /// the permission to manage markers.
+    InvalidKey,
+    /// honeycomb.io rejected the marker, e.g. because the dataset does not exist.
+    Rejected {
+        /// HTTP status of honeycomb.io's response
+        status: u16,
+        /// body of honeycomb.io's response
+        message: String,
+    },
+    /// honeycomb.io could not be reached, or its response could not be read. Holds a
+    /// description of the failure.
+    NetworkError(String),
+}
+
+impl Display for MarkerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MarkerError::InvalidKey => f.write_str("honeycomb rejected the api key"),
+            MarkerError::Rejected { status, message } => write!(
+                f,
+                "honeycomb rejected the marker with status {}, {}",
+                status, message
+            ),
+            MarkerError::NetworkError(message) => {
+                write!(f, "error creating honeycomb marker, {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for MarkerError {}
+
+impl MarkersClient {
+    pub(crate) fn new(api_host: String, api_key: ApiKeyHandle, dataset: String) -> Self {
+        MarkersClient {
+            api_host,
+            api_key,
+            dataset,
+        }
+    }
+
+    /// Create markers in `dataset` instead of the dataset of the telemetry layer, e.g.
+    /// `__all__` for markers shown on all datasets of the environment.
+    pub fn dataset(mut self, dataset: impl Into<String>) -> Self {
+        self.dataset = dataset.into();
+        self
+    }
+
+    /// Create a marker starting now, e.g. `create_marker("v1.2.3", "deploy")`.
+    ///
+    /// The request is sent from a background thread, so the returned future may be awaited
+    /// from any async runtime. Fails after ten seconds if honeycomb.io does not respond.
+    pub async fn create_marker(
+        &self,
+        message: impl Into<String>,
+        marker_type: impl Into<String>,
+    ) -> Result<Marker, MarkerError> {
+        let api_host = normalize_api_host(&self.api_host).ok_or_else(|| {
+            MarkerError::NetworkError(format!("invalid api host {:?}", self.api_host))
+        })?;
+        let url = format!("{}{}{}", api_host, MARKERS_ENDPOINT, self.dataset);
+        let api_key = self.api_key.read().clone();
+        let message = message.into();
+        let marker_type = marker_type.into();
+        OffThread::spawn(move || create_marker(&url, &api_key, message, marker_type)).await
+    }
+}
+
+fn create_marker(
+    url: &str,
+    api_key: &str,
+    message: String,
+    marker_type: String,
+) -> Result<Marker, MarkerError> {
+    let client = reqwest::blocking::Client::builder()
+        .timeout(MARKER_TIMEOUT)
+        .build()
+        .map_err(|err| MarkerError::NetworkError(err.to_string()))?;
+
+    let response = client
+        .post(url)
+        .header("X-Honeycomb-Team", api_key)
+        .header("Content-Type", "application/json")
+        .body(json!({ "message": message, "type": marker_type }).to_string())
+        .send()
+        .map_err(|err| MarkerError::NetworkError(err.to_string()))?;
+    let status = response.status();
+    let body = response
+        .text()
+        .map_err(|err| MarkerError::NetworkError(err.to_string()))?;
+    if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        return Err(MarkerError::InvalidKey);
+    }
+    if !status.is_success() {
+        return Err(MarkerError::Rejected {
+            status: status.as_u16(),
+            message: body,
+        });
+    }
+
+    let marker: Value = body
+        .parse()
+        .map_err(|err| MarkerError::NetworkError(format!("invalid marker response, {}", err)))?;
+    Ok(Marker {
+        id: marker["id"].as_str().unwrap_or_default().to_string(),
+        message: marker["message"].as_str().unwrap_or(&message).to_string(),
+        marker_type: marker["type"].as_str().unwrap_or(&marker_type).to_string(),
+    })
+}
+
+// runs a blocking call on a dedicated thread, completing once it returns. independent of any
+// async runtime, unlike reqwest's async client
+struct OffThread<T>(Arc<Mutex<Completion<T>>>);
+
+// the result of the call once it returned, and the waker of the task awaiting it
+type Completion<T> = (Option<T>, Option<Waker>);
+
+fn lock<T>(
+    completion: &Mutex<Completion<T>>,
+) -> impl std::ops::DerefMut<Target = Completion<T>> + '_ {
+    // succeed or die. failure is unrecoverable (mutex poisoned)
+    #[cfg(not(feature = "use_parking_lot"))]
+    let completion = completion.lock().unwrap();
+    #[cfg(feature = "use_parking_lot")]
+    let completion = completion.lock();
+
+    completion
+}
+
+impl<T: Send + 'static> OffThread<T> {
+    fn spawn<F>(f: F) -> Self
+    where
+        F: FnOnce() -> T + Send + 'static,
+    {
+        let shared = Arc::new(Mutex::new((None, None)));
+        let completion = shared.clone();
+        std::thread::spawn(move || {
+            let res = f();
+            let mut completion = lock(&completion);
+            completion.0 = Some(res);
+            if let Some(waker) = completion.1.take() {
+                waker.wake();
+            }
+        });
+        OffThread(shared)
+    }
+}
+
+impl<T> Future for OffThread<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        let mut completion = lock(&self.0);
+        match completion.0.take() {
+            Some(res) => Poll::Ready(res),
+            None => {
+                completion.1 = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn creates_markers_in_dataset() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_host = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            let body = r#"{"id":"2Jz5Nq","message":"v1.2.3","type":"deploy"}"#;
+            let response = format!(
+                "HTTP/1.1 201 Created\r\ncontent-length: {}\r\n\r\n{}",
+                body.len(),
+                body
+            );
+            stream.write_all(response.as_bytes()).unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let api_key = ApiKeyHandle::new("key".to_string());
+        let client = MarkersClient::new(api_host, api_key.clone(), "app".to_string());
+        let mut rt = tokio::runtime::Runtime::new().unwrap();
+        let marker = rt
+            .block_on(client.create_marker("v1.2.3", "deploy"))
+            .unwrap();
+        assert_eq!(
+            marker,
+            Marker {
+                id: "2Jz5Nq".to_string(),
+                message: "v1.2.3".to_string(),
+                marker_type: "deploy".to_string(),
+            }
+        );
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /1/markers/app HTTP/1.1"));
+        assert!(request.contains("x-honeycomb-team: key"));
+        assert!(request.contains(r#""type":"deploy""#));
+
+        let client =
+            MarkersClient::new("ftp://example.com".to_string(), api_key, "app".to_string());
+        assert!(matches!(
+            rt.block_on(client.create_marker("v1.2.3", "deploy")),
+            Err(MarkerError::NetworkError(_))
+        ));
+    }
+}
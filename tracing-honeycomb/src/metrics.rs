@@ -0,0 +1,264 @@
+use chrono::{DateTime, Utc};
+use libhoney::{json, Value};
+use rand::Rng;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+// bounds the memory used by each histogram, percentiles are computed from a uniform sample of
+// the recorded values beyond this
+const MAX_HISTOGRAM_SAMPLES: usize = 1024;
+
+/// Handle used to record basic service metrics (counters, gauges and histograms), aggregated
+/// in-process and published periodically as honeycomb.io events, see `Builder::report_metrics`.
+///
+/// Handles are cheap to clone and may be freely shared between threads. Metrics recorded
+/// while the layer does not report metrics are aggregated but never published.
+#[derive(Clone, Debug, Default)]
+pub struct MetricsHandle(Arc<Mutex<MetricsState>>);
+
+#[derive(Debug, Default)]
+struct MetricsState {
+    counters: HashMap<String, u64>,
+    gauges: HashMap<String, f64>,
+    histograms: HashMap<String, Histogram>,
+}
+
+#[derive(Debug)]
+struct Histogram {
+    count: u64,
+    sum: f64,
+    min: f64,
+    max: f64,
+    samples: Vec<f64>,
+}
+
+impl Histogram {
+    fn new() -> Self {
+        Histogram {
+            count: 0,
+            sum: 0.0,
+            min: f64::INFINITY,
+            max: f64::NEG_INFINITY,
+            samples: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, value: f64) {
+        self.count += 1;
+        self.sum += value;
+        self.min = self.min.min(value);
+        self.max = self.max.max(value);
+        if self.samples.len() < MAX_HISTOGRAM_SAMPLES {
+            self.samples.push(value);
+        } else {
+            // reservoir sampling, every value is kept with the same probability
+            let i = rand::thread_rng().gen_range(0, self.count) as usize;
+            if i < MAX_HISTOGRAM_SAMPLES {
+                self.samples[i] = value;
+            }
+        }
+    }
+
+    fn insert_values(mut self, name: &str, values: &mut HashMap<String, Value>) {
+        self.samples.sort_by(|a, b| a.total_cmp(b));
+        let percentile = |p: f64| {
+            let rank = (p * self.samples.len() as f64).ceil() as usize;
+            self.samples[rank.clamp(1, self.samples.len()) - 1]
+        };
+        values.insert(format!("{}.count", name), json!(self.count));
+        values.insert(format!("{}.sum", name), json!(self.sum));
+        values.insert(format!("{}.min", name), json!(self.min));
+        values.insert(format!("{}.max", name), json!(self.max));
+        values.insert(format!("{}.avg", name), json!(self.sum / self.count as f64));
+        values.insert(format!("{}.p50", name), json!(percentile(0.5)));
+        values.insert(format!("{}.p90", name), json!(percentile(0.9)));
+        values.insert(format!("{}.p99", name), json!(percentile(0.99)));
+    }
+}
+
+impl MetricsHandle {
+    fn lock(&self) -> impl std::ops::DerefMut<Target = MetricsState> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let state = self.0.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let state = self.0.lock();
+
+        state
+    }
+
+    /// Add `value` to the counter `name`. Counters are published as the sum of the values
+    /// added during each interval.
+    pub fn counter(&self, name: &str, value: u64) {
+        let mut state = self.lock();
+        match state.counters.get_mut(name) {
+            Some(counter) => *counter += value,
+            None => {
+                state.counters.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    /// Set the gauge `name` to `value`. Gauges are published with their latest value, at
+    /// every interval until they are set again.
+    pub fn gauge(&self, name: &str, value: f64) {
+        let mut state = self.lock();
+        match state.gauges.get_mut(name) {
+            Some(gauge) => *gauge = value,
+            None => {
+                state.gauges.insert(name.to_string(), value);
+            }
+        }
+    }
+
+    /// Record `value` in the histogram `name`, e.g. a latency or a payload size. Histograms
+    /// are published as the count, sum, minimum, maximum, average and 50th, 90th and 99th
+    /// percentiles of the values recorded during each interval, as `{name}.count`,
+    /// `{name}.sum`, `{name}.min`, `{name}.max`, `{name}.avg`, `{name}.p50`, `{name}.p90`
+    /// and `{name}.p99` fields.
+    pub fn histogram(&self, name: &str, value: f64) {
+        let mut state = self.lock();
+        match state.histograms.get_mut(name) {
+            Some(histogram) => histogram.record(value),
+            None => {
+                let mut histogram = Histogram::new();
+                histogram.record(value);
+                state.histograms.insert(name.to_string(), histogram);
+            }
+        }
+    }
+}
+
+/// Periodically turns the metrics recorded through a `MetricsHandle` into an event, see
+/// `Builder::report_metrics`.
+#[derive(Debug)]
+pub(crate) struct Metrics {
+    interval: Duration,
+    handle: MetricsHandle,
+    window: Mutex<(SystemTime, Instant)>,
+}
+
+impl Metrics {
+    pub(crate) fn new(interval: Duration, handle: MetricsHandle) -> Self {
+        Metrics {
+            interval,
+            handle,
+            window: Mutex::new((SystemTime::now(), Instant::now())),
+        }
+    }
+
+    pub(crate) fn handle(&self) -> MetricsHandle {
+        self.handle.clone()
+    }
+
+    fn lock(&self) -> impl std::ops::DerefMut<Target = (SystemTime, Instant)> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let window = self.window.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let window = self.window.lock();
+
+        window
+    }
+
+    /// If the current window has elapsed, reset it and return an event holding the metrics
+    /// recorded during the window, if any.
+    pub(crate) fn take_due(&self, service_name: &'static str) -> Option<HashMap<String, Value>> {
+        self.take_due_at(service_name, Instant::now(), SystemTime::now())
+    }
+
+    fn take_due_at(
+        &self,
+        service_name: &'static str,
+        now: Instant,
+        now_utc: SystemTime,
+    ) -> Option<HashMap<String, Value>> {
+        let mut window = self.lock();
+        let (window_started_at, window_started) = *window;
+        let elapsed = now.saturating_duration_since(window_started);
+        if elapsed < self.interval {
+            return None;
+        }
+        *window = (now_utc, now);
+
+        let mut state = self.handle.lock();
+        if state.counters.is_empty() && state.gauges.is_empty() && state.histograms.is_empty() {
+            return None;
+        }
+
+        let mut values = HashMap::new();
+        for (name, counter) in state.counters.drain() {
+            values.insert(name, json!(counter));
+        }
+        for (name, gauge) in &state.gauges {
+            values.insert(name.clone(), json!(gauge));
+        }
+        for (name, histogram) in state.histograms.drain() {
+            histogram.insert_values(&name, &mut values);
+        }
+
+        let window_started_at: DateTime<Utc> = window_started_at.into();
+        values.insert("name".to_string(), json!("metrics"));
+        values.insert("service_name".to_string(), json!(service_name));
+        values.insert(
+            "Timestamp".to_string(),
+            json!(window_started_at.to_rfc3339()),
+        );
+        values.insert("meta.metrics".to_string(), json!(true));
+        values.insert(
+            "metrics.window_secs".to_string(),
+            json!(elapsed.as_secs_f64()),
+        );
+        Some(values)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn aggregates_metrics_per_window() {
+        let handle = MetricsHandle::default();
+        let metrics = Metrics::new(Duration::from_secs(60), handle.clone());
+        let start = Instant::now();
+
+        handle.counter("requests", 2);
+        handle.counter("requests", 3);
+        handle.gauge("connections", 4.0);
+        for ms in 1..=100 {
+            handle.histogram("latency_ms", ms as f64);
+        }
+        assert!(metrics
+            .take_due_at("svc", start, SystemTime::now())
+            .is_none());
+
+        let later = start + Duration::from_secs(61);
+        let values = metrics
+            .take_due_at("svc", later, SystemTime::now())
+            .unwrap();
+        assert_eq!(values["name"], json!("metrics"));
+        assert_eq!(values["meta.metrics"], json!(true));
+        assert_eq!(values["requests"], json!(5));
+        assert_eq!(values["connections"], json!(4.0));
+        assert_eq!(values["latency_ms.count"], json!(100));
+        assert_eq!(values["latency_ms.min"], json!(1.0));
+        assert_eq!(values["latency_ms.p50"], json!(50.0));
+        assert_eq!(values["latency_ms.p99"], json!(99.0));
+
+        // counters and histograms are reset, gauges keep their value
+        let much_later = later + Duration::from_secs(61);
+        let values = metrics
+            .take_due_at("svc", much_later, SystemTime::now())
+            .unwrap();
+        assert_eq!(values.get("requests"), None);
+        assert_eq!(values.get("latency_ms.count"), None);
+        assert_eq!(values["connections"], json!(4.0));
+    }
+}
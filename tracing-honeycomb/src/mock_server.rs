@@ -0,0 +1,363 @@
+use libhoney::{json, Value};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+const BATCH_ENDPOINT: &str = "/1/batch/";
+const AUTH_ENDPOINT: &str = "/1/auth";
+const MARKERS_ENDPOINT: &str = "/1/markers/";
+
+/// Local HTTP server implementing enough of honeycomb.io's Events API to exercise the whole
+/// transmission path (batching, error callbacks, circuit breaking, dead-letter spooling) in
+/// tests, without network access. Point a layer at it with `Builder::api_host`, using
+/// `MockHoneycomb::api_host`.
+///
+/// Supports the batch endpoint (`/1/batch/{dataset}`), the auth endpoint used by
+/// `Builder::check_connection` and the markers endpoint used by `MarkersClient`. Requests with
+/// an API key other than the one the server was started with are rejected with 401, and
+/// failures such as rate limiting can be injected with `MockHoneycomb::fail_next`. The server
+/// stops when dropped.
+#[derive(Debug)]
+pub struct MockHoneycomb {
+    addr: SocketAddr,
+    state: Arc<Mutex<State>>,
+    stopped: Arc<AtomicBool>,
+    worker: Option<JoinHandle<()>>,
+}
+
+/// An event received by a `MockHoneycomb` server.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReceivedEvent {
+    /// Dataset the event was sent to.
+    pub dataset: String,
+    /// Fields of the event, by name.
+    pub data: HashMap<String, Value>,
+    /// Sample rate the event was sent with, if any.
+    pub sample_rate: Option<u64>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    api_key: String,
+    events: Vec<ReceivedEvent>,
+    // statuses of the next batch requests, injected by `fail_next`
+    failures: VecDeque<u16>,
+    batches: usize,
+}
+
+impl MockHoneycomb {
+    /// Start a server on a free local port, accepting requests with `api_key`.
+    pub fn start(api_key: impl Into<String>) -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("failed to bind mock honeycomb");
+        let addr = listener
+            .local_addr()
+            .expect("failed to bind mock honeycomb");
+        let state = Arc::new(Mutex::new(State {
+            api_key: api_key.into(),
+            ..Default::default()
+        }));
+        let stopped = Arc::new(AtomicBool::new(false));
+
+        let worker = {
+            let state = state.clone();
+            let stopped = stopped.clone();
+            std::thread::Builder::new()
+                .name("mock-honeycomb".to_string())
+                .spawn(move || {
+                    for stream in listener.incoming() {
+                        if stopped.load(Ordering::Relaxed) {
+                            return;
+                        }
+                        if let Ok(stream) = stream {
+                            let state = state.clone();
+                            std::thread::spawn(move || serve(stream, &state));
+                        }
+                    }
+                })
+                .expect("failed to spawn mock honeycomb thread")
+        };
+
+        MockHoneycomb {
+            addr,
+            state,
+            stopped,
+            worker: Some(worker),
+        }
+    }
+
+    fn lock(&self) -> impl std::ops::DerefMut<Target = State> + '_ {
+        lock(&self.state)
+    }
+
+    /// URL of the server, to be passed to `Builder::api_host`.
+    pub fn api_host(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    /// Respond to the next `requests` batch requests with `status` instead of accepting their
+    /// events, e.g. 429 to simulate rate limiting or 503 an outage.
+    pub fn fail_next(&self, requests: usize, status: u16) {
+        self.lock()
+            .failures
+            .extend(std::iter::repeat_n(status, requests));
+    }
+
+    /// Accept requests with `api_key` from now on, e.g. after simulating a revoked key.
+    pub fn set_api_key(&self, api_key: impl Into<String>) {
+        self.lock().api_key = api_key.into();
+    }
+
+    /// Get the events accepted so far, in the order they were received.
+    pub fn received(&self) -> Vec<ReceivedEvent> {
+        self.lock().events.clone()
+    }
+
+    /// Number of batch requests received so far, including rejected ones.
+    pub fn batches(&self) -> usize {
+        self.lock().batches
+    }
+
+    /// Wait at most `timeout` for at least `events` events to be accepted, e.g. events queued
+    /// by libhoney's background threads. Returns whether they were.
+    pub fn wait_for(&self, events: usize, timeout: Duration) -> bool {
+        let deadline = Instant::now() + timeout;
+        while self.lock().events.len() < events {
+            if Instant::now() >= deadline {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        true
+    }
+}
+
+impl Drop for MockHoneycomb {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // wake up the accepting thread so it notices it was stopped
+        let _ = TcpStream::connect(self.addr);
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn lock(state: &Mutex<State>) -> impl std::ops::DerefMut<Target = State> + '_ {
+    // succeed or die. failure is unrecoverable (mutex poisoned)
+    #[cfg(not(feature = "use_parking_lot"))]
+    let state = state.lock().unwrap();
+    #[cfg(feature = "use_parking_lot")]
+    let state = state.lock();
+
+    state
+}
+
+struct Request {
+    method: String,
+    path: String,
+    api_key: Option<String>,
+    body: Vec<u8>,
+}
+
+// serves requests on a keep-alive connection until the client closes it
+fn serve(stream: TcpStream, state: &Mutex<State>) {
+    let _ = stream.set_read_timeout(Some(Duration::from_secs(30)));
+    let mut writer = match stream.try_clone() {
+        Ok(writer) => writer,
+        Err(_) => return,
+    };
+    let mut reader = BufReader::new(stream);
+    while let Ok(Some(request)) = read_request(&mut reader) {
+        let (status, body) = respond(request, state);
+        let response = format!(
+            "HTTP/1.1 {} {}\r\ncontent-type: application/json\r\ncontent-length: {}\r\n\r\n{}",
+            status,
+            reason(status),
+            body.len(),
+            body
+        );
+        if writer.write_all(response.as_bytes()).is_err() {
+            return;
+        }
+    }
+}
+
+fn read_request(reader: &mut BufReader<TcpStream>) -> io::Result<Option<Request>> {
+    let mut line = String::new();
+    if reader.read_line(&mut line)? == 0 {
+        return Ok(None);
+    }
+    let mut parts = line.split_whitespace();
+    let method = parts.next().unwrap_or_default().to_string();
+    let path = parts.next().unwrap_or_default().to_string();
+
+    let mut api_key = None;
+    let mut content_length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let value = value.trim();
+            if name.eq_ignore_ascii_case("x-honeycomb-team") {
+                api_key = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.parse().unwrap_or_default();
+            }
+        }
+    }
+
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(Request {
+        method,
+        path,
+        api_key,
+        body,
+    }))
+}
+
+fn respond(request: Request, state: &Mutex<State>) -> (u16, String) {
+    let mut state = lock(state);
+    let is_batch = request.method == "POST" && request.path.starts_with(BATCH_ENDPOINT);
+    if is_batch {
+        state.batches += 1;
+    }
+    if request.api_key.as_deref() != Some(state.api_key.as_str()) {
+        return (401, error("unknown API key - check your credentials"));
+    }
+
+    match request.method.as_str() {
+        "GET" if request.path == AUTH_ENDPOINT => {
+            (200, json!({ "team": { "slug": "mock" } }).to_string())
+        }
+        "POST" if request.path.starts_with(MARKERS_ENDPOINT) => {
+            let mut marker: Value = parse_json(&request.body).unwrap_or_default();
+            marker["id"] = json!("mock-marker");
+            (201, marker.to_string())
+        }
+        "POST" if is_batch => {
+            if let Some(status) = state.failures.pop_front() {
+                return (status, error("injected failure"));
+            }
+            let dataset = request.path[BATCH_ENDPOINT.len()..].to_string();
+            let batch = match parse_json(&request.body) {
+                Some(Value::Array(batch)) => batch,
+                _ => {
+                    return (
+                        400,
+                        error("request body is malformed and cannot be read as JSON"),
+                    )
+                }
+            };
+            let statuses: Vec<Value> = batch.iter().map(|_| json!({ "status": 202 })).collect();
+            for event in batch {
+                let data = match event.get("data") {
+                    Some(Value::Object(data)) => data.clone().into_iter().collect(),
+                    _ => HashMap::new(),
+                };
+                state.events.push(ReceivedEvent {
+                    dataset: dataset.clone(),
+                    data,
+                    sample_rate: event.get("samplerate").and_then(Value::as_u64),
+                });
+            }
+            (200, Value::Array(statuses).to_string())
+        }
+        _ => (404, error("not found")),
+    }
+}
+
+fn parse_json(body: &[u8]) -> Option<Value> {
+    std::str::from_utf8(body).ok()?.parse().ok()
+}
+
+fn error(message: &str) -> String {
+    json!({ "error": message }).to_string()
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        404 => "Not Found",
+        429 => "Too Many Requests",
+        500 => "Internal Server Error",
+        503 => "Service Unavailable",
+        _ => "Unknown",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{register_dist_tracing_root, HoneycombTelemetry, TelemetryError, TraceId};
+    use tracing_subscriber::layer::Layer;
+
+    #[test]
+    fn exercises_the_transmission_path() {
+        let server = MockHoneycomb::start("key");
+        let errors = Arc::new(Mutex::new(Vec::new()));
+        let layer = {
+            let errors = errors.clone();
+            HoneycombTelemetry::builder()
+                .api_host(server.api_host())
+                .api_key("key")
+                .dataset("app")
+                .send_now(Duration::from_secs(5))
+                .on_error(move |err: TelemetryError| lock_errors(&errors).push(err))
+                .build()
+        };
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let report = |name: &str| {
+                let _span = tracing::info_span!("request", name).entered();
+                register_dist_tracing_root(TraceId::new(), None).unwrap();
+            };
+            report("accepted");
+            server.fail_next(1, 429);
+            report("rate limited");
+            server.set_api_key("rotated");
+            report("unauthorized");
+        });
+
+        let received = server.received();
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].dataset, "app");
+        assert_eq!(received[0].data["tracing.name"], json!("accepted"));
+        assert_eq!(server.batches(), 3);
+
+        let errors = lock_errors(&errors);
+        assert_eq!(errors.len(), 2);
+        assert!(errors[0].is_rate_limited());
+        assert!(errors[1].is_unauthorized());
+    }
+
+    fn lock_errors(
+        errors: &Mutex<Vec<TelemetryError>>,
+    ) -> impl std::ops::DerefMut<Target = Vec<TelemetryError>> + '_ {
+        #[cfg(not(feature = "use_parking_lot"))]
+        let errors = errors.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let errors = errors.lock();
+
+        errors
+    }
+}
@@ -0,0 +1,139 @@
+use opentelemetry::trace::{self as otel, TraceContextExt};
+use std::num::NonZeroU64;
+
+use crate::span_id::SpanIdRepr;
+use crate::{PropagationContext, SamplingDecision, SpanId, TraceId};
+
+/// Converted to 32 lowercase hex digits, the W3C Trace Context format used by OpenTelemetry.
+impl From<otel::TraceId> for TraceId {
+    fn from(trace_id: otel::TraceId) -> Self {
+        TraceId(format!("{:032x}", u128::from_be_bytes(trace_id.to_bytes())))
+    }
+}
+
+/// Converted using `TraceId::to_w3c`, so any trace id has a stable OpenTelemetry equivalent.
+impl From<&TraceId> for otel::TraceId {
+    fn from(trace_id: &TraceId) -> Self {
+        let w3c = trace_id.to_w3c();
+        // `to_w3c` always returns 32 hex digits
+        let id = u128::from_str_radix(&w3c.0, 16).unwrap_or(1);
+        otel::TraceId::from_bytes(id.to_be_bytes())
+    }
+}
+
+impl SpanId {
+    /// Convert an OpenTelemetry span id to a `SpanIdFormat::Hex64` span id. Returns `None` for
+    /// the invalid, all zeros, span id.
+    pub fn from_otel(span_id: otel::SpanId) -> Option<Self> {
+        NonZeroU64::new(u64::from_be_bytes(span_id.to_bytes()))
+            .map(|id| SpanId(SpanIdRepr::Hex64(id)))
+    }
+}
+
+/// Converted to 64 bits, the same way as the parent of an `XrayTraceHeader`.
+impl From<&SpanId> for otel::SpanId {
+    fn from(span_id: &SpanId) -> Self {
+        otel::SpanId::from_bytes(span_id.to_u64().get().to_be_bytes())
+    }
+}
+
+impl PropagationContext {
+    /// Convert the span context of a span created by OpenTelemetry instrumentation, e.g. an
+    /// OpenTelemetry-instrumented HTTP library, so that spans registered under it (see
+    /// `register_dist_tracing_root`) belong to the same trace. Returns `None` if the span
+    /// context is invalid.
+    ///
+    /// The sampled trace flag is converted to a `SamplingDecision` with a sample rate of 1.
+    pub fn from_span_context(span_context: &otel::SpanContext) -> Option<Self> {
+        if !span_context.is_valid() {
+            return None;
+        }
+
+        Some(PropagationContext {
+            trace_id: span_context.trace_id().into(),
+            parent_span: SpanId::from_otel(span_context.span_id())?,
+            sampling: Some(SamplingDecision {
+                sampled: span_context.is_sampled(),
+                sample_rate: 1,
+            }),
+            experiments: Default::default(),
+        })
+    }
+
+    /// Convert the span context of the active span of an OpenTelemetry `Context`, if any. See
+    /// `PropagationContext::from_span_context`.
+    pub fn from_otel_context(cx: &opentelemetry::Context) -> Option<Self> {
+        if !cx.has_active_span() {
+            return None;
+        }
+        PropagationContext::from_span_context(cx.span().span_context())
+    }
+
+    /// Convert to a remote OpenTelemetry span context, e.g. to parent spans created by
+    /// OpenTelemetry instrumentation under the current span (see
+    /// `PropagationContext::current`).
+    ///
+    /// The sampled trace flag is set unless the sampling decision drops the trace. Experiment
+    /// assignments are not converted.
+    pub fn to_span_context(&self) -> otel::SpanContext {
+        let sampled = self.sampling.is_none_or(|sampling| sampling.sampled);
+        otel::SpanContext::new(
+            (&self.trace_id).into(),
+            (&self.parent_span).into(),
+            if sampled {
+                otel::TraceFlags::SAMPLED
+            } else {
+                otel::TraceFlags::default()
+            },
+            true,
+            otel::TraceState::default(),
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn span_context_round_trip() {
+        let span_context = otel::SpanContext::new(
+            otel::TraceId::from_hex("4bf92f3577b34da6a3ce929d0e0e4736").unwrap(),
+            otel::SpanId::from_hex("00f067aa0ba902b7").unwrap(),
+            otel::TraceFlags::SAMPLED,
+            true,
+            otel::TraceState::default(),
+        );
+        let ctx = PropagationContext::from_span_context(&span_context).unwrap();
+        assert_eq!(
+            ctx.trace_id,
+            TraceId::from("4bf92f3577b34da6a3ce929d0e0e4736")
+        );
+        assert_eq!(
+            ctx.parent_span,
+            SpanId::from_str("00f067aa0ba902b7").unwrap()
+        );
+        assert_eq!(ctx.sampling.map(|sampling| sampling.sampled), Some(true));
+        assert_eq!(ctx.to_span_context(), span_context);
+
+        assert_eq!(
+            PropagationContext::from_span_context(&otel::SpanContext::empty_context()),
+            None
+        );
+    }
+
+    #[test]
+    fn converts_honeycomb_ids() {
+        let trace_id = TraceId::from("not-a-w3c-trace-id");
+        let otel_trace_id = otel::TraceId::from(&trace_id);
+        assert_eq!(TraceId::from(otel_trace_id), trace_id.to_w3c());
+
+        let span_id = SpanId::from_str("2a-1").unwrap();
+        let otel_span_id = otel::SpanId::from(&span_id);
+        assert_eq!(
+            SpanId::from_otel(otel_span_id).map(|id| id.to_u64()),
+            Some(span_id.to_u64())
+        );
+    }
+}
@@ -0,0 +1,463 @@
+use chrono::{DateTime, Utc};
+use libhoney::{json, Value};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::circuit_breaker::CircuitBreaker;
+use crate::diagnostics::{Diagnostic, InternalLogMode};
+use crate::stats::{Stats, TelemetryStats};
+use crate::telemetry_error::{ErrorHandler, TelemetryError};
+use crate::visitor::TraceFieldNames;
+use crate::{SpanId, TraceId};
+
+const TRACES_ENDPOINT: &str = "/v1/traces";
+
+/// Exports spans and events over OTLP/HTTP, using the JSON encoding, instead of honeycomb.io's
+/// Events API, see `Builder::otlp_endpoint`.
+///
+/// Spans and events are converted from the values published to the Events API, and queued for
+/// a background thread sending them in batches, one HTTP call per dataset and service.
+#[derive(Debug)]
+pub(crate) struct OtlpTransmission {
+    sender: Option<SyncSender<Message>>,
+    worker: Option<JoinHandle<()>>,
+    shared: Arc<Shared>,
+}
+
+#[derive(Debug)]
+struct Shared {
+    endpoint: String,
+    trace_fields: TraceFieldNames,
+    on_error: ErrorHandler,
+    internal_log_mode: InternalLogMode,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    stats: Stats,
+    queue_depth: AtomicUsize,
+    // set while the collector is unreachable or overloaded
+    failing: AtomicBool,
+}
+
+#[derive(Debug)]
+enum Message {
+    Event(PendingEvent),
+    Flush(SyncSender<()>),
+}
+
+#[derive(Debug)]
+struct PendingEvent {
+    options: libhoney::client::Options,
+    data: HashMap<String, Value>,
+}
+
+impl OtlpTransmission {
+    pub(crate) fn new(
+        endpoint: String,
+        options: &libhoney::transmission::Options,
+        trace_fields: TraceFieldNames,
+        on_error: ErrorHandler,
+        internal_log_mode: InternalLogMode,
+        circuit_breaker: Option<Arc<CircuitBreaker>>,
+    ) -> Self {
+        let client = reqwest::blocking::Client::builder()
+            .build()
+            .expect("failed to initialize otlp http client");
+        let shared = Arc::new(Shared {
+            endpoint: endpoint.trim_end_matches('/').to_string(),
+            trace_fields,
+            on_error,
+            internal_log_mode,
+            circuit_breaker,
+            stats: Stats::default(),
+            queue_depth: AtomicUsize::new(0),
+            failing: AtomicBool::new(false),
+        });
+
+        let (sender, receiver) = mpsc::sync_channel(options.pending_work_capacity.max(1));
+        let worker = {
+            let shared = shared.clone();
+            let max_batch_size = options.max_batch_size.max(1);
+            let batch_timeout = options.batch_timeout;
+            std::thread::Builder::new()
+                .name("honeycomb-otlp".to_string())
+                .spawn(move || {
+                    shared.process(client, receiver, max_batch_size, batch_timeout);
+                })
+                .expect("failed to spawn honeycomb otlp thread")
+        };
+
+        OtlpTransmission {
+            sender: Some(sender),
+            worker: Some(worker),
+            shared,
+        }
+    }
+
+    /// Queue an event for sending. Sampling is assumed to have already happened.
+    pub(crate) fn send(&self, options: &libhoney::client::Options, data: HashMap<String, Value>) {
+        let event = PendingEvent {
+            options: options.clone(),
+            data,
+        };
+        let sender = self
+            .sender
+            .as_ref()
+            .expect("otlp transmission already stopped");
+        // counted before sending, as the event may be sent before try_send returns
+        self.shared.queue_depth.fetch_add(1, Ordering::Relaxed);
+        if let Err(err) = sender.try_send(Message::Event(event)) {
+            self.shared.queue_depth.fetch_sub(1, Ordering::Relaxed);
+            self.shared.stats.record_dropped(1);
+            if let TrySendError::Full(_) = err {
+                self.shared
+                    .on_error
+                    .report(TelemetryError::Enqueue(libhoney::Error {
+                        message: "otlp queue is full".to_string(),
+                        kind: libhoney::ErrorKind::ChannelError,
+                    }));
+            }
+        }
+    }
+
+    /// Send the events queued so far right away, and wait at most `timeout` for them to be
+    /// sent.
+    pub(crate) fn flush(&self, timeout: Duration) {
+        let (done, flushed) = mpsc::sync_channel(1);
+        let sender = self
+            .sender
+            .as_ref()
+            .expect("otlp transmission already stopped");
+        if sender.send(Message::Flush(done)).is_ok() {
+            let _ = flushed.recv_timeout(timeout);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> TelemetryStats {
+        let queue_depth = self.shared.queue_depth.load(Ordering::Relaxed);
+        self.shared.stats.snapshot(queue_depth)
+    }
+
+    pub(crate) fn record_rate_limited(&self, events: u64) {
+        self.shared.stats.record_rate_limited(events);
+    }
+
+    pub(crate) fn record_circuit_open(&self, events: u64) {
+        self.shared.stats.record_circuit_open(events);
+    }
+}
+
+impl Drop for OtlpTransmission {
+    fn drop(&mut self) {
+        // the worker sends pending events and exits once the channel is closed
+        drop(self.sender.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+impl Shared {
+    fn process(
+        &self,
+        client: reqwest::blocking::Client,
+        receiver: Receiver<Message>,
+        max_batch_size: usize,
+        batch_timeout: Duration,
+    ) {
+        let mut pending = Vec::new();
+        let mut deadline = Instant::now() + batch_timeout;
+        loop {
+            let timeout = deadline.saturating_duration_since(Instant::now());
+            match receiver.recv_timeout(timeout) {
+                Ok(Message::Event(event)) => {
+                    pending.push(event);
+                    if pending.len() < max_batch_size {
+                        continue;
+                    }
+                }
+                Ok(Message::Flush(done)) => {
+                    self.send_batches(&client, std::mem::take(&mut pending));
+                    let _ = done.try_send(());
+                }
+                Err(RecvTimeoutError::Timeout) => {}
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.send_batches(&client, pending);
+                    return;
+                }
+            }
+            self.send_batches(&client, std::mem::take(&mut pending));
+            deadline = Instant::now() + batch_timeout;
+        }
+    }
+
+    fn send_batches(&self, client: &reqwest::blocking::Client, pending: Vec<PendingEvent>) {
+        if pending.is_empty() {
+            return;
+        }
+
+        let mut batches: HashMap<(String, String, String), Vec<Value>> = HashMap::new();
+        for event in pending {
+            let service_name = event
+                .data
+                .get("service_name")
+                .and_then(Value::as_str)
+                .unwrap_or_default()
+                .to_string();
+            let libhoney::client::Options {
+                api_key, dataset, ..
+            } = event.options;
+            batches
+                .entry((dataset, api_key, service_name))
+                .or_default()
+                .push(to_otlp_span(event.data, &self.trace_fields));
+        }
+
+        for ((dataset, api_key, service_name), spans) in batches {
+            let events = spans.len();
+            let body = json!({
+                "resourceSpans": [{
+                    "resource": {
+                        "attributes": [attribute("service.name", json!(service_name))],
+                    },
+                    "scopeSpans": [{
+                        "scope": {
+                            "name": env!("CARGO_PKG_NAME"),
+                            "version": env!("CARGO_PKG_VERSION"),
+                        },
+                        "spans": spans,
+                    }],
+                }],
+            });
+
+            let sent_at = Instant::now();
+            let res = client
+                .post(&format!("{}{}", self.endpoint, TRACES_ENDPOINT))
+                .header("x-honeycomb-team", api_key)
+                .header("x-honeycomb-dataset", dataset.as_str())
+                .header("Content-Type", "application/json")
+                .body(body.to_string())
+                .send()
+                .and_then(|response| response.error_for_status());
+            self.stats.record_latency(sent_at.elapsed());
+            self.queue_depth.fetch_sub(events, Ordering::Relaxed);
+            match res {
+                Ok(_) => {
+                    self.stats.record_sent(events as u64);
+                    if let Some(circuit_breaker) = &self.circuit_breaker {
+                        circuit_breaker.record_success();
+                    }
+                    if self.failing.swap(false, Ordering::Relaxed) {
+                        self.internal_log_mode.emit(&Diagnostic::Recovered);
+                    }
+                }
+                Err(err) => {
+                    self.stats.record_send_errors(events as u64);
+                    let unavailable = err.status().is_none_or(|status| status.is_server_error());
+                    if unavailable {
+                        self.failing.store(true, Ordering::Relaxed);
+                        if let Some(circuit_breaker) = &self.circuit_breaker {
+                            circuit_breaker.record_failure();
+                        }
+                    }
+                    self.on_error.report(TelemetryError::Send {
+                        dataset,
+                        events,
+                        status: err.status().map(|status| status.as_u16()),
+                        message: err.to_string(),
+                    });
+                }
+            }
+        }
+    }
+}
+
+// values holding the span's identity and timing, converted to the corresponding OTLP fields
+// instead of attributes
+const SPAN_FIELDS: [&str; 5] = [
+    "name",
+    "Timestamp",
+    "duration_ms",
+    "service_name",
+    "span.kind",
+];
+
+/// Convert the values of a span or event, as published to the Events API, to an OTLP span.
+/// Events are converted to spans without duration.
+fn to_otlp_span(mut data: HashMap<String, Value>, trace_fields: &TraceFieldNames) -> Value {
+    let trace_id = data
+        .remove(&trace_fields.trace_id)
+        .and_then(|id| id.as_str().map(|id| TraceId::from(id).to_w3c().to_string()))
+        .unwrap_or_default();
+    let span_id = data
+        .remove(&trace_fields.span_id)
+        .and_then(|id| id.as_str().map(to_otlp_span_id))
+        // events have no span id of their own
+        .unwrap_or_else(|| format!("{:016x}", rand::random::<u64>().max(1)));
+    let parent_span_id = data
+        .remove(&trace_fields.parent_id)
+        .and_then(|id| id.as_str().map(to_otlp_span_id))
+        .unwrap_or_default();
+
+    let start = data
+        .get("Timestamp")
+        .and_then(Value::as_str)
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now);
+    let start_ns = start.timestamp_nanos_opt().unwrap_or_default();
+    let duration_ns = data
+        .get("duration_ms")
+        .and_then(Value::as_f64)
+        .map(|duration_ms| (duration_ms * 1_000_000.0) as i64)
+        .unwrap_or_default();
+    let kind = match data.get("span.kind").and_then(Value::as_str) {
+        Some("server") => 2,
+        Some("client") => 3,
+        Some("producer") => 4,
+        Some("consumer") => 5,
+        _ => 1,
+    };
+    // STATUS_CODE_ERROR, otherwise unset
+    let status = match data.get("error") {
+        Some(Value::Bool(true)) => json!({ "code": 2 }),
+        _ => json!({}),
+    };
+    let name = data
+        .get("name")
+        .and_then(Value::as_str)
+        .unwrap_or_default()
+        .to_string();
+
+    let mut attributes: Vec<Value> = data
+        .into_iter()
+        .filter(|(key, value)| !SPAN_FIELDS.contains(&key.as_str()) && !value.is_null())
+        .map(|(key, value)| attribute(&key, value))
+        .collect();
+    attributes.sort_by(|a, b| a["key"].as_str().cmp(&b["key"].as_str()));
+
+    json!({
+        "traceId": trace_id,
+        "spanId": span_id,
+        "parentSpanId": parent_span_id,
+        "name": name,
+        "kind": kind,
+        // 64-bit integers are encoded as strings
+        "startTimeUnixNano": start_ns.to_string(),
+        "endTimeUnixNano": (start_ns + duration_ns).to_string(),
+        "attributes": attributes,
+        "status": status,
+    })
+}
+
+// span ids are published as `span-{id}`, OTLP requires 16 hex digits
+fn to_otlp_span_id(id: &str) -> String {
+    let id = id.strip_prefix("span-").unwrap_or(id);
+    match id.parse::<SpanId>() {
+        Ok(span_id) => format!("{:016x}", span_id.to_u64()),
+        Err(_) => TraceId::from(id).to_w3c().to_string()[..16].to_string(),
+    }
+}
+
+fn attribute(key: &str, value: Value) -> Value {
+    let value = match value {
+        Value::Bool(value) => json!({ "boolValue": value }),
+        Value::Number(number) => match number.as_i64() {
+            Some(value) => json!({ "intValue": value.to_string() }),
+            None => json!({ "doubleValue": number.as_f64() }),
+        },
+        Value::String(value) => json!({ "stringValue": value }),
+        value => json!({ "stringValue": value.to_string() }),
+    };
+    json!({ "key": key, "value": value })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn converts_published_values_to_otlp_spans() {
+        let mut data = HashMap::new();
+        data.insert("trace.trace_id".to_string(), json!("my-trace"));
+        data.insert("trace.span_id".to_string(), json!("span-2a-1"));
+        data.insert("trace.parent_id".to_string(), json!(null));
+        data.insert("name".to_string(), json!("request"));
+        data.insert(
+            "Timestamp".to_string(),
+            json!("2020-01-01T00:00:00.000000001+00:00"),
+        );
+        data.insert("duration_ms".to_string(), json!(1.5));
+        data.insert("span.kind".to_string(), json!("server"));
+        data.insert("error".to_string(), json!(true));
+        data.insert("http.status".to_string(), json!(500));
+
+        let span = to_otlp_span(data, &TraceFieldNames::honeycomb());
+        assert_eq!(
+            span["traceId"],
+            json!(TraceId::from("my-trace").to_w3c().to_string())
+        );
+        assert_eq!(span["traceId"].as_str().unwrap().len(), 32);
+        assert_eq!(span["spanId"].as_str().unwrap().len(), 16);
+        assert_eq!(span["parentSpanId"], json!(""));
+        assert_eq!(span["kind"], json!(2));
+        assert_eq!(span["status"]["code"], json!(2));
+        assert_eq!(span["startTimeUnixNano"], json!("1577836800000000001"));
+        assert_eq!(span["endTimeUnixNano"], json!("1577836800001500001"));
+        assert_eq!(
+            span["attributes"],
+            json!([
+                { "key": "error", "value": { "boolValue": true } },
+                { "key": "http.status", "value": { "intValue": "500" } },
+            ])
+        );
+    }
+
+    #[test]
+    fn sends_batches_to_traces_endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let endpoint = format!("http://{}/", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // the body is the last thing sent, a json object
+            while !request.ends_with(b"}") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n{}")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let transmission = OtlpTransmission::new(
+            endpoint,
+            &libhoney::transmission::Options::default(),
+            TraceFieldNames::honeycomb(),
+            ErrorHandler::default(),
+            InternalLogMode::Silent,
+            None,
+        );
+        let options = libhoney::client::Options {
+            dataset: "otlp".to_string(),
+            ..Default::default()
+        };
+        let mut data = HashMap::new();
+        data.insert("service_name".to_string(), json!("svc"));
+        data.insert("name".to_string(), json!("request"));
+        transmission.send(&options, data);
+        transmission.flush(Duration::from_secs(5));
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /v1/traces HTTP/1.1"));
+        assert!(request.contains("x-honeycomb-dataset: otlp"));
+        assert!(request.contains(r#""stringValue":"svc""#));
+        assert_eq!(transmission.stats().events_sent, 1);
+    }
+}
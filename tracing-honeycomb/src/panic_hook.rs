@@ -0,0 +1,104 @@
+use std::any::Any;
+use std::backtrace::Backtrace;
+use std::panic::Location;
+use std::time::Duration;
+
+use crate::honeycomb::Reporter;
+
+// maximum time spent waiting for honeycomb.io before handing the panic to the previous hook
+const PANIC_FLUSH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Install a panic hook that reports panics to honeycomb.io, so crashes show up attached to
+/// the trace that was executing when they happened.
+///
+/// On panic, an error event holding the panic message (`panic.message`), its location
+/// (`panic.location`) and a backtrace (`panic.backtrace`) is emitted within the current span,
+/// which is marked as errored. The telemetry layer of the default subscriber is then flushed,
+/// waiting at most five seconds, before the previously installed hook (by default, the one
+/// printing the panic to stderr) is called. Like any event, the panic is only reported if it
+/// happens within a span.
+///
+/// Should be called once, after the subscriber is installed. Installing the hook again
+/// reports panics twice.
+pub fn install_panic_hook() {
+    let previous = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        report_panic(info.payload(), info.location());
+        previous(info);
+    }));
+}
+
+fn report_panic(payload: &(dyn Any + Send), location: Option<&Location<'_>>) {
+    let message = match payload.downcast_ref::<&str>() {
+        Some(message) => message,
+        None => payload
+            .downcast_ref::<String>()
+            .map(String::as_str)
+            .unwrap_or("Box<dyn Any>"),
+    };
+    let location = location.map(ToString::to_string).unwrap_or_default();
+    let backtrace = Backtrace::force_capture();
+    tracing::error!(
+        error = true,
+        panic.message = message,
+        panic.location = location.as_str(),
+        panic.backtrace = %backtrace,
+        "panic"
+    );
+
+    tracing::dispatcher::get_default(|dispatch| {
+        if let Some(reporter) = dispatch.downcast_ref::<Reporter>() {
+            reporter.flush(PANIC_FLUSH_TIMEOUT);
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{register_dist_tracing_root, HoneycombTelemetry, TraceId};
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+    use tracing_subscriber::layer::Layer;
+
+    #[test]
+    fn panics_are_reported_and_flushed() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let api_host = format!("http://{}", listener.local_addr().unwrap());
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            while !request.ends_with(b"]") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n[]")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let layer = HoneycombTelemetry::builder()
+            .api_host(api_host)
+            .dataset("crashes")
+            .send_now(Duration::from_secs(5))
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+        install_panic_hook();
+
+        let trace_id = TraceId::new();
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            let _guard = span.enter();
+            register_dist_tracing_root(trace_id.clone(), None).unwrap();
+            let res = std::panic::catch_unwind(|| panic!("boom"));
+            assert!(res.is_err());
+
+            // sent before the span closes
+            let request = server.join().unwrap();
+            assert!(request.contains(r#""panic.message":"boom""#));
+            assert!(request.contains(&trace_id.to_string()));
+        });
+    }
+}
@@ -0,0 +1,164 @@
+use crossbeam_queue::SegQueue;
+use libhoney::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of field maps kept for reuse by default, see `Builder::buffer_pool`.
+pub(crate) const DEFAULT_POOL_SIZE: usize = 64;
+
+// maps grown to hold more fields are dropped instead of being kept, so a few unusually large
+// spans don't pin memory for the lifetime of the process
+const MAX_POOLED_FIELDS: usize = 128;
+
+// strings of recycled maps, i.e. their keys and string values, kept for reuse by `pooled_string`
+// and shared by all pools. Bounded like the maps: strings beyond `MAX_SPARE_STRINGS` or longer
+// than `MAX_SPARE_STRING_LEN` are dropped
+static SPARE_STRINGS: SegQueue<String> = SegQueue::new();
+static SPARE_STRINGS_LEN: AtomicUsize = AtomicUsize::new(0);
+const MAX_SPARE_STRINGS: usize = 4096;
+const MAX_SPARE_STRING_LEN: usize = 256;
+
+/// Get an owned copy of `s`, reusing the allocation of a string of a recycled map if one is
+/// spare. Field names, span names and targets repeat for every span and event published, and
+/// copying them into spare strings saves allocating them each time.
+pub(crate) fn pooled_string(s: &str) -> String {
+    match SPARE_STRINGS.pop() {
+        Some(mut spare) => {
+            SPARE_STRINGS_LEN.fetch_sub(1, Ordering::Relaxed);
+            spare.clear();
+            spare.push_str(s);
+            spare
+        }
+        None => s.to_string(),
+    }
+}
+
+fn recycle_string(s: String) {
+    if s.capacity() == 0 || s.capacity() > MAX_SPARE_STRING_LEN {
+        return;
+    }
+    // reserve a slot first, so concurrent recycling can't exceed the bound
+    if SPARE_STRINGS_LEN.fetch_add(1, Ordering::Relaxed) >= MAX_SPARE_STRINGS {
+        SPARE_STRINGS_LEN.fetch_sub(1, Ordering::Relaxed);
+        return;
+    }
+    SPARE_STRINGS.push(s);
+}
+
+/// Field maps kept for reuse across spans and events, so each report doesn't allocate (and
+/// grow) a fresh map. Maps are taken when a span or event is converted to the fields published
+/// to honeycomb.io, and recycled once these fields are serialized.
+///
+/// The pool is bounded: maps recycled while `size` of them are pooled already are dropped.
+#[derive(Debug)]
+pub(crate) struct BufferPool {
+    maps: SegQueue<HashMap<String, Value>>,
+    len: AtomicUsize,
+    size: AtomicUsize,
+}
+
+impl Default for BufferPool {
+    fn default() -> Self {
+        BufferPool::new(DEFAULT_POOL_SIZE)
+    }
+}
+
+impl BufferPool {
+    pub(crate) fn new(size: usize) -> Self {
+        BufferPool {
+            maps: SegQueue::new(),
+            len: AtomicUsize::new(0),
+            size: AtomicUsize::new(size),
+        }
+    }
+
+    /// Set the number of maps kept for reuse. Maps pooled beyond the new size are dropped as
+    /// they are taken.
+    pub(crate) fn set_size(&self, size: usize) {
+        self.size.store(size, Ordering::Relaxed);
+        while self.len.load(Ordering::Relaxed) > size {
+            if self.take_pooled().is_none() {
+                return;
+            }
+        }
+    }
+
+    /// Take an empty map, reused if one is pooled.
+    pub(crate) fn take(&self) -> HashMap<String, Value> {
+        self.take_pooled().unwrap_or_default()
+    }
+
+    fn take_pooled(&self) -> Option<HashMap<String, Value>> {
+        let map = self.maps.pop()?;
+        self.len.fetch_sub(1, Ordering::Relaxed);
+        Some(map)
+    }
+
+    /// Return a map to the pool, once its fields are no longer needed. Its keys and string
+    /// values are kept for reuse by `pooled_string`.
+    pub(crate) fn recycle(&self, mut map: HashMap<String, Value>) {
+        let size = self.size.load(Ordering::Relaxed);
+        if size == 0 {
+            return;
+        }
+        for (key, value) in map.drain() {
+            recycle_string(key);
+            if let Value::String(value) = value {
+                recycle_string(value);
+            }
+        }
+
+        if map.capacity() == 0 || map.capacity() > MAX_POOLED_FIELDS {
+            return;
+        }
+        // reserve a slot first, so concurrent recycling can't exceed the size
+        if self.len.fetch_add(1, Ordering::Relaxed) >= size {
+            self.len.fetch_sub(1, Ordering::Relaxed);
+            return;
+        }
+        self.maps.push(map);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libhoney::json;
+
+    #[test]
+    fn recycled_maps_are_reused_up_to_the_pool_size() {
+        let pool = BufferPool::new(1);
+        let mut map = pool.take();
+        map.insert("name".to_string(), json!("span"));
+        let capacity = map.capacity();
+        pool.recycle(map);
+        pool.recycle(HashMap::with_capacity(4));
+
+        let reused = pool.take();
+        assert!(reused.is_empty());
+        assert_eq!(reused.capacity(), capacity);
+        // the second map was dropped, the pool being full
+        assert_eq!(pool.take().capacity(), 0);
+
+        pool.recycle(HashMap::with_capacity(MAX_POOLED_FIELDS * 2));
+        assert_eq!(pool.take().capacity(), 0);
+
+        pool.recycle(reused);
+        pool.set_size(0);
+        assert_eq!(pool.take().capacity(), 0);
+    }
+
+    #[test]
+    fn strings_of_recycled_maps_are_reused() {
+        let pool = BufferPool::new(1);
+        let mut map = pool.take();
+        map.insert("service_name".to_string(), json!("checkout"));
+        map.insert("duration_ms".to_string(), json!(1.5));
+        pool.recycle(map);
+
+        // spare strings are shared with concurrently running tests, only check their contents
+        for name in &["name", "Timestamp", "a.much.longer.field.name"] {
+            assert_eq!(pooled_string(name), *name);
+        }
+    }
+}
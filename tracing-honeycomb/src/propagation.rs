@@ -0,0 +1,671 @@
+use serde_json::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display};
+use std::hash::BuildHasher;
+use std::str::FromStr;
+
+use crate::experiments::EXPERIMENT_FIELD_PREFIX;
+use crate::sampling::SamplingDecision;
+use crate::{SpanId, TraceCtxError, TraceId};
+
+/// Name of the header used to propagate trace context between services.
+///
+/// Uses the same `1;trace_id=...,parent_id=...` format as Honeycomb's beelines.
+pub const HONEYCOMB_TRACE_HEADER: &str = "x-honeycomb-trace";
+
+/// Name of the header used by AWS X-Ray, and set by e.g. ALB and API Gateway, to propagate
+/// trace context. See `XrayTraceHeader`.
+pub const XRAY_TRACE_HEADER: &str = "x-amzn-trace-id";
+
+/// Name of the header used by W3C Trace Context, and by OpenTelemetry by default, to propagate
+/// trace context, e.g. `00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01`.
+pub const W3C_TRACE_HEADER: &str = "traceparent";
+
+/// Name of the header used by the single-header B3 format of Zipkin to propagate trace
+/// context, e.g. `80f198ee56343ba864fe8b2a57d3eff7-e457b5a2e4d86bd1-1`.
+pub const B3_TRACE_HEADER: &str = "b3";
+
+// multi-header B3 format
+const B3_TRACE_ID_HEADER: &str = "x-b3-traceid";
+const B3_SPAN_ID_HEADER: &str = "x-b3-spanid";
+const B3_SAMPLED_HEADER: &str = "x-b3-sampled";
+const B3_FLAGS_HEADER: &str = "x-b3-flags";
+
+/// Distributed trace context propagated from a caller to the services it calls.
+///
+/// `Display` and `FromStr` are guaranteed to round-trip, using the value format of the
+/// `HONEYCOMB_TRACE_HEADER` header.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct PropagationContext {
+    /// Trace to which the remote span belongs.
+    pub trace_id: TraceId,
+    /// Remote span that should become the parent of the local root span.
+    pub parent_span: SpanId,
+    /// Sampling decision made for the trace, if any.
+    pub sampling: Option<SamplingDecision>,
+    /// Experiment and feature flag assignments of the trace, by flag name. See
+    /// `set_trace_experiment`.
+    ///
+    /// Propagated as `experiment.`-prefixed fields of the beeline `context` field, a base64
+    /// encoded JSON object of trace-level fields. Other trace-level fields are ignored.
+    pub experiments: BTreeMap<String, String>,
+}
+
+impl PropagationContext {
+    /// Capture the distributed trace context associated with the current span.
+    pub fn current() -> Result<Self, TraceCtxError> {
+        let (trace_id, parent_span) = crate::current_dist_trace_ctx()?;
+        let sampling = crate::current_sampling_decision().ok();
+        let (trace_id, experiments) = crate::with_current_telemetry(|telemetry| {
+            let experiments = telemetry.experiments(&trace_id);
+            (telemetry.propagated_trace_id(trace_id.clone()), experiments)
+        })
+        .unwrap_or((trace_id, Default::default()));
+
+        Ok(PropagationContext {
+            trace_id,
+            parent_span,
+            sampling,
+            experiments,
+        })
+    }
+
+    /// Register the current span as the local root of the distributed trace described by this
+    /// context, honoring its sampling decision and experiment assignments, if any.
+    pub fn register_dist_tracing_root(self) -> Result<(), TraceCtxError> {
+        match self.sampling {
+            Some(sampling) => crate::register_dist_tracing_root_with_sampling(
+                self.trace_id,
+                Some(self.parent_span),
+                sampling,
+            )?,
+            None => crate::register_dist_tracing_root(self.trace_id, Some(self.parent_span))?,
+        }
+
+        for (flag, variant) in self.experiments {
+            crate::set_trace_experiment(flag, variant)?;
+        }
+        Ok(())
+    }
+}
+
+/// Error returned when parsing a `PropagationContext` fails.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParsePropagationContextError {
+    /// The header version was missing or is not supported.
+    UnsupportedVersion,
+    /// A required field was missing.
+    MissingField(&'static str),
+    /// A field could not be parsed.
+    InvalidField(&'static str),
+}
+
+impl Display for ParsePropagationContextError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsupportedVersion => write!(f, "unsupported trace header version"),
+            Self::MissingField(field) => write!(f, "trace header is missing {}", field),
+            Self::InvalidField(field) => write!(f, "trace header has invalid {}", field),
+        }
+    }
+}
+
+impl std::error::Error for ParsePropagationContextError {}
+
+impl FromStr for PropagationContext {
+    type Err = ParsePropagationContextError;
+
+    /// Parses a `HONEYCOMB_TRACE_HEADER` header value. Unknown fields are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = match s.split_once(';') {
+            Some(("1", fields)) => fields,
+            _ => return Err(ParsePropagationContextError::UnsupportedVersion),
+        };
+
+        let mut trace_id = None;
+        let mut parent_span = None;
+        let mut sampling = None;
+        let mut experiments = BTreeMap::new();
+        for (key, value) in fields.split(',').filter_map(|kv| kv.split_once('=')) {
+            match key {
+                "trace_id" => trace_id = Some(TraceId::from(value)),
+                "parent_id" => {
+                    let span_id = SpanId::from_str(value)
+                        .map_err(|_| ParsePropagationContextError::InvalidField("parent_id"))?;
+                    parent_span = Some(span_id);
+                }
+                "sampling" => {
+                    let decision = SamplingDecision::from_str(value)
+                        .map_err(|_| ParsePropagationContextError::InvalidField("sampling"))?;
+                    sampling = Some(decision);
+                }
+                "context" => experiments = decode_experiments(value)?,
+                _ => {}
+            }
+        }
+
+        Ok(PropagationContext {
+            trace_id: trace_id.ok_or(ParsePropagationContextError::MissingField("trace_id"))?,
+            parent_span: parent_span
+                .ok_or(ParsePropagationContextError::MissingField("parent_id"))?,
+            sampling,
+            experiments,
+        })
+    }
+}
+
+fn decode_experiments(
+    context: &str,
+) -> Result<BTreeMap<String, String>, ParsePropagationContextError> {
+    let invalid = ParsePropagationContextError::InvalidField("context");
+    let context = base64::decode(context).map_err(|_| invalid)?;
+    let context = String::from_utf8(context).map_err(|_| invalid)?;
+
+    let fields = match Value::from_str(&context) {
+        Ok(Value::Object(fields)) => fields,
+        _ => return Err(invalid),
+    };
+    Ok(fields
+        .into_iter()
+        .filter_map(|(name, value)| match value {
+            Value::String(variant) => name
+                .strip_prefix(EXPERIMENT_FIELD_PREFIX)
+                .map(|flag| (flag.to_string(), variant)),
+            _ => None,
+        })
+        .collect())
+}
+
+impl Display for PropagationContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "1;trace_id={},parent_id={}",
+            self.trace_id, self.parent_span
+        )?;
+        if let Some(sampling) = &self.sampling {
+            write!(f, ",sampling={}", sampling)?;
+        }
+        if !self.experiments.is_empty() {
+            let context: BTreeMap<String, &String> = self
+                .experiments
+                .iter()
+                .map(|(flag, variant)| (format!("{}{}", EXPERIMENT_FIELD_PREFIX, flag), variant))
+                .collect();
+            write!(f, ",context={}", base64::encode(json!(context).to_string()))?;
+        }
+        Ok(())
+    }
+}
+
+/// Distributed trace context propagated using the AWS X-Ray `XRAY_TRACE_HEADER` header, e.g.
+/// `Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1`.
+///
+/// Load balancers such as ALB only set `Root`, so services behind them join the trace as
+/// local roots without a remote parent. Trace ids are converted with `TraceId::from_xray`
+/// and `TraceId::to_xray`. X-Ray parent ids are 64 bits, so only `SpanIdFormat::Hex64` span
+/// ids are propagated as is, and other span ids are converted to 64 bits.
+///
+/// `Display` and `FromStr` are guaranteed to round-trip, using the value format of the
+/// `XRAY_TRACE_HEADER` header.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct XrayTraceHeader {
+    /// Trace to which the remote span belongs, the `Root` field.
+    pub trace_id: TraceId,
+    /// Remote span that should become the parent of the local root span, the `Parent` field.
+    pub parent_span: Option<SpanId>,
+    /// Whether the caller sampled the trace, the `Sampled` field.
+    pub sampled: Option<bool>,
+}
+
+impl XrayTraceHeader {
+    /// Capture the distributed trace context associated with the current span.
+    pub fn current() -> Result<Self, TraceCtxError> {
+        let (trace_id, parent_span) = crate::current_dist_trace_ctx()?;
+        let sampled = crate::current_sampling_decision()
+            .ok()
+            .map(|sampling| sampling.sampled);
+
+        Ok(XrayTraceHeader {
+            trace_id,
+            parent_span: Some(parent_span),
+            sampled,
+        })
+    }
+
+    /// Register the current span as the local root of the distributed trace described by this
+    /// header, honoring the caller's sampling decision, if any.
+    pub fn register_dist_tracing_root(self) -> Result<(), TraceCtxError> {
+        match self.sampled {
+            Some(sampled) => crate::register_dist_tracing_root_with_sampling(
+                self.trace_id,
+                self.parent_span,
+                SamplingDecision {
+                    sampled,
+                    sample_rate: 1,
+                },
+            ),
+            None => crate::register_dist_tracing_root(self.trace_id, self.parent_span),
+        }
+    }
+}
+
+impl FromStr for XrayTraceHeader {
+    type Err = ParsePropagationContextError;
+
+    /// Parses a `XRAY_TRACE_HEADER` header value. Unknown fields, e.g. `Self` or `Lineage`,
+    /// are ignored.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut trace_id = None;
+        let mut parent_span = None;
+        let mut sampled = None;
+        for (key, value) in s.split(';').filter_map(|kv| kv.trim().split_once('=')) {
+            match key {
+                "Root" => {
+                    let id = TraceId::from_xray(value)
+                        .map_err(|_| ParsePropagationContextError::InvalidField("Root"))?;
+                    trace_id = Some(id);
+                }
+                "Parent" => {
+                    let invalid = ParsePropagationContextError::InvalidField("Parent");
+                    if value.len() != 16 {
+                        return Err(invalid);
+                    }
+                    parent_span = Some(SpanId::from_str(value).map_err(|_| invalid)?);
+                }
+                "Sampled" => match value {
+                    "1" => sampled = Some(true),
+                    "0" => sampled = Some(false),
+                    // sampling deferred to the callee
+                    "?" => {}
+                    _ => return Err(ParsePropagationContextError::InvalidField("Sampled")),
+                },
+                _ => {}
+            }
+        }
+
+        Ok(XrayTraceHeader {
+            trace_id: trace_id.ok_or(ParsePropagationContextError::MissingField("Root"))?,
+            parent_span,
+            sampled,
+        })
+    }
+}
+
+impl Display for XrayTraceHeader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Root={}", self.trace_id.to_xray())?;
+        if let Some(parent_span) = &self.parent_span {
+            write!(f, ";Parent={:016x}", parent_span.to_u64())?;
+        }
+        if let Some(sampled) = self.sampled {
+            write!(f, ";Sampled={}", if sampled { 1 } else { 0 })?;
+        }
+        Ok(())
+    }
+}
+
+/// Headers of an incoming request, from which `register_dist_tracing_root_from` extracts the
+/// remote trace context.
+pub trait HeaderExtractor {
+    /// Get the value of the header `name`, given in lowercase. Header names are
+    /// case-insensitive, so headers should be matched regardless of case.
+    fn get(&self, name: &str) -> Option<&str>;
+}
+
+impl<S: BuildHasher> HeaderExtractor for HashMap<String, String, S> {
+    fn get(&self, name: &str) -> Option<&str> {
+        HashMap::get(self, name)
+            .or_else(|| {
+                self.iter()
+                    .find(|(header, _)| header.eq_ignore_ascii_case(name))
+                    .map(|(_, value)| value)
+            })
+            .map(String::as_str)
+    }
+}
+
+#[cfg(feature = "awc")]
+impl HeaderExtractor for awc::http::header::HeaderMap {
+    fn get(&self, name: &str) -> Option<&str> {
+        awc::http::header::HeaderMap::get(self, name)?.to_str().ok()
+    }
+}
+
+/// Remote trace context extracted from the headers of an incoming request.
+#[derive(Debug, Eq, PartialEq)]
+pub(crate) enum ExtractedContext {
+    Propagation(PropagationContext),
+    Xray(XrayTraceHeader),
+}
+
+impl ExtractedContext {
+    /// Extract the remote trace context from the first of the `HONEYCOMB_TRACE_HEADER`,
+    /// `W3C_TRACE_HEADER`, `B3_TRACE_HEADER`, multi-header B3 and `XRAY_TRACE_HEADER` formats
+    /// present and valid, if any.
+    pub(crate) fn extract<H: HeaderExtractor + ?Sized>(headers: &H) -> Option<Self> {
+        let header = |name| headers.get(name).map(str::trim);
+
+        if let Some(ctx) = header(HONEYCOMB_TRACE_HEADER).and_then(|s| s.parse().ok()) {
+            return Some(ExtractedContext::Propagation(ctx));
+        }
+        if let Some(ctx) = header(W3C_TRACE_HEADER).and_then(parse_traceparent) {
+            return Some(ExtractedContext::Propagation(ctx));
+        }
+        if let Some(ctx) = header(B3_TRACE_HEADER).and_then(parse_b3) {
+            return Some(ExtractedContext::Propagation(ctx));
+        }
+        if let Some(ctx) = parse_b3_headers(headers) {
+            return Some(ExtractedContext::Propagation(ctx));
+        }
+        header(XRAY_TRACE_HEADER)
+            .and_then(|s| s.parse().ok())
+            .map(ExtractedContext::Xray)
+    }
+
+    pub(crate) fn register_dist_tracing_root(self) -> Result<(), TraceCtxError> {
+        match self {
+            ExtractedContext::Propagation(ctx) => ctx.register_dist_tracing_root(),
+            ExtractedContext::Xray(header) => header.register_dist_tracing_root(),
+        }
+    }
+}
+
+// `{version}-{trace_id}-{parent_id}-{flags}`, the sampled flag being the lowest bit of `flags`
+fn parse_traceparent(s: &str) -> Option<PropagationContext> {
+    let mut fields = s.split('-');
+    let version = fields.next()?;
+    let (trace_id, parent_id, flags) = (fields.next()?, fields.next()?, fields.next()?);
+    // later versions may append fields, version `ff` is invalid
+    let supported = match version {
+        "00" => fields.next().is_none(),
+        "ff" => false,
+        _ => version.len() == 2,
+    };
+    if !supported || flags.len() != 2 {
+        return None;
+    }
+
+    let flags = u8::from_str_radix(flags, 16).ok()?;
+    Some(PropagationContext {
+        trace_id: TraceId::from_w3c(trace_id).ok()?,
+        parent_span: parse_hex64_span_id(parent_id)?,
+        sampling: Some(SamplingDecision {
+            sampled: flags & 1 == 1,
+            sample_rate: 1,
+        }),
+        experiments: BTreeMap::new(),
+    })
+}
+
+// `{trace_id}-{span_id}[-{sampled}[-{parent_span_id}]]`. A lone `{sampled}` carries no trace
+// context
+fn parse_b3(s: &str) -> Option<PropagationContext> {
+    let mut fields = s.split('-');
+    let (trace_id, span_id) = (fields.next()?, fields.next()?);
+    let sampled = match fields.next() {
+        Some(sampled) => Some(parse_b3_sampled(sampled)?),
+        None => None,
+    };
+    b3_context(trace_id, span_id, sampled)
+}
+
+fn parse_b3_headers<H: HeaderExtractor + ?Sized>(headers: &H) -> Option<PropagationContext> {
+    let trace_id = headers.get(B3_TRACE_ID_HEADER)?.trim();
+    let span_id = headers.get(B3_SPAN_ID_HEADER)?.trim();
+    // debug traces are always sampled
+    let sampled = match headers.get(B3_FLAGS_HEADER).map(str::trim) {
+        Some("1") => Some(true),
+        _ => match headers.get(B3_SAMPLED_HEADER).map(str::trim) {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            Some(sampled) => Some(parse_b3_sampled(sampled)?),
+            None => None,
+        },
+    };
+    b3_context(trace_id, span_id, sampled)
+}
+
+fn parse_b3_sampled(s: &str) -> Option<bool> {
+    match s {
+        "1" | "d" => Some(true),
+        "0" => Some(false),
+        _ => None,
+    }
+}
+
+// B3 trace ids are 16 or 32 hex digits, the former are left-padded to W3C trace ids
+fn b3_context(trace_id: &str, span_id: &str, sampled: Option<bool>) -> Option<PropagationContext> {
+    let trace_id = match trace_id.len() {
+        16 => TraceId::from_w3c(&format!("{:0>32}", trace_id)),
+        _ => TraceId::from_w3c(trace_id),
+    };
+    Some(PropagationContext {
+        trace_id: trace_id.ok()?,
+        parent_span: parse_hex64_span_id(span_id)?,
+        sampling: sampled.map(|sampled| SamplingDecision {
+            sampled,
+            sample_rate: 1,
+        }),
+        experiments: BTreeMap::new(),
+    })
+}
+
+// 16 lowercase hex digits, not all zeros
+fn parse_hex64_span_id(s: &str) -> Option<SpanId> {
+    if s.len() != 16
+        || !s
+            .bytes()
+            .all(|b| b.is_ascii_digit() || (b'a'..=b'f').contains(&b))
+    {
+        return None;
+    }
+    SpanId::from_str(s).ok()
+}
+
+/// Extension trait for HTTP client requests, injecting the current distributed trace context
+/// as a `HONEYCOMB_TRACE_HEADER` header.
+///
+/// Requests made outside of a distributed trace are left untouched.
+pub trait TraceHeadersExt: Sized {
+    /// Add the current distributed trace context to this request's headers.
+    fn with_trace_headers(self) -> Self;
+}
+
+#[cfg(feature = "awc")]
+impl TraceHeadersExt for awc::ClientRequest {
+    fn with_trace_headers(self) -> Self {
+        match PropagationContext::current() {
+            Ok(ctx) => self.insert_header((HONEYCOMB_TRACE_HEADER, ctx.to_string())),
+            Err(_) => self,
+        }
+    }
+}
+
+#[cfg(feature = "surf")]
+impl TraceHeadersExt for surf::RequestBuilder {
+    fn with_trace_headers(self) -> Self {
+        match PropagationContext::current() {
+            Ok(ctx) => self.header(HONEYCOMB_TRACE_HEADER, ctx.to_string()),
+            Err(_) => self,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn propagation_context_round_trip() {
+        let ctx = PropagationContext {
+            trace_id: TraceId::new(),
+            parent_span: SpanId::from_str("2a").unwrap(),
+            sampling: Some(SamplingDecision {
+                sampled: false,
+                sample_rate: 4,
+            }),
+            experiments: vec![("checkout".to_string(), "variant_b".to_string())]
+                .into_iter()
+                .collect(),
+        };
+        let s = ctx.to_string();
+        assert_eq!(Ok(ctx), PropagationContext::from_str(&s));
+    }
+
+    #[test]
+    fn ignores_unknown_beeline_fields() {
+        let ctx = PropagationContext::from_str("1;dataset=foo,trace_id=abc,parent_id=1f").unwrap();
+        assert_eq!(ctx.trace_id, TraceId::from("abc"));
+        assert_eq!(ctx.parent_span, SpanId::from_str("1f").unwrap());
+        assert_eq!(ctx.sampling, None);
+        assert!(ctx.experiments.is_empty());
+
+        // {"experiment.checkout":"b","user_id":1}
+        let ctx = PropagationContext::from_str(
+            "1;trace_id=abc,parent_id=1f,context=eyJleHBlcmltZW50LmNoZWNrb3V0IjoiYiIsInVzZXJfaWQiOjF9",
+        )
+        .unwrap();
+        assert_eq!(ctx.experiments.len(), 1);
+        assert_eq!(ctx.experiments["checkout"], "b");
+
+        assert_eq!(
+            PropagationContext::from_str("2;trace_id=abc,parent_id=1f"),
+            Err(ParsePropagationContextError::UnsupportedVersion)
+        );
+        assert_eq!(
+            PropagationContext::from_str("1;trace_id=abc"),
+            Err(ParsePropagationContextError::MissingField("parent_id"))
+        );
+    }
+
+    #[test]
+    fn xray_trace_header_round_trip() {
+        let s = "Root=1-5759e988-bd862e3fe1be46a994272793;Parent=53995c3f42cd8ad8;Sampled=1";
+        let header = XrayTraceHeader::from_str(s).unwrap();
+        assert_eq!(
+            header.trace_id,
+            TraceId::from("5759e988bd862e3fe1be46a994272793")
+        );
+        assert_eq!(
+            header.parent_span,
+            Some(SpanId::from_str("53995c3f42cd8ad8").unwrap())
+        );
+        assert_eq!(header.sampled, Some(true));
+        assert_eq!(header.to_string(), s);
+
+        // as set by ALB
+        let header = XrayTraceHeader::from_str(
+            "Self=1-67891234-12456789abcdef012345678;Root=1-67891233-abcdef012345678912345678",
+        )
+        .unwrap();
+        assert_eq!(header.parent_span, None);
+        assert_eq!(header.sampled, None);
+        assert_eq!(
+            header.to_string(),
+            "Root=1-67891233-abcdef012345678912345678"
+        );
+
+        assert_eq!(
+            XrayTraceHeader::from_str("Parent=53995c3f42cd8ad8"),
+            Err(ParsePropagationContextError::MissingField("Root"))
+        );
+        assert_eq!(
+            XrayTraceHeader::from_str("Root=abc"),
+            Err(ParsePropagationContextError::InvalidField("Root"))
+        );
+    }
+
+    fn headers(headers: &[(&str, &str)]) -> HashMap<String, String> {
+        headers
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn extracted(trace_id: &str, parent_span: &str, sampled: Option<bool>) -> ExtractedContext {
+        ExtractedContext::Propagation(PropagationContext {
+            trace_id: TraceId::from(trace_id),
+            parent_span: SpanId::from_str(parent_span).unwrap(),
+            sampling: sampled.map(|sampled| SamplingDecision {
+                sampled,
+                sample_rate: 1,
+            }),
+            experiments: BTreeMap::new(),
+        })
+    }
+
+    #[test]
+    fn extracts_w3c_and_b3_trace_context() {
+        let trace_id = "4bf92f3577b34da6a3ce929d0e0e4736";
+        let parent_span = "00f067aa0ba902b7";
+
+        let traceparent = format!("00-{}-{}-01", trace_id, parent_span);
+        assert_eq!(
+            ExtractedContext::extract(&headers(&[("Traceparent", &traceparent)])),
+            Some(extracted(trace_id, parent_span, Some(true)))
+        );
+        // later versions may append fields
+        let traceparent = format!("01-{}-{}-00-extra", trace_id, parent_span);
+        assert_eq!(
+            ExtractedContext::extract(&headers(&[("traceparent", &traceparent)])),
+            Some(extracted(trace_id, parent_span, Some(false)))
+        );
+
+        let b3 = format!("{}-{}-d", trace_id, parent_span);
+        assert_eq!(
+            ExtractedContext::extract(&headers(&[("b3", &b3)])),
+            Some(extracted(trace_id, parent_span, Some(true)))
+        );
+        // 64-bit trace ids are left-padded
+        let b3 = format!("a3ce929d0e0e4736-{}", parent_span);
+        assert_eq!(
+            ExtractedContext::extract(&headers(&[("b3", &b3)])),
+            Some(extracted(
+                "0000000000000000a3ce929d0e0e4736",
+                parent_span,
+                None
+            ))
+        );
+        assert_eq!(
+            ExtractedContext::extract(&headers(&[
+                ("X-B3-TraceId", trace_id),
+                ("X-B3-SpanId", parent_span),
+                ("X-B3-Sampled", "0"),
+            ])),
+            Some(extracted(trace_id, parent_span, Some(false)))
+        );
+    }
+
+    #[test]
+    fn extracts_the_first_valid_trace_context() {
+        let traceparent = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let xray = "Root=1-5759e988-bd862e3fe1be46a994272793";
+
+        assert_eq!(
+            ExtractedContext::extract(&headers(&[
+                (HONEYCOMB_TRACE_HEADER, "1;trace_id=abc,parent_id=1f"),
+                (W3C_TRACE_HEADER, traceparent),
+            ])),
+            Some(extracted("abc", "1f", None))
+        );
+        // invalid headers are skipped
+        assert_eq!(
+            ExtractedContext::extract(&headers(&[
+                (
+                    W3C_TRACE_HEADER,
+                    "00-00000000000000000000000000000000-00f067aa0ba902b7-01"
+                ),
+                (B3_TRACE_HEADER, "1"),
+                (XRAY_TRACE_HEADER, xray),
+            ])),
+            Some(ExtractedContext::Xray(
+                XrayTraceHeader::from_str(xray).unwrap()
+            ))
+        );
+        assert_eq!(
+            ExtractedContext::extract(&headers(&[(W3C_TRACE_HEADER, "ff-abc")])),
+            None
+        );
+    }
+}
@@ -0,0 +1,91 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+/// Token bucket limiting the number of events sent to honeycomb per second.
+///
+/// The bucket holds at most one second's worth of tokens, so short bursts are allowed
+/// while sustained throughput is capped at `max_events_per_sec`.
+#[derive(Debug)]
+pub(crate) struct RateLimiter {
+    max_events_per_sec: f64,
+    bucket: Mutex<Bucket>,
+    // number of events dropped since the last successfully acquired token
+    dropped: AtomicU64,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(max_events_per_sec: u32) -> Self {
+        let max_events_per_sec = f64::from(max_events_per_sec);
+
+        RateLimiter {
+            max_events_per_sec,
+            bucket: Mutex::new(Bucket {
+                tokens: max_events_per_sec,
+                last_refill: Instant::now(),
+            }),
+            dropped: AtomicU64::new(0),
+        }
+    }
+
+    /// Attempt to take a token from the bucket.
+    ///
+    /// Returns `None` if the event should be dropped, otherwise returns the number of
+    /// events dropped since the last call that returned `Some`.
+    pub(crate) fn try_acquire(&self) -> Option<u64> {
+        self.try_acquire_at(Instant::now())
+    }
+
+    fn try_acquire_at(&self, now: Instant) -> Option<u64> {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let mut bucket = self.bucket.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let mut bucket = self.bucket.lock();
+
+        let elapsed = now.saturating_duration_since(bucket.last_refill);
+        bucket.tokens = (bucket.tokens + elapsed.as_secs_f64() * self.max_events_per_sec)
+            .min(self.max_events_per_sec);
+        bucket.last_refill = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Some(self.dropped.swap(0, Ordering::Relaxed))
+        } else {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn limits_bursts_and_accounts_for_drops() {
+        let limiter = RateLimiter::new(2);
+        let start = Instant::now();
+
+        assert_eq!(limiter.try_acquire_at(start), Some(0));
+        assert_eq!(limiter.try_acquire_at(start), Some(0));
+        assert_eq!(limiter.try_acquire_at(start), None);
+        assert_eq!(limiter.try_acquire_at(start), None);
+
+        // half a second later one token has been refilled
+        let later = start + Duration::from_millis(500);
+        assert_eq!(limiter.try_acquire_at(later), Some(2));
+        assert_eq!(limiter.try_acquire_at(later), None);
+    }
+}
@@ -0,0 +1,181 @@
+use libhoney::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::RwLock;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::RwLock;
+
+use crate::sampling::SampleRateHandle;
+
+/// Settings of a telemetry layer that can be changed at runtime using a `ReloadHandle`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RuntimeSettings {
+    /// Whether spans and events are published, see `Builder::enabled`.
+    pub enabled: bool,
+    /// Dataset to which spans and events not routed elsewhere are published, see
+    /// `Builder::dataset`.
+    pub dataset: String,
+    /// Constant fields attached to every span and event, see `Builder::with_static_field`.
+    pub static_fields: HashMap<String, Value>,
+    /// Trace-level sample rate, see `Builder::sample_rate`.
+    pub sample_rate: u32,
+}
+
+/// Handle used to change the settings of a telemetry layer at runtime, e.g. from an admin
+/// endpoint or when a configuration file changes, without rebuilding the subscriber. Similar
+/// to `tracing_subscriber::reload::Handle`.
+///
+/// Handles are cheap to clone and may be freely shared between threads. Each span or event is
+/// published using a consistent snapshot of the settings: changes made by a single call to
+/// `modify` are never observed partially.
+#[derive(Clone, Debug)]
+pub struct ReloadHandle {
+    settings: Arc<RwLock<Arc<RuntimeSettings>>>,
+    // checked for every span and event, and flipped during incidents, so kept out of the lock
+    enabled: Arc<AtomicBool>,
+    // shared with `SampleRateHandle`s, which read and update the sample rate directly
+    sample_rate: SampleRateHandle,
+}
+
+impl ReloadHandle {
+    pub(crate) fn new(dataset: String, sample_rate: SampleRateHandle) -> Self {
+        let settings = RuntimeSettings {
+            enabled: true,
+            dataset,
+            static_fields: HashMap::new(),
+            sample_rate: sample_rate.get(),
+        };
+        ReloadHandle {
+            settings: Arc::new(RwLock::new(Arc::new(settings))),
+            enabled: Arc::new(AtomicBool::new(true)),
+            sample_rate,
+        }
+    }
+
+    /// Get the settings currently in effect.
+    pub fn settings(&self) -> RuntimeSettings {
+        let mut settings = (*self.load()).clone();
+        settings.enabled = self.is_enabled();
+        settings.sample_rate = self.sample_rate.get();
+        settings
+    }
+
+    /// Update the settings used for all spans and events published from now on.
+    pub fn modify<F>(&self, f: F)
+    where
+        F: FnOnce(&mut RuntimeSettings),
+    {
+        // succeed or die. failure is unrecoverable (lock poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let mut current = self.settings.write().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let mut current = self.settings.write();
+
+        let mut settings = (**current).clone();
+        settings.enabled = self.is_enabled();
+        settings.sample_rate = self.sample_rate.get();
+        f(&mut settings);
+        self.enabled.store(settings.enabled, Ordering::Relaxed);
+        self.sample_rate.set(settings.sample_rate);
+        *current = Arc::new(settings);
+    }
+
+    /// Replace all settings at once.
+    pub fn reload(&self, settings: RuntimeSettings) {
+        self.modify(|current| *current = settings);
+    }
+
+    /// Enable or disable publishing spans and events. Takes effect immediately: while disabled,
+    /// the layer does not record fields or report anything, see `HoneycombTelemetry::set_enabled`.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    pub(crate) fn is_enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    /// Set the dataset to which spans and events not routed elsewhere are published.
+    pub fn set_dataset(&self, dataset: impl Into<String>) {
+        let dataset = dataset.into();
+        self.modify(|settings| settings.dataset = dataset);
+    }
+
+    /// Replace the constant fields attached to every span and event.
+    pub fn set_static_fields(&self, static_fields: HashMap<String, Value>) {
+        self.modify(|settings| settings.static_fields = static_fields);
+    }
+
+    /// Set the trace-level sample rate, see `SampleRateHandle::set`.
+    pub fn set_sample_rate(&self, sample_rate: u32) {
+        self.sample_rate.set(sample_rate);
+    }
+
+    // snapshot of the settings, the enabled flag and sample rate are read from their atomics
+    // instead
+    pub(crate) fn load(&self) -> Arc<RuntimeSettings> {
+        // succeed or die. failure is unrecoverable (lock poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let current = self.settings.read().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let current = self.settings.read();
+
+        current.clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::HoneycombTelemetry;
+    use libhoney::json;
+
+    #[test]
+    fn changes_are_applied_together() {
+        let builder = HoneycombTelemetry::builder()
+            .dataset("first")
+            .with_static_field("env", "staging");
+        let handle = builder.reload_handle();
+        let sample_rate = builder.sample_rate_handle();
+
+        handle.modify(|settings| {
+            settings.dataset = "second".to_string();
+            settings
+                .static_fields
+                .insert("region".to_string(), json!("eu"));
+            settings.sample_rate = 10;
+        });
+        assert_eq!(sample_rate.get(), 10);
+
+        sample_rate.set(20);
+        let settings = builder.reload_handle().settings();
+        assert!(settings.enabled);
+        assert_eq!(settings.dataset, "second");
+        assert_eq!(settings.static_fields.len(), 2);
+        assert_eq!(settings.sample_rate, 20);
+
+        handle.set_enabled(false);
+        assert!(!handle.settings().enabled);
+        assert_eq!(handle.settings().dataset, "second");
+    }
+
+    #[test]
+    fn kill_switch_reaches_default_subscriber() {
+        use tracing_subscriber::layer::Layer;
+
+        let builder = HoneycombTelemetry::builder();
+        let handle = builder.reload_handle();
+        let layer = builder.build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(crate::set_telemetry_enabled(false));
+            assert!(!handle.settings().enabled);
+            assert!(crate::set_telemetry_enabled(true));
+        });
+        assert!(handle.settings().enabled);
+        assert!(!crate::set_telemetry_enabled(false));
+    }
+}
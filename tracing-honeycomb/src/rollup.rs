@@ -0,0 +1,204 @@
+use chrono::{DateTime, Utc};
+use libhoney::{json, Value};
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+use crate::TraceId;
+
+// upper bounds (inclusive, in milliseconds) of the root span duration histogram buckets
+static DURATION_BUCKETS_MS: [u64; 12] = [1, 5, 10, 25, 50, 100, 250, 500, 1000, 2500, 5000, 10000];
+
+// bounds the number of sampled-out traces with errors awaiting their root span's completion
+const MAX_PENDING_ERRORED_TRACES: usize = 10_000;
+
+/// Accumulates lightweight statistics about traces that were sampled out, keyed by the name
+/// of their local root span, and periodically turns them into aggregate events.
+#[derive(Debug)]
+pub(crate) struct Rollup {
+    interval: Duration,
+    state: Mutex<RollupState>,
+}
+
+#[derive(Debug)]
+struct RollupState {
+    window_started_at: SystemTime,
+    window_started: Instant,
+    by_root: HashMap<&'static str, RootStats>,
+    // sampled-out traces that recorded an error-level event and whose root has not yet closed
+    errored_traces: HashSet<TraceId>,
+}
+
+#[derive(Debug)]
+struct RootStats {
+    service_name: &'static str,
+    count: u64,
+    errors: u64,
+    duration_ms_sum: f64,
+    // cumulative: bucket `i` counts all traces with a duration <= DURATION_BUCKETS_MS[i]
+    duration_ms_buckets: [u64; 12],
+}
+
+impl Rollup {
+    pub(crate) fn new(interval: Duration) -> Self {
+        Rollup {
+            interval,
+            state: Mutex::new(RollupState {
+                window_started_at: SystemTime::now(),
+                window_started: Instant::now(),
+                by_root: HashMap::new(),
+                errored_traces: HashSet::new(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> impl std::ops::DerefMut<Target = RollupState> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let state = self.state.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let state = self.state.lock();
+
+        state
+    }
+
+    /// Record an error-level event belonging to a sampled-out trace.
+    pub(crate) fn record_error(&self, trace_id: &TraceId) {
+        let mut state = self.lock();
+        if state.errored_traces.len() < MAX_PENDING_ERRORED_TRACES {
+            state.errored_traces.insert(trace_id.clone());
+        }
+    }
+
+    /// Stop tracking a sampled-out trace whose local root span never closed.
+    pub(crate) fn forget(&self, trace_id: &TraceId) {
+        self.lock().errored_traces.remove(trace_id);
+    }
+
+    /// Record the completion of the local root span of a sampled-out trace.
+    pub(crate) fn record_root(
+        &self,
+        name: &'static str,
+        service_name: &'static str,
+        trace_id: &TraceId,
+        duration: Duration,
+    ) {
+        let mut state = self.lock();
+        let errored = state.errored_traces.remove(trace_id);
+
+        let stats = state.by_root.entry(name).or_insert_with(|| RootStats {
+            service_name,
+            count: 0,
+            errors: 0,
+            duration_ms_sum: 0.0,
+            duration_ms_buckets: [0; 12],
+        });
+
+        stats.count += 1;
+        if errored {
+            stats.errors += 1;
+        }
+
+        let duration_ms = duration.as_secs_f64() * 1000.0;
+        stats.duration_ms_sum += duration_ms;
+        for (bound, bucket) in DURATION_BUCKETS_MS
+            .iter()
+            .zip(stats.duration_ms_buckets.iter_mut())
+        {
+            if duration_ms <= *bound as f64 {
+                *bucket += 1;
+            }
+        }
+    }
+
+    /// If the current window has elapsed, reset it and return one aggregate event per root
+    /// span name observed during the window.
+    pub(crate) fn take_due(&self) -> Vec<HashMap<String, Value>> {
+        self.take_due_at(Instant::now(), SystemTime::now())
+    }
+
+    fn take_due_at(&self, now: Instant, now_utc: SystemTime) -> Vec<HashMap<String, Value>> {
+        let mut state = self.lock();
+        let elapsed = now.saturating_duration_since(state.window_started);
+        if elapsed < self.interval {
+            return Vec::new();
+        }
+
+        let window_started_at: DateTime<Utc> = state.window_started_at.into();
+        state.window_started_at = now_utc;
+        state.window_started = now;
+
+        state
+            .by_root
+            .drain()
+            .map(|(name, stats)| {
+                let mut values = HashMap::new();
+                values.insert("name".to_string(), json!(name));
+                values.insert("service_name".to_string(), json!(stats.service_name));
+                values.insert(
+                    "Timestamp".to_string(),
+                    json!(window_started_at.to_rfc3339()),
+                );
+                values.insert("meta.sampled_out_rollup".to_string(), json!(true));
+                values.insert(
+                    "rollup.window_secs".to_string(),
+                    json!(elapsed.as_secs_f64()),
+                );
+                values.insert("rollup.count".to_string(), json!(stats.count));
+                values.insert("rollup.errors".to_string(), json!(stats.errors));
+                values.insert(
+                    "rollup.duration_ms.sum".to_string(),
+                    json!(stats.duration_ms_sum),
+                );
+                for (bound, count) in DURATION_BUCKETS_MS
+                    .iter()
+                    .zip(stats.duration_ms_buckets.iter())
+                {
+                    values.insert(format!("rollup.duration_ms.le_{}", bound), json!(count));
+                }
+                values.insert("rollup.duration_ms.le_inf".to_string(), json!(stats.count));
+                values
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn aggregates_by_root_span_name() {
+        let rollup = Rollup::new(Duration::from_secs(60));
+        let errored = TraceId::from("errored");
+
+        rollup.record_error(&errored);
+        rollup.record_root("handle", "svc", &errored, Duration::from_millis(3));
+        rollup.record_root("handle", "svc", &"ok".into(), Duration::from_millis(700));
+        rollup.record_root("poll", "svc", &"other".into(), Duration::from_millis(20));
+
+        let start = Instant::now();
+        assert!(rollup.take_due_at(start, SystemTime::now()).is_empty());
+
+        let later = start + Duration::from_secs(61);
+        let events = rollup.take_due_at(later, SystemTime::now());
+        assert_eq!(events.len(), 2);
+
+        let handle = events
+            .iter()
+            .find(|values| values["name"] == json!("handle"))
+            .unwrap();
+        assert_eq!(handle["rollup.count"], json!(2));
+        assert_eq!(handle["rollup.errors"], json!(1));
+        assert_eq!(handle["rollup.duration_ms.le_1"], json!(0));
+        assert_eq!(handle["rollup.duration_ms.le_5"], json!(1));
+        assert_eq!(handle["rollup.duration_ms.le_1000"], json!(2));
+
+        // window was reset
+        assert!(rollup.take_due_at(later, SystemTime::now()).is_empty());
+    }
+}
@@ -0,0 +1,118 @@
+use libhoney::Value;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::export_filter::is_within;
+
+type RouteFn = dyn Fn(&HashMap<String, Value>) -> Option<String> + Send + Sync;
+
+#[derive(Clone, Debug)]
+enum RouteRule {
+    // matches the target itself and its submodules
+    TargetPrefix(String),
+    Field(String, Value),
+}
+
+impl RouteRule {
+    fn matches(&self, data: &HashMap<String, Value>) -> bool {
+        match self {
+            RouteRule::TargetPrefix(prefix) => match data.get("target") {
+                Some(Value::String(target)) => is_within(target, prefix),
+                _ => false,
+            },
+            RouteRule::Field(name, value) => data.get(name) == Some(value),
+        }
+    }
+}
+
+/// Overrides the dataset of individual spans and events, see
+/// `Builder::route_target_to_dataset`, `Builder::route_field_to_dataset` and
+/// `Builder::dataset_router`.
+#[derive(Clone, Default)]
+pub(crate) struct DatasetRouter {
+    rules: Vec<(RouteRule, String)>,
+    router: Option<Arc<RouteFn>>,
+}
+
+impl fmt::Debug for DatasetRouter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DatasetRouter")
+            .field("rules", &self.rules)
+            .field("router", &self.router.as_ref().map(|_| "<fn>"))
+            .finish()
+    }
+}
+
+impl DatasetRouter {
+    pub(crate) fn route_target(&mut self, target: String, dataset: String) {
+        self.rules.push((RouteRule::TargetPrefix(target), dataset));
+    }
+
+    pub(crate) fn route_field(&mut self, name: String, value: Value, dataset: String) {
+        self.rules.push((RouteRule::Field(name, value), dataset));
+    }
+
+    // takes precedence over the rules added so far
+    pub(crate) fn route_field_first(&mut self, name: String, value: Value, dataset: String) {
+        self.rules
+            .insert(0, (RouteRule::Field(name, value), dataset));
+    }
+
+    pub(crate) fn set_router<F>(&mut self, router: F)
+    where
+        F: Fn(&HashMap<String, Value>) -> Option<String> + Send + Sync + 'static,
+    {
+        self.router = Some(Arc::new(router));
+    }
+
+    // rules are evaluated in the order they were added, before the router callback. `None`
+    // keeps the configured dataset
+    pub(crate) fn route(&self, data: &HashMap<String, Value>) -> Option<String> {
+        self.rules
+            .iter()
+            .find(|(rule, _)| rule.matches(data))
+            .map(|(_, dataset)| dataset.clone())
+            .or_else(|| self.router.as_ref().and_then(|router| router(data)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use libhoney::json;
+
+    #[test]
+    fn first_matching_rule_wins_over_router() {
+        let mut router = DatasetRouter::default();
+        router.route_target("my_app::infra".to_string(), "infra".to_string());
+        router.route_field(
+            "tenant".to_string(),
+            json!("internal"),
+            "internal".to_string(),
+        );
+        router.set_router(|data| data.get("name").map(|_| "named".to_string()));
+
+        let data = |pairs: &[(&str, Value)]| -> HashMap<String, Value> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.clone()))
+                .collect()
+        };
+
+        let infra = data(&[
+            ("target", json!("my_app::infra::db")),
+            ("tenant", json!("internal")),
+        ]);
+        assert_eq!(router.route(&infra), Some("infra".to_string()));
+
+        let not_infra = data(&[("target", json!("my_app::infrastructure"))]);
+        assert_eq!(router.route(&not_infra), None);
+
+        let internal = data(&[("target", json!("my_app")), ("tenant", json!("internal"))]);
+        assert_eq!(router.route(&internal), Some("internal".to_string()));
+
+        let named = data(&[("name", json!("span"))]);
+        assert_eq!(router.route(&named), Some("named".to_string()));
+    }
+}
@@ -0,0 +1,245 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{self, Display};
+use std::num::ParseIntError;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+use crate::TraceId;
+
+/// Handle used to adjust the trace-level sample rate of a `HoneycombTelemetry` instance at
+/// runtime, without rebuilding the subscriber.
+///
+/// Handles are cheap to clone and may be freely shared between threads. A sample rate of `1`
+/// keeps every trace, a sample rate of `n` keeps one out of every `n` traces. A sample rate of
+/// `0` is treated as `1`.
+#[derive(Clone, Debug)]
+pub struct SampleRateHandle(Arc<AtomicU32>);
+
+impl SampleRateHandle {
+    pub(crate) fn new(sample_rate: u32) -> Self {
+        SampleRateHandle(Arc::new(AtomicU32::new(sample_rate)))
+    }
+
+    /// Get the sample rate currently in effect.
+    pub fn get(&self) -> u32 {
+        self.0.load(Ordering::Relaxed)
+    }
+
+    /// Set the sample rate used for all traces sampled from now on.
+    pub fn set(&self, sample_rate: u32) {
+        self.0.store(sample_rate, Ordering::Relaxed)
+    }
+}
+
+// bounds the number of errored traces whose local root span has not yet closed
+const MAX_ERRORED_TRACES: usize = 10_000;
+
+/// Tracks traces that recorded an error-level event, overriding the sampling decision for
+/// them until their local root span closes.
+#[derive(Debug, Default)]
+pub(crate) struct ErroredTraces(Mutex<HashSet<TraceId>>);
+
+impl ErroredTraces {
+    fn lock(&self) -> impl std::ops::DerefMut<Target = HashSet<TraceId>> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let traces = self.0.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let traces = self.0.lock();
+
+        traces
+    }
+
+    /// Mark the trace as errored. Returns `false` if too many errored traces are already
+    /// being tracked, in which case only the error event itself can be kept.
+    pub(crate) fn insert(&self, trace_id: &TraceId) -> bool {
+        let mut traces = self.lock();
+        if traces.contains(trace_id) {
+            true
+        } else if traces.len() < MAX_ERRORED_TRACES {
+            traces.insert(trace_id.clone())
+        } else {
+            false
+        }
+    }
+
+    pub(crate) fn contains(&self, trace_id: &TraceId) -> bool {
+        self.lock().contains(trace_id)
+    }
+
+    /// Stop tracking the trace, called once its local root span has closed.
+    pub(crate) fn remove(&self, trace_id: &TraceId) {
+        self.lock().remove(trace_id);
+    }
+}
+
+/// Sampling decision made for a trace, propagated alongside its `TraceId` so that
+/// downstream services keep or drop the trace as a whole.
+///
+/// `Display` and `FromStr` are guaranteed to round-trip, using the format
+/// `{sampled}-{sample_rate}` where `sampled` is `1` or `0`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct SamplingDecision {
+    /// Whether the trace is kept.
+    pub sampled: bool,
+    /// Sample rate in effect when the decision was made.
+    pub sample_rate: u32,
+}
+
+impl SamplingDecision {
+    /// Metadata field name associated with `SamplingDecision` values.
+    pub fn meta_field_name() -> &'static str {
+        "sampling-decision"
+    }
+}
+
+/// Error returned when parsing a `SamplingDecision` fails.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParseSamplingDecisionError {
+    /// The input was not of the form `{sampled}-{sample_rate}`.
+    InvalidFormat,
+    /// The sample rate was not a valid `u32`.
+    ParseIntError(ParseIntError),
+}
+
+impl Display for ParseSamplingDecisionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidFormat => {
+                write!(f, "expected sampling decision of the form {{0|1}}-<rate>")
+            }
+            Self::ParseIntError(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl std::error::Error for ParseSamplingDecisionError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ParseIntError(e) => Some(e),
+            Self::InvalidFormat => None,
+        }
+    }
+}
+
+impl From<ParseIntError> for ParseSamplingDecisionError {
+    fn from(err: ParseIntError) -> Self {
+        Self::ParseIntError(err)
+    }
+}
+
+impl FromStr for SamplingDecision {
+    type Err = ParseSamplingDecisionError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (sampled, sample_rate) = match s.split_once('-') {
+            Some(("1", sample_rate)) => (true, sample_rate),
+            Some(("0", sample_rate)) => (false, sample_rate),
+            _ => return Err(ParseSamplingDecisionError::InvalidFormat),
+        };
+
+        Ok(SamplingDecision {
+            sampled,
+            sample_rate: sample_rate.parse()?,
+        })
+    }
+}
+
+impl Display for SamplingDecision {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", self.sampled as u8, self.sample_rate)
+    }
+}
+
+// bounds the number of propagated decisions whose local root span has not yet closed
+const MAX_PROPAGATED_DECISIONS: usize = 10_000;
+
+/// Sampling decisions received from upstream services, honored in place of the local
+/// sampling decision until the local root span of the trace closes.
+#[derive(Debug, Default)]
+pub(crate) struct PropagatedDecisions(Mutex<HashMap<TraceId, SamplingDecision>>);
+
+impl PropagatedDecisions {
+    fn lock(&self) -> impl std::ops::DerefMut<Target = HashMap<TraceId, SamplingDecision>> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let decisions = self.0.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let decisions = self.0.lock();
+
+        decisions
+    }
+
+    pub(crate) fn insert(&self, trace_id: TraceId, decision: SamplingDecision) {
+        let mut decisions = self.lock();
+        if decisions.len() < MAX_PROPAGATED_DECISIONS || decisions.contains_key(&trace_id) {
+            decisions.insert(trace_id, decision);
+        }
+    }
+
+    pub(crate) fn get(&self, trace_id: &TraceId) -> Option<SamplingDecision> {
+        self.lock().get(trace_id).copied()
+    }
+
+    pub(crate) fn remove(&self, trace_id: &TraceId) {
+        self.lock().remove(trace_id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn errored_traces_are_bounded() {
+        let errored = ErroredTraces::default();
+        for i in 0..MAX_ERRORED_TRACES {
+            assert!(errored.insert(&TraceId::from(i as u128)));
+        }
+
+        let overflow = TraceId::from("overflow");
+        assert!(!errored.insert(&overflow));
+        assert!(!errored.contains(&overflow));
+
+        let first = TraceId::from(0);
+        assert!(errored.insert(&first));
+        errored.remove(&first);
+        assert!(!errored.contains(&first));
+        assert!(errored.insert(&overflow));
+    }
+
+    #[test]
+    fn sampling_decision_round_trip() {
+        for decision in &[
+            SamplingDecision {
+                sampled: true,
+                sample_rate: 1,
+            },
+            SamplingDecision {
+                sampled: false,
+                sample_rate: 20,
+            },
+        ] {
+            let s = decision.to_string();
+            assert_eq!(Ok(*decision), SamplingDecision::from_str(&s));
+        }
+
+        assert_eq!(
+            SamplingDecision::from_str("yes-1"),
+            Err(ParseSamplingDecisionError::InvalidFormat)
+        );
+        assert_eq!(
+            ParseSamplingDecisionError::InvalidFormat.to_string(),
+            "expected sampling decision of the form {0|1}-<rate>"
+        );
+
+        let err: Box<dyn std::error::Error> = SamplingDecision::from_str("1-x").unwrap_err().into();
+        assert!(err.source().is_some());
+    }
+}
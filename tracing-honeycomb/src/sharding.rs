@@ -0,0 +1,81 @@
+use sha1::{Digest, Sha1};
+
+use crate::TraceId;
+
+/// Options of each dataset shard, see `Builder::shard_datasets`.
+#[derive(Debug)]
+pub(crate) struct DatasetShards(Vec<libhoney::client::Options>);
+
+impl DatasetShards {
+    /// Split the dataset of `options` into `shards` datasets, `{dataset}-1` to
+    /// `{dataset}-{shards}`.
+    pub(crate) fn new(options: &libhoney::client::Options, shards: u32) -> Self {
+        DatasetShards(
+            (1..=shards)
+                .map(|shard| libhoney::client::Options {
+                    dataset: format!("{}-{}", options.dataset, shard),
+                    ..options.clone()
+                })
+                .collect(),
+        )
+    }
+
+    /// Options of the shard the given trace is routed to. Data that does not belong to a
+    /// trace is routed to the first shard.
+    pub(crate) fn options(&self, trace_id: Option<&TraceId>) -> &libhoney::client::Options {
+        let shard = trace_id.map_or(0, |trace_id| shard(self.0.len() as u32, trace_id));
+        &self.0[shard as usize]
+    }
+
+    /// Name of the shard of `dataset` the given trace is routed to, for data routed to a
+    /// dataset other than the configured one.
+    pub(crate) fn shard_dataset(&self, dataset: &str, trace_id: Option<&TraceId>) -> String {
+        let shard = trace_id.map_or(0, |trace_id| shard(self.0.len() as u32, trace_id));
+        format!("{}-{}", dataset, shard + 1)
+    }
+}
+
+/// Stable shard index in `0..shards` for the given trace.
+///
+/// Uses different bits of the trace id's SHA-1 hash than the deterministic sampler, so sampled
+/// traces are spread evenly across shards.
+fn shard(shards: u32, trace_id: &TraceId) -> u32 {
+    let sum = Sha1::digest(trace_id.as_ref());
+
+    u32::from_be_bytes([sum[4], sum[5], sum[6], sum[7]]) % shards.max(1)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn traces_are_routed_to_a_stable_shard() {
+        let options = libhoney::client::Options {
+            dataset: "spans".to_string(),
+            ..Default::default()
+        };
+        let shards = DatasetShards::new(&options, 4);
+
+        let mut seen = std::collections::HashSet::new();
+        for i in 0..100u128 {
+            let trace_id = TraceId::from(i);
+            let dataset = &shards.options(Some(&trace_id)).dataset;
+            assert_eq!(dataset, &shards.options(Some(&trace_id)).dataset);
+            seen.insert(dataset.clone());
+        }
+
+        assert_eq!(seen.len(), 4);
+        assert!(seen.contains("spans-1") && seen.contains("spans-4"));
+        assert_eq!(shards.options(None).dataset, "spans-1");
+
+        let trace_id = TraceId::from(7u128);
+        assert_eq!(
+            shards.shard_dataset("infra", Some(&trace_id)),
+            shards
+                .options(Some(&trace_id))
+                .dataset
+                .replace("spans", "infra")
+        );
+    }
+}
@@ -1,15 +1,25 @@
+use sha1::{Digest, Sha1};
 use std::convert::TryFrom;
 use std::fmt::{self, Display};
 use std::num::{NonZeroU64, ParseIntError, TryFromIntError};
 use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, Ordering};
 /// Unique Span identifier.
 ///
-/// Wraps a `tracing::span::Id` with a suitable parser.
+/// Wraps a `tracing::span::Id`, optionally salted with an `instance_id`, or a 64-bit id
+/// derived from both (see `SpanIdFormat::Hex64`), with a suitable parser.
 ///
 /// `Display` and `FromStr` are guaranteed to round-trip.
 #[derive(Clone, Debug, Eq, Hash, PartialEq)]
-pub struct SpanId {
-    pub(crate) tracing_id: tracing::span::Id,
+pub struct SpanId(pub(crate) SpanIdRepr);
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+pub(crate) enum SpanIdRepr {
+    Tracing {
+        tracing_id: tracing::span::Id,
+        instance_id: Option<u64>,
+    },
+    Hex64(NonZeroU64),
 }
 
 impl SpanId {
@@ -17,12 +27,119 @@ impl SpanId {
     pub fn meta_field_name() -> &'static str {
         "span-id"
     }
+
+    /// This span id as 64 bits, as used by e.g. AWS X-Ray. `SpanIdFormat::Hex64` ids are
+    /// returned as is, other ids are hashed the same way `SpanIdFormat::Hex64` derives them.
+    pub(crate) fn to_u64(&self) -> NonZeroU64 {
+        match &self.0 {
+            SpanIdRepr::Tracing {
+                tracing_id,
+                instance_id,
+            } => NonZeroU64::new(hash64(tracing_id.into_u64(), instance_id.unwrap_or(0)))
+                .unwrap_or(NonZeroU64::new(1).unwrap()),
+            SpanIdRepr::Hex64(id) => *id,
+        }
+    }
 }
 
+/// Format used to derive `SpanId`s from `tracing::span::Id`s.
+///
+/// `tracing::span::Id`s are only unique among the spans that are open at the same time in
+/// a single subscriber: they are reused once spans close, and different processes
+/// participating in the same trace hand out the same ids.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SpanIdFormat {
+    /// `{tracing_id}`, in hex. Compatible with span ids produced by previous versions of
+    /// this crate, but may alias spans within a trace.
+    Legacy,
+    /// `{tracing_id}-{instance_id}`, in hex. The `instance_id` is a random per-layer value
+    /// combined with a per-span generation counter, making collisions practically
+    /// impossible even when `tracing::span::Id`s are reused.
+    #[default]
+    Salted,
+    /// 16 hex digits, i.e. 8 bytes, derived from the `tracing_id` and a salted `instance_id`
+    /// using a stable hash. Can be used as the parent-id of a W3C `traceparent` header, to
+    /// interoperate with OpenTelemetry-instrumented services.
+    Hex64,
+}
+
+/// Promotes `tracing::span::Id`s to `SpanId`s using a given `SpanIdFormat`.
+#[derive(Debug)]
+pub(crate) struct SpanIdGenerator {
+    format: SpanIdFormat,
+    instance_id: u64,
+    generation: AtomicU64,
+}
+
+impl SpanIdGenerator {
+    pub(crate) fn new(format: SpanIdFormat, instance_id: u64) -> Self {
+        SpanIdGenerator {
+            format,
+            instance_id,
+            generation: AtomicU64::new(0),
+        }
+    }
+
+    pub(crate) fn promote(&self, tracing_id: tracing::span::Id) -> SpanId {
+        match self.format {
+            SpanIdFormat::Legacy => SpanId(SpanIdRepr::Tracing {
+                tracing_id,
+                instance_id: None,
+            }),
+            SpanIdFormat::Salted => SpanId(SpanIdRepr::Tracing {
+                tracing_id,
+                instance_id: Some(self.next_instance_id()),
+            }),
+            SpanIdFormat::Hex64 => {
+                let id = hash64(tracing_id.into_u64(), self.next_instance_id());
+                // 0 is not a valid span id
+                SpanId(SpanIdRepr::Hex64(
+                    NonZeroU64::new(id).unwrap_or(NonZeroU64::MIN),
+                ))
+            }
+        }
+    }
+
+    fn next_instance_id(&self) -> u64 {
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+        self.instance_id.wrapping_add(generation)
+    }
+}
+
+/// Derive an instance id from the host name and the process id, see
+/// `Builder::host_instance_id`.
+pub(crate) fn host_instance_id() -> u64 {
+    let hostname = std::fs::read_to_string("/proc/sys/kernel/hostname")
+        .ok()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .or_else(|| std::env::var("COMPUTERNAME").ok())
+        .unwrap_or_default();
+
+    let sum = Sha1::digest(format!("{}:{}", hostname.trim(), std::process::id()).as_bytes());
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&sum[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+// stable across platforms and releases, unlike `std::hash::Hash`. based on splitmix64
+fn hash64(tracing_id: u64, instance_id: u64) -> u64 {
+    let mut x = tracing_id ^ instance_id.rotate_left(32);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+/// Error returned when parsing a `SpanId` fails.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub enum ParseSpanIdError {
+    /// The span id or instance id is not a valid hex number.
     ParseIntError(ParseIntError),
+    /// The span id is zero.
     TryFromIntError(TryFromIntError),
+    /// The span id or instance id contains characters other than hex digits, e.g. a sign.
+    InvalidDigit,
+    /// The instance id is followed by further dash-separated data.
+    TrailingData,
 }
 
 impl Display for ParseSpanIdError {
@@ -30,6 +147,18 @@ impl Display for ParseSpanIdError {
         match self {
             Self::ParseIntError(e) => write!(f, "{}", e),
             Self::TryFromIntError(e) => write!(f, "{}", e),
+            Self::InvalidDigit => write!(f, "span id contains a non-hex character"),
+            Self::TrailingData => write!(f, "span id has trailing data after the instance id"),
+        }
+    }
+}
+
+impl std::error::Error for ParseSpanIdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::ParseIntError(e) => Some(e),
+            Self::TryFromIntError(e) => Some(e),
+            Self::InvalidDigit | Self::TrailingData => None,
         }
     }
 }
@@ -46,23 +175,77 @@ impl From<TryFromIntError> for ParseSpanIdError {
     }
 }
 
+// `from_str_radix` accepts a leading sign, which `Display` never produces
+fn parse_hex(s: &str) -> Result<u64, ParseSpanIdError> {
+    if !s.is_empty() && !s.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ParseSpanIdError::InvalidDigit);
+    }
+    Ok(u64::from_str_radix(s, 16)?)
+}
+
 impl FromStr for SpanId {
     type Err = ParseSpanIdError;
 
-    /// Parses a Span Id from a hex value.
+    /// Parses a Span Id from a hex value, optionally followed by a hex instance id
+    /// separated by a dash. Exactly 16 hex digits are parsed as a `SpanIdFormat::Hex64` id.
     fn from_str(s: &str) -> Result<Self, Self::Err> {
-        let raw_id = u64::from_str_radix(s, 16)?;
+        let mut segments = s.split('-');
+        let tracing_id = segments.next().unwrap_or_default();
+        let instance_id = segments.next().map(parse_hex).transpose()?;
+        if segments.next().is_some() {
+            return Err(ParseSpanIdError::TrailingData);
+        }
+
+        let raw_id = parse_hex(tracing_id)?;
         let id = NonZeroU64::try_from(raw_id)?;
+        if tracing_id.len() == 16 && instance_id.is_none() {
+            return Ok(SpanId(SpanIdRepr::Hex64(id)));
+        }
 
-        Ok(SpanId {
+        Ok(SpanId(SpanIdRepr::Tracing {
             tracing_id: tracing::Id::from_non_zero_u64(id),
-        })
+            instance_id,
+        }))
     }
 }
 
 impl Display for SpanId {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:x}", self.tracing_id.into_u64())
+        match &self.0 {
+            SpanIdRepr::Tracing {
+                tracing_id,
+                instance_id: Some(instance_id),
+            } => write!(f, "{:x}-{:x}", tracing_id.into_u64(), instance_id),
+            SpanIdRepr::Tracing {
+                tracing_id,
+                instance_id: None,
+            } => write!(f, "{:x}", tracing_id.into_u64()),
+            SpanIdRepr::Hex64(id) => write!(f, "{:016x}", id),
+        }
+    }
+}
+
+/// Serialized as a string, using the `Display` representation.
+#[cfg(feature = "serde")]
+impl serde::Serialize for SpanId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for SpanId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        SpanId::from_str(&s).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Serialized as its message.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ParseSpanIdError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
     }
 }
 
@@ -76,13 +259,88 @@ mod test {
     proptest! {
         #[test]
         // ua is [1..] and not [0..] because 0 is not a valid tracing::Id (tracing::from_u64 throws on 0)
-        fn span_id_round_trip(ua in 1u64..) {
-            let span_id = SpanId {
+        // ua is below 1 << 60 as ids of 16 hex digits are parsed as 64-bit ids
+        fn span_id_round_trip(ua in 1u64..(1 << 60), ub in proptest::option::of(any::<u64>())) {
+            let span_id = SpanId(SpanIdRepr::Tracing {
                 tracing_id: tracing::Id::from_u64(ua),
-            };
+                instance_id: ub,
+            });
             let s = span_id.to_string();
             let res = SpanId::from_str(&s);
             assert_eq!(Ok(span_id), res);
         }
+
+        #[test]
+        fn span_ids_with_trailing_data_are_rejected(ua in 1u64.., ub in any::<u64>(), rest in "[0-9a-z-]*") {
+            let s = format!("{:x}-{:x}-{}", ua, ub, rest);
+            assert_eq!(SpanId::from_str(&s), Err(ParseSpanIdError::TrailingData));
+        }
+
+        #[test]
+        fn span_ids_with_non_hex_characters_are_rejected(s in "[0-9a-f]*[^0-9a-fA-F-][0-9a-f]*") {
+            assert_eq!(SpanId::from_str(&s), Err(ParseSpanIdError::InvalidDigit));
+        }
+
+        #[test]
+        fn hex64_span_id_round_trip(id in 1u64..) {
+            let span_id = SpanId(SpanIdRepr::Hex64(NonZeroU64::new(id).unwrap()));
+            let s = span_id.to_string();
+            assert_eq!(s.len(), 16);
+            let res = SpanId::from_str(&s);
+            assert_eq!(Ok(span_id), res);
+        }
+    }
+
+    #[test]
+    fn parses_strictly() {
+        assert_eq!(
+            SpanId::from_str("1-ffffffffffffff").unwrap().to_string(),
+            "1-ffffffffffffff"
+        );
+        assert_eq!(SpanId::from_str("+2a"), Err(ParseSpanIdError::InvalidDigit));
+        assert_eq!(
+            SpanId::from_str("2a-+ff"),
+            Err(ParseSpanIdError::InvalidDigit)
+        );
+        assert!(matches!(
+            SpanId::from_str(""),
+            Err(ParseSpanIdError::ParseIntError(_))
+        ));
+        assert!(matches!(
+            SpanId::from_str("0"),
+            Err(ParseSpanIdError::TryFromIntError(_))
+        ));
+        assert!(std::error::Error::source(&SpanId::from_str("0").unwrap_err()).is_some());
+    }
+
+    #[test]
+    fn salted_span_ids_are_unique_across_id_reuse() {
+        let generator = SpanIdGenerator::new(SpanIdFormat::Salted, rand::random());
+        let first = generator.promote(tracing::Id::from_u64(1));
+        let reused = generator.promote(tracing::Id::from_u64(1));
+        assert_ne!(first, reused);
+
+        let generator = SpanIdGenerator::new(SpanIdFormat::Legacy, rand::random());
+        let first = generator.promote(tracing::Id::from_u64(1));
+        assert_eq!(first.to_string(), "1");
+
+        let generator = SpanIdGenerator::new(SpanIdFormat::Hex64, rand::random());
+        let first = generator.promote(tracing::Id::from_u64(1));
+        let reused = generator.promote(tracing::Id::from_u64(1));
+        assert_ne!(first, reused);
+        assert_eq!(first.to_string().len(), 16);
+    }
+
+    #[test]
+    fn span_ids_are_stable_for_a_given_instance_id() {
+        let first = SpanIdGenerator::new(SpanIdFormat::Salted, 42);
+        let restarted = SpanIdGenerator::new(SpanIdFormat::Salted, 42);
+        for i in 1..4 {
+            assert_eq!(
+                first.promote(tracing::Id::from_u64(i)),
+                restarted.promote(tracing::Id::from_u64(i))
+            );
+        }
+        assert_eq!(host_instance_id(), host_instance_id());
     }
 }
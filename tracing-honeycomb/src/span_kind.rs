@@ -0,0 +1,45 @@
+use std::fmt::{self, Display};
+
+/// Role of a span in a distributed trace, published as the `span.kind` column used by
+/// honeycomb.io's service map and latency-by-kind breakdowns.
+///
+/// Declare it as a field when creating a span, e.g.
+/// `tracing::info_span!("GET /users", span.kind = %SpanKind::Server)`. Spans using the
+/// `otel.kind` field name of `tracing-opentelemetry` are published with `span.kind` as well.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SpanKind {
+    /// Handles a synchronous request from a remote client.
+    Server,
+    /// Makes a synchronous request to a remote server.
+    Client,
+    /// Initiates an asynchronous request, e.g. publishes a message to a queue.
+    Producer,
+    /// Handles an asynchronous request, e.g. processes a message from a queue.
+    Consumer,
+    /// Internal operation, without remote parent or children.
+    Internal,
+}
+
+impl SpanKind {
+    /// Field name associated with `SpanKind` values.
+    pub fn field_name() -> &'static str {
+        "span.kind"
+    }
+
+    /// Lowercase name of this kind, as published to honeycomb.io.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SpanKind::Server => "server",
+            SpanKind::Client => "client",
+            SpanKind::Producer => "producer",
+            SpanKind::Consumer => "consumer",
+            SpanKind::Internal => "internal",
+        }
+    }
+}
+
+impl Display for SpanKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
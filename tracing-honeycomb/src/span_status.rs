@@ -0,0 +1,36 @@
+use std::fmt::{self, Display};
+
+/// Outcome of the operation a span covers, published as the `span.status` column of every
+/// span, e.g. to break down error rates by endpoint.
+///
+/// Spans are errored if `set_span_status` (or `set_span_error`) marked them as such, or
+/// otherwise if an error was recorded on them or on one of their events, and ok otherwise.
+/// Errored spans are published with `error = true` as well.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum SpanStatus {
+    /// The operation succeeded.
+    Ok,
+    /// The operation failed.
+    Error,
+}
+
+impl SpanStatus {
+    /// Field name associated with `SpanStatus` values.
+    pub fn field_name() -> &'static str {
+        "span.status"
+    }
+
+    /// Lowercase name of this status, as published to honeycomb.io.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SpanStatus::Ok => "ok",
+            SpanStatus::Error => "error",
+        }
+    }
+}
+
+impl Display for SpanStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
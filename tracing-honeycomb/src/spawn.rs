@@ -0,0 +1,97 @@
+use std::future::Future;
+use tracing::instrument::{Instrument, Instrumented};
+
+use crate::PropagationContext;
+
+/// Extension trait running futures in the distributed trace of the current span, e.g. futures
+/// spawned as separate tasks, which otherwise lose the trace unless instrumented by hand.
+pub trait TracedFutureExt: Future + Sized {
+    /// Run this future in a new `spawned_task` span, registered as a local root of the
+    /// distributed trace of the current span (see `PropagationContext::current`) with the
+    /// current span as its parent. The sampling decision and experiment assignments of the
+    /// trace carry over.
+    ///
+    /// The trace is captured when this method is called, not when the future is first
+    /// polled, so call it before spawning. Unlike instrumenting the future with the current
+    /// span, the current span may close before the future completes. Outside of a
+    /// distributed trace, the future runs in a span without trace.
+    fn in_current_trace(self) -> Instrumented<Self> {
+        let span = tracing::info_span!(parent: None, "spawned_task");
+        if let Ok(ctx) = PropagationContext::current() {
+            // fails only if the span is disabled, in which case there is nothing to report
+            let _ = span.in_scope(|| ctx.register_dist_tracing_root());
+        }
+        self.instrument(span)
+    }
+}
+
+impl<F: Future> TracedFutureExt for F {}
+
+/// Spawn `future` as a new tokio task running in the distributed trace of the current span,
+/// see `TracedFutureExt::in_current_trace`. Requires the `tokio` feature and a tokio 1.x
+/// runtime.
+#[cfg(feature = "tokio")]
+pub fn spawn_traced<F>(future: F) -> tokio1::task::JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    tokio1::spawn(future.in_current_trace())
+}
+
+#[cfg(all(test, feature = "honeycomb"))]
+mod test {
+    use super::*;
+    use crate::{current_dist_trace_ctx, register_dist_tracing_root, HoneycombTelemetry, TraceId};
+    use tracing_subscriber::layer::Layer;
+
+    #[test]
+    fn futures_run_in_current_trace() {
+        let layer = HoneycombTelemetry::builder().enabled(false).build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let trace_id = TraceId::new();
+            let span = tracing::info_span!("request");
+            let (task, parent_span) = span.in_scope(|| {
+                register_dist_tracing_root(trace_id.clone(), None).unwrap();
+                let (_, parent_span) = current_dist_trace_ctx().unwrap();
+                (
+                    async { current_dist_trace_ctx() }.in_current_trace(),
+                    parent_span,
+                )
+            });
+            // the spawning span may close before the task completes
+            drop(span);
+
+            let mut rt = tokio::runtime::Runtime::new().unwrap();
+            let (task_trace_id, task_span) = rt.block_on(task).unwrap();
+            assert_eq!(task_trace_id, trace_id);
+            assert_ne!(task_span, parent_span);
+        });
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn spawned_tasks_run_in_current_trace() {
+        let layer = HoneycombTelemetry::builder().enabled(false).build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let rt = tokio1::runtime::Builder::new_current_thread()
+                .build()
+                .unwrap();
+            let trace_id = TraceId::new();
+            let task_trace_id = rt.block_on(async {
+                let span = tracing::info_span!("request");
+                let task = span.in_scope(|| {
+                    register_dist_tracing_root(trace_id.clone(), None).unwrap();
+                    spawn_traced(async { current_dist_trace_ctx().map(|(trace_id, _)| trace_id) })
+                });
+                drop(span);
+                task.await.unwrap()
+            });
+            assert_eq!(task_trace_id, Ok(trace_id));
+        });
+    }
+}
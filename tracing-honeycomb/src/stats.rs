@@ -0,0 +1,143 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+// upper bounds (inclusive, in milliseconds) of the response latency histogram buckets
+static LATENCY_BUCKETS_MS: [u64; 9] = [10, 25, 50, 100, 250, 500, 1000, 2500, 5000];
+
+/// Snapshot of the counters maintained by the reporting path of a telemetry layer, e.g. to
+/// alert on silent data loss. See `HoneycombTelemetry::stats` and `current_telemetry_stats`.
+///
+/// Counters are cumulative since the layer was built. Layers using the same
+/// `SharedTransmission` share its counters.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct TelemetryStats {
+    /// Number of events accepted by honeycomb.io.
+    pub events_sent: u64,
+    /// Number of events dropped before being sent, because of the rate limit (see
+    /// `Builder::rate_limit`), the circuit breaker (see `Builder::circuit_breaker`), the memory
+    /// budget (see `Builder::memory_budget`) or because libhoney's queue was full.
+    pub events_dropped: u64,
+    /// Number of events dropped because of the rate limit, included in `events_dropped`.
+    pub dropped_by_rate_limit: u64,
+    /// Number of events dropped while the circuit breaker was open (see
+    /// `Builder::circuit_breaker`), included in `events_dropped`.
+    pub dropped_by_circuit_breaker: u64,
+    /// Number of events shed to keep buffered telemetry within the memory budget (see
+    /// `Builder::memory_budget`), included in `events_dropped`.
+    pub dropped_by_memory_budget: u64,
+    /// Number of events that were sent but not delivered, because honeycomb.io rejected them
+    /// or the request failed.
+    pub send_errors: u64,
+    /// Number of events queued or in flight.
+    pub queue_depth: usize,
+    /// Estimated size in bytes of the events buffered by the transmission, tracked once a
+    /// memory budget is set (see `Builder::memory_budget`), 0 otherwise.
+    pub buffered_bytes: usize,
+    /// Cumulative histogram of the latency of honeycomb.io responses, as pairs of an upper
+    /// bound in milliseconds and the number of responses received within that bound. The last
+    /// bound is `u64::MAX`. libhoney's transmission reports one response per event, while
+    /// `Builder::send_now` reports one response per batch.
+    pub response_latency_ms: Vec<(u64, u64)>,
+}
+
+/// Counters behind `TelemetryStats`, updated by the reporting path and transmissions.
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    events_sent: AtomicU64,
+    events_dropped: AtomicU64,
+    dropped_by_rate_limit: AtomicU64,
+    dropped_by_circuit_breaker: AtomicU64,
+    dropped_by_memory_budget: AtomicU64,
+    send_errors: AtomicU64,
+    // per bucket, the last one counts responses above the largest bound
+    latency_buckets: [AtomicU64; 10],
+}
+
+impl Stats {
+    pub(crate) fn record_sent(&self, events: u64) {
+        self.events_sent.fetch_add(events, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dropped(&self, events: u64) {
+        self.events_dropped.fetch_add(events, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_rate_limited(&self, events: u64) {
+        self.record_dropped(events);
+        self.dropped_by_rate_limit
+            .fetch_add(events, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_circuit_open(&self, events: u64) {
+        self.record_dropped(events);
+        self.dropped_by_circuit_breaker
+            .fetch_add(events, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_shed(&self, events: u64) {
+        self.record_dropped(events);
+        self.dropped_by_memory_budget
+            .fetch_add(events, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_send_errors(&self, events: u64) {
+        self.send_errors.fetch_add(events, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_latency(&self, latency: Duration) {
+        let latency_ms = latency.as_millis();
+        let bucket = LATENCY_BUCKETS_MS
+            .iter()
+            .position(|&bound| latency_ms <= u128::from(bound))
+            .unwrap_or(LATENCY_BUCKETS_MS.len());
+        self.latency_buckets[bucket].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn snapshot(&self, queue_depth: usize) -> TelemetryStats {
+        let bounds = LATENCY_BUCKETS_MS.iter().copied().chain(Some(u64::MAX));
+        let mut cumulative = 0;
+        let response_latency_ms = bounds
+            .zip(self.latency_buckets.iter())
+            .map(|(bound, count)| {
+                cumulative += count.load(Ordering::Relaxed);
+                (bound, cumulative)
+            })
+            .collect();
+
+        TelemetryStats {
+            events_sent: self.events_sent.load(Ordering::Relaxed),
+            events_dropped: self.events_dropped.load(Ordering::Relaxed),
+            dropped_by_rate_limit: self.dropped_by_rate_limit.load(Ordering::Relaxed),
+            dropped_by_circuit_breaker: self.dropped_by_circuit_breaker.load(Ordering::Relaxed),
+            dropped_by_memory_budget: self.dropped_by_memory_budget.load(Ordering::Relaxed),
+            send_errors: self.send_errors.load(Ordering::Relaxed),
+            queue_depth,
+            buffered_bytes: 0,
+            response_latency_ms,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn latency_histogram_is_cumulative() {
+        let stats = Stats::default();
+        stats.record_sent(3);
+        stats.record_dropped(1);
+        for ms in &[5, 10, 11, 60_000] {
+            stats.record_latency(Duration::from_millis(*ms));
+        }
+
+        let snapshot = stats.snapshot(2);
+        assert_eq!(snapshot.events_sent, 3);
+        assert_eq!(snapshot.events_dropped, 1);
+        assert_eq!(snapshot.queue_depth, 2);
+        assert_eq!(snapshot.response_latency_ms[0], (10, 2));
+        assert_eq!(snapshot.response_latency_ms[1], (25, 3));
+        assert_eq!(snapshot.response_latency_ms[8], (5000, 3));
+        assert_eq!(snapshot.response_latency_ms[9], (u64::MAX, 4));
+    }
+}
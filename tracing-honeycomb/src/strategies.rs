@@ -0,0 +1,136 @@
+//! `proptest` strategies generating valid `TraceId`s and `SpanId`s, for downstream crates
+//! embedding these ids to property-test their own serialization. Requires the `proptest`
+//! feature.
+//!
+//! Every generated id round-trips through its `Display` and `FromStr` implementations.
+//! `TraceId` and `SpanId` also implement `proptest::arbitrary::Arbitrary`, using `trace_id`
+//! and `span_id`.
+
+use proptest::prelude::*;
+use std::num::NonZeroU64;
+
+use crate::span_id::SpanIdRepr;
+use crate::{SpanId, TraceId};
+
+/// Any trace id: uuid-backed, string-backed or an edge case.
+pub fn trace_id() -> impl Strategy<Value = TraceId> {
+    prop_oneof![uuid_trace_id(), string_trace_id(), edge_case_trace_id()]
+}
+
+/// Trace ids generated from uuids, as by `TraceId::new()`: 32 lowercase hex digits, also
+/// valid W3C Trace Context trace ids.
+pub fn uuid_trace_id() -> impl Strategy<Value = TraceId> {
+    (1u128..).prop_map(TraceId::from)
+}
+
+/// Trace ids propagated as arbitrary strings, e.g. by services using their own trace id
+/// format.
+pub fn string_trace_id() -> impl Strategy<Value = TraceId> {
+    "\\PC{1,64}".prop_map(TraceId::from)
+}
+
+/// Trace ids at the edges of the supported formats: empty, all zeros (invalid in W3C Trace
+/// Context), all ones, AWS X-Ray formatted, and made of separators, whitespace or non-ASCII
+/// characters.
+pub fn edge_case_trace_id() -> impl Strategy<Value = TraceId> {
+    prop_oneof![
+        Just(TraceId::from("")),
+        Just(TraceId::from("0".repeat(32))),
+        Just(TraceId::from(u128::MAX)),
+        Just(TraceId::from("1-5759e988-bd862e3fe1be46a994272793")),
+        Just(TraceId::from("-")),
+        Just(TraceId::from(" ")),
+        Just(TraceId::from("trace,id;with=separators")),
+        Just(TraceId::from("tracé-🐝")),
+    ]
+}
+
+/// Any span id: legacy, salted, 64-bit or an edge case, see `SpanIdFormat`.
+pub fn span_id() -> impl Strategy<Value = SpanId> {
+    prop_oneof![
+        legacy_span_id(),
+        salted_span_id(),
+        hex64_span_id(),
+        edge_case_span_id()
+    ]
+}
+
+// ids of 16 hex digits are parsed as 64-bit ids, and 0 is not a valid `tracing::Id`
+const MAX_TRACING_ID: u64 = (1 << 60) - 1;
+
+fn tracing_span_id(tracing_id: u64, instance_id: Option<u64>) -> SpanId {
+    SpanId(SpanIdRepr::Tracing {
+        tracing_id: tracing::Id::from_u64(tracing_id),
+        instance_id,
+    })
+}
+
+/// Span ids in the `SpanIdFormat::Legacy` format.
+pub fn legacy_span_id() -> impl Strategy<Value = SpanId> {
+    (1..=MAX_TRACING_ID).prop_map(|tracing_id| tracing_span_id(tracing_id, None))
+}
+
+/// Span ids in the `SpanIdFormat::Salted` format.
+pub fn salted_span_id() -> impl Strategy<Value = SpanId> {
+    (1..=MAX_TRACING_ID, any::<u64>())
+        .prop_map(|(tracing_id, instance_id)| tracing_span_id(tracing_id, Some(instance_id)))
+}
+
+/// Span ids in the `SpanIdFormat::Hex64` format, also valid W3C Trace Context parent ids.
+pub fn hex64_span_id() -> impl Strategy<Value = SpanId> {
+    (1u64..).prop_map(|id| SpanId(SpanIdRepr::Hex64(NonZeroU64::new(id).unwrap())))
+}
+
+/// Span ids at the edges of each format: the smallest and largest ids, and salted ids with
+/// the smallest and largest instance ids.
+pub fn edge_case_span_id() -> impl Strategy<Value = SpanId> {
+    prop_oneof![
+        Just(tracing_span_id(1, None)),
+        Just(tracing_span_id(MAX_TRACING_ID, None)),
+        Just(tracing_span_id(1, Some(0))),
+        Just(tracing_span_id(MAX_TRACING_ID, Some(u64::MAX))),
+        Just(SpanId(SpanIdRepr::Hex64(NonZeroU64::MIN))),
+        Just(SpanId(SpanIdRepr::Hex64(NonZeroU64::MAX))),
+    ]
+}
+
+impl Arbitrary for TraceId {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<TraceId>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        trace_id().boxed()
+    }
+}
+
+impl Arbitrary for SpanId {
+    type Parameters = ();
+    type Strategy = BoxedStrategy<SpanId>;
+
+    fn arbitrary_with(_: ()) -> Self::Strategy {
+        span_id().boxed()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::str::FromStr;
+
+    proptest! {
+        #[test]
+        fn generated_trace_ids_round_trip(trace_id in any::<TraceId>()) {
+            assert_eq!(TraceId::from_str(&trace_id.to_string()), Ok(trace_id));
+        }
+
+        #[test]
+        fn generated_span_ids_round_trip(span_id in any::<SpanId>()) {
+            assert_eq!(SpanId::from_str(&span_id.to_string()), Ok(span_id));
+        }
+
+        #[test]
+        fn uuid_trace_ids_are_w3c(trace_id in uuid_trace_id()) {
+            assert_eq!(trace_id.to_w3c(), trace_id);
+        }
+    }
+}
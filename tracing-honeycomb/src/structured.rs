@@ -0,0 +1,58 @@
+use crate::lazy::Lazy;
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fmt;
+
+/// A structured field value, published to honeycomb.io as nested JSON instead of a `Debug`
+/// string.
+///
+/// Record it as a `Debug` field: `tracing::info_span!("request", user = ?Structured::new(&user))`.
+/// Other layers observing the same field see its JSON representation.
+#[derive(Clone)]
+pub struct Structured(Lazy);
+
+impl Structured {
+    /// Serialize `value` for recording as a structured field.
+    pub fn new<T: Serialize + ?Sized>(value: &T) -> Self {
+        let value = serde_json::to_value(value)
+            .unwrap_or_else(|err| json!(format!("<failed to serialize field: {}>", err)));
+        Structured(Lazy::new(move || value.clone()))
+    }
+}
+
+impl From<Value> for Structured {
+    fn from(value: Value) -> Self {
+        Structured(Lazy::new(move || value.clone()))
+    }
+}
+
+impl fmt::Debug for Structured {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::lazy::format_or_capture;
+
+    #[derive(Serialize)]
+    struct User {
+        id: u64,
+        roles: Vec<&'static str>,
+    }
+
+    #[test]
+    fn structured_values_are_recorded_as_json() {
+        let user = User {
+            id: 7,
+            roles: vec!["admin"],
+        };
+        let structured = Structured::new(&user);
+
+        let captured = format_or_capture(&structured).unwrap_err();
+        assert_eq!(captured.evaluate(), json!({"id": 7, "roles": ["admin"]}));
+        assert_eq!(format!("{:?}", structured), r#"{"id":7,"roles":["admin"]}"#);
+    }
+}
@@ -0,0 +1,135 @@
+use std::fmt::{self, Display};
+use std::sync::Arc;
+
+use crate::diagnostics::{Diagnostic, InternalLogMode};
+
+/// Failure to publish telemetry to honeycomb.io, passed to the callback registered with
+/// `Builder::on_error`.
+#[derive(Debug)]
+pub enum TelemetryError {
+    /// An event could not be handed to libhoney's transmission, e.g. because its queue is full.
+    /// The event is dropped.
+    Enqueue(libhoney::Error),
+    /// Events were rejected by honeycomb.io or could not be delivered. The events are dropped,
+    /// unless spooled (see `Builder::dead_letter_spool`).
+    ///
+    /// Batches sent synchronously (see `Builder::send_now`) are reported individually. Failures
+    /// of libhoney's transmission are reported per dataset and status: the first one right
+    /// away, repeated ones at most every ten seconds, counting the events that failed since.
+    Send {
+        /// dataset the events were sent to
+        dataset: String,
+        /// number of events that failed
+        events: usize,
+        /// HTTP status of honeycomb.io's response, if any
+        status: Option<u16>,
+        /// description of the failure
+        message: String,
+    },
+    /// Events could not be written to, or read from, the dead-letter spool (see
+    /// `Builder::dead_letter_spool`).
+    DeadLetter(std::io::Error),
+}
+
+impl TelemetryError {
+    /// Whether honeycomb.io rejected the API key, e.g. because it is invalid or was revoked.
+    /// Nothing is published until the API key is replaced, see `ApiKeyHandle`.
+    pub fn is_unauthorized(&self) -> bool {
+        match self {
+            TelemetryError::Send {
+                status: Some(status),
+                ..
+            } => *status == 401 || *status == 403,
+            _ => false,
+        }
+    }
+
+    /// Whether honeycomb.io rejected events because the team or dataset is rate limited.
+    pub fn is_rate_limited(&self) -> bool {
+        match self {
+            TelemetryError::Send {
+                status: Some(status),
+                ..
+            } => *status == 429,
+            _ => false,
+        }
+    }
+}
+
+impl Display for TelemetryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TelemetryError::Enqueue(err) => {
+                write!(f, "error sending event to honeycomb, {}", err.message)
+            }
+            TelemetryError::Send {
+                dataset,
+                events,
+                message,
+                ..
+            } => {
+                if self.is_unauthorized() {
+                    f.write_str("honeycomb rejected the api key, ")?;
+                } else if self.is_rate_limited() {
+                    f.write_str("honeycomb is rate limiting events, ")?;
+                }
+                write!(
+                    f,
+                    "error sending {} events to honeycomb dataset {}, {}",
+                    events, dataset, message
+                )
+            }
+            TelemetryError::DeadLetter(err) => {
+                write!(f, "error accessing honeycomb dead-letter spool, {}", err)
+            }
+        }
+    }
+}
+
+impl std::error::Error for TelemetryError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            TelemetryError::Enqueue(err) => Some(err),
+            TelemetryError::Send { .. } => None,
+            TelemetryError::DeadLetter(err) => Some(err),
+        }
+    }
+}
+
+type ErrorCallback = dyn Fn(TelemetryError) + Send + Sync;
+
+/// Callback invoked when telemetry cannot be published, see `Builder::on_error`. Logs to
+/// stderr by default, see `Builder::internal_log_mode`.
+#[derive(Clone)]
+pub(crate) struct ErrorHandler(Arc<ErrorCallback>);
+
+impl fmt::Debug for ErrorHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ErrorHandler")
+    }
+}
+
+impl Default for ErrorHandler {
+    fn default() -> Self {
+        ErrorHandler::logging(InternalLogMode::default())
+    }
+}
+
+impl ErrorHandler {
+    pub(crate) fn new<F>(callback: F) -> Self
+    where
+        F: Fn(TelemetryError) + Send + Sync + 'static,
+    {
+        ErrorHandler(Arc::new(callback))
+    }
+
+    /// Report errors as diagnostics, using `mode`.
+    pub(crate) fn logging(mode: InternalLogMode) -> Self {
+        // unable to report telemetry so log msg instead
+        ErrorHandler::new(move |err| mode.emit(&Diagnostic::Error(&err)))
+    }
+
+    pub(crate) fn report(&self, err: TelemetryError) {
+        (self.0)(err)
+    }
+}
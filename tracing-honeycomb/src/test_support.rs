@@ -0,0 +1,1020 @@
+//! Utilities for testing instrumentation against the telemetry layer provided by this crate,
+//! e.g. that a request handler records a span with the expected fields, as a child of the
+//! expected span. Requires the `test-support` feature.
+//!
+//! Layers built with `Builder::record_to` record the spans and events they would publish to
+//! honeycomb.io, with the same fields and ids, instead of publishing them. Assertions such as
+//! `TelemetryRecorder::assert_span_exists` and `TelemetryRecorder::assert_parent_child` then
+//! check the structure of the recorded traces, and `Recorded::assert_field` and
+//! `Recorded::assert_field_matches` the fields of individual spans and events.
+//!
+//! `with_captured_telemetry` records the spans and events of a closure only, so tests running
+//! in parallel threads don't see each other's spans.
+//!
+//! `TelemetryRecorder::assert_golden` compares the recorded traces as a whole to a golden
+//! file, catching unintended changes of span structure or fields across refactors.
+//!
+//! `MockHoneycomb` is a local stand-in for honeycomb.io's Events API, to exercise the whole
+//! transmission path (batching, error callbacks, rate limiting) end to end, and
+//! `FaultInjector` scripts failures of the batches sent, e.g. to test circuit breaking or
+//! dead-letter spooling.
+
+use libhoney::{json, Value};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+pub use crate::faults::{Fault, FaultInjector};
+pub use crate::mock_server::{MockHoneycomb, ReceivedEvent};
+use crate::stats::{Stats, TelemetryStats};
+use crate::visitor::TraceFieldNames;
+use crate::{Builder, HoneycombTelemetry};
+use tracing_subscriber::layer::Layer;
+
+/// Records the spans and events published by telemetry layers built with
+/// `Builder::record_to`, in the order they were published, and provides assertions on them.
+///
+/// Recorders are cheap to clone, clones share the recorded spans and events.
+#[derive(Clone, Debug, Default)]
+pub struct TelemetryRecorder(Arc<Mutex<Vec<Recorded>>>);
+
+/// A span or event recorded by a `TelemetryRecorder`, holding the fields that would have been
+/// published to honeycomb.io, static fields included.
+#[derive(Clone, PartialEq)]
+pub struct Recorded {
+    /// The published fields, by name.
+    pub fields: HashMap<String, Value>,
+    trace_fields: Arc<TraceFieldNames>,
+}
+
+impl fmt::Debug for Recorded {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_map().entries(&self.fields).finish()
+    }
+}
+
+impl Recorded {
+    /// Name of the span, or of the event's callsite.
+    pub fn name(&self) -> &str {
+        self.fields
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+    }
+
+    /// Get the value of a published field, if any.
+    pub fn field(&self, name: &str) -> Option<&Value> {
+        self.fields.get(name)
+    }
+
+    /// Id of the trace this span or event belongs to.
+    pub fn trace_id(&self) -> Option<&Value> {
+        self.field(&self.trace_fields.trace_id)
+    }
+
+    /// Id of this span, `None` for events.
+    pub fn span_id(&self) -> Option<&Value> {
+        self.field(&self.trace_fields.span_id)
+    }
+
+    /// Id of the parent span of this span, or of the span this event occurred in. `None` for
+    /// root spans.
+    pub fn parent_id(&self) -> Option<&Value> {
+        self.field(&self.trace_fields.parent_id)
+            .filter(|parent_id| !parent_id.is_null())
+    }
+
+    /// Whether this is a span, as opposed to an event.
+    pub fn is_span(&self) -> bool {
+        self.span_id().is_some()
+    }
+
+    /// Whether `child` is a child span, or an event, of this span.
+    pub fn is_parent_of(&self, child: &Recorded) -> bool {
+        self.span_id().is_some() && self.span_id() == child.parent_id()
+    }
+
+    /// Assert that the field `name` was published with the value `expected`.
+    #[track_caller]
+    pub fn assert_field(&self, name: &str, expected: impl Into<Value>) -> &Self {
+        let expected = expected.into();
+        assert_eq!(
+            self.field(name),
+            Some(&expected),
+            "field {:?} of {:?} does not match, recorded {:?}",
+            name,
+            self.name(),
+            self
+        );
+        self
+    }
+
+    /// Assert that the field `name` was published with a value satisfying `matcher`, e.g.
+    /// `|duration| duration.as_f64() > Some(0.0)`.
+    #[track_caller]
+    pub fn assert_field_matches<F>(&self, name: &str, matcher: F) -> &Self
+    where
+        F: Fn(&Value) -> bool,
+    {
+        match self.field(name) {
+            Some(value) => assert!(
+                matcher(value),
+                "field {:?} of {:?} does not match, value {}",
+                name,
+                self.name(),
+                value
+            ),
+            None => panic!(
+                "field {:?} of {:?} was not published, recorded {:?}",
+                name,
+                self.name(),
+                self
+            ),
+        }
+        self
+    }
+
+    /// Assert that the field `name` was not published, e.g. because it is dropped.
+    #[track_caller]
+    pub fn assert_no_field(&self, name: &str) -> &Self {
+        assert!(
+            self.field(name).is_none(),
+            "field {:?} of {:?} was published, recorded {:?}",
+            name,
+            self.name(),
+            self
+        );
+        self
+    }
+}
+
+impl TelemetryRecorder {
+    /// Create a recorder without recorded spans and events.
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    fn lock(&self) -> impl std::ops::DerefMut<Target = Vec<Recorded>> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let recorded = self.0.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let recorded = self.0.lock();
+
+        recorded
+    }
+
+    pub(crate) fn record(
+        &self,
+        fields: HashMap<String, Value>,
+        trace_fields: Arc<TraceFieldNames>,
+    ) {
+        self.lock().push(Recorded {
+            fields,
+            trace_fields,
+        });
+    }
+
+    /// Get the spans and events recorded so far, in the order they were published. Spans are
+    /// published when they close, so children come before their parents.
+    pub fn recorded(&self) -> Vec<Recorded> {
+        self.lock().clone()
+    }
+
+    /// Get the spans recorded so far, see `TelemetryRecorder::recorded`.
+    pub fn spans(&self) -> Vec<Recorded> {
+        self.lock()
+            .iter()
+            .filter(|recorded| recorded.is_span())
+            .cloned()
+            .collect()
+    }
+
+    /// Get the events recorded so far, see `TelemetryRecorder::recorded`.
+    pub fn events(&self) -> Vec<Recorded> {
+        self.lock()
+            .iter()
+            .filter(|recorded| !recorded.is_span())
+            .cloned()
+            .collect()
+    }
+
+    /// Forget the spans and events recorded so far.
+    pub fn clear(&self) {
+        self.lock().clear();
+    }
+
+    fn span_names(&self) -> Vec<String> {
+        self.spans()
+            .iter()
+            .map(|span| span.name().to_string())
+            .collect()
+    }
+
+    /// Assert that a span named `name` was recorded, returning the first one for further
+    /// assertions on its fields.
+    #[track_caller]
+    pub fn assert_span_exists(&self, name: &str) -> Recorded {
+        match self.spans().into_iter().find(|span| span.name() == name) {
+            Some(span) => span,
+            None => panic!(
+                "no span named {:?} was recorded, recorded spans {:?}",
+                name,
+                self.span_names()
+            ),
+        }
+    }
+
+    /// Assert that no span named `name` was recorded, e.g. because it was filtered out.
+    #[track_caller]
+    pub fn assert_no_span(&self, name: &str) {
+        assert!(
+            self.spans().iter().all(|span| span.name() != name),
+            "a span named {:?} was recorded",
+            name
+        );
+    }
+
+    /// Assert that a span named `child` was recorded as a child of a span named `parent`.
+    #[track_caller]
+    pub fn assert_parent_child(&self, parent: &str, child: &str) {
+        let spans = self.spans();
+        let is_child = spans
+            .iter()
+            .filter(|span| span.name() == parent)
+            .any(|parent| {
+                spans
+                    .iter()
+                    .filter(|span| span.name() == child)
+                    .any(|child| parent.is_parent_of(child))
+            });
+        assert!(
+            is_child,
+            "no span named {:?} was recorded as a child of a span named {:?}, recorded spans {:?}",
+            child,
+            parent,
+            self.span_names()
+        );
+    }
+
+    /// Get a canonical JSON representation of the recorded traces, suitable for golden-file
+    /// comparisons, see `TelemetryRecorder::assert_golden`.
+    ///
+    /// Spans are nested under their parent span as `children`, events under the span they
+    /// occurred in as `events`, each with their fields sorted by name. Ids are normalized:
+    /// span ids and parent ids are left out, as the nesting holds them, and trace ids are
+    /// replaced with `trace-1`, `trace-2`, ... Fields that vary from run to run
+    /// (`Timestamp`, `duration_ms`, `thread.id` and `thread.name`) are left out. Siblings are
+    /// sorted by their representation, so it doesn't depend on timing.
+    pub fn to_golden_json(&self) -> String {
+        let recorded = self.recorded();
+        let span_ids: Vec<&Value> = recorded.iter().filter_map(Recorded::span_id).collect();
+        // spans and events whose parent span was not recorded are roots
+        let roots: Vec<&Recorded> = recorded
+            .iter()
+            .filter(|recorded| {
+                recorded
+                    .parent_id()
+                    .is_none_or(|parent_id| !span_ids.contains(&parent_id))
+            })
+            .collect();
+
+        let trace_id_field = match recorded.first() {
+            Some(recorded) => recorded.trace_fields.trace_id.clone(),
+            None => TraceFieldNames::default().trace_id,
+        };
+        let mut roots = golden_nodes(&roots, &recorded, &trace_id_field);
+        let mut trace_ids = Vec::new();
+        for root in &mut roots {
+            normalize_trace_ids(root, &trace_id_field, &mut trace_ids);
+        }
+        format!("{:#}\n", Value::Array(roots))
+    }
+
+    /// Assert that the recorded traces match the golden file at `path`, see
+    /// `TelemetryRecorder::to_golden_json`.
+    ///
+    /// The golden file is written instead if it does not exist, or if the
+    /// `HONEYCOMB_UPDATE_GOLDEN` environment variable is set, e.g. after an intended change of
+    /// the recorded traces.
+    #[track_caller]
+    pub fn assert_golden(&self, path: impl AsRef<Path>) {
+        let path = path.as_ref();
+        let golden = self.to_golden_json();
+        if std::env::var_os(UPDATE_GOLDEN).is_some() || !path.exists() {
+            std::fs::write(path, golden)
+                .unwrap_or_else(|err| panic!("failed to write golden file {:?}, {}", path, err));
+            return;
+        }
+
+        let expected = std::fs::read_to_string(path)
+            .unwrap_or_else(|err| panic!("failed to read golden file {:?}, {}", path, err));
+        assert!(
+            expected == golden,
+            "recorded traces do not match golden file {:?}, set {} to update it\n\
+             expected:\n{}\nrecorded:\n{}",
+            path,
+            UPDATE_GOLDEN,
+            expected,
+            golden
+        );
+    }
+}
+
+/// Run `f` with a telemetry layer recording to a new `TelemetryRecorder` as the default
+/// subscriber of the current thread, and get the spans and events it recorded, in the order
+/// they were published.
+///
+/// The subscriber is only installed for the duration of `f`, and only on the current thread,
+/// so tests running in parallel threads capture their own spans only. Spans opened on other
+/// threads, e.g. by tasks spawned within `f`, are not captured. As when publishing to
+/// honeycomb.io, only spans and events within a distributed trace are recorded, see
+/// `register_dist_tracing_root`.
+pub fn with_captured_telemetry<F: FnOnce()>(f: F) -> Vec<Recorded> {
+    with_captured_telemetry_from(HoneycombTelemetry::builder(), f)
+}
+
+/// Same as `with_captured_telemetry`, using a layer built by `builder`, e.g. to test the
+/// effect of field or sampling settings on the recorded spans.
+pub fn with_captured_telemetry_from<F: FnOnce()>(builder: Builder, f: F) -> Vec<Recorded> {
+    let recorder = TelemetryRecorder::new();
+    let layer = builder.record_to(&recorder).build();
+    let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+    tracing::subscriber::with_default(subscriber, f);
+    recorder.recorded()
+}
+
+/// Environment variable set to update golden files, see `TelemetryRecorder::assert_golden`.
+pub const UPDATE_GOLDEN: &str = "HONEYCOMB_UPDATE_GOLDEN";
+
+// fields left out of golden files, as they vary from run to run
+const VOLATILE_FIELDS: [&str; 4] = ["Timestamp", "duration_ms", "thread.id", "thread.name"];
+
+// the canonical representation of `nodes` and their descendants, sorted
+fn golden_nodes(nodes: &[&Recorded], recorded: &[Recorded], trace_id_field: &str) -> Vec<Value> {
+    let mut golden: Vec<Value> = nodes
+        .iter()
+        .map(|node| {
+            let mut fields = BTreeMap::new();
+            for (name, value) in &node.fields {
+                let trace_fields = &node.trace_fields;
+                if VOLATILE_FIELDS.contains(&name.as_str())
+                    || *name == trace_fields.span_id
+                    || *name == trace_fields.parent_id
+                {
+                    continue;
+                }
+                fields.insert(name.clone(), value.clone());
+            }
+
+            let mut golden = BTreeMap::new();
+            golden.insert("fields".to_string(), json!(fields));
+            if node.is_span() {
+                let (spans, events): (Vec<&Recorded>, Vec<&Recorded>) = recorded
+                    .iter()
+                    .filter(|child| node.is_parent_of(child))
+                    .partition(|child| child.is_span());
+                golden.insert(
+                    "children".to_string(),
+                    json!(golden_nodes(&spans, recorded, trace_id_field)),
+                );
+                golden.insert(
+                    "events".to_string(),
+                    json!(golden_nodes(&events, recorded, trace_id_field)),
+                );
+            }
+            json!(golden)
+        })
+        .collect();
+    // sorted regardless of the actual trace ids
+    golden.sort_by_cached_key(|node| {
+        let mut node = node.clone();
+        normalize_trace_ids(&mut node, trace_id_field, &mut Vec::new());
+        node.to_string()
+    });
+    golden
+}
+
+// replaces trace ids with `trace-{n}`, numbered in order of appearance
+fn normalize_trace_ids(node: &mut Value, trace_id_field: &str, trace_ids: &mut Vec<Value>) {
+    let fields = node.get_mut("fields").and_then(Value::as_object_mut);
+    if let Some(value) = fields.and_then(|fields| fields.get_mut(trace_id_field)) {
+        let n = match trace_ids.iter().position(|trace_id| trace_id == value) {
+            Some(n) => n,
+            None => {
+                trace_ids.push(value.clone());
+                trace_ids.len() - 1
+            }
+        };
+        *value = json!(format!("trace-{}", n + 1));
+    }
+    for nested in ["children", "events"] {
+        if let Some(Value::Array(nodes)) = node.get_mut(nested) {
+            for node in nodes {
+                normalize_trace_ids(node, trace_id_field, trace_ids);
+            }
+        }
+    }
+}
+
+/// Transport recording spans and events instead of publishing them, see
+/// `Builder::record_to`.
+#[derive(Debug)]
+pub(crate) struct RecordingTransmission {
+    recorder: TelemetryRecorder,
+    trace_fields: Arc<TraceFieldNames>,
+    stats: Stats,
+}
+
+impl RecordingTransmission {
+    pub(crate) fn new(recorder: TelemetryRecorder, trace_fields: TraceFieldNames) -> Self {
+        RecordingTransmission {
+            recorder,
+            trace_fields: Arc::new(trace_fields),
+            stats: Stats::default(),
+        }
+    }
+
+    pub(crate) fn send(&self, fields: HashMap<String, Value>) {
+        self.recorder.record(fields, self.trace_fields.clone());
+        self.stats.record_sent(1);
+    }
+
+    pub(crate) fn stats(&self) -> TelemetryStats {
+        self.stats.snapshot(0)
+    }
+
+    pub(crate) fn record_rate_limited(&self, events: u64) {
+        self.stats.record_rate_limited(events);
+    }
+
+    pub(crate) fn record_circuit_open(&self, events: u64) {
+        self.stats.record_circuit_open(events);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{register_dist_tracing_root, MockClock, SpanId, SpanStatus, TraceId};
+    use libhoney::json;
+
+    #[test]
+    fn captures_telemetry_of_the_current_thread_only() {
+        let capture = |name: &'static str| {
+            std::thread::spawn(move || {
+                with_captured_telemetry(|| {
+                    for _ in 0..100 {
+                        let _span = tracing::info_span!("request", name).entered();
+                        register_dist_tracing_root(TraceId::new(), None).unwrap();
+                    }
+                })
+            })
+        };
+        let (a, b) = (capture("a"), capture("b"));
+
+        for (name, recorded) in [("a", a.join().unwrap()), ("b", b.join().unwrap())] {
+            assert_eq!(recorded.len(), 100);
+            assert!(recorded
+                .iter()
+                .all(|span| span.field("tracing.name") == Some(&json!(name))));
+        }
+    }
+
+    #[test]
+    fn records_exact_durations_with_mock_clock() {
+        let recorder = TelemetryRecorder::new();
+        let clock = MockClock::default();
+        let layer = HoneycombTelemetry::builder()
+            .record_to(&recorder)
+            .clock(clock.clone())
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _span = tracing::info_span!("request").entered();
+            register_dist_tracing_root(TraceId::from("trace"), None).unwrap();
+            clock.advance(std::time::Duration::from_micros(1500));
+        });
+
+        recorder
+            .assert_span_exists("request")
+            .assert_field("duration_ms", 1.5)
+            .assert_field("Timestamp", "1970-01-01T00:00:00+00:00");
+    }
+
+    #[test]
+    fn span_start_events_are_published_while_spans_are_open() {
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder()
+            .span_start_events(true)
+            .record_to(&recorder)
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = tracing::info_span!("request");
+            request.in_scope(|| {
+                register_dist_tracing_root(TraceId::from("trace"), None).unwrap();
+            });
+            request.in_scope(|| {
+                let _query = tracing::info_span!("query").entered();
+                // both spans are still open
+                let events = recorder.events();
+                assert_eq!(events.len(), 2);
+                events[0].assert_field("meta.annotation_type", "span_start");
+                assert_eq!(events[0].name(), "request");
+                assert_eq!(events[1].name(), "query");
+                assert_eq!(
+                    events[1].parent_id(),
+                    events[0].field("meta.started_span_id")
+                );
+                assert_eq!(events[1].trace_id(), Some(&json!("trace")));
+            });
+        });
+
+        let request = recorder.assert_span_exists("request");
+        let query = recorder.assert_span_exists("query");
+        let starts = recorder.events();
+        assert_eq!(starts[0].field("meta.started_span_id"), request.span_id());
+        assert_eq!(starts[1].field("meta.started_span_id"), query.span_id());
+        assert_eq!(starts[1].field("Timestamp"), query.field("Timestamp"));
+    }
+
+    #[test]
+    fn spans_are_published_with_their_status() {
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder().record_to(&recorder).build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _request = tracing::info_span!("request").entered();
+            register_dist_tracing_root(TraceId::from("trace"), None).unwrap();
+            tracing::info_span!("succeeded").in_scope(|| {});
+            tracing::info_span!("failed").in_scope(|| crate::set_span_error().unwrap());
+            tracing::info_span!("recovered").in_scope(|| {
+                tracing::info!(error = true, "retrying");
+                crate::set_span_status(SpanStatus::Ok).unwrap();
+            });
+            tracing::info_span!("errored").in_scope(|| tracing::info!(error = true, "failed"));
+        });
+
+        recorder
+            .assert_span_exists("succeeded")
+            .assert_field("span.status", "ok")
+            .assert_no_field("error");
+        recorder
+            .assert_span_exists("failed")
+            .assert_field("span.status", "error")
+            .assert_field("error", true);
+        recorder
+            .assert_span_exists("recovered")
+            .assert_field("span.status", "ok");
+        recorder
+            .assert_span_exists("errored")
+            .assert_field("span.status", "error")
+            .assert_field("error", true);
+        recorder
+            .assert_span_exists("request")
+            .assert_field("span.status", "ok");
+    }
+
+    #[test]
+    fn orphan_events_are_published_in_traces_of_their_own() {
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder()
+            .orphan_events(true)
+            .record_to(&recorder)
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info!("starting");
+            tracing::info_span!("untraced").in_scope(|| tracing::warn!("retrying"));
+            tracing::info_span!("request").in_scope(|| {
+                register_dist_tracing_root(TraceId::from("trace"), None).unwrap();
+                tracing::info!("handled");
+            });
+        });
+
+        let events = recorder.events();
+        assert_eq!(events.len(), 3);
+        let (starting, retrying, handled) = (&events[0], &events[1], &events[2]);
+        for orphan in &[starting, retrying] {
+            orphan.assert_field("orphan", true);
+            assert_eq!(orphan.parent_id(), None);
+        }
+        assert_ne!(starting.trace_id(), retrying.trace_id());
+        assert!(recorder.assert_span_exists("request").is_parent_of(handled));
+        handled.assert_no_field("orphan");
+    }
+
+    #[test]
+    fn roots_are_registered_from_request_headers() {
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder().record_to(&recorder).build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        let traced: HashMap<String, String> = vec![(
+            "traceparent".to_string(),
+            "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01".to_string(),
+        )]
+        .into_iter()
+        .collect();
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("traced")
+                .in_scope(|| crate::register_dist_tracing_root_from(&traced).unwrap());
+            tracing::info_span!("untraced")
+                .in_scope(|| crate::register_dist_tracing_root_from(&HashMap::new()).unwrap());
+        });
+
+        let traced = recorder.assert_span_exists("traced");
+        assert_eq!(
+            traced.trace_id(),
+            Some(&json!("4bf92f3577b34da6a3ce929d0e0e4736"))
+        );
+        assert_eq!(traced.parent_id(), Some(&json!("span-00f067aa0ba902b7")));
+        let untraced = recorder.assert_span_exists("untraced");
+        assert!(untraced.trace_id().is_some());
+        assert_eq!(untraced.parent_id(), None);
+    }
+
+    #[test]
+    fn span_links_are_attached_to_the_current_span() {
+        use std::str::FromStr;
+
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder().record_to(&recorder).build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let _batch = tracing::info_span!("batch").entered();
+            register_dist_tracing_root(TraceId::from("consumer"), None).unwrap();
+            for producer in ["a", "b"] {
+                let span_id = SpanId::from_str("2a-1").unwrap();
+                crate::add_span_link(TraceId::from(producer), span_id).unwrap();
+            }
+        });
+
+        let batch = recorder.assert_span_exists("batch");
+        let links = recorder.events();
+        assert_eq!(links.len(), 2);
+        for (link, producer) in links.iter().zip(["a", "b"]) {
+            assert!(batch.is_parent_of(link));
+            link.assert_field("meta.annotation_type", "link")
+                .assert_field("name", "batch")
+                .assert_field("trace.trace_id", "consumer")
+                .assert_field("trace.link.trace_id", producer)
+                .assert_field("trace.link.span_id", "span-2a-1");
+        }
+
+        // outside of any span
+        let span_id = SpanId::from_str("2a-1").unwrap();
+        assert_eq!(
+            crate::add_span_link(TraceId::from("a"), span_id),
+            Err(crate::TraceCtxError::NoEnabledSpan)
+        );
+    }
+
+    #[test]
+    fn follows_from_relationships_are_published_as_links() {
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder().record_to(&recorder).build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let producer = tracing::info_span!("producer");
+            producer.in_scope(|| {
+                register_dist_tracing_root(TraceId::from("producer"), None).unwrap();
+            });
+            let consumer = tracing::info_span!("consumer");
+            consumer.follows_from(&producer);
+            consumer.in_scope(|| {
+                register_dist_tracing_root(TraceId::from("consumer"), None).unwrap();
+            });
+        });
+
+        let producer = recorder.assert_span_exists("producer");
+        let consumer = recorder.assert_span_exists("consumer");
+        let links = recorder.events();
+        assert_eq!(links.len(), 1);
+        assert!(consumer.is_parent_of(&links[0]));
+        links[0]
+            .assert_field("meta.annotation_type", "link")
+            .assert_field("trace.trace_id", "consumer")
+            .assert_field("trace.link.trace_id", "producer");
+        assert_eq!(links[0].field("trace.link.span_id"), producer.span_id());
+    }
+
+    #[test]
+    fn trace_fields_are_published_on_every_span_of_the_trace() {
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder().record_to(&recorder).build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request").in_scope(|| {
+                register_dist_tracing_root(TraceId::from("trace"), None).unwrap();
+                tracing::info_span!("authenticate").in_scope(|| {
+                    crate::add_trace_field("user_id", 42).unwrap();
+                });
+                tracing::info_span!("handle", user_id = 7).in_scope(|| {
+                    tracing::info!("handled");
+                });
+            });
+            tracing::info_span!("next").in_scope(|| {
+                register_dist_tracing_root(TraceId::from("trace"), None).unwrap();
+            });
+        });
+
+        for name in &["request", "authenticate"] {
+            recorder
+                .assert_span_exists(name)
+                .assert_field("user_id", 42);
+        }
+        recorder
+            .assert_span_exists("handle")
+            .assert_field("user_id", 7);
+        recorder.events()[0].assert_field("user_id", 42);
+        // forgotten once the local root span has closed
+        recorder
+            .assert_span_exists("next")
+            .assert_no_field("user_id");
+
+        assert_eq!(
+            crate::add_trace_field("user_id", 42),
+            Err(crate::TraceCtxError::NoEnabledSpan)
+        );
+    }
+
+    #[test]
+    fn recorded_fields_colliding_with_custom_trace_field_names_are_prefixed() {
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder()
+            .trace_field_names(crate::TraceFieldNames {
+                trace_id: "traceId".to_string(),
+                span_id: "spanId".to_string(),
+                parent_id: "parentId".to_string(),
+            })
+            .record_to(&recorder)
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request", traceId = "recorded", trace.trace_id = "kept").in_scope(
+                || {
+                    register_dist_tracing_root(TraceId::from("trace"), None).unwrap();
+                },
+            );
+        });
+
+        recorder
+            .assert_span_exists("request")
+            .assert_field("traceId", "trace")
+            .assert_field("tracing.traceId", "recorded")
+            .assert_field("trace.trace_id", "kept")
+            .assert_no_field("tracing.trace.trace_id");
+    }
+
+    #[test]
+    fn trace_fields_are_redacted() {
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder()
+            .redact_field("authorization")
+            .drop_field("password")
+            .record_to(&recorder)
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request").in_scope(|| {
+                register_dist_tracing_root(TraceId::from("trace"), None).unwrap();
+                crate::add_trace_field("Authorization", "Bearer secret").unwrap();
+                crate::add_trace_field("password", "hunter2").unwrap();
+                crate::add_trace_field("user_id", 42).unwrap();
+                tracing::info!("handled");
+            });
+        });
+
+        recorder
+            .assert_span_exists("request")
+            .assert_field("Authorization", "[REDACTED]")
+            .assert_no_field("password")
+            .assert_field("user_id", 42);
+        recorder.events()[0]
+            .assert_field("Authorization", "[REDACTED]")
+            .assert_no_field("password")
+            .assert_field("user_id", 42);
+    }
+
+    #[test]
+    fn static_fields_are_published_on_every_span_and_event() {
+        let recorder = TelemetryRecorder::new();
+        let builder = HoneycombTelemetry::builder()
+            .with_static_field("env", "test")
+            .with_static_field("region", "eu")
+            .record_to(&recorder);
+        let reload = builder.reload_handle();
+        let subscriber = builder
+            .build()
+            .with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            tracing::info_span!("request", region = "us").in_scope(|| {
+                register_dist_tracing_root(TraceId::from("trace"), None).unwrap();
+                tracing::info!("handled");
+                tracing::info!(env = "dev", "overridden");
+            });
+            let mut static_fields = HashMap::new();
+            static_fields.insert("env".to_string(), json!("prod"));
+            reload.set_static_fields(static_fields);
+            tracing::info_span!("reloaded").in_scope(|| {
+                register_dist_tracing_root(TraceId::from("other"), None).unwrap();
+                tracing::info!("handled");
+            });
+        });
+
+        // fields recorded on a span or event take precedence over static fields
+        recorder
+            .assert_span_exists("request")
+            .assert_field("env", "test")
+            .assert_field("region", "us");
+        let events = recorder.events();
+        events[0]
+            .assert_field("env", "test")
+            .assert_field("region", "eu");
+        events[1]
+            .assert_field("env", "dev")
+            .assert_field("region", "eu");
+
+        recorder
+            .assert_span_exists("reloaded")
+            .assert_field("env", "prod")
+            .assert_no_field("region");
+        events[2]
+            .assert_field("env", "prod")
+            .assert_no_field("region");
+    }
+
+    #[test]
+    fn traces_of_spans_are_looked_up_by_id() {
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder().record_to(&recorder).build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let first = tracing::info_span!("first");
+            let second = tracing::info_span!("second");
+            first.in_scope(|| register_dist_tracing_root(TraceId::from("first"), None).unwrap());
+            second.in_scope(|| register_dist_tracing_root(TraceId::from("second"), None).unwrap());
+
+            let first_id = first.id().unwrap();
+            assert_eq!(crate::trace_id_of(&first_id), Some(TraceId::from("first")));
+            assert_eq!(
+                crate::trace_id_of(&second.id().unwrap()),
+                Some(TraceId::from("second"))
+            );
+            assert_eq!(crate::current_trace_id(), None);
+
+            drop(first);
+            assert_eq!(
+                crate::dist_trace_ctx_of(&first_id),
+                Err(crate::TraceCtxError::NoEnabledSpan)
+            );
+        });
+
+        assert_eq!(recorder.spans().len(), 2);
+    }
+
+    #[test]
+    fn sampled_out_traces_are_not_recorded() {
+        // counts how often the field is recorded
+        struct Expensive(Arc<std::sync::atomic::AtomicUsize>);
+        impl fmt::Debug for Expensive {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                write!(f, "expensive")
+            }
+        }
+
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder()
+            .sample_rate(2)
+            .record_to(&recorder)
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+        let trace_id = |sampled| {
+            std::iter::repeat_with(TraceId::new)
+                .find(|trace_id| crate::deterministic_sampler::sample(2, trace_id) == sampled)
+                .unwrap()
+        };
+        let recorded = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        tracing::subscriber::with_default(subscriber, || {
+            for (name, sampled) in [("sampled out", false), ("sampled", true)] {
+                let _span = tracing::info_span!("request", name).entered();
+                register_dist_tracing_root(trace_id(sampled), None).unwrap();
+                let span = tracing::info_span!("query", field = ?Expensive(recorded.clone()));
+                span.record("field", tracing::field::debug(Expensive(recorded.clone())));
+                span.in_scope(|| tracing::info!(field = ?Expensive(recorded.clone())));
+            }
+        });
+
+        // once for each of the sampled trace's span, recorded field and event
+        assert_eq!(recorded.load(std::sync::atomic::Ordering::Relaxed), 3);
+        assert_eq!(recorder.spans().len(), 2);
+        assert_eq!(recorder.events().len(), 1);
+        recorder
+            .assert_span_exists("query")
+            .assert_field("field", "expensive");
+    }
+
+    #[test]
+    fn records_published_spans_and_events() {
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder()
+            .with_static_field("env", "test")
+            .record_to(&recorder)
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let span = tracing::info_span!("request");
+            let _guard = span.enter();
+            register_dist_tracing_root(TraceId::from("trace"), None).unwrap();
+            tracing::info_span!("query", table = "users").in_scope(|| {
+                tracing::info!(rows = 3, "fetched");
+            });
+        });
+
+        recorder
+            .assert_span_exists("query")
+            .assert_field("table", "users")
+            .assert_field("env", "test")
+            .assert_field_matches("duration_ms", |duration| duration.is_f64())
+            .assert_no_field("rows");
+        recorder.assert_parent_child("request", "query");
+        recorder.assert_no_span("fetched");
+
+        let events = recorder.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].field("rows"), Some(&json!(3)));
+        assert_eq!(events[0].trace_id(), Some(&json!("trace")));
+        assert!(recorder
+            .assert_span_exists("query")
+            .is_parent_of(&events[0]));
+
+        recorder.clear();
+        assert!(recorder.recorded().is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "no span named \"missing\"")]
+    fn missing_spans_fail_assertions() {
+        TelemetryRecorder::new().assert_span_exists("missing");
+    }
+
+    #[test]
+    fn golden_json_is_canonical() {
+        let recorder = TelemetryRecorder::new();
+        let layer = HoneycombTelemetry::builder()
+            .service_name("shop")
+            .record_to(&recorder)
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            for user in &[2, 1] {
+                let _request = tracing::info_span!("request", user).entered();
+                register_dist_tracing_root(TraceId::new(), None).unwrap();
+                tracing::info_span!("query").in_scope(|| tracing::info!("fetched"));
+            }
+        });
+
+        let golden = recorder.to_golden_json();
+        let traces: Value = golden.parse().unwrap();
+        assert_eq!(traces.as_array().unwrap().len(), 2);
+        let first = &traces[0];
+        assert_eq!(first["fields"]["user"], json!(1));
+        assert_eq!(first["fields"]["trace.trace_id"], json!("trace-1"));
+        assert_eq!(first["fields"].get("duration_ms"), None);
+        assert_eq!(first["fields"].get("trace.span_id"), None);
+        let query = &first["children"][0];
+        assert_eq!(query["fields"]["name"], json!("query"));
+        assert_eq!(query["fields"]["trace.trace_id"], json!("trace-1"));
+        assert_eq!(query["events"].as_array().unwrap().len(), 1);
+        assert_eq!(traces[1]["fields"]["trace.trace_id"], json!("trace-2"));
+
+        let path = std::env::temp_dir().join(format!("golden-{}.json", uuid::Uuid::new_v4()));
+        recorder.assert_golden(&path);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), golden);
+        recorder.assert_golden(&path);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
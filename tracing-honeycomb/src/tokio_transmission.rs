@@ -0,0 +1,250 @@
+use libhoney::Value;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{mpsc as std_mpsc, Arc};
+use std::time::Duration;
+use tokio1::runtime::Handle;
+use tokio1::sync::mpsc::{self, error::TrySendError, Receiver, Sender};
+use tokio1::time::Instant;
+
+use crate::blocking::{BlockingTransmission, PendingEvent};
+use crate::stats::TelemetryStats;
+use crate::telemetry_error::{ErrorHandler, TelemetryError};
+
+/// Time after which a batch sent by a `TokioTransmission` fails if honeycomb.io didn't respond.
+pub(crate) const SEND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Sends events to honeycomb.io from a task spawned on a tokio 1.x runtime, instead of
+/// libhoney's dedicated background threads, see `Builder::tokio_transmission`.
+///
+/// Events are pushed to a bounded channel without blocking, and sent in batches by the task
+/// once `max_batch_size` of them are pending or `batch_timeout` passed, one batch at a time.
+/// reqwest 0.10's async client requires a tokio 0.2 runtime, so batches are posted using
+/// `BlockingTransmission` on the runtime's blocking thread pool, retaining its spooling,
+/// circuit breaking and error reporting.
+#[derive(Debug)]
+pub(crate) struct TokioTransmission {
+    sender: Sender<Message>,
+    batches: Arc<BlockingTransmission>,
+    on_error: ErrorHandler,
+    queue_depth: Arc<AtomicUsize>,
+}
+
+#[derive(Debug)]
+enum Message {
+    Event(PendingEvent),
+    Flush(std_mpsc::SyncSender<()>),
+}
+
+impl TokioTransmission {
+    /// Spawn the task sending events on `runtime`, posting batches with `batches`.
+    pub(crate) fn new(
+        runtime: Handle,
+        batches: BlockingTransmission,
+        options: &libhoney::transmission::Options,
+        on_error: ErrorHandler,
+    ) -> Self {
+        let (sender, receiver) = mpsc::channel(options.pending_work_capacity.max(1));
+        let batches = Arc::new(batches);
+        let queue_depth = Arc::new(AtomicUsize::new(0));
+
+        runtime.spawn(process(
+            receiver,
+            batches.clone(),
+            queue_depth.clone(),
+            options.max_batch_size.max(1),
+            options.batch_timeout,
+        ));
+
+        TokioTransmission {
+            sender,
+            batches,
+            on_error,
+            queue_depth,
+        }
+    }
+
+    /// Queue an event for sending, without blocking. Sampling is assumed to have already
+    /// happened.
+    ///
+    /// Queued events can't be shed, so the event is dropped instead if it would exceed the
+    /// memory budget.
+    pub(crate) fn send(&self, options: &libhoney::client::Options, data: HashMap<String, Value>) {
+        let bytes = match self.batches.charge(&data) {
+            Some(bytes) => bytes,
+            None => return,
+        };
+        // counted before sending, as the event may be sent before try_send returns
+        self.queue_depth.fetch_add(1, Ordering::Relaxed);
+        let event = PendingEvent::new(options.clone(), data, bytes);
+        if let Err(err) = self.sender.try_send(Message::Event(event)) {
+            self.queue_depth.fetch_sub(1, Ordering::Relaxed);
+            self.batches.release(bytes);
+            self.batches.record_dropped(1);
+            // the channel is closed once the runtime shuts down, dropping the task
+            if let TrySendError::Full(_) = err {
+                self.on_error
+                    .report(TelemetryError::Enqueue(libhoney::Error {
+                        message: "tokio transmission queue is full".to_string(),
+                        kind: libhoney::ErrorKind::ChannelError,
+                    }));
+            }
+        }
+    }
+
+    /// Send the events queued so far right away, and wait at most `timeout` for them to be
+    /// sent.
+    ///
+    /// Blocks the calling thread, so flushing from within a current-thread runtime, whose
+    /// only thread runs the sending task, times out without sending the events.
+    pub(crate) fn flush(&self, timeout: Duration) {
+        let (done, flushed) = std_mpsc::sync_channel(1);
+        if self.sender.try_send(Message::Flush(done)).is_ok() {
+            let _ = flushed.recv_timeout(timeout);
+        }
+    }
+
+    pub(crate) fn stats(&self) -> TelemetryStats {
+        let mut stats = self.batches.stats();
+        stats.queue_depth = self.queue_depth.load(Ordering::Relaxed);
+        stats
+    }
+
+    pub(crate) fn record_rate_limited(&self, events: u64) {
+        self.batches.record_rate_limited(events);
+    }
+
+    pub(crate) fn record_circuit_open(&self, events: u64) {
+        self.batches.record_circuit_open(events);
+    }
+}
+
+// batches events until the last sender is dropped, then sends the pending events and exits
+async fn process(
+    mut receiver: Receiver<Message>,
+    batches: Arc<BlockingTransmission>,
+    queue_depth: Arc<AtomicUsize>,
+    max_batch_size: usize,
+    batch_timeout: Duration,
+) {
+    let mut pending = Vec::new();
+    let mut deadline = Instant::now() + batch_timeout;
+    loop {
+        match tokio1::time::timeout_at(deadline, receiver.recv()).await {
+            Ok(Some(Message::Event(event))) => {
+                pending.push(event);
+                if pending.len() < max_batch_size {
+                    continue;
+                }
+            }
+            Ok(Some(Message::Flush(done))) => {
+                send_batches(&batches, &queue_depth, std::mem::take(&mut pending)).await;
+                let _ = done.try_send(());
+            }
+            Ok(None) => {
+                send_batches(&batches, &queue_depth, pending).await;
+                return;
+            }
+            Err(_) => {}
+        }
+        send_batches(&batches, &queue_depth, std::mem::take(&mut pending)).await;
+        deadline = Instant::now() + batch_timeout;
+    }
+}
+
+async fn send_batches(
+    batches: &Arc<BlockingTransmission>,
+    queue_depth: &AtomicUsize,
+    pending: Vec<PendingEvent>,
+) {
+    if pending.is_empty() {
+        return;
+    }
+
+    let events = pending.len();
+    let batches = batches.clone();
+    // fails only if the runtime is shutting down, in which case the events are lost
+    let _ = tokio1::task::spawn_blocking(move || batches.send_pending(pending)).await;
+    queue_depth.fetch_sub(events, Ordering::Relaxed);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::diagnostics::InternalLogMode;
+    use libhoney::json;
+    use std::io::{Read, Write};
+    use std::net::TcpListener;
+
+    #[test]
+    fn sends_batches_from_the_runtime() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let options = libhoney::client::Options {
+            api_host: format!("http://{}", listener.local_addr().unwrap()),
+            dataset: "service".to_string(),
+            ..Default::default()
+        };
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut buf = [0; 4096];
+            // the body is the last thing sent, a json array
+            while !request.ends_with(b"]") {
+                let n = stream.read(&mut buf).unwrap();
+                request.extend_from_slice(&buf[..n]);
+            }
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\ncontent-length: 2\r\n\r\n[]")
+                .unwrap();
+            String::from_utf8(request).unwrap()
+        });
+
+        let runtime = tokio1::runtime::Builder::new_current_thread()
+            .enable_time()
+            .build()
+            .unwrap();
+        let batches = BlockingTransmission::new(
+            SEND_TIMEOUT,
+            10,
+            ErrorHandler::default(),
+            InternalLogMode::Silent,
+        );
+        let transmission = TokioTransmission::new(
+            runtime.handle().clone(),
+            batches,
+            &libhoney::transmission::Options {
+                batch_timeout: Duration::from_secs(60),
+                ..Default::default()
+            },
+            ErrorHandler::default(),
+        );
+        for i in 0..2 {
+            let mut data = HashMap::new();
+            data.insert("i".to_string(), json!(i));
+            transmission.send(&options, data);
+        }
+        assert_eq!(transmission.stats().queue_depth, 2);
+
+        // the task runs while the runtime is driven, here by a thread other than the flushing one
+        let stopped = Arc::new(std::sync::atomic::AtomicBool::new(false));
+        let driver = {
+            let stopped = stopped.clone();
+            std::thread::spawn(move || {
+                runtime.block_on(async {
+                    while !stopped.load(Ordering::Relaxed) {
+                        tokio1::time::sleep(Duration::from_millis(10)).await;
+                    }
+                })
+            })
+        };
+        transmission.flush(Duration::from_secs(5));
+
+        let request = server.join().unwrap();
+        assert!(request.starts_with("POST /1/batch/service HTTP/1.1"));
+        assert!(request.contains(r#"{"i":0}"#) && request.contains(r#"{"i":1}"#));
+        assert_eq!(transmission.stats().queue_depth, 0);
+        assert_eq!(transmission.stats().events_sent, 2);
+        stopped.store(true, Ordering::Relaxed);
+        driver.join().unwrap();
+    }
+}
@@ -0,0 +1,81 @@
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::bounded_map::BoundedMap;
+use crate::TraceId;
+
+// bounds the number of traces with fields whose local root span has not yet closed
+const MAX_TRACE_FIELDS: usize = 10_000;
+
+/// Fields added to in-flight traces, e.g. `user_id`, stamped on all of their spans and events
+/// until the local root span of the trace closes.
+#[derive(Debug)]
+pub(crate) struct TraceFields(BoundedMap<TraceId, BTreeMap<String, Value>>);
+
+impl Default for TraceFields {
+    fn default() -> Self {
+        TraceFields(BoundedMap::new(MAX_TRACE_FIELDS))
+    }
+}
+
+impl TraceFields {
+    pub(crate) fn insert(&self, trace_id: &TraceId, key: String, value: Value) {
+        self.0.update(trace_id, |fields| {
+            fields.insert(key, value);
+        });
+    }
+
+    /// Fields to stamp on spans and events belonging to the given trace, leaving out those for
+    /// which `recorded` returns true: fields recorded on a span or event take precedence over
+    /// fields of its trace.
+    pub(crate) fn fields(
+        &self,
+        trace_id: &TraceId,
+        recorded: impl Fn(&str) -> bool,
+    ) -> Vec<(String, Value)> {
+        self.0
+            .get(trace_id, |fields| {
+                fields
+                    .iter()
+                    .filter(|(key, _)| !recorded(key))
+                    .map(|(key, value)| (key.clone(), value.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+
+    pub(crate) fn remove(&self, trace_id: &TraceId) {
+        self.0.remove(trace_id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn recorded_fields_take_precedence() {
+        let fields = TraceFields::default();
+        let trace_id = TraceId::from("abc");
+        fields.insert(&trace_id, "user_id".to_string(), json!(1));
+        fields.insert(&trace_id, "user_id".to_string(), json!(2));
+        fields.insert(&trace_id, "plan".to_string(), json!("free"));
+
+        assert_eq!(
+            fields.fields(&trace_id, |_| false),
+            vec![
+                ("plan".to_string(), json!("free")),
+                ("user_id".to_string(), json!(2)),
+            ]
+        );
+        assert_eq!(
+            fields.fields(&trace_id, |key| key == "plan"),
+            vec![("user_id".to_string(), json!(2))]
+        );
+        assert!(fields.fields(&TraceId::from("other"), |_| false).is_empty());
+
+        fields.remove(&trace_id);
+        assert!(fields.fields(&trace_id, |_| false).is_empty());
+    }
+}
@@ -3,6 +3,7 @@ use std::convert::{Infallible, TryInto};
 use std::fmt::{self, Display};
 use std::str::FromStr;
 
+use sha1::{Digest, Sha1};
 use uuid::Uuid;
 
 /// A Honeycomb Trace ID.
@@ -26,6 +27,82 @@ impl TraceId {
         Uuid::new_v4().into()
     }
 
+    /// Generate a new time-ordered `TraceId` from a UUID V7, whose first 48 bits are the
+    /// current unix time in milliseconds.
+    ///
+    /// Ids generated this way sort by creation time, so they double as rough timestamps in
+    /// logs and improve locality in downstream storage. Use it with
+    /// `Builder::trace_id_generator(TraceId::new_v7)`.
+    #[cfg(feature = "uuid_v7")]
+    pub fn new_v7() -> Self {
+        Uuid::now_v7().into()
+    }
+
+    /// Parse a `TraceId` in the W3C Trace Context format: 32 lowercase hex digits, not all
+    /// zeros.
+    pub fn from_w3c(s: &str) -> Result<Self, ParseTraceIdError> {
+        if !is_w3c(s) {
+            return Err(ParseTraceIdError::InvalidW3cFormat);
+        }
+        if s.bytes().all(|b| b == b'0') {
+            return Err(ParseTraceIdError::ZeroW3cTraceId);
+        }
+        Ok(Self(s.to_owned()))
+    }
+
+    /// Convert this `TraceId` to the W3C Trace Context format, 32 lowercase hex digits.
+    ///
+    /// Trace ids generated by `TraceId::new()` are already in that format and are returned
+    /// unchanged, UUIDs in other formats are reformatted, and any other id is replaced with
+    /// the first 16 bytes of its SHA-1 hash. The conversion is stable, so the same trace id
+    /// always converts to the same W3C trace id.
+    pub fn to_w3c(&self) -> Self {
+        if TraceId::from_w3c(&self.0).is_ok() {
+            return self.clone();
+        }
+        if let Ok(uuid) = Uuid::parse_str(&self.0) {
+            if !uuid.is_nil() {
+                return uuid.into();
+            }
+        }
+
+        let sum = Sha1::digest(self.0.as_bytes());
+        let mut bytes = [0; 16];
+        bytes.copy_from_slice(&sum[..16]);
+        u128::from_be_bytes(bytes).max(1).into()
+    }
+
+    /// Parse a `TraceId` in the AWS X-Ray format, `1-{epoch}-{random}` where `epoch` is 8 and
+    /// `random` is 24 lowercase hex digits, e.g. the `Root` of an `X-Amzn-Trace-Id` header.
+    ///
+    /// The trace id is converted to the W3C Trace Context format by concatenating `epoch` and
+    /// `random`, the same mapping used by the AWS distribution of OpenTelemetry, so
+    /// `to_xray` returns the original id.
+    pub fn from_xray(s: &str) -> Result<Self, ParseTraceIdError> {
+        let (epoch, random) = match s.strip_prefix("1-").and_then(|s| s.split_once('-')) {
+            Some(parts) => parts,
+            None => return Err(ParseTraceIdError::InvalidXrayFormat),
+        };
+        if epoch.len() != 8 || random.len() != 24 {
+            return Err(ParseTraceIdError::InvalidXrayFormat);
+        }
+        match TraceId::from_w3c(&format!("{}{}", epoch, random)) {
+            Err(ParseTraceIdError::InvalidW3cFormat) => Err(ParseTraceIdError::InvalidXrayFormat),
+            res => res,
+        }
+    }
+
+    /// Convert this `TraceId` to the AWS X-Ray format, `1-{epoch}-{random}`, by splitting its
+    /// `to_w3c` conversion after the first 8 hex digits.
+    ///
+    /// Round-trips with `from_xray`. Other trace ids, e.g. ones generated by `TraceId::new()`,
+    /// are converted consistently but their `epoch` is not a timestamp, so X-Ray may reject
+    /// them if it ingests them directly.
+    pub fn to_xray(&self) -> String {
+        let w3c = self.to_w3c();
+        format!("1-{}-{}", &w3c.0[..8], &w3c.0[8..])
+    }
+
     #[deprecated(since = "0.2.0", note = "Use `TraceId::new()` instead.")]
     /// Generate a new `TraceId` from a UUID V4.
     ///
@@ -35,6 +112,97 @@ impl TraceId {
     }
 }
 
+/// Generates the `TraceId`s of new traces, e.g. ULIDs, request ids assigned by a load
+/// balancer or ids that are deterministic in tests. See `Builder::trace_id_generator` and
+/// `new_trace_id`.
+///
+/// Implemented for closures returning a `TraceId`.
+pub trait TraceIdGenerator: Send + Sync {
+    /// Generate the `TraceId` of a new trace.
+    fn generate(&self) -> TraceId;
+}
+
+impl<F> TraceIdGenerator for F
+where
+    F: Fn() -> TraceId + Send + Sync,
+{
+    fn generate(&self) -> TraceId {
+        self()
+    }
+}
+
+pub(crate) struct BoxedTraceIdGenerator(Box<dyn TraceIdGenerator>);
+
+impl BoxedTraceIdGenerator {
+    pub(crate) fn new<G: TraceIdGenerator + 'static>(generator: G) -> Self {
+        BoxedTraceIdGenerator(Box::new(generator))
+    }
+
+    pub(crate) fn generate(&self) -> TraceId {
+        self.0.generate()
+    }
+}
+
+impl Default for BoxedTraceIdGenerator {
+    fn default() -> Self {
+        BoxedTraceIdGenerator::new(TraceId::new)
+    }
+}
+
+impl fmt::Debug for BoxedTraceIdGenerator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TraceIdGenerator")
+    }
+}
+
+fn is_w3c(s: &str) -> bool {
+    s.len() == 32 && s.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Format of the `TraceId`s reported and propagated by a telemetry layer, see
+/// `Builder::trace_id_format`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum TraceIdFormat {
+    /// Trace ids are reported and propagated as is.
+    #[default]
+    Opaque,
+    /// Trace ids are reported and propagated in the W3C Trace Context format, 32 lowercase
+    /// hex digits, converting them with `TraceId::to_w3c` if needed.
+    W3c,
+}
+
+impl TraceIdFormat {
+    pub(crate) fn apply(self, trace_id: &TraceId) -> TraceId {
+        match self {
+            TraceIdFormat::Opaque => trace_id.clone(),
+            TraceIdFormat::W3c => trace_id.to_w3c(),
+        }
+    }
+}
+
+/// Error returned when parsing a `TraceId` fails.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ParseTraceIdError {
+    /// The trace id is not made of 32 lowercase hex digits.
+    InvalidW3cFormat,
+    /// The trace id is all zeros, which W3C Trace Context reserves as invalid.
+    ZeroW3cTraceId,
+    /// The trace id is not in the AWS X-Ray `1-{8 hex digits}-{24 hex digits}` format.
+    InvalidXrayFormat,
+}
+
+impl Display for ParseTraceIdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidW3cFormat => write!(f, "trace id is not 32 lowercase hex digits"),
+            Self::ZeroW3cTraceId => write!(f, "trace id is all zeros"),
+            Self::InvalidXrayFormat => write!(f, "trace id is not in the X-Ray format"),
+        }
+    }
+}
+
+impl std::error::Error for ParseTraceIdError {}
+
 impl Default for TraceId {
     fn default() -> Self {
         TraceId::new()
@@ -47,9 +215,9 @@ impl AsRef<[u8]> for TraceId {
     }
 }
 
-impl Into<String> for TraceId {
-    fn into(self) -> String {
-        format!("{}", self)
+impl From<TraceId> for String {
+    fn from(trace_id: TraceId) -> Self {
+        format!("{}", trace_id)
     }
 }
 
@@ -65,7 +233,7 @@ impl TryInto<Uuid> for TraceId {
     type Error = uuid::Error;
 
     fn try_into(self) -> Result<Uuid, Self::Error> {
-        Ok(Uuid::parse_str(&self.0)?)
+        Uuid::parse_str(&self.0)
     }
 }
 
@@ -90,7 +258,7 @@ impl From<String> for TraceId {
 impl From<Uuid> for TraceId {
     fn from(uuid: Uuid) -> Self {
         let buf = &mut [0; 36];
-        let id = uuid.to_simple().encode_lower(buf);
+        let id = uuid.simple().encode_lower(buf);
         Self(id.to_owned())
     }
 }
@@ -116,6 +284,29 @@ impl Display for TraceId {
     }
 }
 
+/// Serialized as a string, using the `Display` representation.
+#[cfg(feature = "serde")]
+impl serde::Serialize for TraceId {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for TraceId {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(TraceId)
+    }
+}
+
+/// Serialized as its message.
+#[cfg(feature = "serde")]
+impl serde::Serialize for ParseTraceIdError {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -140,6 +331,80 @@ mod test {
         assert_eq!(Ok(trace_id), res);
     }
 
+    #[test]
+    fn w3c_trace_ids() {
+        let trace_id = TraceId::new();
+        assert_eq!(
+            TraceId::from_w3c(&trace_id.to_string()),
+            Ok(trace_id.clone())
+        );
+        assert_eq!(trace_id.to_w3c(), trace_id);
+
+        let uuid = TraceId::from("67E55044-10B1-426F-9247-BB680E5FE0C8");
+        assert_eq!(
+            uuid.to_w3c().to_string(),
+            "67e5504410b1426f9247bb680e5fe0c8"
+        );
+
+        let opaque = TraceId::from("a string");
+        let w3c = opaque.to_w3c();
+        assert_eq!(TraceId::from_w3c(&w3c.to_string()), Ok(w3c.clone()));
+        assert_eq!(opaque.to_w3c(), w3c);
+
+        assert_eq!(
+            TraceId::from_w3c("a string"),
+            Err(ParseTraceIdError::InvalidW3cFormat)
+        );
+        assert_eq!(
+            TraceId::from_w3c("00000000000000000000000000000000"),
+            Err(ParseTraceIdError::ZeroW3cTraceId)
+        );
+    }
+
+    #[test]
+    fn xray_trace_ids() {
+        let xray = "1-5759e988-bd862e3fe1be46a994272793";
+        let trace_id = TraceId::from_xray(xray).unwrap();
+        assert_eq!(trace_id.to_string(), "5759e988bd862e3fe1be46a994272793");
+        assert_eq!(trace_id.to_xray(), xray);
+
+        let opaque = TraceId::from("a string");
+        assert_eq!(TraceId::from_xray(&opaque.to_xray()), Ok(opaque.to_w3c()));
+
+        for invalid in &[
+            "5759e988-bd862e3fe1be46a994272793",
+            "1-5759e988bd862e3fe1be46a994272793",
+            "1-5759e98-8bd862e3fe1be46a994272793",
+            "1-5759E988-BD862E3FE1BE46A994272793",
+        ] {
+            assert_eq!(
+                TraceId::from_xray(invalid),
+                Err(ParseTraceIdError::InvalidXrayFormat)
+            );
+        }
+        assert_eq!(
+            TraceId::from_xray("1-00000000-000000000000000000000000"),
+            Err(ParseTraceIdError::ZeroW3cTraceId)
+        );
+    }
+
+    #[cfg(feature = "uuid_v7")]
+    #[test]
+    fn v7_trace_ids_are_time_ordered() {
+        let before = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_millis();
+        let ids: Vec<TraceId> = (0..3).map(|_| TraceId::new_v7()).collect();
+
+        let millis = u128::from_str_radix(&ids[0].to_string()[..12], 16).unwrap();
+        assert!(millis >= before && millis - before < 60_000);
+        assert!(ids
+            .windows(2)
+            .all(|pair| pair[0].to_string() < pair[1].to_string()));
+        assert_eq!(TraceId::from_w3c(&ids[0].to_string()), Ok(ids[0].clone()));
+    }
+
     #[test]
     fn trace_id_round_trip_empty_str() {
         let trace_id: TraceId = "".into();
@@ -147,4 +412,77 @@ mod test {
         let res = TraceId::from_str(&s);
         assert_eq!(Ok(trace_id), res);
     }
+
+    #[test]
+    #[cfg(feature = "honeycomb")]
+    fn new_trace_id_uses_configured_generator() {
+        use tracing_subscriber::layer::Layer;
+
+        let config = libhoney::Config {
+            options: Default::default(),
+            transmission_options: Default::default(),
+        };
+        let layer = crate::Builder::new("test", config)
+            .enabled(false)
+            .trace_id_generator(|| TraceId::from("fixed"))
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert_eq!(crate::new_trace_id(), TraceId::from("fixed"));
+        });
+        assert_ne!(crate::new_trace_id(), TraceId::from("fixed"));
+    }
+
+    #[test]
+    #[cfg(feature = "honeycomb")]
+    fn current_trace_id_uses_reported_format() {
+        use tracing_subscriber::layer::Layer;
+
+        let config = libhoney::Config {
+            options: Default::default(),
+            transmission_options: Default::default(),
+        };
+        let layer = crate::Builder::new("test", config)
+            .enabled(false)
+            .trace_id_format(TraceIdFormat::W3c)
+            .build();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            assert_eq!(crate::current_trace_id(), None);
+            assert_eq!(crate::current_span_id(), None);
+
+            let trace_id = TraceId::from("a string");
+            tracing::info_span!("root").in_scope(|| {
+                crate::register_dist_tracing_root(trace_id.clone(), None).unwrap();
+                assert_eq!(crate::current_trace_id(), Some(trace_id.to_w3c()));
+                assert_eq!(
+                    crate::current_span_id(),
+                    crate::current_dist_trace_ctx()
+                        .ok()
+                        .map(|(_, span_id)| span_id)
+                );
+            });
+        });
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn ids_serialize_as_strings() {
+        #[derive(serde::Deserialize, serde::Serialize)]
+        struct Job {
+            trace_id: TraceId,
+            parent_span: crate::SpanId,
+        }
+
+        let json = r#"{"trace_id":"abc","parent_span":"2a-ff"}"#;
+        let job: Job = serde_json::from_str(json).unwrap();
+        assert_eq!(job.trace_id, TraceId::from("abc"));
+        assert_eq!(job.parent_span, crate::SpanId::from_str("2a-ff").unwrap());
+        assert_eq!(serde_json::to_string(&job).unwrap(), json);
+
+        let invalid = r#"{"trace_id":"abc","parent_span":"0"}"#;
+        assert!(serde_json::from_str::<Job>(invalid).is_err());
+    }
 }
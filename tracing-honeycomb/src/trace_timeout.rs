@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+use crate::TraceId;
+
+// bounds the number of in-flight traces tracked for timeouts
+const MAX_TRACKED_TRACES: usize = 10_000;
+
+// expired traces are looked for at most this often
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Tracks in-flight traces, i.e. traces for which spans or events were reported but whose
+/// local root span has not closed yet, so that per-trace state can be finalized for traces
+/// that exceed the configured maximum duration.
+#[derive(Debug)]
+pub(crate) struct TraceTimeouts {
+    max_duration: Duration,
+    state: Mutex<TimeoutState>,
+}
+
+#[derive(Debug)]
+struct TimeoutState {
+    // time at which activity was first observed for each in-flight trace
+    started: HashMap<TraceId, (Instant, SystemTime)>,
+    last_sweep: Instant,
+}
+
+impl TraceTimeouts {
+    pub(crate) fn new(max_duration: Duration) -> Self {
+        TraceTimeouts {
+            max_duration,
+            state: Mutex::new(TimeoutState {
+                started: HashMap::new(),
+                last_sweep: Instant::now(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> impl std::ops::DerefMut<Target = TimeoutState> + '_ {
+        // succeed or die. failure is unrecoverable (mutex poisoned)
+        #[cfg(not(feature = "use_parking_lot"))]
+        let state = self.state.lock().unwrap();
+        #[cfg(feature = "use_parking_lot")]
+        let state = self.state.lock();
+
+        state
+    }
+
+    /// Record activity for the trace, starting its clock if it is not tracked yet.
+    pub(crate) fn observe(&self, trace_id: &TraceId) {
+        self.observe_at(trace_id, Instant::now(), SystemTime::now())
+    }
+
+    fn observe_at(&self, trace_id: &TraceId, now: Instant, now_utc: SystemTime) {
+        let mut state = self.lock();
+        if !state.started.contains_key(trace_id) && state.started.len() < MAX_TRACKED_TRACES {
+            state.started.insert(trace_id.clone(), (now, now_utc));
+        }
+    }
+
+    /// Stop tracking the trace, called once its local root span has closed.
+    pub(crate) fn finish(&self, trace_id: &TraceId) {
+        self.lock().started.remove(trace_id);
+    }
+
+    /// Stop tracking and return traces that have been in flight for longer than the maximum
+    /// trace duration, along with the time at which they started.
+    pub(crate) fn take_expired(&self) -> Vec<(TraceId, SystemTime)> {
+        self.take_expired_at(Instant::now())
+    }
+
+    fn take_expired_at(&self, now: Instant) -> Vec<(TraceId, SystemTime)> {
+        let mut state = self.lock();
+        if now.saturating_duration_since(state.last_sweep) < SWEEP_INTERVAL {
+            return Vec::new();
+        }
+        state.last_sweep = now;
+
+        let max_duration = self.max_duration;
+        let expired: Vec<TraceId> = state
+            .started
+            .iter()
+            .filter(|(_, (started, _))| now.saturating_duration_since(*started) > max_duration)
+            .map(|(trace_id, _)| trace_id.clone())
+            .collect();
+
+        expired
+            .into_iter()
+            .filter_map(|trace_id| {
+                let (_, started_at) = state.started.remove(&trace_id)?;
+                Some((trace_id, started_at))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn expires_traces_whose_root_never_closes() {
+        let timeouts = TraceTimeouts::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let stuck = TraceId::from("stuck");
+        let finished = TraceId::from("finished");
+
+        timeouts.observe_at(&stuck, start, SystemTime::now());
+        timeouts.observe_at(&finished, start, SystemTime::now());
+        timeouts.finish(&finished);
+
+        assert!(timeouts
+            .take_expired_at(start + Duration::from_secs(30))
+            .is_empty());
+
+        let expired = timeouts.take_expired_at(start + Duration::from_secs(61));
+        assert_eq!(expired.len(), 1);
+        assert_eq!(expired[0].0, stuck);
+
+        // no longer tracked
+        assert!(timeouts
+            .take_expired_at(start + Duration::from_secs(120))
+            .is_empty());
+    }
+}
@@ -0,0 +1,893 @@
+use crossbeam_queue::ArrayQueue;
+use libhoney::transmission::{self, Transmission};
+use libhoney::{json, FieldHolder};
+use std::collections::hash_map::{DefaultHasher, Entry, HashMap};
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
+use std::thread::Thread;
+use std::time::{Duration, Instant};
+
+use crate::budget::MemoryBudget;
+use crate::circuit_breaker::CircuitBreaker;
+use crate::dead_letter::DeadLetterSpool;
+use crate::diagnostics::{self, Diagnostic, InternalLogMode, SharedDiagnostics};
+use crate::pool::BufferPool;
+use crate::stats::{Stats, TelemetryStats};
+use crate::telemetry_error::{ErrorHandler, TelemetryError};
+use crate::TraceId;
+
+#[cfg(feature = "use_parking_lot")]
+use parking_lot::Mutex;
+#[cfg(not(feature = "use_parking_lot"))]
+use std::sync::Mutex;
+
+/// Handle to a libhoney transmission that can be shared by multiple `HoneycombTelemetry`
+/// instances, e.g. several telemetry layers publishing different subsystems to different
+/// datasets.
+///
+/// All layers using the same `SharedTransmission` share a single pool of background
+/// threads, HTTP connections and a single queue of pending events, while each layer keeps
+/// its own API key and dataset. Handles are cheap to clone. The transmission is stopped once
+/// the last handle referencing it is dropped.
+///
+/// Events are pushed to a bounded lock-free queue, drained into libhoney's transmission by a
+/// background thread, so threads reporting spans concurrently never contend on a lock. Under
+/// high concurrency, a single libhoney client can still become the bottleneck: `sharded`
+/// starts several independent queues and clients instead, see `Builder::transmission_shards`.
+///
+/// ```ignore
+/// let transmission = SharedTransmission::new(libhoney::transmission::Options::default());
+///
+/// // each subsystem gets its own subscriber, publishing to its own dataset
+/// let api_layer = Builder::new("api", api_config)
+///     .shared_transmission(transmission.clone())
+///     .build();
+/// let jobs_layer = Builder::new("jobs", jobs_config)
+///     .shared_transmission(transmission)
+///     .build();
+/// ```
+///
+/// A `SharedTransmission` can also be used by a single layer to observe its queue, e.g. via
+/// `on_queue_depth`.
+#[derive(Clone, Debug)]
+pub struct SharedTransmission {
+    // one queue per shard, each drained into its own libhoney client
+    shards: Arc<[Arc<EventQueue>]>,
+    // stops the flusher threads once the last handle is dropped
+    _flushers: Arc<FlusherGuard>,
+    queue: Arc<QueueState>,
+    stats: Arc<Stats>,
+    layers: Arc<Mutex<Vec<Arc<RegisteredLayer>>>>,
+    // field maps of sent events, reclaimed by the flusher threads
+    buffers: Arc<BufferPool>,
+}
+
+/// Depth of the queue of events waiting to be sent (or being sent) to honeycomb.io, passed to
+/// queue depth hooks when it crosses their threshold.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct QueueDepth {
+    /// number of events queued or in flight
+    pub depth: usize,
+    /// capacity of the queue, i.e. the `pending_work_capacity` of the transmission options
+    /// times the number of shards
+    pub capacity: usize,
+    /// threshold, as a fraction of `capacity`, that was crossed
+    pub threshold: f64,
+    /// `true` if the depth rose above the threshold, `false` if it fell back below it
+    pub rising: bool,
+}
+
+type QueueDepthCallback = dyn Fn(QueueDepth) + Send + Sync;
+
+struct QueueDepthHook {
+    threshold: f64,
+    above: AtomicBool,
+    callback: Arc<QueueDepthCallback>,
+}
+
+/// Append-only list of queue depth hooks, read by the threads reporting events without taking
+/// a lock. Hooks are registered rarely, and never removed.
+#[derive(Debug, Default)]
+struct QueueDepthHooks(OnceLock<Box<QueueDepthHookNode>>);
+
+#[derive(Debug)]
+struct QueueDepthHookNode {
+    hook: QueueDepthHook,
+    next: QueueDepthHooks,
+}
+
+impl QueueDepthHooks {
+    fn push(&self, hook: QueueDepthHook) {
+        let mut node = Box::new(QueueDepthHookNode {
+            hook,
+            next: QueueDepthHooks::default(),
+        });
+        let mut last = self;
+        loop {
+            while let Some(next) = last.0.get() {
+                last = &next.next;
+            }
+            // another hook may have been appended concurrently, in which case try again after it
+            match last.0.set(node) {
+                Ok(()) => return,
+                Err(rejected) => node = rejected,
+            }
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = &QueueDepthHook> {
+        std::iter::successors(self.0.get(), |node| node.next.0.get()).map(|node| &node.hook)
+    }
+}
+
+#[derive(Debug)]
+struct QueueState {
+    capacity: usize,
+    depth: AtomicUsize,
+    hooks: QueueDepthHooks,
+    under_pressure: AtomicBool,
+    diagnostics: SharedDiagnostics,
+    // bytes of the events waiting to be handed to libhoney, across shards
+    budget: MemoryBudget,
+}
+
+/// Events waiting to be handed to libhoney's transmission by the flusher thread, along with
+/// their size as charged to the memory budget.
+struct EventQueue {
+    events: ArrayQueue<(libhoney::Event, usize)>,
+    // set by `SharedTransmission::flush`, cleared once the flusher flushed libhoney's batches
+    flush_requested: AtomicBool,
+    stopped: AtomicBool,
+    flusher: OnceLock<Thread>,
+}
+
+impl fmt::Debug for EventQueue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EventQueue")
+            .field("len", &self.events.len())
+            .field("capacity", &self.events.capacity())
+            .finish()
+    }
+}
+
+impl EventQueue {
+    fn wake_flusher(&self) {
+        if let Some(flusher) = self.flusher.get() {
+            flusher.unpark();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct FlusherGuard(Arc<[Arc<EventQueue>]>);
+
+impl Drop for FlusherGuard {
+    fn drop(&mut self) {
+        for shard in self.0.iter() {
+            shard.stopped.store(true, Ordering::Release);
+            shard.wake_flusher();
+        }
+    }
+}
+
+impl fmt::Debug for QueueDepthHook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("QueueDepthHook")
+            .field("threshold", &self.threshold)
+            .field("above", &self.above)
+            .finish()
+    }
+}
+
+impl QueueState {
+    fn enqueued(&self) {
+        let depth = self.depth.fetch_add(1, Ordering::Relaxed) + 1;
+        self.run_hooks(depth);
+    }
+
+    fn dequeued(&self) {
+        let depth = self.depth.fetch_sub(1, Ordering::Relaxed).saturating_sub(1);
+        self.run_hooks(depth);
+    }
+
+    fn run_hooks(&self, depth: usize) {
+        let under_pressure = self.under_pressure.load(Ordering::Relaxed);
+        if let Some(rising) = diagnostics::queue_pressure(depth, self.capacity, under_pressure) {
+            self.under_pressure.store(rising, Ordering::Relaxed);
+            self.diagnostics.emit(&Diagnostic::QueuePressure {
+                depth,
+                capacity: self.capacity,
+                rising,
+            });
+        }
+
+        let fraction = depth as f64 / self.capacity.max(1) as f64;
+        for hook in self.hooks.iter() {
+            let above = fraction >= hook.threshold;
+            // only call the hook when the threshold is crossed
+            if hook.above.swap(above, Ordering::Relaxed) != above {
+                (hook.callback)(QueueDepth {
+                    depth,
+                    capacity: self.capacity,
+                    threshold: hook.threshold,
+                    rising: above,
+                });
+            }
+        }
+    }
+}
+
+impl SharedTransmission {
+    /// Start a new transmission using the provided options.
+    pub fn new(options: transmission::Options) -> Self {
+        Self::sharded(options, 1)
+    }
+
+    /// Start a new transmission made of `shards` independent libhoney clients, each with its
+    /// own queue, background threads and HTTP connections, using the provided options.
+    ///
+    /// Events are routed to a shard by a hash of their trace id, so a trace's events are
+    /// batched together, and events not belonging to a trace are sent by the first shard.
+    /// Flushing the transmission flushes all shards, and its queue depth and counters cover
+    /// all shards. Each shard queues up to `pending_work_capacity` events.
+    pub fn sharded(options: transmission::Options, shards: usize) -> Self {
+        let shards = shards.max(1);
+        let capacity = options.pending_work_capacity;
+
+        let queue = Arc::new(QueueState {
+            capacity: capacity * shards,
+            depth: AtomicUsize::new(0),
+            hooks: QueueDepthHooks::default(),
+            under_pressure: AtomicBool::new(false),
+            diagnostics: SharedDiagnostics::default(),
+            budget: MemoryBudget::default(),
+        });
+
+        let shards: Arc<[Arc<EventQueue>]> = (0..shards)
+            .map(|_| {
+                Arc::new(EventQueue {
+                    events: ArrayQueue::new(capacity.max(1)),
+                    flush_requested: AtomicBool::new(false),
+                    stopped: AtomicBool::new(false),
+                    flusher: OnceLock::new(),
+                })
+            })
+            .collect();
+
+        let transmission = SharedTransmission {
+            _flushers: Arc::new(FlusherGuard(shards.clone())),
+            shards,
+            queue,
+            stats: Arc::new(Stats::default()),
+            layers: Arc::new(Mutex::new(Vec::new())),
+            buffers: Arc::new(BufferPool::default()),
+        };
+        for shard in transmission.shards.iter() {
+            transmission.start_shard(shard, options.clone());
+        }
+
+        transmission
+    }
+
+    // starts the libhoney client draining `events`, along with its flusher and response threads
+    fn start_shard(&self, events: &Arc<EventQueue>, options: transmission::Options) {
+        // events carry their own client options, so the client's options are never used
+        let client = libhoney::init(libhoney::Config {
+            options: libhoney::client::Options::default(),
+            transmission_options: options,
+        });
+
+        // libhoney reports one response per event, successfully sent or not. responses must
+        // be drained or the transmission stalls once the response channel is full, and are
+        // the only way to learn about events rejected by honeycomb.io. the thread exits once
+        // the transmission is dropped.
+        let responses = client.responses();
+
+        // publishing requires &mut, so the client is owned by the thread draining the queue
+        let flusher = {
+            let events = events.clone();
+            let drained = self.drained(&events);
+            let buffers = self.buffers.clone();
+            std::thread::Builder::new()
+                .name("honeycomb-flusher".to_string())
+                .spawn(move || flush_events(client, &events, &drained, &buffers))
+                .expect("failed to spawn honeycomb flusher thread")
+        };
+        let _ = events.flusher.set(flusher.thread().clone());
+
+        // only pushes spooled events to the queue, so it does not keep the transmission alive
+        let drained = self.drained(events);
+        std::thread::Builder::new()
+            .name("honeycomb-responses".to_string())
+            .spawn(move || {
+                // libhoney does not export its response type, so pass its fields instead
+                for response in responses.iter() {
+                    drained.handle(
+                        response.status_code,
+                        response.duration,
+                        response.error.or(response.body),
+                        response.metadata,
+                    );
+                }
+            })
+            .expect("failed to spawn honeycomb response thread");
+    }
+
+    // spooled events are replayed through the shard whose responses are drained
+    fn drained(&self, events: &Arc<EventQueue>) -> DrainedResponses {
+        DrainedResponses {
+            events: events.clone(),
+            queue: self.queue.clone(),
+            stats: self.stats.clone(),
+            layers: self.layers.clone(),
+            failing: AtomicBool::new(false),
+        }
+    }
+
+    /// Register a layer publishing through this transmission, returning the index to store in
+    /// the `layer` metadata of its events, along with their dataset and, when spooling, their
+    /// spool record. Failures to send the layer's events are reported to `on_error`, and
+    /// spilled to `spool` when honeycomb.io is unreachable. The outcome of sending them is fed
+    /// to `circuit_breaker`. Diagnostics concerning the transmission as a whole are reported
+    /// using the `internal_log_mode` of each layer.
+    pub(crate) fn register_layer(
+        &self,
+        on_error: ErrorHandler,
+        spool: Option<Arc<DeadLetterSpool>>,
+        circuit_breaker: Option<Arc<CircuitBreaker>>,
+        internal_log_mode: InternalLogMode,
+    ) -> usize {
+        self.queue.diagnostics.add(internal_log_mode);
+        let mut layers = lock(&self.layers);
+        layers.push(Arc::new(RegisteredLayer {
+            on_error,
+            spool,
+            circuit_breaker,
+            failures: Mutex::new(HashMap::new()),
+        }));
+        layers.len() - 1
+    }
+
+    /// Call `callback` each time the number of events queued or in flight crosses
+    /// `threshold`, a fraction of the queue's capacity (e.g. `0.5` or `0.9`), in either
+    /// direction, e.g. to feed exporter backpressure into load shedding or autoscaling.
+    ///
+    /// Callbacks run on the threads reporting spans and events, and on the transmission's
+    /// response thread, so they should be cheap and must not block.
+    pub fn on_queue_depth<F>(&self, threshold: f64, callback: F)
+    where
+        F: Fn(QueueDepth) + Send + Sync + 'static,
+    {
+        self.queue.hooks.push(QueueDepthHook {
+            threshold,
+            above: AtomicBool::new(false),
+            callback: Arc::new(callback),
+        });
+    }
+
+    /// Number of events queued or in flight.
+    pub fn queue_depth(&self) -> usize {
+        self.queue.depth.load(Ordering::Relaxed)
+    }
+
+    /// Get the counters of this transmission, covering all layers using it.
+    pub fn stats(&self) -> TelemetryStats {
+        let mut stats = self.stats.snapshot(self.queue_depth());
+        stats.buffered_bytes = self.queue.budget.used();
+        stats
+    }
+
+    pub(crate) fn record_rate_limited(&self, events: u64) {
+        self.stats.record_rate_limited(events);
+    }
+
+    pub(crate) fn record_circuit_open(&self, events: u64) {
+        self.stats.record_circuit_open(events);
+    }
+
+    /// Send the events queued so far right away, instead of once their batch is full or times
+    /// out, and wait at most `timeout` for all queued events to be sent.
+    pub(crate) fn flush(&self, timeout: Duration) {
+        for shard in self.shards.iter() {
+            shard.flush_requested.store(true, Ordering::Release);
+            shard.wake_flusher();
+        }
+        let deadline = Instant::now() + timeout;
+        while self.queue_depth() > 0 && Instant::now() < deadline {
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    /// Number of independent libhoney clients sending events, see `sharded`.
+    pub fn shards(&self) -> usize {
+        self.shards.len()
+    }
+
+    /// Send an event of the trace `trace_id`, if any, using this transmission. Sampling is
+    /// assumed to have already happened.
+    ///
+    /// If queueing the event would exceed the memory budget, the oldest queued events are shed
+    /// to make room for it, or the event itself if it is larger than the budget.
+    pub(crate) fn send(
+        &self,
+        mut event: libhoney::Event,
+        trace_id: Option<&TraceId>,
+    ) -> libhoney::Result<()> {
+        let shard = self.shard(trace_id);
+        let bytes = self.queue.budget.size_of(event.get_fields_mut());
+        if self.queue.budget.exceeded_by(bytes) && !self.shed_oldest(shard, bytes) {
+            self.stats.record_shed(1);
+            self.buffers.recycle(std::mem::take(event.get_fields_mut()));
+            return Ok(());
+        }
+        send(&self.shards[shard], &self.queue, &self.stats, event, bytes)
+    }
+
+    /// Set the number of bytes of events waiting to be handed to libhoney, across shards, see
+    /// `Builder::memory_budget`.
+    pub(crate) fn set_memory_budget(&self, bytes: usize) {
+        self.queue.budget.set_limit(bytes);
+    }
+
+    // sheds queued events, oldest first and starting with the given shard, until `bytes` more
+    // fit in the memory budget. returns false if they don't fit once all queues are empty
+    fn shed_oldest(&self, shard: usize, bytes: usize) -> bool {
+        let shards = self.shards.len();
+        for events in (0..shards).map(|i| &self.shards[(shard + i) % shards]) {
+            while self.queue.budget.exceeded_by(bytes) {
+                let (mut event, shed) = match events.events.pop() {
+                    Some(queued) => queued,
+                    None => break,
+                };
+                self.queue.budget.release(shed);
+                self.queue.dequeued();
+                self.stats.record_shed(1);
+                self.buffers.recycle(std::mem::take(event.get_fields_mut()));
+            }
+        }
+        !self.queue.budget.exceeded_by(bytes)
+    }
+
+    /// Field maps reused by the layers publishing through this transmission, recycled once
+    /// their events are handed to libhoney.
+    pub(crate) fn buffers(&self) -> &Arc<BufferPool> {
+        &self.buffers
+    }
+
+    fn shard(&self, trace_id: Option<&TraceId>) -> usize {
+        match trace_id {
+            Some(trace_id) if self.shards.len() > 1 => {
+                let mut hasher = DefaultHasher::new();
+                trace_id.hash(&mut hasher);
+                (hasher.finish() % self.shards.len() as u64) as usize
+            }
+            _ => 0,
+        }
+    }
+}
+
+fn lock<T>(mutex: &Mutex<T>) -> impl std::ops::DerefMut<Target = T> + '_ {
+    // succeed or die. failure is unrecoverable (mutex poisoned)
+    #[cfg(not(feature = "use_parking_lot"))]
+    let guard = mutex.lock().unwrap();
+    #[cfg(feature = "use_parking_lot")]
+    let guard = mutex.lock();
+
+    guard
+}
+
+fn send(
+    events: &EventQueue,
+    queue: &QueueState,
+    stats: &Stats,
+    event: libhoney::Event,
+    bytes: usize,
+) -> libhoney::Result<()> {
+    // counted before sending, as the response may be drained before send returns
+    queue.enqueued();
+    queue.budget.charge(bytes);
+    if events.events.push((event, bytes)).is_err() {
+        queue.budget.release(bytes);
+        queue.dequeued();
+        stats.record_dropped(1);
+        return Err(libhoney::Error {
+            message: "sender 'honeycomb queue' is full".to_string(),
+            kind: libhoney::ErrorKind::ChannelError,
+        });
+    }
+    events.wake_flusher();
+    Ok(())
+}
+
+// hands queued events to libhoney's transmission until the last `SharedTransmission` handle is
+// dropped, then drops the client, stopping the transmission
+fn flush_events(
+    mut client: libhoney::Client<Transmission>,
+    events: &EventQueue,
+    drained: &DrainedResponses,
+    buffers: &BufferPool,
+) {
+    loop {
+        // read before draining, so events queued before the transmission stopped are sent
+        let stopped = events.stopped.load(Ordering::Acquire);
+        while let Some((mut event, bytes)) = events.events.pop() {
+            // libhoney's own queue is bounded by `pending_work_capacity` instead
+            drained.queue.budget.release(bytes);
+            if let Err(err) = event.send_presampled(&mut client) {
+                drained.rejected(&event, err);
+            }
+            // libhoney sends a copy of the event, so its fields can be reused
+            buffers.recycle(std::mem::take(event.get_fields_mut()));
+        }
+        if events.flush_requested.swap(false, Ordering::AcqRel) {
+            // only fails if libhoney's work queue is full, in which case its batches are sent
+            // as soon as possible anyway
+            let _ = client.flush();
+        }
+        if stopped {
+            return;
+        }
+        std::thread::park();
+    }
+}
+
+// error of the responses libhoney reports for events it drops because its queue is full
+const QUEUE_OVERFLOW: &str = "queue overflow";
+
+// minimum time between reports of failures with the same dataset and status. libhoney reports
+// one response per event, so an invalid API key would otherwise flood the error callback
+const FAILURE_REPORT_INTERVAL: Duration = Duration::from_secs(10);
+
+// dataset and status of failed responses
+type FailureKey = (String, Option<u16>);
+
+/// A layer publishing through a `SharedTransmission`, see `SharedTransmission::register_layer`.
+#[derive(Debug)]
+struct RegisteredLayer {
+    on_error: ErrorHandler,
+    spool: Option<Arc<DeadLetterSpool>>,
+    circuit_breaker: Option<Arc<CircuitBreaker>>,
+    // per dataset and status, the time failures were last reported and the number of
+    // failures not reported since
+    failures: Mutex<HashMap<FailureKey, (Instant, usize)>>,
+}
+
+impl RegisteredLayer {
+    fn report_failure(&self, dataset: String, status: Option<u16>, message: String, now: Instant) {
+        let events = {
+            let mut failures = lock(&self.failures);
+            match failures.entry((dataset.clone(), status)) {
+                Entry::Vacant(entry) => {
+                    entry.insert((now, 0));
+                    1
+                }
+                Entry::Occupied(mut entry) => {
+                    let (reported_at, unreported) = entry.get_mut();
+                    *unreported += 1;
+                    if now.saturating_duration_since(*reported_at) < FAILURE_REPORT_INTERVAL {
+                        return;
+                    }
+                    *reported_at = now;
+                    std::mem::take(unreported)
+                }
+            }
+        };
+        self.on_error.report(TelemetryError::Send {
+            dataset,
+            events,
+            status,
+            message,
+        });
+    }
+}
+
+/// State used by the response thread to account for, and act on, libhoney's responses.
+struct DrainedResponses {
+    events: Arc<EventQueue>,
+    queue: Arc<QueueState>,
+    stats: Arc<Stats>,
+    layers: Arc<Mutex<Vec<Arc<RegisteredLayer>>>>,
+    // set while honeycomb.io is unreachable or overloaded
+    failing: AtomicBool,
+}
+
+impl DrainedResponses {
+    fn handle(
+        &self,
+        status_code: Option<reqwest::StatusCode>,
+        duration: Duration,
+        error: Option<String>,
+        metadata: libhoney::Metadata,
+    ) {
+        self.queue.dequeued();
+        if error.as_deref() == Some(QUEUE_OVERFLOW) {
+            // dropped without being sent
+            self.stats.record_dropped(1);
+            return;
+        }
+        self.stats.record_latency(duration);
+        let metadata = metadata.unwrap_or_default();
+        let layer = metadata["layer"]
+            .as_u64()
+            .and_then(|index| lock(&self.layers).get(index as usize).cloned());
+        let circuit_breaker = layer
+            .as_ref()
+            .and_then(|layer| layer.circuit_breaker.as_ref());
+        let accepted = error.is_none() && status_code.is_some_and(|status| status.is_success());
+        if accepted {
+            self.stats.record_sent(1);
+            if let Some(circuit_breaker) = circuit_breaker {
+                circuit_breaker.record_success();
+            }
+            if self.failing.swap(false, Ordering::Relaxed) {
+                self.queue.diagnostics.emit(&Diagnostic::Recovered);
+            }
+            self.replay_spooled();
+            return;
+        }
+
+        self.stats.record_send_errors(1);
+        // honeycomb.io was unreachable or overloaded, as opposed to rejecting the event
+        let retryable = status_code.is_none_or(|status| {
+            status.is_server_error() || status == reqwest::StatusCode::TOO_MANY_REQUESTS
+        });
+        if retryable {
+            self.failing.store(true, Ordering::Relaxed);
+        }
+        let unavailable = status_code.is_none_or(|status| status.is_server_error());
+        if let (true, Some(circuit_breaker)) = (unavailable, circuit_breaker) {
+            circuit_breaker.record_failure();
+        }
+        let layer = match layer {
+            Some(layer) => layer,
+            None => return,
+        };
+        if let (true, Some(spool)) = (retryable, &layer.spool) {
+            spool.spill(&[metadata["record"].clone()]);
+        }
+        let message = error
+            .or_else(|| status_code.map(|status| status.to_string()))
+            .unwrap_or_default();
+        layer.report_failure(
+            metadata["dataset"].as_str().unwrap_or_default().to_string(),
+            status_code.map(|status| status.as_u16()),
+            message.trim().to_string(),
+            Instant::now(),
+        );
+    }
+
+    // accounts for an event libhoney refused to send, e.g. because it has no fields
+    fn rejected(&self, event: &libhoney::Event, err: libhoney::Error) {
+        self.queue.dequeued();
+        self.stats.record_dropped(1);
+        let layer = event.metadata().and_then(|metadata| {
+            let index = metadata["layer"].as_u64()?;
+            lock(&self.layers).get(index as usize).cloned()
+        });
+        if let Some(layer) = layer {
+            layer.on_error.report(TelemetryError::Enqueue(err));
+        }
+    }
+
+    fn replay_spooled(&self) {
+        let layers: Vec<_> = lock(&self.layers).iter().cloned().enumerate().collect();
+        for (index, spool) in layers
+            .iter()
+            .filter_map(|(index, layer)| Some((*index, layer.spool.as_ref()?)))
+        {
+            let spooled = spool.take();
+            if spooled.is_empty() {
+                continue;
+            }
+            self.queue.diagnostics.emit(&Diagnostic::Replaying {
+                events: spooled.len(),
+            });
+            for event in spooled {
+                let record = DeadLetterSpool::record(&event.options, event.time, &event.data);
+                // replayed events are older than queued ones, so they are spilled again rather
+                // than shedding queued events
+                let bytes = self.queue.budget.size_of(&event.data);
+                if self.queue.budget.exceeded_by(bytes) {
+                    spool.spill(&[record]);
+                    continue;
+                }
+                let mut ev = libhoney::Event::new(&event.options);
+                ev.set_timestamp(event.time);
+                ev.add(event.data);
+                ev.set_metadata(Some(json!({
+                    "layer": index,
+                    "dataset": event.options.dataset,
+                    "record": record,
+                })));
+                if send(&self.events, &self.queue, &self.stats, ev, bytes).is_err() {
+                    spool.spill(&[record]);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn queue_depth_hooks_fire_when_crossing_thresholds() {
+        let queue = QueueState {
+            capacity: 4,
+            depth: AtomicUsize::new(0),
+            hooks: QueueDepthHooks::default(),
+            under_pressure: AtomicBool::new(false),
+            diagnostics: SharedDiagnostics::default(),
+            budget: MemoryBudget::default(),
+        };
+        let crossings = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = crossings.clone();
+        queue.hooks.push(QueueDepthHook {
+            threshold: 0.5,
+            above: AtomicBool::new(false),
+            callback: Arc::new(move |depth: QueueDepth| {
+                recorded.lock().unwrap().push((depth.depth, depth.rising))
+            }),
+        });
+
+        for _ in 0..3 {
+            queue.enqueued();
+        }
+        for _ in 0..3 {
+            queue.dequeued();
+        }
+
+        assert_eq!(*crossings.lock().unwrap(), vec![(2, true), (1, false)]);
+    }
+
+    #[test]
+    fn queue_depth_hooks_registered_concurrently_all_fire() {
+        let transmission = SharedTransmission::new(transmission::Options {
+            pending_work_capacity: 4,
+            ..Default::default()
+        });
+        let calls = Arc::new(AtomicUsize::new(0));
+        let registering: Vec<_> = (0..8)
+            .map(|_| {
+                let transmission = transmission.clone();
+                let calls = calls.clone();
+                std::thread::spawn(move || {
+                    transmission.on_queue_depth(0.0, move |_| {
+                        calls.fetch_add(1, Ordering::Relaxed);
+                    })
+                })
+            })
+            .collect();
+        for thread in registering {
+            thread.join().unwrap();
+        }
+
+        transmission.queue.enqueued();
+        assert_eq!(calls.load(Ordering::Relaxed), 8);
+    }
+
+    #[test]
+    fn concurrently_sent_events_are_accounted_for() {
+        let transmission = SharedTransmission::new(transmission::Options {
+            pending_work_capacity: 64,
+            ..Default::default()
+        });
+        let errors = Arc::new(AtomicUsize::new(0));
+        let layer = {
+            let errors = errors.clone();
+            transmission.register_layer(
+                ErrorHandler::new(move |_| {
+                    errors.fetch_add(1, Ordering::Relaxed);
+                }),
+                None,
+                None,
+                InternalLogMode::Silent,
+            )
+        };
+
+        // without an api key, libhoney refuses to send events
+        let options = libhoney::client::Options::default();
+        let refused: usize = (0..8)
+            .map(|_| {
+                let transmission = transmission.clone();
+                let options = options.clone();
+                std::thread::spawn(move || {
+                    (0..100)
+                        .filter(|_| {
+                            let mut ev = libhoney::Event::new(&options);
+                            ev.add_field("name", json!("span"));
+                            ev.set_metadata(Some(json!({ "layer": layer })));
+                            transmission.send(ev, None).is_err()
+                        })
+                        .count()
+                })
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .map(|thread| thread.join().unwrap())
+            .sum();
+
+        transmission.flush(Duration::from_secs(5));
+        assert_eq!(transmission.queue_depth(), 0);
+        assert_eq!(transmission.stats().events_dropped, 800);
+        assert_eq!(refused + errors.load(Ordering::Relaxed), 800);
+    }
+
+    #[test]
+    fn traces_are_spread_across_shards() {
+        let transmission = SharedTransmission::sharded(
+            transmission::Options {
+                pending_work_capacity: 16,
+                ..Default::default()
+            },
+            4,
+        );
+        assert_eq!(transmission.shards(), 4);
+        assert_eq!(transmission.queue.capacity, 64);
+
+        let options = libhoney::client::Options::default();
+        let mut used = std::collections::HashSet::new();
+        for _ in 0..100 {
+            let trace_id = TraceId::new();
+            assert_eq!(
+                transmission.shard(Some(&trace_id)),
+                transmission.shard(Some(&trace_id))
+            );
+            used.insert(transmission.shard(Some(&trace_id)));
+            let mut ev = libhoney::Event::new(&options);
+            ev.add_field("name", json!("span"));
+            let _ = transmission.send(ev, Some(&trace_id));
+        }
+        assert_eq!(used.len(), 4);
+        assert_eq!(transmission.shard(None), 0);
+
+        // without an api key, libhoney refuses to send events, whichever shard they're sent to
+        transmission.flush(Duration::from_secs(5));
+        assert_eq!(transmission.queue_depth(), 0);
+        assert_eq!(transmission.stats().events_dropped, 100);
+    }
+
+    #[test]
+    fn repeated_failures_are_summarized() {
+        let errors = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = errors.clone();
+        let layer = RegisteredLayer {
+            on_error: ErrorHandler::new(move |err| recorded.lock().unwrap().push(err)),
+            spool: None,
+            circuit_breaker: None,
+            failures: Mutex::new(HashMap::new()),
+        };
+        let start = Instant::now();
+        let fail = |status, after| {
+            let message = "unknown API key".to_string();
+            layer.report_failure("spans".to_string(), Some(status), message, start + after);
+        };
+
+        fail(401, Duration::from_secs(0));
+        fail(401, Duration::from_secs(1));
+        fail(429, Duration::from_secs(2));
+        fail(401, Duration::from_secs(3));
+        fail(401, Duration::from_secs(11));
+
+        let errors = errors.lock().unwrap();
+        let reported: Vec<_> = errors
+            .iter()
+            .map(|err| match err {
+                TelemetryError::Send { events, status, .. } => (*status, *events),
+                err => panic!("unexpected error {:?}", err),
+            })
+            .collect();
+        assert_eq!(
+            reported,
+            vec![(Some(401), 1), (Some(429), 1), (Some(401), 3)]
+        );
+        assert!(errors[0].is_unauthorized());
+        assert!(errors[1].is_rate_limited());
+        assert!(errors[0]
+            .to_string()
+            .starts_with("honeycomb rejected the api key"));
+    }
+}
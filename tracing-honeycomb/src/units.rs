@@ -0,0 +1,140 @@
+use libhoney::{json, Value};
+use std::collections::HashMap;
+
+/// Unit of a numeric field, published as a suffix of the field name so honeycomb.io columns
+/// are self-describing, e.g. `latency_ms` or `body_bytes`. See `Builder::field_unit`.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum FieldUnit {
+    /// Nanoseconds, suffixed with `_ns`.
+    Nanoseconds,
+    /// Microseconds, suffixed with `_us`.
+    Microseconds,
+    /// Milliseconds, suffixed with `_ms`.
+    Milliseconds,
+    /// Seconds, suffixed with `_s`.
+    Seconds,
+    /// Bytes, suffixed with `_bytes`.
+    Bytes,
+    /// Percentage, from 0 to 100, suffixed with `_pct`.
+    Percent,
+}
+
+const ALL_UNITS: [FieldUnit; 6] = [
+    FieldUnit::Nanoseconds,
+    FieldUnit::Microseconds,
+    FieldUnit::Milliseconds,
+    FieldUnit::Seconds,
+    FieldUnit::Bytes,
+    FieldUnit::Percent,
+];
+
+impl FieldUnit {
+    /// Suffix appended to the names of fields with this unit.
+    pub fn suffix(self) -> &'static str {
+        match self {
+            FieldUnit::Nanoseconds => "_ns",
+            FieldUnit::Microseconds => "_us",
+            FieldUnit::Milliseconds => "_ms",
+            FieldUnit::Seconds => "_s",
+            FieldUnit::Bytes => "_bytes",
+            FieldUnit::Percent => "_pct",
+        }
+    }
+
+    // length of this unit in nanoseconds, for time units
+    fn nanos(self) -> Option<f64> {
+        match self {
+            FieldUnit::Nanoseconds => Some(1.0),
+            FieldUnit::Microseconds => Some(1e3),
+            FieldUnit::Milliseconds => Some(1e6),
+            FieldUnit::Seconds => Some(1e9),
+            FieldUnit::Bytes | FieldUnit::Percent => None,
+        }
+    }
+}
+
+/// Units declared for numeric fields, by field name without suffix.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FieldUnits(HashMap<String, FieldUnit>);
+
+impl FieldUnits {
+    pub(crate) fn set_unit(&mut self, name: String, unit: FieldUnit) {
+        self.0.insert(name, unit);
+    }
+
+    // finds the declared field and its unit for a recorded field name, and the unit the value
+    // was recorded in: `latency` and `latency_ms` are both recorded in milliseconds if
+    // `latency` is declared in milliseconds, while `latency_s` is recorded in seconds
+    fn resolve(&self, name: &str) -> Option<(&str, FieldUnit, FieldUnit)> {
+        if let Some((declared, unit)) = self.0.get_key_value(name) {
+            return Some((declared, *unit, *unit));
+        }
+
+        ALL_UNITS.iter().find_map(|&recorded| {
+            let base = name.strip_suffix(recorded.suffix())?;
+            let (declared, &unit) = self.0.get_key_value(base)?;
+            let convertible = recorded.nanos().is_some() && unit.nanos().is_some();
+            if recorded == unit || convertible {
+                Some((declared.as_str(), unit, recorded))
+            } else {
+                None
+            }
+        })
+    }
+
+    /// Append the unit suffix to the names of numeric fields with a declared unit, converting
+    /// values recorded in another unit of time, e.g. `latency_s = 1.5` is published as
+    /// `latency_ms = 1500.0` if `latency` is declared in milliseconds. Other fields are left
+    /// untouched.
+    pub(crate) fn apply(&self, values: HashMap<String, Value>) -> HashMap<String, Value> {
+        if self.0.is_empty() {
+            return values;
+        }
+        values
+            .into_iter()
+            .map(|(name, value)| {
+                let resolved = match (&value, self.resolve(&name)) {
+                    (Value::Number(_), Some(resolved)) => resolved,
+                    _ => return (name, value),
+                };
+                let (declared, unit, recorded) = resolved;
+                let value = match (recorded.nanos(), unit.nanos(), value.as_f64()) {
+                    (Some(from), Some(to), Some(v)) if recorded != unit => json!(v * from / to),
+                    _ => value,
+                };
+                (format!("{}{}", declared, unit.suffix()), value)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn appends_suffixes_and_converts_time_units() {
+        let mut units = FieldUnits::default();
+        units.set_unit("latency".to_string(), FieldUnit::Milliseconds);
+        units.set_unit("body".to_string(), FieldUnit::Bytes);
+
+        let mut values = HashMap::new();
+        values.insert("latency".to_string(), json!(12));
+        values.insert("body_bytes".to_string(), json!(512));
+        values.insert("other".to_string(), json!(1));
+        let values = units.apply(values);
+        assert_eq!(values["latency_ms"], json!(12));
+        assert_eq!(values["body_bytes"], json!(512));
+        assert_eq!(values["other"], json!(1));
+        assert_eq!(values.len(), 3);
+
+        let mut values = HashMap::new();
+        values.insert("latency_s".to_string(), json!(1.5));
+        values.insert("body".to_string(), json!("not a number"));
+        values.insert("body_ms".to_string(), json!(3));
+        let values = units.apply(values);
+        assert_eq!(values["latency_ms"], json!(1500.0));
+        assert_eq!(values["body"], json!("not a number"));
+        assert_eq!(values["body_ms"], json!(3));
+    }
+}
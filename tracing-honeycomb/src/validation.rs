@@ -0,0 +1,518 @@
+use std::fmt::{self, Display};
+
+use crate::pool;
+use crate::Builder;
+
+/// Effective configuration of a `Builder`, along with detected misconfigurations, returned by
+/// `Builder::validate`.
+///
+/// `Display` renders one `name = value` line per setting followed by one line per issue,
+/// meant to be logged at startup.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConfigReport {
+    /// Effective value of each setting, in a stable order.
+    pub settings: Vec<ConfigSetting>,
+    /// Misconfigurations detected, empty if none were.
+    pub issues: Vec<ConfigIssue>,
+}
+
+impl ConfigReport {
+    /// Whether no misconfigurations were detected.
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+    /// Get the effective value of the setting with the given name, if any.
+    pub fn get(&self, name: &str) -> Option<&ConfigSetting> {
+        self.settings.iter().find(|setting| setting.name == name)
+    }
+}
+
+/// Effective value of a single setting.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ConfigSetting {
+    /// Name of the setting, usually that of the `Builder` method setting it.
+    pub name: &'static str,
+    /// Value of the setting. Secrets such as the API key are never included.
+    pub value: String,
+    /// Whether the value is the default one, i.e. the setting was not configured.
+    pub is_default: bool,
+    /// Environment variable the value was read from by `Builder::from_env`, if any.
+    pub env_var: Option<&'static str>,
+}
+
+/// Misconfiguration detected by `Builder::validate`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ConfigIssue {
+    /// Telemetry is enabled but no API key is configured, so honeycomb.io rejects all of it.
+    MissingApiKey,
+    /// The API host is not an `http` or `https` URL, see `Builder::api_host`.
+    InvalidApiHost,
+    /// Telemetry is enabled but no dataset is configured, so it is published to libhoney's
+    /// default `librust-dataset` dataset.
+    DefaultDataset,
+    /// Both trace-level sampling (`Builder::sample_rate`) and libhoney's per-event sampling
+    /// (the `sample_rate` of the honeycomb config) are enabled. Events are sampled twice,
+    /// breaking traces apart and skewing the sample rates reported to honeycomb.io.
+    DoubleSampling,
+    /// Both `Builder::send_now` and `Builder::shared_transmission` are configured. The shared
+    /// transmission is ignored.
+    SharedTransmissionIgnored,
+    /// The rate limit is zero, so all spans and events are dropped.
+    ZeroRateLimit,
+    /// `Builder::keep_errored_traces` or `Builder::sampled_out_rollup` is configured, but
+    /// trace-level sampling is not enabled, so no traces are sampled out. Harmless if the
+    /// sample rate is raised at runtime.
+    SamplingFeaturesWithoutSampling,
+}
+
+impl Display for ConfigIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MissingApiKey => write!(f, "telemetry is enabled but no api key is set"),
+            Self::InvalidApiHost => write!(f, "api host is not an http(s) url"),
+            Self::DefaultDataset => write!(f, "telemetry is enabled but no dataset is set"),
+            Self::DoubleSampling => write!(
+                f,
+                "both trace sampling and libhoney event sampling are enabled"
+            ),
+            Self::SharedTransmissionIgnored => {
+                write!(f, "send_now ignores the configured shared transmission")
+            }
+            Self::ZeroRateLimit => write!(f, "rate limit of zero drops all telemetry"),
+            Self::SamplingFeaturesWithoutSampling => write!(
+                f,
+                "keep_errored_traces or sampled_out_rollup is set but traces are not sampled"
+            ),
+        }
+    }
+}
+
+/// Error returned by `Builder::try_build` when the configuration cannot be used to publish
+/// telemetry.
+#[derive(Clone, Debug, Eq, PartialEq)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// The API host is not an `http` or `https` URL, see `Builder::api_host`. Holds the
+    /// configured value.
+    InvalidApiHost(String),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InvalidApiHost(api_host) => write!(
+                f,
+                "invalid honeycomb api host {:?}, expected an http(s) url",
+                api_host
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+impl Display for ConfigReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "honeycomb telemetry configuration:")?;
+        for setting in &self.settings {
+            write!(f, "\n  {} = {}", setting.name, setting.value)?;
+            if let Some(env_var) = setting.env_var {
+                write!(f, " (from {})", env_var)?;
+            } else if setting.is_default {
+                write!(f, " (default)")?;
+            }
+        }
+        for issue in &self.issues {
+            write!(f, "\n  warning: {}", issue)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn validate(builder: &Builder) -> ConfigReport {
+    let defaults = libhoney::client::Options::default();
+    let options = &builder.honeycomb_config.options;
+    let trace_sample_rate = builder.sample_rate.get();
+    let limits = builder.field_options.limits;
+
+    let mut settings = Vec::new();
+    let mut add = |name, value: String, is_default| {
+        let env_var = match name {
+            "api_key" => Some(crate::HONEYCOMB_API_KEY),
+            "dataset" => Some(crate::HONEYCOMB_DATASET),
+            "api_host" => Some(crate::HONEYCOMB_API_HOST),
+            "sample_rate" => Some(crate::HONEYCOMB_SAMPLE_RATE),
+            _ => None,
+        }
+        .filter(|var| builder.env_vars.contains(var));
+        settings.push(ConfigSetting {
+            name,
+            value,
+            is_default,
+            env_var,
+        })
+    };
+
+    add("service_name", builder.service_name.to_string(), false);
+    add("enabled", builder.enabled.to_string(), builder.enabled);
+    add(
+        "api_key",
+        match options.api_key.is_empty() {
+            true => "unset".to_string(),
+            false => "set".to_string(),
+        },
+        options.api_key == defaults.api_key,
+    );
+    add(
+        "api_host",
+        options.api_host.clone(),
+        options.api_host == defaults.api_host,
+    );
+    add(
+        "dataset",
+        options.dataset.clone(),
+        options.dataset == defaults.dataset,
+    );
+    #[cfg(feature = "tokio-transmission")]
+    let tokio = builder.tokio_runtime.is_some();
+    #[cfg(not(feature = "tokio-transmission"))]
+    let tokio = false;
+    let transmission = match (builder.send_now, tokio, &builder.transmission) {
+        (Some(deadline), _, _) => format!("send_now({:?})", deadline),
+        (None, true, _) => "tokio".to_string(),
+        (None, false, Some(_)) => "shared".to_string(),
+        (None, false, None) => "queued".to_string(),
+    };
+    add(
+        "transmission",
+        transmission,
+        builder.send_now.is_none() && !tokio && builder.transmission.is_none(),
+    );
+    add(
+        "transmission_shards",
+        builder.transmission_shards.to_string(),
+        builder.transmission_shards == 1,
+    );
+    add(
+        "buffer_pool",
+        builder.buffer_pool.to_string(),
+        builder.buffer_pool == pool::DEFAULT_POOL_SIZE,
+    );
+    add(
+        "memory_budget",
+        optional(
+            builder
+                .memory_budget
+                .map(|bytes| format!("{} bytes", bytes)),
+        ),
+        builder.memory_budget.is_none(),
+    );
+    add(
+        "dead_letter_spool",
+        optional(
+            builder
+                .dead_letter
+                .as_ref()
+                .map(|(dir, max_bytes)| format!("{} ({} bytes)", dir.display(), max_bytes)),
+        ),
+        builder.dead_letter.is_none(),
+    );
+    add(
+        "internal_log_mode",
+        format!("{:?}", builder.internal_log_mode),
+        builder.internal_log_mode == Default::default(),
+    );
+    add(
+        "circuit_breaker",
+        optional(builder.circuit_breaker.map(|(max_failures, cool_down)| {
+            format!("{} failures, {:?} cool-down", max_failures, cool_down)
+        })),
+        builder.circuit_breaker.is_none(),
+    );
+    add(
+        "shard_datasets",
+        builder.dataset_shards.unwrap_or(1).to_string(),
+        builder.dataset_shards.is_none(),
+    );
+    add(
+        "sample_rate",
+        trace_sample_rate.to_string(),
+        trace_sample_rate == 1,
+    );
+    add(
+        "event_sample_rate",
+        options.sample_rate.to_string(),
+        options.sample_rate == defaults.sample_rate,
+    );
+    add(
+        "rate_limit",
+        optional(builder.rate_limit),
+        builder.rate_limit.is_none(),
+    );
+    add(
+        "sampled_out_rollup",
+        optional(
+            builder
+                .rollup_interval
+                .map(|interval| format!("{:?}", interval)),
+        ),
+        builder.rollup_interval.is_none(),
+    );
+    add(
+        "report_data_loss",
+        optional(
+            builder
+                .data_loss_interval
+                .map(|interval| format!("{:?}", interval)),
+        ),
+        builder.data_loss_interval.is_none(),
+    );
+    add(
+        "report_metrics",
+        optional(
+            builder
+                .metrics_report
+                .as_ref()
+                .map(|(interval, dataset)| format!("{:?} to {}", interval, dataset)),
+        ),
+        builder.metrics_report.is_none(),
+    );
+    add(
+        "keep_errored_traces",
+        builder.keep_errored_traces.to_string(),
+        !builder.keep_errored_traces,
+    );
+    add(
+        "span_id_format",
+        format!("{:?}", builder.span_id_format),
+        builder.span_id_format == Default::default(),
+    );
+    add(
+        "trace_id_format",
+        format!("{:?}", builder.field_options.trace_id_format),
+        builder.field_options.trace_id_format == Default::default(),
+    );
+    let trace_fields = &builder.field_options.trace_fields;
+    add(
+        "trace_field_names",
+        format!(
+            "[{}, {}, {}]",
+            trace_fields.trace_id, trace_fields.span_id, trace_fields.parent_id
+        ),
+        *trace_fields == Default::default(),
+    );
+    add(
+        "field_naming",
+        format!("{:?}", builder.field_options.naming),
+        builder.field_options.naming == Default::default(),
+    );
+    add(
+        "redundant_root_policy",
+        format!("{:?}", builder.redundant_root_policy),
+        builder.redundant_root_policy == Default::default(),
+    );
+    add(
+        "clock",
+        match &builder.clock {
+            Some(clock) => format!("{:?}", clock),
+            None => "SystemClock".to_string(),
+        },
+        builder.clock.is_none(),
+    );
+    add(
+        "min_export_level",
+        optional(builder.export_filter.min_level),
+        builder.export_filter.min_level.is_none(),
+    );
+    add(
+        "exclude_target",
+        format!("{:?}", builder.export_filter.excluded_targets),
+        builder.export_filter.excluded_targets.is_empty(),
+    );
+    add(
+        "include_target_prefix",
+        format!("{:?}", builder.export_filter.included_prefixes),
+        builder.export_filter.included_prefixes.is_empty(),
+    );
+    add(
+        "clamp_to_parent",
+        builder.clamp_to_parent.to_string(),
+        !builder.clamp_to_parent,
+    );
+    add(
+        "span_transition_events",
+        builder.span_transition_events.to_string(),
+        !builder.span_transition_events,
+    );
+    add(
+        "span_start_events",
+        builder.span_start_events.to_string(),
+        !builder.span_start_events,
+    );
+    add(
+        "orphan_events",
+        builder.orphan_events.to_string(),
+        !builder.orphan_events,
+    );
+    add(
+        "max_trace_duration",
+        optional(builder.max_trace_duration.map(|d| format!("{:?}", d))),
+        builder.max_trace_duration.is_none(),
+    );
+    add(
+        "max_fields",
+        optional(limits.max_fields),
+        limits.max_fields.is_none(),
+    );
+    add(
+        "max_string_len",
+        optional(limits.max_string_len),
+        limits.max_string_len.is_none(),
+    );
+    let prefixing = &builder.field_options.prefixing;
+    add(
+        "reserved_field_prefix",
+        format!("{:?}", prefixing.prefix()),
+        prefixing.prefix() == "tracing.",
+    );
+    add(
+        "unprefixed_fields",
+        sorted(prefixing.exempted().iter()),
+        prefixing.exempted().is_empty(),
+    );
+    add(
+        "static_fields",
+        sorted(builder.static_fields.keys()),
+        builder.static_fields.is_empty(),
+    );
+    add(
+        "inherited_fields",
+        sorted(builder.inherited_fields.iter()),
+        builder.inherited_fields.is_empty(),
+    );
+
+    let mut issues = Vec::new();
+    if builder.enabled && options.api_key.is_empty() {
+        issues.push(ConfigIssue::MissingApiKey);
+    }
+    if normalize_api_host(&options.api_host).is_none() {
+        issues.push(ConfigIssue::InvalidApiHost);
+    }
+    if builder.enabled && options.dataset == defaults.dataset {
+        issues.push(ConfigIssue::DefaultDataset);
+    }
+    if trace_sample_rate > 1 && options.sample_rate > 1 {
+        issues.push(ConfigIssue::DoubleSampling);
+    }
+    if builder.send_now.is_some() && builder.transmission.is_some() {
+        issues.push(ConfigIssue::SharedTransmissionIgnored);
+    }
+    if builder.rate_limit == Some(0) {
+        issues.push(ConfigIssue::ZeroRateLimit);
+    }
+    if trace_sample_rate <= 1 && (builder.keep_errored_traces || builder.rollup_interval.is_some())
+    {
+        issues.push(ConfigIssue::SamplingFeaturesWithoutSampling);
+    }
+
+    ConfigReport { settings, issues }
+}
+
+/// The API host without trailing slashes, as libhoney appends paths to it verbatim, or `None`
+/// if it is not an `http` or `https` URL with a host. Paths are allowed, e.g. for proxies.
+pub(crate) fn normalize_api_host(api_host: &str) -> Option<String> {
+    let url = reqwest::Url::parse(api_host).ok()?;
+    let is_http = url.scheme() == "http" || url.scheme() == "https";
+    if !is_http || url.host_str().is_none() || url.query().is_some() {
+        return None;
+    }
+    Some(api_host.trim_end_matches('/').to_string())
+}
+
+fn optional<T: Display>(value: Option<T>) -> String {
+    match value {
+        Some(value) => value.to_string(),
+        None => "none".to_string(),
+    }
+}
+
+fn sorted<'a>(names: impl Iterator<Item = &'a String>) -> String {
+    let mut names: Vec<&str> = names.map(String::as_str).collect();
+    names.sort_unstable();
+    format!("[{}]", names.join(", "))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn normalizes_api_hosts() {
+        assert_eq!(
+            normalize_api_host("https://api.eu1.honeycomb.io/"),
+            Some("https://api.eu1.honeycomb.io".to_string())
+        );
+        assert_eq!(
+            normalize_api_host("http://proxy.internal:8080/honeycomb"),
+            Some("http://proxy.internal:8080/honeycomb".to_string())
+        );
+        assert_eq!(normalize_api_host("api.honeycomb.io"), None);
+        assert_eq!(normalize_api_host("ftp://api.honeycomb.io"), None);
+
+        let report = crate::HoneycombTelemetry::builder()
+            .api_host("api.honeycomb.io")
+            .validate();
+        assert!(report.issues.contains(&ConfigIssue::InvalidApiHost));
+
+        let err = crate::HoneycombTelemetry::builder()
+            .api_host("api.honeycomb.io")
+            .try_build()
+            .err();
+        assert_eq!(
+            err,
+            Some(ConfigError::InvalidApiHost("api.honeycomb.io".to_string()))
+        );
+    }
+
+    #[test]
+    fn reports_effective_configuration_and_conflicts() {
+        let options = libhoney::client::Options {
+            api_key: "secret".to_string(),
+            dataset: "prod".to_string(),
+            sample_rate: 4,
+            ..Default::default()
+        };
+        let config = libhoney::Config {
+            options,
+            transmission_options: Default::default(),
+        };
+
+        let report = Builder::new("svc", config)
+            .sample_rate(10)
+            .rate_limit(100)
+            .with_static_field("env", "prod")
+            .validate();
+
+        assert_eq!(report.get("api_key").unwrap().value, "set");
+        assert!(!report.to_string().contains("secret"));
+        assert_eq!(report.get("dataset").unwrap().value, "prod");
+        assert!(!report.get("sample_rate").unwrap().is_default);
+        assert_eq!(report.get("rate_limit").unwrap().value, "100");
+        assert!(report.get("clamp_to_parent").unwrap().is_default);
+        assert_eq!(report.get("static_fields").unwrap().value, "[env]");
+        assert_eq!(report.issues, vec![ConfigIssue::DoubleSampling]);
+
+        let report = crate::HoneycombTelemetry::builder()
+            .keep_errored_traces(true)
+            .validate();
+        assert_eq!(
+            report.issues,
+            vec![
+                ConfigIssue::MissingApiKey,
+                ConfigIssue::DefaultDataset,
+                ConfigIssue::SamplingFeaturesWithoutSampling,
+            ]
+        );
+    }
+}
@@ -2,19 +2,118 @@ use eaze_tracing_distributed as tracing_distributed;
 
 use chrono::{DateTime, Utc};
 use libhoney::{json, Value};
-use std::collections::HashMap;
+use sha1::{Digest, Sha1};
+use smallvec::SmallVec;
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
+use std::sync::Arc;
+use std::time::SystemTime;
 use tracing::field::{Field, Visit};
-use tracing_distributed::{Event, Span};
+use tracing_distributed::{Event, Span, SpanStart, Transition, TransitionKind};
 
-use crate::{SpanId, TraceId};
+use crate::diagnostics::{Diagnostic, InternalLogMode};
+use crate::errors::error_values;
+use crate::intern::intern;
+use crate::lazy::{format_or_capture, Lazy};
+use crate::links::SpanLink;
+use crate::log_bridge::take_log_record_target;
+use crate::pool::pooled_string;
+use crate::units::FieldUnits;
+use crate::{SpanId, SpanKind, TraceId, TraceIdFormat};
 
 // Visitor that builds honeycomb-compatible values from tracing fields.
+//
+// Errors recorded as `std::error::Error` values are expanded into the honeycomb-conventional
+// `error`, `error.message`, `error.type` and `error.source_chain` fields.
 #[derive(Default, Debug)]
 #[doc(hidden)]
-pub struct HoneycombVisitor(pub(crate) HashMap<String, Value>);
+pub struct HoneycombVisitor(
+    pub(crate) Fields<Value>,
+    // lazy fields, evaluated only once the span or event is published
+    pub(crate) Fields<Lazy>,
+    pub(crate) FieldLimits,
+    pub(crate) FieldPrefixing,
+);
 
-// reserved field names (TODO: document)
+// number of fields stored inline by `Fields`, most spans and events have fewer
+const INLINE_FIELDS: usize = 8;
+
+/// Fields recorded by a `HoneycombVisitor`, by name, stored inline up to `INLINE_FIELDS`
+/// fields. Cheaper to build than a `HashMap` for the few fields of a typical span, and
+/// converted to one only when the span is published.
+///
+/// Names are usually `&'static str`s, either the names of tracing fields or interned (see
+/// `intern`), so recording a field doesn't allocate its name. Published fields are keyed by
+/// owned strings, copied into the strings of recycled field maps (see `pooled_string`).
+#[derive(Clone, Debug)]
+pub(crate) struct Fields<T>(SmallVec<[(Cow<'static, str>, T); INLINE_FIELDS]>);
+
+impl<T> Default for Fields<T> {
+    fn default() -> Self {
+        Fields(SmallVec::new())
+    }
+}
+
+impl<T> Fields<T> {
+    fn position(&self, name: &str) -> Option<usize> {
+        self.0.iter().position(|(key, _)| key == name)
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<&T> {
+        self.get_key_value(name).map(|(_, value)| value)
+    }
+
+    pub(crate) fn get_key_value(&self, name: &str) -> Option<(&Cow<'static, str>, &T)> {
+        self.0
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(key, value)| (key, value))
+    }
+
+    pub(crate) fn contains_key(&self, name: &str) -> bool {
+        self.position(name).is_some()
+    }
+
+    pub(crate) fn insert(&mut self, name: impl Into<Cow<'static, str>>, value: T) {
+        let name = name.into();
+        match self.position(&name) {
+            Some(index) => self.0[index].1 = value,
+            None => self.0.push((name, value)),
+        }
+    }
+
+    pub(crate) fn remove(&mut self, name: &str) -> Option<T> {
+        let index = self.position(name)?;
+        Some(self.0.swap_remove(index).1)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.0.len()
+    }
+}
+
+impl<T> std::ops::Index<&str> for Fields<T> {
+    type Output = T;
+
+    fn index(&self, name: &str) -> &T {
+        self.get(name).expect("no field with this name")
+    }
+}
+
+impl<T> IntoIterator for Fields<T> {
+    type Item = (Cow<'static, str>, T);
+    type IntoIter = smallvec::IntoIter<[(Cow<'static, str>, T); INLINE_FIELDS]>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+// names of the fields provided by this crate, recorded fields with these names are prefixed,
+// see `FieldPrefixing`. The first three are the default trace field names, replaced by those
+// configured with `Builder::trace_field_names`
 static RESERVED_WORDS: [&str; 9] = [
     "trace.span_id",
     "trace.trace_id",
@@ -29,127 +128,1142 @@ static RESERVED_WORDS: [&str; 9] = [
 
 impl Visit for HoneycombVisitor {
     fn record_i64(&mut self, field: &Field, value: i64) {
-        self.0
-            .insert(mk_field_name(field.name().to_string()), json!(value));
+        self.insert(self.3.field_name(field.name()), json!(value));
     }
 
     fn record_u64(&mut self, field: &Field, value: u64) {
-        self.0
-            .insert(mk_field_name(field.name().to_string()), json!(value));
+        self.insert(self.3.field_name(field.name()), json!(value));
     }
 
     fn record_bool(&mut self, field: &Field, value: bool) {
-        self.0
-            .insert(mk_field_name(field.name().to_string()), json!(value));
+        self.insert(self.3.field_name(field.name()), json!(value));
     }
 
     fn record_str(&mut self, field: &Field, value: &str) {
-        self.0
-            .insert(mk_field_name(field.name().to_string()), json!(value));
+        self.insert(self.3.field_name(field.name()), json!(value));
+    }
+
+    fn record_error(&mut self, _field: &Field, value: &(dyn std::error::Error + 'static)) {
+        for (name, value) in error_values(value) {
+            self.insert(name, value);
+        }
     }
 
     fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
-        let s = format!("{:?}", value);
-        self.0
-            .insert(mk_field_name(field.name().to_string()), json!(s));
+        let name = self.3.field_name(field.name());
+        match format_or_capture(value) {
+            Ok(s) => self.insert(name, json!(s)),
+            Err(lazy) => {
+                if self.has_room_for(name) {
+                    self.0.remove(name);
+                    self.1.insert(name, lazy);
+                }
+            }
+        }
     }
 }
 
-fn mk_field_name(s: String) -> String {
-    // TODO: do another pass, optimize for efficiency (lazy static set?)
-    if RESERVED_WORDS.contains(&&s[..]) {
-        format!("tracing.{}", s)
-    } else {
-        s
+impl HoneycombVisitor {
+    pub(crate) fn new(limits: FieldLimits, prefixing: FieldPrefixing) -> Self {
+        HoneycombVisitor(Fields::default(), Fields::default(), limits, prefixing)
     }
+
+    fn insert(&mut self, name: impl Into<Cow<'static, str>>, mut value: Value) {
+        let name = name.into();
+        if !self.has_room_for(&name) {
+            return;
+        }
+        if self.2.truncate(&mut value) {
+            self.mark_truncated();
+        }
+        self.1.remove(&name);
+        self.0.insert(name, value);
+    }
+
+    // whether the field can be recorded without exceeding the maximum number of fields,
+    // marking the visitor as truncated otherwise
+    fn has_room_for(&mut self, name: &str) -> bool {
+        let max_fields = match self.2.max_fields {
+            Some(max_fields) => max_fields,
+            None => return true,
+        };
+        if self.0.contains_key(name) || self.1.contains_key(name) {
+            return true;
+        }
+
+        let recorded = self.0.len() + self.1.len() - self.0.contains_key(TRUNCATED) as usize;
+        if recorded < max_fields {
+            true
+        } else {
+            self.mark_truncated();
+            false
+        }
+    }
+
+    fn mark_truncated(&mut self) {
+        self.0.insert(TRUNCATED.to_string(), json!(true));
+    }
+}
+
+/// Visitor recording the fields of spans and events published to honeycomb.io.
+///
+/// `HoneycombVisitor` is used by default. Implement this trait to plug a custom visitor into
+/// `HoneycombTelemetry` using `Builder::build_with_visitor`, e.g. to encode typed fields or
+/// extract metrics, while reusing the transport, sampling and id machinery.
+pub trait HoneycombValues: Visit + Sized + Send + Sync + 'static {
+    /// Consume the visitor, returning the recorded fields.
+    fn into_values(self) -> HashMap<String, Value>;
+
+    /// Consume the visitor, returning the recorded fields with `action` applied to each of
+    /// them: masked fields are replaced with `"[REDACTED]"` and dropped fields are omitted.
+    /// Fields to anonymize are returned as recorded, and anonymized by the caller.
+    ///
+    /// Override to avoid computing the values of masked or dropped fields.
+    fn into_redacted_values(self, action: &dyn Fn(&str) -> FieldAction) -> HashMap<String, Value> {
+        self.into_values()
+            .into_iter()
+            .filter_map(|(name, value)| match action(&name) {
+                FieldAction::Keep | FieldAction::Anonymize => Some((name, value)),
+                FieldAction::Mask => Some((name, json!("[REDACTED]"))),
+                FieldAction::Drop => None,
+            })
+            .collect()
+    }
+
+    /// Like `into_redacted_values`, adding the redacted fields to `values`, an empty map
+    /// reused across spans and events, see `Builder::buffer_pool`. Defaults to extending
+    /// `values` with the result of `into_redacted_values`.
+    ///
+    /// Override to record fields straight into `values`, without an intermediate map.
+    fn redact_into(
+        self,
+        action: &dyn Fn(&str) -> FieldAction,
+        values: &mut HashMap<String, Value>,
+    ) {
+        values.extend(self.into_redacted_values(action));
+    }
+
+    /// Get the value of a recorded field, if readily available. Used to detect errors
+    /// recorded on events. Defaults to `None`.
+    fn get(&self, _name: &str) -> Option<&Value> {
+        None
+    }
+
+    /// Copy the named fields recorded on `ancestor` that were not recorded on this visitor,
+    /// see `Builder::inherit_field`. Defaults to doing nothing.
+    fn inherit(&mut self, _ancestor: &Self, _names: &HashSet<String>) {}
+}
+
+impl HoneycombValues for HoneycombVisitor {
+    fn into_values(self) -> HashMap<String, Value> {
+        self.into_redacted_values(&|_| FieldAction::Keep)
+    }
+
+    fn into_redacted_values(self, action: &dyn Fn(&str) -> FieldAction) -> HashMap<String, Value> {
+        let mut values = HashMap::new();
+        self.redact_into(action, &mut values);
+        values
+    }
+
+    // evaluates only those lazy values that are kept
+    fn redact_into(
+        self,
+        action: &dyn Fn(&str) -> FieldAction,
+        redacted: &mut HashMap<String, Value>,
+    ) {
+        let HoneycombVisitor(values, lazy_values, limits, _) = self;
+        let mut truncated = false;
+        let lazy_values = lazy_values
+            .into_iter()
+            .map(|(name, lazy)| (name, LazyOrValue::Lazy(lazy)));
+        redacted.reserve(values.len() + lazy_values.len());
+        let values = values
+            .into_iter()
+            .map(|(name, value)| (name, LazyOrValue::Value(value)))
+            .chain(lazy_values)
+            .filter_map(|(name, value)| match action(&name) {
+                FieldAction::Keep | FieldAction::Anonymize => {
+                    let is_lazy = matches!(value, LazyOrValue::Lazy(_));
+                    let mut value = value.evaluate();
+                    // eagerly recorded values were truncated as they were recorded
+                    if is_lazy && limits.truncate(&mut value) {
+                        truncated = true;
+                    }
+                    Some((owned_name(name), value))
+                }
+                FieldAction::Mask => Some((owned_name(name), json!("[REDACTED]"))),
+                FieldAction::Drop => None,
+            });
+        redacted.extend(values);
+
+        if truncated {
+            redacted.insert(pooled_string(TRUNCATED), json!(true));
+        }
+    }
+
+    fn get(&self, name: &str) -> Option<&Value> {
+        self.0.get(name)
+    }
+
+    fn inherit(&mut self, ancestor: &HoneycombVisitor, names: &HashSet<String>) {
+        for name in names {
+            if self.0.contains_key(name) || self.1.contains_key(name) {
+                continue;
+            }
+            // names are copied from the ancestor, usually without allocating
+            if let Some((name, value)) = ancestor.0.get_key_value(name) {
+                self.0.insert(name.clone(), value.clone());
+            } else if let Some((name, lazy)) = ancestor.1.get_key_value(name) {
+                self.1.insert(name.clone(), lazy.clone());
+            }
+        }
+    }
+}
+
+// marks spans and events whose fields were truncated to honor `FieldLimits`
+const TRUNCATED: &str = "meta.truncated";
+
+/// Limits on the fields recorded on a single span or event.
+#[derive(Clone, Copy, Debug, Default)]
+pub(crate) struct FieldLimits {
+    pub(crate) max_fields: Option<usize>,
+    pub(crate) max_string_len: Option<usize>,
 }
 
-pub(crate) fn event_to_values(
-    event: Event<HoneycombVisitor, SpanId, TraceId>,
+impl FieldLimits {
+    // truncates string values longer than the maximum length (in bytes, on a char boundary),
+    // returning whether the value was truncated
+    fn truncate(&self, value: &mut Value) -> bool {
+        match (self.max_string_len, value) {
+            (Some(max_len), Value::String(s)) if s.len() > max_len => {
+                let mut end = max_len;
+                while !s.is_char_boundary(end) {
+                    end -= 1;
+                }
+                s.truncate(end);
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+/// Options controlling how recorded fields are turned into outgoing values.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct FieldOptions {
+    pub(crate) trace_id_format: TraceIdFormat,
+    pub(crate) key_mapping: KeyMapping,
+    pub(crate) redaction: Redaction,
+    pub(crate) units: FieldUnits,
+    pub(crate) limits: FieldLimits,
+    pub(crate) prefixing: FieldPrefixing,
+    pub(crate) trace_fields: TraceFieldNames,
+    pub(crate) naming: FieldNaming,
+    pub(crate) internal_log_mode: InternalLogMode,
+}
+
+/// Names of the columns holding the trace id, span id and parent span id of published spans
+/// and events, from which honeycomb.io's tracing UI reconstructs trace waterfalls. See
+/// `Builder::trace_field_names`.
+///
+/// Defaults to honeycomb.io's standard `trace.trace_id`, `trace.span_id` and
+/// `trace.parent_id` names. Root spans are published with a `null` parent id.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceFieldNames {
+    /// Name of the trace id column.
+    pub trace_id: String,
+    /// Name of the span id column, only present on spans.
+    pub span_id: String,
+    /// Name of the parent span id column.
+    pub parent_id: String,
+}
+
+impl TraceFieldNames {
+    /// honeycomb.io's standard names, used by its tracing UI unless the dataset is configured
+    /// otherwise.
+    pub fn honeycomb() -> Self {
+        TraceFieldNames {
+            trace_id: "trace.trace_id".to_string(),
+            span_id: "trace.span_id".to_string(),
+            parent_id: "trace.parent_id".to_string(),
+        }
+    }
+}
+
+impl Default for TraceFieldNames {
+    fn default() -> Self {
+        TraceFieldNames::honeycomb()
+    }
+}
+
+/// Preset of the names and formats of the fields provided by this crate, see
+/// `Builder::field_naming`.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum FieldNaming {
+    /// Span ids are published with a `span-` prefix, e.g. `span-2a-1`.
+    #[default]
+    Default,
+    /// The exact fields published by honeycomb.io's beelines: `trace.trace_id`,
+    /// `trace.span_id`, `trace.parent_id`, `duration_ms`, `name` and `service_name`, with
+    /// span ids published as is. Traces from services instrumented with beelines, e.g. in Go
+    /// or Node, merge cleanly with traces published by this crate.
+    Beeline,
+}
+
+impl FieldNaming {
+    fn span_id(self, span_id: &SpanId) -> Value {
+        match self {
+            FieldNaming::Default => json!(format!("span-{}", span_id)),
+            FieldNaming::Beeline => json!(span_id.to_string()),
+        }
+    }
+}
+
+/// What to do with a field before it is sent to honeycomb.io.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum FieldAction {
+    /// Send the field as recorded.
+    Keep,
+    /// Send the field with its value replaced by `"[REDACTED]"`.
+    Mask,
+    /// Do not send the field.
+    Drop,
+    /// Send the field with its value replaced by a hash of the value, e.g. `"anon-3f2a..."`.
+    /// Equal values are replaced by equal hashes within a layer, see
+    /// `Builder::anonymization_key`, so traces can be shared without leaking customer data
+    /// while remaining analyzable.
+    Anonymize,
+}
+
+type FieldFilter = dyn Fn(&str) -> FieldAction + Send + Sync;
+
+/// Drops, masks or anonymizes sensitive fields recorded on spans and events.
+#[derive(Clone)]
+pub(crate) struct Redaction {
+    // keyed by lowercase field name
+    names: HashMap<String, FieldAction>,
+    filter: Option<Arc<FieldFilter>>,
+    anonymization_key: String,
+}
+
+impl Default for Redaction {
+    fn default() -> Self {
+        Redaction {
+            names: HashMap::new(),
+            filter: None,
+            // random, so hashes cannot be reversed by hashing candidate values
+            anonymization_key: uuid::Uuid::new_v4().to_string(),
+        }
+    }
+}
+
+impl fmt::Debug for Redaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Redaction")
+            .field("names", &self.names)
+            .field("filter", &self.filter.as_ref().map(|_| "<fn>"))
+            .field("anonymization_key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl Redaction {
+    pub(crate) fn set_action(&mut self, name: &str, action: FieldAction) {
+        self.names.insert(name.to_lowercase(), action);
+    }
+
+    pub(crate) fn set_filter<F>(&mut self, filter: F)
+    where
+        F: Fn(&str) -> FieldAction + Send + Sync + 'static,
+    {
+        self.filter = Some(Arc::new(filter));
+    }
+
+    // actions registered by name take precedence over the filter
+    fn action(&self, name: &str) -> FieldAction {
+        if !self.names.is_empty() {
+            // most names are already lowercase, only allocate for those that may not be
+            let action = if !name.is_ascii() || name.bytes().any(|b| b.is_ascii_uppercase()) {
+                self.names.get(&name.to_lowercase())
+            } else {
+                self.names.get(name)
+            };
+            if let Some(action) = action {
+                return *action;
+            }
+        }
+        match &self.filter {
+            Some(filter) => filter(name),
+            None => FieldAction::Keep,
+        }
+    }
+
+    pub(crate) fn set_anonymization_key(&mut self, key: String) {
+        self.anonymization_key = key;
+    }
+
+    // consistent for a given key, so anonymized values can still be grouped and compared
+    fn anonymize(&self, value: &Value) -> Value {
+        let sum = Sha1::digest(format!("{}:{}", self.anonymization_key, value).as_bytes());
+        let hex: String = sum[..8].iter().map(|b| format!("{:02x}", b)).collect();
+        json!(format!("anon-{}", hex))
+    }
+
+    // redacts recorded values into `values`, evaluating only those lazy values that are kept
+    fn apply<V: HoneycombValues>(
+        &self,
+        visitor: V,
+        mut values: HashMap<String, Value>,
+    ) -> HashMap<String, Value> {
+        let anonymized = RefCell::new(Vec::new());
+        let action = |name: &str| {
+            let action = self.action(name);
+            if action == FieldAction::Anonymize {
+                anonymized.borrow_mut().push(name.to_string());
+            }
+            action
+        };
+        visitor.redact_into(&action, &mut values);
+
+        for name in anonymized.into_inner() {
+            if let Some(value) = values.get_mut(&name) {
+                *value = self.anonymize(value);
+            }
+        }
+        values
+    }
+
+    // redacts fields added to traces, e.g. with `add_trace_field`, alike recorded fields
+    pub(crate) fn redact(&self, fields: Vec<(String, Value)>) -> Vec<(String, Value)> {
+        if self.names.is_empty() && self.filter.is_none() {
+            return fields;
+        }
+        fields
+            .into_iter()
+            .filter_map(|(name, value)| match self.action(&name) {
+                FieldAction::Keep => Some((name, value)),
+                FieldAction::Anonymize => {
+                    let value = self.anonymize(&value);
+                    Some((name, value))
+                }
+                FieldAction::Mask => Some((name, json!("[REDACTED]"))),
+                FieldAction::Drop => None,
+            })
+            .collect()
+    }
+}
+
+enum LazyOrValue {
+    Lazy(Lazy),
+    Value(Value),
+}
+
+impl LazyOrValue {
+    fn evaluate(self) -> Value {
+        match self {
+            LazyOrValue::Lazy(lazy) => lazy.evaluate(),
+            LazyOrValue::Value(value) => value,
+        }
+    }
+}
+
+// the name of a published field, copied into a spare string unless it was already owned
+fn owned_name(name: Cow<'static, str>) -> String {
+    match name {
+        Cow::Borrowed(name) => pooled_string(name),
+        Cow::Owned(name) => name,
+    }
+}
+
+type KeyMapper = dyn Fn(&str) -> Option<String> + Send + Sync;
+
+/// Rewrites the keys of outgoing spans and events, e.g. to keep existing honeycomb boards
+/// and derived columns working after a change of field names.
+#[derive(Clone, Default)]
+pub(crate) struct KeyMapping {
+    renames: HashMap<String, String>,
+    mapper: Option<Arc<KeyMapper>>,
+}
+
+impl fmt::Debug for KeyMapping {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyMapping")
+            .field("renames", &self.renames)
+            .field("mapper", &self.mapper.as_ref().map(|_| "<fn>"))
+            .finish()
+    }
+}
+
+impl KeyMapping {
+    pub(crate) fn rename(&mut self, from: String, to: String) {
+        self.renames.insert(from, to);
+    }
+
+    pub(crate) fn set_mapper<F>(&mut self, mapper: F)
+    where
+        F: Fn(&str) -> Option<String> + Send + Sync + 'static,
+    {
+        self.mapper = Some(Arc::new(mapper));
+    }
+
+    // explicit renames take precedence over the mapper, which leaves keys it returns
+    // `None` for untouched
+    fn map_key(&self, key: &str) -> Option<String> {
+        if let Some(renamed) = self.renames.get(key) {
+            return Some(renamed.clone());
+        }
+        self.mapper.as_ref().and_then(|mapper| mapper(key))
+    }
+
+    // renames keys in place, so that `values` can still be recycled by its `BufferPool`
+    pub(crate) fn apply(&self, mut values: HashMap<String, Value>) -> HashMap<String, Value> {
+        if self.renames.is_empty() && self.mapper.is_none() {
+            return values;
+        }
+        let renamed: Vec<(String, String)> = values
+            .keys()
+            .filter_map(|key| match self.map_key(key) {
+                Some(to) if to != *key => Some((key.clone(), to)),
+                _ => None,
+            })
+            .collect();
+        // removed before reinserting, as keys may be renamed to other renamed keys
+        let moved: Vec<(String, Value)> = renamed
+            .into_iter()
+            .filter_map(|(from, to)| values.remove(&from).map(|value| (to, value)))
+            .collect();
+        values.extend(moved);
+        values
+    }
+}
+
+/// Prefix added to the names of recorded fields that collide with the fields provided by this
+/// crate, e.g. `name` or `level`, see `Builder::reserved_field_prefix`.
+#[derive(Clone, Debug)]
+pub(crate) struct FieldPrefixing {
+    prefix: Arc<str>,
+    // `RESERVED_WORDS` with the configured trace field names, interned
+    reserved: [&'static str; 9],
+    // `reserved` with the prefix, interned
+    prefixed: [&'static str; 9],
+    // recorded as is, replacing the field provided by this crate
+    exempt: Arc<HashSet<String>>,
+}
+
+impl Default for FieldPrefixing {
+    fn default() -> Self {
+        let mut prefixing = FieldPrefixing {
+            prefix: "".into(),
+            reserved: RESERVED_WORDS,
+            prefixed: RESERVED_WORDS,
+            exempt: Default::default(),
+        };
+        prefixing.set_prefix("tracing.".to_string());
+        prefixing
+    }
+}
+
+impl FieldPrefixing {
+    pub(crate) fn set_prefix(&mut self, prefix: String) {
+        for (prefixed, word) in self.prefixed.iter_mut().zip(&self.reserved) {
+            *prefixed = intern(&format!("{}{}", prefix, word));
+        }
+        self.prefix = prefix.into();
+    }
+
+    // reserves the trace field names the layer publishes instead of the default ones
+    pub(crate) fn set_trace_fields(&mut self, trace_fields: &TraceFieldNames) {
+        self.reserved[0] = intern(&trace_fields.span_id);
+        self.reserved[1] = intern(&trace_fields.trace_id);
+        self.reserved[2] = intern(&trace_fields.parent_id);
+        let prefix = self.prefix.to_string();
+        self.set_prefix(prefix);
+    }
+
+    pub(crate) fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub(crate) fn exempt(&mut self, name: String) {
+        Arc::make_mut(&mut self.exempt).insert(name);
+    }
+
+    pub(crate) fn exempted(&self) -> &HashSet<String> {
+        &self.exempt
+    }
+
+    fn field_name(&self, name: &'static str) -> &'static str {
+        match self.reserved.iter().position(|word| *word == name) {
+            Some(index) if !self.exempt.contains(name) => self.prefixed[index],
+            _ => name,
+        }
+    }
+
+    // removes the recorded values of exempt fields, to be restored once the fields provided
+    // by this crate are added
+    fn take_exempt(&self, values: &mut HashMap<String, Value>) -> Vec<(String, Value)> {
+        if self.exempt.is_empty() {
+            return Vec::new();
+        }
+        self.exempt
+            .iter()
+            .filter(|name| self.reserved.contains(&name.as_str()))
+            .filter_map(|name| values.remove_entry(name))
+            .collect()
+    }
+}
+
+// `extra` holds fields added by the telemetry itself, e.g. meta fields. `values` is an empty
+// map to fill, taken from a `BufferPool`
+pub(crate) fn event_to_values<V: HoneycombValues>(
+    event: Event<V, SpanId, TraceId>,
+    options: &FieldOptions,
+    extra: Vec<(String, Value)>,
+    values: HashMap<String, Value>,
 ) -> HashMap<String, libhoney::Value> {
-    let mut values = event.values.0;
+    let values = options.redaction.apply(event.values, values);
+    let mut values = options.units.apply(values);
+    let exempt = options.prefixing.take_exempt(&mut values);
+    values.extend(extra);
+    // events converted from `log` records report the record's target, see `init_log_bridge`
+    let target = take_log_record_target(event.meta, &mut values);
 
     values.insert(
         // magic honeycomb string (trace.trace_id)
-        "trace.trace_id".to_string(),
+        pooled_string(&options.trace_fields.trace_id),
         // using explicit trace id passed in from ctx (req'd for lazy eval)
-        json!(event.trace_id.to_string()),
+        json!(options.trace_id_format.apply(&event.trace_id).to_string()),
     );
 
     values.insert(
         // magic honeycomb string (trace.parent_id)
-        "trace.parent_id".to_string(),
+        pooled_string(&options.trace_fields.parent_id),
         event
             .parent_id
-            .map(|pid| json!(format!("span-{}", pid.to_string())))
+            .map(|pid| options.naming.span_id(&pid))
             .unwrap_or(json!(null)),
     );
 
     // magic honeycomb string (service_name)
-    values.insert("service_name".to_string(), json!(event.service_name));
+    values.insert(pooled_string("service_name"), json!(event.service_name));
 
     values.insert(
-        "level".to_string(),
+        pooled_string("level"),
         json!(format!("{}", event.meta.level())),
     );
 
     let initialized_at: DateTime<Utc> = event.initialized_at.into();
-    values.insert("Timestamp".to_string(), json!(initialized_at.to_rfc3339()));
+    values.insert(
+        pooled_string("Timestamp"),
+        json!(initialized_at.to_rfc3339()),
+    );
 
     // not honeycomb-special but tracing-provided
-    values.insert("name".to_string(), json!(event.meta.name()));
-    values.insert("target".to_string(), json!(event.meta.target()));
+    values.insert(
+        pooled_string("name"),
+        Value::from(pooled_string(event.meta.name())),
+    );
+    let meta = event.meta;
+    values.insert(
+        pooled_string("target"),
+        target.unwrap_or_else(|| Value::from(pooled_string(meta.target()))),
+    );
 
-    values
+    values.extend(exempt);
+    options.key_mapping.apply(values)
 }
 
-pub(crate) fn span_to_values(
-    span: Span<HoneycombVisitor, SpanId, TraceId>,
+// `extra` holds fields added by the telemetry itself, e.g. meta fields. `values` is an empty
+// map to fill, taken from a `BufferPool`
+pub(crate) fn span_to_values<V: HoneycombValues>(
+    span: Span<V, SpanId, TraceId>,
+    options: &FieldOptions,
+    extra: Vec<(String, Value)>,
+    values: HashMap<String, Value>,
 ) -> HashMap<String, libhoney::Value> {
-    let mut values = span.values.0;
+    let values = options.redaction.apply(span.values, values);
+    let mut values = options.units.apply(values);
+    let exempt = options.prefixing.take_exempt(&mut values);
+    values.extend(extra);
+
+    // accept the `otel.kind` convention used by tracing-opentelemetry
+    if let Some(kind) = values.remove("otel.kind") {
+        values
+            .entry(pooled_string(SpanKind::field_name()))
+            .or_insert(kind);
+    }
 
     values.insert(
         // magic honeycomb string (trace.span_id)
-        "trace.span_id".to_string(),
-        json!(format!("span-{}", span.id.to_string())),
+        pooled_string(&options.trace_fields.span_id),
+        options.naming.span_id(&span.id),
     );
 
     values.insert(
         // magic honeycomb string (trace.trace_id)
-        "trace.trace_id".to_string(),
+        pooled_string(&options.trace_fields.trace_id),
         // using explicit trace id passed in from ctx (req'd for lazy eval)
-        json!(span.trace_id.to_string()),
+        json!(options.trace_id_format.apply(&span.trace_id).to_string()),
     );
 
     values.insert(
         // magic honeycomb string (trace.parent_id)
-        "trace.parent_id".to_string(),
+        pooled_string(&options.trace_fields.parent_id),
         span.parent_id
-            .map(|pid| json!(format!("span-{}", pid.to_string())))
+            .map(|pid| options.naming.span_id(&pid))
             .unwrap_or(json!(null)),
     );
 
     // magic honeycomb string (service_name)
-    values.insert("service_name".to_string(), json!(span.service_name));
+    values.insert(pooled_string("service_name"), json!(span.service_name));
 
-    values.insert("level".to_string(), json!(format!("{}", span.meta.level())));
+    values.insert(
+        pooled_string("level"),
+        json!(format!("{}", span.meta.level())),
+    );
 
     let initialized_at: DateTime<Utc> = span.initialized_at.into();
-    values.insert("Timestamp".to_string(), json!(initialized_at.to_rfc3339()));
+    values.insert(
+        pooled_string("Timestamp"),
+        json!(initialized_at.to_rfc3339()),
+    );
 
     // not honeycomb-special but tracing-provided
-    values.insert("name".to_string(), json!(span.meta.name()));
-    values.insert("target".to_string(), json!(span.meta.target()));
+    values.insert(
+        pooled_string("name"),
+        Value::from(pooled_string(span.meta.name())),
+    );
+    values.insert(
+        pooled_string("target"),
+        Value::from(pooled_string(span.meta.target())),
+    );
 
-    match span.completed_at.duration_since(span.initialized_at) {
-        Ok(d) => {
-            // honeycomb-special (I think, todo: get full list of known values)
-            values.insert("duration_ms".to_string(), json!(d.as_millis() as u64));
-        }
+    // honeycomb-special, used along with Timestamp (the span's start) to render waterfalls
+    values.insert(
+        pooled_string("duration_ms"),
+        json!(duration_ms(
+            span.initialized_at,
+            span.completed_at,
+            options.internal_log_mode
+        )),
+    );
+
+    values.extend(exempt);
+    options.key_mapping.apply(values)
+}
+
+// `values` is an empty map to fill, taken from a `BufferPool`
+pub(crate) fn transition_to_values(
+    transition: Transition<SpanId, TraceId>,
+    options: &FieldOptions,
+    mut values: HashMap<String, Value>,
+) -> HashMap<String, libhoney::Value> {
+    values.insert(
+        pooled_string("meta.span_transition"),
+        json!(match transition.kind {
+            TransitionKind::Enter => "enter",
+            TransitionKind::Exit => "exit",
+        }),
+    );
+
+    // recorded on the thread that entered or exited the span
+    let thread = std::thread::current();
+    values.insert(
+        pooled_string("thread.id"),
+        json!(format!("{:?}", thread.id())),
+    );
+    if let Some(name) = thread.name() {
+        values.insert(pooled_string("thread.name"), json!(name));
+    }
+
+    values.insert(
+        pooled_string(&options.trace_fields.trace_id),
+        json!(options
+            .trace_id_format
+            .apply(&transition.trace_id)
+            .to_string()),
+    );
+
+    // a span event, attached to the span that was entered or exited
+    values.insert(pooled_string("meta.annotation_type"), json!("span_event"));
+    values.insert(
+        pooled_string(&options.trace_fields.parent_id),
+        options.naming.span_id(&transition.span_id),
+    );
+
+    values.insert(
+        pooled_string("service_name"),
+        json!(transition.service_name),
+    );
+
+    let occurred_at: DateTime<Utc> = transition.occurred_at.into();
+    values.insert(pooled_string("Timestamp"), json!(occurred_at.to_rfc3339()));
+
+    values.insert(
+        pooled_string("name"),
+        Value::from(pooled_string(transition.meta.name())),
+    );
+    values.insert(
+        pooled_string("target"),
+        Value::from(pooled_string(transition.meta.target())),
+    );
+
+    options.key_mapping.apply(values)
+}
+
+// `values` is an empty map to fill, taken from a `BufferPool`
+pub(crate) fn link_to_values(
+    link: SpanLink,
+    service_name: &'static str,
+    options: &FieldOptions,
+    mut values: HashMap<String, Value>,
+) -> HashMap<String, libhoney::Value> {
+    values.insert(
+        pooled_string(&options.trace_fields.trace_id),
+        json!(options.trace_id_format.apply(&link.trace_id).to_string()),
+    );
+
+    // a link annotation, attached to the linking span
+    values.insert(pooled_string("meta.annotation_type"), json!("link"));
+    values.insert(
+        pooled_string(&options.trace_fields.parent_id),
+        options.naming.span_id(&link.span_id),
+    );
+    values.insert(
+        pooled_string("trace.link.trace_id"),
+        json!(options
+            .trace_id_format
+            .apply(&link.linked_trace_id)
+            .to_string()),
+    );
+    values.insert(
+        pooled_string("trace.link.span_id"),
+        options.naming.span_id(&link.linked_span_id),
+    );
+
+    values.insert(pooled_string("service_name"), json!(service_name));
+
+    let linked_at: DateTime<Utc> = link.linked_at.into();
+    values.insert(pooled_string("Timestamp"), json!(linked_at.to_rfc3339()));
+
+    values.insert(
+        pooled_string("name"),
+        Value::from(pooled_string(link.meta.name())),
+    );
+    values.insert(
+        pooled_string("target"),
+        Value::from(pooled_string(link.meta.target())),
+    );
+
+    options.key_mapping.apply(values)
+}
+
+// `values` is an empty map to fill, taken from a `BufferPool`
+pub(crate) fn span_start_to_values(
+    start: SpanStart<SpanId, TraceId>,
+    options: &FieldOptions,
+    mut values: HashMap<String, Value>,
+) -> HashMap<String, libhoney::Value> {
+    values.insert(
+        pooled_string(&options.trace_fields.trace_id),
+        json!(options.trace_id_format.apply(&start.trace_id).to_string()),
+    );
+
+    // not a span itself, so the span reported on close isn't duplicated in waterfalls. attached
+    // to the parent of the span that started, which is usually reported already
+    values.insert(pooled_string("meta.annotation_type"), json!("span_start"));
+    values.insert(
+        pooled_string("meta.started_span_id"),
+        options.naming.span_id(&start.span_id),
+    );
+    values.insert(
+        pooled_string(&options.trace_fields.parent_id),
+        start
+            .parent_id
+            .map(|pid| options.naming.span_id(&pid))
+            .unwrap_or(json!(null)),
+    );
+
+    values.insert(pooled_string("service_name"), json!(start.service_name));
+    values.insert(
+        pooled_string("level"),
+        json!(format!("{}", start.meta.level())),
+    );
+
+    let initialized_at: DateTime<Utc> = start.initialized_at.into();
+    values.insert(
+        pooled_string("Timestamp"),
+        json!(initialized_at.to_rfc3339()),
+    );
+
+    values.insert(
+        pooled_string("name"),
+        Value::from(pooled_string(start.meta.name())),
+    );
+    values.insert(
+        pooled_string("target"),
+        Value::from(pooled_string(start.meta.target())),
+    );
+
+    options.key_mapping.apply(values)
+}
+
+// in milliseconds, with sub-millisecond precision
+fn duration_ms(
+    initialized_at: SystemTime,
+    completed_at: SystemTime,
+    internal_log_mode: InternalLogMode,
+) -> f64 {
+    match completed_at.duration_since(initialized_at) {
+        Ok(d) => d.as_secs_f64() * 1000.0,
         Err(e) => {
-            eprintln!("error comparing system times in tracing-honeycomg, indicates possible clock skew: {:?}", e);
+            internal_log_mode.emit(&Diagnostic::ClockSkew(&e));
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn duration_ms_has_sub_millisecond_precision() {
+        let start = SystemTime::now();
+        let end = start + std::time::Duration::from_micros(2250);
+        let silent = InternalLogMode::Silent;
+        assert_eq!(duration_ms(start, end, silent), 2.25);
+        assert_eq!(duration_ms(end, start, silent), 0.0);
+    }
+
+    #[test]
+    fn field_limits_truncate_and_mark_values() {
+        let limits = FieldLimits {
+            max_fields: Some(2),
+            max_string_len: Some(4),
+        };
+        let mut visitor = HoneycombVisitor::new(limits, FieldPrefixing::default());
+        visitor.insert("a".to_string(), json!("short"));
+        visitor.insert("b".to_string(), json!(1));
+        visitor.insert("c".to_string(), json!(2));
+        // overwriting an existing field is always allowed
+        visitor.insert("b".to_string(), json!("éé"));
+
+        assert_eq!(visitor.0.len(), 3);
+        assert_eq!(visitor.0["a"], json!("shor"));
+        assert_eq!(visitor.0["b"], json!("éé"));
+        assert_eq!(visitor.0[TRUNCATED], json!(true));
+    }
+
+    #[test]
+    fn fields_spill_beyond_inline_capacity() {
+        let mut visitor = HoneycombVisitor::default();
+        for n in 0..INLINE_FIELDS * 2 {
+            visitor.insert(format!("field.{}", n), json!(n));
+        }
+        visitor.insert("field.3".to_string(), json!("replaced"));
+        visitor.0.remove("field.0");
+
+        let values = visitor.into_values();
+        assert_eq!(values.len(), INLINE_FIELDS * 2 - 1);
+        assert_eq!(values["field.3"], json!("replaced"));
+        assert_eq!(values["field.15"], json!(15));
+        assert!(!values.contains_key("field.0"));
+    }
+
+    #[test]
+    fn prefixes_reserved_fields_unless_exempt() {
+        let mut prefixing = FieldPrefixing::default();
+        assert_eq!(prefixing.field_name("name"), "tracing.name");
+        assert_eq!(prefixing.field_name("user_id"), "user_id");
+
+        prefixing.set_prefix("app.".to_string());
+        prefixing.exempt("service_name".to_string());
+        assert_eq!(prefixing.field_name("level"), "app.level");
+        assert_eq!(prefixing.field_name("service_name"), "service_name");
+
+        let mut values = HashMap::new();
+        values.insert("service_name".to_string(), json!("checkout"));
+        values.insert("user_id".to_string(), json!(42));
+        assert_eq!(
+            prefixing.take_exempt(&mut values),
+            vec![("service_name".to_string(), json!("checkout"))]
+        );
+        assert_eq!(values.len(), 1);
+    }
+
+    #[test]
+    fn inherits_only_named_fields_not_recorded_on_child() {
+        let mut parent = HoneycombVisitor::default();
+        parent.0.insert("user_id".to_string(), json!(42));
+        parent.0.insert("tenant".to_string(), json!("parent"));
+        parent.0.insert("path".to_string(), json!("/users"));
+
+        let mut child = HoneycombVisitor::default();
+        child.0.insert("tenant".to_string(), json!("child"));
+
+        let names = ["user_id", "tenant"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect();
+        child.inherit(&parent, &names);
+
+        assert_eq!(child.0.len(), 2);
+        assert_eq!(child.0["user_id"], json!(42));
+        assert_eq!(child.0["tenant"], json!("child"));
+    }
+
+    #[test]
+    fn beeline_naming_publishes_span_ids_as_is() {
+        use std::str::FromStr;
+
+        let span_id = SpanId::from_str("2a-1").unwrap();
+        assert_eq!(FieldNaming::Default.span_id(&span_id), json!("span-2a-1"));
+        assert_eq!(FieldNaming::Beeline.span_id(&span_id), json!("2a-1"));
+    }
+
+    #[test]
+    fn renames_take_precedence_over_mapper() {
+        let mut key_mapping = KeyMapping::default();
+        key_mapping.rename("trace.trace_id".to_string(), "trace-id".to_string());
+        key_mapping.set_mapper(|key| {
+            key.strip_prefix("tracing.")
+                .map(|key| format!("user_{}", key))
+        });
+
+        let mut values = HashMap::new();
+        values.insert("trace.trace_id".to_string(), json!("abc"));
+        values.insert("tracing.name".to_string(), json!("user field"));
+        values.insert("name".to_string(), json!("span"));
+
+        let values = key_mapping.apply(values);
+        assert_eq!(values["trace-id"], json!("abc"));
+        assert_eq!(values["user_name"], json!("user field"));
+        assert_eq!(values["name"], json!("span"));
+    }
+
+    #[test]
+    fn keys_are_renamed_in_place() {
+        let mut key_mapping = KeyMapping::default();
+        key_mapping.rename("a".to_string(), "b".to_string());
+        key_mapping.rename("b".to_string(), "a".to_string());
+
+        let mut values = HashMap::with_capacity(64);
+        values.insert("a".to_string(), json!(1));
+        values.insert("b".to_string(), json!(2));
+        values.insert("c".to_string(), json!(3));
+        let capacity = values.capacity();
+
+        let values = key_mapping.apply(values);
+        assert_eq!(values.capacity(), capacity);
+        assert_eq!(values.len(), 3);
+        assert_eq!(values["a"], json!(2));
+        assert_eq!(values["b"], json!(1));
+        assert_eq!(values["c"], json!(3));
+    }
+
+    #[test]
+    fn redaction_masks_and_drops_fields() {
+        let mut redaction = Redaction::default();
+        redaction.set_action("password", FieldAction::Drop);
+        redaction.set_filter(|name| {
+            if name.contains("token") {
+                FieldAction::Mask
+            } else {
+                FieldAction::Keep
+            }
+        });
+
+        let mut visitor = HoneycombVisitor::default();
+        visitor.0.insert("Password".to_string(), json!("hunter2"));
+        visitor.0.insert("user".to_string(), json!("alice"));
+        visitor.1.insert(
+            "auth_token".to_string(),
+            Lazy::new(|| -> &'static str { panic!("masked lazy values are not evaluated") }),
+        );
+
+        let values = redaction.apply(visitor, HashMap::new());
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["user"], json!("alice"));
+        assert_eq!(values["auth_token"], json!("[REDACTED]"));
+
+        redaction.set_action("Schlüssel", FieldAction::Drop);
+        assert_eq!(redaction.action("password"), FieldAction::Drop);
+        assert_eq!(redaction.action("SCHLÜSSEL"), FieldAction::Drop);
+        assert_eq!(redaction.action("schlüssel"), FieldAction::Drop);
+    }
+
+    #[test]
+    fn anonymized_values_are_consistent_hashes() {
+        let mut redaction = Redaction::default();
+        redaction.set_anonymization_key("key".to_string());
+        redaction.set_action("email", FieldAction::Anonymize);
+
+        let mut first = HoneycombVisitor::default();
+        first
+            .0
+            .insert("email".to_string(), json!("alice@example.com"));
+        let mut second = HoneycombVisitor::default();
+        second
+            .0
+            .insert("Email".to_string(), json!("alice@example.com"));
+        let mut other = HoneycombVisitor::default();
+        other
+            .0
+            .insert("email".to_string(), json!("bob@example.com"));
+
+        let first = redaction.apply(first, HashMap::new());
+        let second = redaction.apply(second, HashMap::new());
+        let other = redaction.apply(other, HashMap::new());
+        assert!(first["email"].as_str().unwrap().starts_with("anon-"));
+        assert_eq!(first["email"], second["Email"]);
+        assert_ne!(first["email"], other["email"]);
+
+        redaction.set_anonymization_key("other key".to_string());
+        let mut rekeyed = HoneycombVisitor::default();
+        rekeyed
+            .0
+            .insert("email".to_string(), json!("alice@example.com"));
+        assert_ne!(
+            first["email"],
+            redaction.apply(rekeyed, HashMap::new())["email"]
+        );
+    }
+
+    // records only string fields, prefixed
+    #[derive(Default)]
+    struct StringsVisitor(Vec<(String, String)>);
+
+    impl Visit for StringsVisitor {
+        fn record_str(&mut self, field: &Field, value: &str) {
+            self.0
+                .push((format!("app.{}", field.name()), value.to_string()));
+        }
+
+        fn record_debug(&mut self, _: &Field, _: &dyn fmt::Debug) {}
+    }
+
+    impl HoneycombValues for StringsVisitor {
+        fn into_values(self) -> HashMap<String, Value> {
+            self.0
+                .into_iter()
+                .map(|(name, value)| (name, json!(value)))
+                .collect()
         }
     }
 
-    values
+    #[test]
+    fn redaction_applies_to_custom_visitors() {
+        let mut redaction = Redaction::default();
+        redaction.set_action("app.secret", FieldAction::Mask);
+
+        let visitor = StringsVisitor(vec![
+            ("app.secret".to_string(), "hunter2".to_string()),
+            ("app.user".to_string(), "alice".to_string()),
+        ]);
+
+        let values = redaction.apply(visitor, HashMap::new());
+        assert_eq!(values["app.secret"], json!("[REDACTED]"));
+        assert_eq!(values["app.user"], json!("alice"));
+    }
 }
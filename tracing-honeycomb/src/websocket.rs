@@ -0,0 +1,153 @@
+//! Helpers for tracing long-lived WebSocket connections established using `tungstenite`.
+//!
+//! A connection is traced as a single distributed trace, rooted at a connection-scoped span
+//! and continuing the trace propagated by the client in the handshake request, if any. Each
+//! message gets its own child span.
+//!
+//! ```ignore
+//! let mut connection_span = None;
+//! let mut socket = tungstenite::accept_hdr(stream, |request: &Request, response| {
+//!     connection_span = Some(websocket::connection_span(request));
+//!     Ok(response)
+//! })?;
+//! let connection_span = connection_span.unwrap();
+//!
+//! loop {
+//!     let message = socket.read()?;
+//!     let _guard = websocket::message_span(&connection_span, MessageDirection::Inbound, &message)
+//!         .entered();
+//!     // handle message
+//! }
+//! ```
+
+use std::str::FromStr;
+use tungstenite::http::{HeaderValue, Request};
+use tungstenite::Message;
+
+use crate::propagation::{PropagationContext, TraceHeadersExt, HONEYCOMB_TRACE_HEADER};
+use crate::SpanKind;
+
+/// Direction of a WebSocket message, relative to this service.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MessageDirection {
+    /// Message received from the peer.
+    Inbound,
+    /// Message sent to the peer.
+    Outbound,
+}
+
+impl MessageDirection {
+    fn as_str(self) -> &'static str {
+        match self {
+            MessageDirection::Inbound => "inbound",
+            MessageDirection::Outbound => "outbound",
+        }
+    }
+}
+
+/// Create a span scoped to the WebSocket connection upgraded from `request`, registered as
+/// the local root of a distributed trace.
+///
+/// If the handshake request carries an `x-honeycomb-trace` header, the connection continues
+/// the trace (and honors the sampling decision and experiment assignments) propagated by the
+/// client. Otherwise a new trace is started, see `new_trace_id`.
+pub fn connection_span<B>(request: &Request<B>) -> tracing::Span {
+    let span = tracing::info_span!(
+        "websocket.connection",
+        span.kind = %SpanKind::Server,
+        ws.path = %request.uri().path(),
+    );
+
+    let propagated = request
+        .headers()
+        .get(HONEYCOMB_TRACE_HEADER)
+        .and_then(|header| header.to_str().ok())
+        .and_then(|header| PropagationContext::from_str(header).ok());
+
+    span.in_scope(|| {
+        // registration only fails if no telemetry layer is installed, in which case there is
+        // no trace to record
+        let _ = match propagated {
+            Some(ctx) => ctx.register_dist_tracing_root(),
+            None => crate::register_dist_tracing_root(crate::new_trace_id(), None),
+        };
+    });
+
+    span
+}
+
+/// Create a span for a single message sent or received on the connection traced by
+/// `connection`, recording the message's direction, type and size.
+pub fn message_span(
+    connection: &tracing::Span,
+    direction: MessageDirection,
+    message: &Message,
+) -> tracing::Span {
+    let message_type = match message {
+        Message::Text(_) => "text",
+        Message::Binary(_) => "binary",
+        Message::Ping(_) => "ping",
+        Message::Pong(_) => "pong",
+        Message::Close(_) => "close",
+        Message::Frame(_) => "frame",
+    };
+
+    tracing::info_span!(
+        parent: connection,
+        "websocket.message",
+        ws.direction = direction.as_str(),
+        ws.message_type = message_type,
+        ws.message_size = message.len() as u64,
+    )
+}
+
+/// Propagates the current trace to the server in the WebSocket handshake request.
+impl<B> TraceHeadersExt for Request<B> {
+    fn with_trace_headers(mut self) -> Self {
+        if let Ok(ctx) = PropagationContext::current() {
+            if let Ok(value) = HeaderValue::from_str(&ctx.to_string()) {
+                self.headers_mut().insert(HONEYCOMB_TRACE_HEADER, value);
+            }
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{SpanId, TraceId};
+    use tracing_subscriber::layer::Layer;
+
+    #[test]
+    fn connection_span_continues_propagated_trace() {
+        let layer = crate::new_blackhole_telemetry_layer();
+        let subscriber = layer.with_subscriber(tracing_subscriber::registry::Registry::default());
+
+        tracing::subscriber::with_default(subscriber, || {
+            let request = Request::builder()
+                .uri("/ws")
+                .header(HONEYCOMB_TRACE_HEADER, "1;trace_id=abc,parent_id=2a")
+                .body(())
+                .unwrap();
+
+            let connection = connection_span(&request);
+            let message = message_span(
+                &connection,
+                MessageDirection::Inbound,
+                &Message::Text("hi".into()),
+            );
+            let (trace_id, _) = message.in_scope(crate::current_dist_trace_ctx).unwrap();
+            assert_eq!(trace_id, TraceId::from("abc"));
+
+            let forwarded =
+                message.in_scope(|| Request::builder().body(()).unwrap().with_trace_headers());
+            let header = forwarded.headers()[HONEYCOMB_TRACE_HEADER]
+                .to_str()
+                .unwrap();
+            let ctx = PropagationContext::from_str(header).unwrap();
+            assert_eq!(ctx.trace_id, TraceId::from("abc"));
+            assert_ne!(ctx.parent_span, SpanId::from_str("2a").unwrap());
+        });
+    }
+}
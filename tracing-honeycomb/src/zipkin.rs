@@ -0,0 +1,289 @@
+use chrono::{DateTime, Utc};
+use libhoney::{json, Value};
+use std::collections::HashMap;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+use crate::diagnostics::{Diagnostic, InternalLogMode};
+use crate::telemetry_error::TelemetryError;
+use crate::visitor::TraceFieldNames;
+use crate::{SpanId, TraceId};
+
+const SPANS_ENDPOINT: &str = "/api/v2/spans";
+
+// spans mirrored at most once per second, in batches of at most this many spans. spans
+// reported while the queue is full are not mirrored
+const MAX_BATCH_SIZE: usize = 100;
+const BATCH_TIMEOUT: Duration = Duration::from_secs(1);
+const QUEUE_CAPACITY: usize = 10_000;
+
+/// Mirrors spans to a Zipkin-compatible collector, e.g. a local Jaeger or Zipkin instance, in
+/// addition to publishing them to honeycomb.io, see `Builder::mirror_to_zipkin`.
+///
+/// Spans are converted from the values published to honeycomb.io, and queued for a background
+/// thread posting them in batches using Zipkin's v2 JSON encoding.
+#[derive(Debug)]
+pub(crate) struct ZipkinMirror {
+    sender: Option<SyncSender<Message>>,
+    worker: Option<JoinHandle<()>>,
+    trace_fields: TraceFieldNames,
+}
+
+#[derive(Debug)]
+enum Message {
+    Span(Value),
+    Flush(SyncSender<()>),
+}
+
+impl ZipkinMirror {
+    pub(crate) fn new(
+        endpoint: String,
+        trace_fields: TraceFieldNames,
+        internal_log_mode: InternalLogMode,
+    ) -> Self {
+        let client = reqwest::blocking::Client::builder()
+            .build()
+            .expect("failed to initialize zipkin http client");
+        let url = format!("{}{}", endpoint.trim_end_matches('/'), SPANS_ENDPOINT);
+
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        let worker = std::thread::Builder::new()
+            .name("honeycomb-zipkin".to_string())
+            .spawn(move || process(client, url, receiver, internal_log_mode))
+            .expect("failed to spawn honeycomb zipkin thread");
+
+        ZipkinMirror {
+            sender: Some(sender),
+            worker: Some(worker),
+            trace_fields,
+        }
+    }
+
+    /// Queue a copy of a span for mirroring, ignoring events (values without span id).
+    pub(crate) fn send(
+        &self,
+        data: &HashMap<String, Value>,
+        static_fields: &HashMap<String, Value>,
+    ) {
+        if !data.contains_key(&self.trace_fields.span_id) {
+            return;
+        }
+        let span = to_zipkin_span(data, static_fields, &self.trace_fields);
+        let sender = self.sender.as_ref().expect("zipkin mirror already stopped");
+        // best effort, spans are dropped if the collector can't keep up
+        let _ = sender.try_send(Message::Span(span));
+    }
+
+    /// Mirror the spans queued so far right away, and wait at most `timeout` for them to be
+    /// sent.
+    pub(crate) fn flush(&self, timeout: Duration) {
+        let (done, flushed) = mpsc::sync_channel(1);
+        let sender = self.sender.as_ref().expect("zipkin mirror already stopped");
+        if sender.send(Message::Flush(done)).is_ok() {
+            let _ = flushed.recv_timeout(timeout);
+        }
+    }
+}
+
+impl Drop for ZipkinMirror {
+    fn drop(&mut self) {
+        // the worker sends pending spans and exits once the channel is closed
+        drop(self.sender.take());
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn process(
+    client: reqwest::blocking::Client,
+    url: String,
+    receiver: Receiver<Message>,
+    internal_log_mode: InternalLogMode,
+) {
+    let mut pending = Vec::new();
+    // reported once until spans are mirrored again, the collector is often not running
+    let mut failing = false;
+    let mut send = |spans: Vec<Value>| {
+        if spans.is_empty() {
+            return;
+        }
+        let events = spans.len();
+        let res = client
+            .post(&url)
+            .header("Content-Type", "application/json")
+            .body(Value::Array(spans).to_string())
+            .send()
+            .and_then(|response| response.error_for_status());
+        match res {
+            Ok(_) => failing = false,
+            Err(err) if !failing => {
+                failing = true;
+                internal_log_mode.emit(&Diagnostic::Error(&TelemetryError::Send {
+                    dataset: url.clone(),
+                    events,
+                    status: err.status().map(|status| status.as_u16()),
+                    message: err.to_string(),
+                }));
+            }
+            Err(_) => {}
+        }
+    };
+
+    let mut deadline = Instant::now() + BATCH_TIMEOUT;
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match receiver.recv_timeout(timeout) {
+            Ok(Message::Span(span)) => {
+                pending.push(span);
+                if pending.len() < MAX_BATCH_SIZE {
+                    continue;
+                }
+            }
+            Ok(Message::Flush(done)) => {
+                send(std::mem::take(&mut pending));
+                let _ = done.try_send(());
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                send(pending);
+                return;
+            }
+        }
+        send(std::mem::take(&mut pending));
+        deadline = Instant::now() + BATCH_TIMEOUT;
+    }
+}
+
+// values holding the span's identity and timing, converted to the corresponding Zipkin fields
+// instead of tags
+const SPAN_FIELDS: [&str; 5] = [
+    "name",
+    "Timestamp",
+    "duration_ms",
+    "service_name",
+    "span.kind",
+];
+
+/// Convert the values of a span, as published to honeycomb.io, to a Zipkin v2 span.
+fn to_zipkin_span(
+    data: &HashMap<String, Value>,
+    static_fields: &HashMap<String, Value>,
+    trace_fields: &TraceFieldNames,
+) -> Value {
+    let id = |name: &str| data.get(name).and_then(Value::as_str);
+    let trace_id = id(&trace_fields.trace_id)
+        .map(|id| TraceId::from(id).to_w3c().to_string())
+        .unwrap_or_default();
+    let span_id = id(&trace_fields.span_id).map(to_zipkin_span_id);
+    let parent_id = id(&trace_fields.parent_id).map(to_zipkin_span_id);
+
+    let start = data
+        .get("Timestamp")
+        .and_then(Value::as_str)
+        .and_then(|timestamp| DateTime::parse_from_rfc3339(timestamp).ok())
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .unwrap_or_else(Utc::now);
+    let duration_us = data
+        .get("duration_ms")
+        .and_then(Value::as_f64)
+        .map(|duration_ms| (duration_ms * 1000.0) as u64)
+        .unwrap_or_default();
+    let kind = match data.get("span.kind").and_then(Value::as_str) {
+        Some("server") => json!("SERVER"),
+        Some("client") => json!("CLIENT"),
+        Some("producer") => json!("PRODUCER"),
+        Some("consumer") => json!("CONSUMER"),
+        _ => Value::Null,
+    };
+
+    // zipkin tags are strings
+    let tags: HashMap<&str, String> = static_fields
+        .iter()
+        .chain(data)
+        .filter(|(key, value)| {
+            !SPAN_FIELDS.contains(&key.as_str())
+                && ![
+                    &trace_fields.trace_id,
+                    &trace_fields.span_id,
+                    &trace_fields.parent_id,
+                ]
+                .contains(key)
+                && !value.is_null()
+        })
+        .map(|(key, value)| match value {
+            Value::String(s) => (key.as_str(), s.clone()),
+            value => (key.as_str(), value.to_string()),
+        })
+        .collect();
+
+    let mut span = json!({
+        "traceId": trace_id,
+        "id": span_id,
+        "name": data.get("name").cloned().unwrap_or_default(),
+        "timestamp": start.timestamp_micros(),
+        "duration": duration_us.max(1),
+        "localEndpoint": { "serviceName": data.get("service_name").cloned().unwrap_or_default() },
+        "tags": tags,
+    });
+    if let Some(parent_id) = parent_id {
+        span["parentId"] = json!(parent_id);
+    }
+    if !kind.is_null() {
+        span["kind"] = kind;
+    }
+    span
+}
+
+// span ids may be published as `span-{id}`, zipkin requires 16 hex digits
+fn to_zipkin_span_id(id: &str) -> String {
+    let id = id.strip_prefix("span-").unwrap_or(id);
+    match id.parse::<SpanId>() {
+        Ok(span_id) => format!("{:016x}", span_id.to_u64()),
+        Err(_) => TraceId::from(id).to_w3c().to_string()[..16].to_string(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn converts_spans_to_zipkin() {
+        let mut data = HashMap::new();
+        data.insert(
+            "trace.trace_id".to_string(),
+            json!("4bf92f3577b34da6a3ce929d0e0e4736"),
+        );
+        data.insert("trace.span_id".to_string(), json!("span-2a-1"));
+        data.insert("trace.parent_id".to_string(), Value::Null);
+        data.insert("name".to_string(), json!("checkout"));
+        data.insert("service_name".to_string(), json!("shop"));
+        data.insert("span.kind".to_string(), json!("server"));
+        data.insert("duration_ms".to_string(), json!(2.5));
+        data.insert(
+            "Timestamp".to_string(),
+            json!("2020-01-01T00:00:00.000001+00:00"),
+        );
+        data.insert("user_id".to_string(), json!(42));
+        let mut static_fields = HashMap::new();
+        static_fields.insert("region".to_string(), json!("eu"));
+
+        let span = to_zipkin_span(&data, &static_fields, &TraceFieldNames::default());
+        let span_id = "2a-1".parse::<SpanId>().unwrap().to_u64();
+        assert_eq!(
+            span,
+            json!({
+                "traceId": "4bf92f3577b34da6a3ce929d0e0e4736",
+                "id": format!("{:016x}", span_id),
+                "name": "checkout",
+                "kind": "SERVER",
+                "timestamp": 1_577_836_800_000_001i64,
+                "duration": 2500,
+                "localEndpoint": { "serviceName": "shop" },
+                "tags": { "user_id": "42", "region": "eu" },
+            })
+        );
+    }
+}